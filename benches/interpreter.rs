@@ -0,0 +1,55 @@
+// Interpreter throughput, instructions per second through Cpu::run_batch().
+// Each program loops forever so a batch never runs out of code. Compare dispatchers with
+// `cargo bench` against `cargo bench --features dispatch-table`.
+
+use std::hint::black_box;
+
+use chip_8_emulator::{Cpu, Quirks};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const BATCH: u64 = 100_000;
+
+// Nothing but register arithmetic and a jump back to the top
+const ALU: &[u8] = &[
+    0x60, 0x05, // 200: LD V0, 0x05
+    0x61, 0x03, // 202: LD V1, 0x03
+    0x70, 0x01, // 204: ADD V0, 0x01
+    0x80, 0x14, // 206: ADD V0, V1
+    0x81, 0x03, // 208: XOR V1, V0
+    0x82, 0x05, // 20A: SUB V2, V0
+    0x83, 0x16, // 20C: SHR V3, V1
+    0x84, 0x21, // 20E: OR V4, V2
+    0x12, 0x04, // 210: JP 0x204
+];
+
+// Drawing, a call and a skip, closer to what a game does
+const MIXED: &[u8] = &[
+    0xA2, 0x16, // 200: LD I, 0x216
+    0x60, 0x00, // 202: LD V0, 0x00
+    0x61, 0x00, // 204: LD V1, 0x00
+    0xD0, 0x15, // 206: DRW V0, V1, 5
+    0x70, 0x03, // 208: ADD V0, 0x03
+    0x22, 0x0E, // 20A: CALL 0x20E
+    0x12, 0x06, // 20C: JP 0x206
+    0x81, 0x04, // 20E: ADD V1, V0
+    0x31, 0x00, // 210: SE V1, 0x00
+    0x71, 0x01, // 212: ADD V1, 0x01
+    0x00, 0xEE, // 214: RET
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 216: sprite, a 0
+];
+
+fn batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run_batch");
+    group.throughput(Throughput::Elements(BATCH));
+
+    for (name, rom) in [("alu", ALU), ("mixed", MIXED)] {
+        // no display wait, run_batch() never ticks the timers that would end it
+        let mut cpu = Cpu::new(Quirks { display_wait: false, ..Quirks::default() });
+        cpu.load_rom(rom).unwrap();
+        group.bench_function(name, |b| b.iter(|| cpu.run_batch(black_box(BATCH))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batch);
+criterion_main!(benches);
@@ -0,0 +1,37 @@
+// The original demo: CALL a function that adds twice, twice.
+use chip_8_emulator::{Cpu, Quirks};
+
+fn main() {
+    // Values are held in the registers
+    // Instructions on what to do with them are decoded from memory
+    let mut cpu = Cpu::new(Quirks::default());
+
+    // Use our CPU to calculate: 5 + (10 * 2) + (10 * 2) = 45
+
+    // load some data into our registers for processing
+    cpu.registers[0] = 5;
+    cpu.registers[1] = 10;
+
+    let mem = &mut cpu.memory;
+
+    // set opcode to 0x2100: CALL the function at 0x100
+    mem[0x000] = 0x21; mem[0x001] = 0x00;
+    // set opcode to 0x2100: CALL the function at 0x100
+    mem[0x002] = 0x21; mem[0x003] = 0x00;
+    // sets opcode to 0x0000: HALT (not really needed as cpu.memory is initialized with null bytes)
+    mem[0x004] = 0x00; mem[0x005] = 0x00;
+
+
+    // sets opcode to 0x8014: ADD register 1s value to register 0
+    mem[0x100] = 0x80; mem[0x101] = 0x14;
+    // sets opcode to 0x8014: ADD register 1s value to register 0
+    mem[0x102] = 0x80; mem[0x103] = 0x14;
+    // sets opcode to 0x00EE: RETURN
+    mem[0x104] = 0x00; mem[0x105] = 0xEE;
+
+    // execute main cpu loop
+    cpu.run();
+
+    assert_eq!(cpu.registers[0], 45);
+
+}
@@ -0,0 +1,57 @@
+/*
+ * C interface to the chip_8_emulator core (src/ffi.rs).
+ * Build the library with:
+ *   cargo rustc --lib --release --no-default-features --features ffi --crate-type cdylib
+ */
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CHIP8_VARIANT_CHIP8 0
+#define CHIP8_VARIANT_SCHIP 1
+#define CHIP8_VARIANT_XOCHIP 2
+#define CHIP8_VARIANT_CHIP8X 3
+#define CHIP8_VARIANT_MEGACHIP 4
+#define CHIP8_VARIANT_ETI660 5
+#define CHIP8_VARIANT_CHIP8HIRES 6
+
+typedef struct Chip8 Chip8;
+
+/* A new machine, NULL for an unknown variant. Release with chip8_free. */
+Chip8 *chip8_new(uint32_t variant);
+void chip8_free(Chip8 *cpu);
+
+/* Copy a ROM into memory at 0x200. 0 on success, -1 if it doesn't fit. */
+int32_t chip8_load_rom(Chip8 *cpu, const uint8_t *rom, size_t len);
+
+/* Execute one instruction. */
+void chip8_step(Chip8 *cpu);
+/* Run one 60Hz frame: the frame's share of instructions, then a timer tick. */
+void chip8_run_frame(Chip8 *cpu);
+/* Tick the delay and sound timers, when driving chip8_step yourself at 60Hz. */
+void chip8_tick_timers(Chip8 *cpu);
+
+/*
+ * Copy the screen into out, one byte per pixel row by row (0 is unlit, XO-CHIP uses 0 to 3).
+ * Writes the resolution to width/height (either may be NULL) and returns the bytes needed.
+ * Nothing is copied if len is too small, so call with out = NULL first to size the buffer.
+ */
+size_t chip8_framebuffer(const Chip8 *cpu, uint8_t *out, size_t len, size_t *width, size_t *height);
+
+/* Press (pressed != 0) or release keypad key 0x0 to 0xF. */
+void chip8_key_event(Chip8 *cpu, uint8_t key, int32_t pressed);
+
+int32_t chip8_sound_playing(const Chip8 *cpu);
+int32_t chip8_is_halted(const Chip8 *cpu);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
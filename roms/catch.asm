@@ -0,0 +1,106 @@
+; Catch
+; Balls drop from the top of the screen, move the paddle with 4 and 6 to catch them. Every
+; catch scores a point and every 8 the balls fall faster. Miss one and it's game over, press
+; any key to play again.
+;
+; VA, VB  paddle x, y        VC, VD  ball x, y
+; VE      score              V7      frames per move, fewer is faster
+; V0-V4   scratch (the score's digits, where they go)
+
+start:
+    CLS
+    LD VE, 0
+    LD V7, 5
+    LD VA, 28
+    LD VB, 30
+    CALL new_ball
+    CALL draw_score
+    LD I, paddle
+    DRW VA, VB, 1
+    LD I, ball
+    DRW VC, VD, 2
+
+loop:
+    LD V6, DT           ; one move every V7 frames
+    SE V6, 0
+    JP loop
+    LD DT, V7
+
+    LD I, paddle
+    DRW VA, VB, 1
+    LD V6, 4
+    SKNP V6
+    CALL left
+    LD V6, 6
+    SKNP V6
+    CALL right
+    DRW VA, VB, 1
+
+    LD I, ball
+    DRW VC, VD, 2
+    ADD VD, 1
+    DRW VC, VD, 2
+    SE VF, 0            ; it landed on the paddle
+    JP caught
+    SE VD, 30           ; or went past it
+    JP loop
+
+game_over:
+    LD V6, K
+    JP start
+
+caught:
+    DRW VC, VD, 2
+    CALL draw_score
+    ADD VE, 1
+    CALL draw_score
+    LD V6, 7            ; faster every 8 points, down to a move a frame
+    AND V6, VE
+    SE V6, 0
+    JP next_ball
+    SE V7, 1
+    ADD V7, 0xFF
+next_ball:
+    CALL new_ball
+    LD I, ball
+    DRW VC, VD, 2
+    JP loop
+
+left:
+    SE VA, 0
+    ADD VA, 0xFE
+    RET
+
+right:
+    SE VA, 58
+    ADD VA, 2
+    RET
+
+new_ball:
+    RND VC, 0x3E
+    LD VD, 8
+    RET
+
+; Draws the score in the top middle, drawing it again rubs it out
+draw_score:
+    LD I, digits
+    LD B, VE
+    LD V2, [I]
+    LD V3, 26
+    LD V4, 1
+    LD F, V0
+    DRW V3, V4, 5
+    ADD V3, 5
+    LD F, V1
+    DRW V3, V4, 5
+    ADD V3, 5
+    LD F, V2
+    DRW V3, V4, 5
+    RET
+
+paddle:
+    DB 0b######..
+ball:
+    DB 0b##......, 0b##......
+digits:
+    DB 0, 0, 0
@@ -0,0 +1,46 @@
+; IBM logo
+; Draws the letters IBM in the striped style of IBM's logo and stops. Uses nothing but CLS,
+; LD, DRW and JP, so it's the first thing to get working in a new interpreter.
+
+start:
+    CLS
+    LD V1, 8            ; every letter is 15 rows tall, centred
+    LD V0, 4
+    LD I, letter_i
+    DRW V0, V1, 15
+    LD V0, 16
+    LD I, letter_b1
+    DRW V0, V1, 15
+    LD V0, 24
+    LD I, letter_b2
+    DRW V0, V1, 15
+    LD V0, 36
+    LD I, letter_m1
+    DRW V0, V1, 15
+    LD V0, 44
+    LD I, letter_m2
+    DRW V0, V1, 15
+    LD V0, 52
+    LD I, letter_m3
+    DRW V0, V1, 15
+done:
+    JP done
+
+letter_i:
+    DB 0b########, 0, 0b..####.., 0, 0b..####.., 0, 0b..####.., 0
+    DB 0b..####.., 0, 0b..####.., 0, 0b..####.., 0, 0b########
+letter_b1:
+    DB 0b########, 0, 0b..####.., 0, 0b..####.., 0, 0b..######, 0
+    DB 0b..####.., 0, 0b..####.., 0, 0b..####.., 0, 0b########
+letter_b2:
+    DB 0b####...., 0, 0b..####.., 0, 0b..####.., 0, 0b####...., 0
+    DB 0b..####.., 0, 0b..####.., 0, 0b..####.., 0, 0b####....
+letter_m1:
+    DB 0b#####..., 0, 0b..####.., 0, 0b..#####., 0, 0b..##.###, 0
+    DB 0b..##..##, 0, 0b..##...#, 0, 0b..##...., 0, 0b######..
+letter_m2:
+    DB 0b........, 0, 0b........, 0, 0b........, 0, 0b........, 0
+    DB 0b#......#, 0, 0b##....##, 0, 0b.######., 0, 0b..####..
+letter_m3:
+    DB 0b...#####, 0, 0b..####.., 0, 0b.#####.., 0, 0b###.##.., 0
+    DB 0b##..##.., 0, 0b#...##.., 0, 0b....##.., 0, 0b..######
@@ -0,0 +1,241 @@
+; Opcode test
+; Checks the basic CHIP-8 instructions one at a time and shows OK if they all worked. If one
+; didn't, it stops at that test and shows E and the test's number (in hex):
+;   01 LD/SE  02 SNE  03 SE VX, VY  04 SNE VX, VY  05 ADD VX, NN (wraps, VF alone)
+;   06 OR  07 AND  08 XOR  09 ADD VX, VY (carry)  0A SUB (borrow)  0B SUBN  0C SHR  0D SHL
+;   0E CALL/RET  0F LD B  10 LD [I]/LD [I]  11 ADD I  12 timers  13 DRW (collision)
+;   14 JP V0  15 RND  16 LD F
+; It only checks what every interpreter agrees on, so it passes whichever quirks are on.
+
+start:
+    LD V5, 0x01
+    LD V0, 0x42
+    SE V0, 0x42
+    JP fail
+
+    LD V5, 0x02
+    SNE V0, 0x43
+    JP fail
+
+    LD V5, 0x03
+    LD V1, 0x42
+    SE V0, V1
+    JP fail
+
+    LD V5, 0x04
+    LD V1, 0x24
+    SNE V0, V1
+    JP fail
+
+    LD V5, 0x05
+    LD VF, 0x33
+    LD V0, 0xFF
+    ADD V0, 2
+    SE V0, 1
+    JP fail
+    SE VF, 0x33
+    JP fail
+
+    LD V5, 0x06
+    LD V0, 0x0F
+    LD V1, 0xF0
+    OR V0, V1
+    SE V0, 0xFF
+    JP fail
+
+    LD V5, 0x07
+    LD V0, 0x3C
+    LD V1, 0x0F
+    AND V0, V1
+    SE V0, 0x0C
+    JP fail
+
+    LD V5, 0x08
+    LD V0, 0x3C
+    XOR V0, V1
+    SE V0, 0x33
+    JP fail
+
+    LD V5, 0x09
+    LD V0, 0xF0
+    LD V1, 0x20
+    ADD V0, V1
+    SE V0, 0x10
+    JP fail
+    SE VF, 1
+    JP fail
+    LD V0, 1
+    LD V1, 2
+    ADD V0, V1
+    SE V0, 3
+    JP fail
+    SE VF, 0
+    JP fail
+
+    LD V5, 0x0A
+    LD V0, 5
+    LD V1, 3
+    SUB V0, V1
+    SE V0, 2
+    JP fail
+    SE VF, 1
+    JP fail
+    LD V0, 3
+    LD V1, 5
+    SUB V0, V1
+    SE V0, 0xFE
+    JP fail
+    SE VF, 0
+    JP fail
+
+    LD V5, 0x0B
+    LD V0, 3
+    SUBN V0, V1
+    SE V0, 2
+    JP fail
+    SE VF, 1
+    JP fail
+
+    LD V5, 0x0C
+    LD V0, 0x81         ; shifting VX by itself works with and without the shift quirk
+    SHR V0, V0
+    SE V0, 0x40
+    JP fail
+    SE VF, 1
+    JP fail
+
+    LD V5, 0x0D
+    LD V0, 0x81
+    SHL V0, V0
+    SE V0, 0x02
+    JP fail
+    SE VF, 1
+    JP fail
+
+    LD V5, 0x0E
+    LD V0, 0
+    CALL set_v0
+    SE V0, 0x77
+    JP fail
+
+    LD V5, 0x0F
+    LD V0, 234
+    LD I, scratch
+    LD B, V0
+    LD I, scratch
+    LD V2, [I]
+    SE V0, 2
+    JP fail
+    SE V1, 3
+    JP fail
+    SE V2, 4
+    JP fail
+
+    LD V5, 0x10
+    LD V0, 1
+    LD V1, 2
+    LD V2, 3
+    LD V3, 4
+    LD I, scratch
+    LD [I], V3
+    LD V0, 0
+    LD V3, 0
+    LD I, scratch
+    LD V3, [I]
+    SE V0, 1
+    JP fail
+    SE V3, 4
+    JP fail
+
+    LD V5, 0x11
+    LD I, scratch
+    LD V0, 2
+    ADD I, V0
+    LD V0, [I]
+    SE V0, 3
+    JP fail
+
+    LD V5, 0x12
+    LD V0, 0x10
+    LD DT, V0
+    LD V1, DT
+    SNE V1, 0
+    JP fail
+
+    LD V5, 0x13
+    CLS
+    LD V0, 0
+    LD I, pixel
+    DRW V0, V0, 1
+    SE VF, 0
+    JP fail
+    DRW V0, V0, 1
+    SE VF, 1
+    JP fail
+
+    LD V5, 0x14
+    LD V0, 0            ; with the jump quirk it's BXNN and adds V2, so clear that too
+    LD V2, 0
+    JP V0, jump_target
+    JP fail
+jump_target:
+
+    LD V5, 0x15
+    RND V0, 0
+    SE V0, 0
+    JP fail
+
+    LD V5, 0x16
+    LD V0, 0xA
+    LD F, V0
+    LD V0, [I]
+    SE V0, 0xF0         ; the top row of A in every CHIP-8 font
+    JP fail
+
+pass:
+    CLS
+    LD V0, 24
+    LD V1, 12
+    LD I, letter_o
+    DRW V0, V1, 7
+    LD V0, 33
+    LD I, letter_k
+    DRW V0, V1, 7
+pass_done:
+    JP pass_done
+
+fail:
+    CLS
+    LD V0, 22
+    LD V1, 13
+    LD V2, 0xE
+    LD F, V2
+    DRW V0, V1, 5
+    LD V2, V5           ; the high digit, shifting V2 by itself again
+    SHR V2, V2
+    SHR V2, V2
+    SHR V2, V2
+    SHR V2, V2
+    ADD V0, 8
+    LD F, V2
+    DRW V0, V1, 5
+    LD V2, 0xF
+    AND V2, V5
+    ADD V0, 5
+    LD F, V2
+    DRW V0, V1, 5
+fail_done:
+    JP fail_done
+
+set_v0:
+    LD V0, 0x77
+    RET
+
+pixel:
+    DB 0b#.......
+letter_o:
+    DB 0b.#####.., 0b##...##., 0b##...##., 0b##...##., 0b##...##., 0b##...##., 0b.#####..
+letter_k:
+    DB 0b##...##., 0b##..##.., 0b##.##..., 0b####...., 0b##.##..., 0b##..##.., 0b##...##.
+scratch:
+    DB 0, 0, 0, 0
@@ -0,0 +1,280 @@
+// Static analysis.
+// What can be worked out about a ROM without running it, from the control flow graph
+// (flow_graph.rs) and a bit of constant tracking on top:
+//
+//   Which bytes are code, which are data (something loads I with an address in them, so
+//   they're sprites or tables), and which nothing seems to use at all. Data with code on
+//   both sides is data embedded in the code stream, the thing that trips up a linear
+//   disassembler.
+//
+//   Where the code runs straight into something that isn't an instruction, which is either
+//   a bug or code the walk can't see the real way into.
+//
+//   Writes into code (FX33, FX55 and XO-CHIP's 5XY2 with I pointing at an instruction), which
+//   is self-modifying code. I is only known when it was set earlier in the same basic block,
+//   LD I, NNN and ADD I, VX with VX set from a constant. Writes where it isn't known are
+//   listed separately, they could go anywhere.
+//
+// Everything only reached through BNNN's computed jumps looks unreachable, so those are listed
+// too: where there are any, take "unreachable" with a pinch of salt.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::flow_graph::FlowGraph;
+use crate::instruction::Instruction;
+use crate::variant::Variant;
+
+/// What a stretch of the ROM is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Instructions the control flow reaches
+    Code,
+    /// Not reached, but something points I into it
+    Data,
+    /// Not reached and nothing points at it
+    Unreachable,
+}
+
+/// A stretch of the ROM, in memory addresses (the ROM starts at Variant::program_start()).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub kind: RegionKind,
+}
+
+/// An instruction that writes over code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// Where the writing instruction is
+    pub pc: usize,
+    /// The bytes it writes
+    pub target: Range<usize>,
+}
+
+/// Everything analyze() found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// The whole ROM, in order
+    pub regions: Vec<Region>,
+    /// Reachable addresses that don't hold an instruction, (address, the bytes there)
+    pub runs_into_data: Vec<(usize, u16)>,
+    pub code_writes: Vec<CodeWrite>,
+    /// Instructions that write memory at an I that couldn't be worked out
+    pub unknown_writes: Vec<usize>,
+    /// BNNN instructions, whose targets weren't followed
+    pub computed_jumps: Vec<usize>,
+}
+
+impl Analysis {
+    /// Data regions with code before and after them.
+    pub fn embedded_data(&self) -> impl Iterator<Item = &Region> {
+        let code = || self.regions.iter().filter(|region| region.kind == RegionKind::Code);
+        let first = code().map(|region| region.range.start).min().unwrap_or(0);
+        let last = code().map(|region| region.range.end).max().unwrap_or(0);
+        self.regions
+            .iter()
+            .filter(move |region| region.kind == RegionKind::Data && region.range.start > first && region.range.end < last)
+    }
+}
+
+/// Analyse a ROM loaded where `variant` loads them in `memory` (a CPU's memory), `rom_len`
+/// bytes long.
+pub fn analyze(memory: &[u8], rom_len: usize, variant: Variant) -> Analysis {
+    let graph = FlowGraph::build(memory, variant);
+    let start = variant.program_start();
+    let rom = start..(start + rom_len).min(memory.len());
+    let mut analysis = Analysis::default();
+
+    // which ROM bytes are code, and which addresses I gets pointed at
+    let mut is_code = vec![false; rom.len()];
+    let mut pointed_at = Vec::new();
+    for block in graph.blocks.values() {
+        for &(addr, instruction) in &block.instructions {
+            for byte in addr..addr + instruction.size() {
+                if rom.contains(&byte) {
+                    is_code[byte - rom.start] = true;
+                }
+            }
+            match instruction {
+                Instruction::LoadIndex(nnn) => pointed_at.push(nnn as usize),
+                Instruction::LoadLongIndex(nnnn) => pointed_at.push(nnnn as usize),
+                Instruction::JumpWithOffset(..) => analysis.computed_jumps.push(addr),
+                Instruction::Unknown(opcode) => analysis.runs_into_data.push((addr, opcode)),
+                _ => {}
+            }
+        }
+    }
+
+    // cut the ROM into runs of code and not code, then sort the not code by whether it's used
+    let mut start = rom.start;
+    for addr in rom.clone() {
+        let next = addr + 1;
+        if next == rom.end || is_code[next - rom.start] != is_code[addr - rom.start] {
+            let range = start..next;
+            let kind = if is_code[addr - rom.start] {
+                RegionKind::Code
+            } else if pointed_at.iter().any(|target| range.contains(target)) {
+                RegionKind::Data
+            } else {
+                RegionKind::Unreachable
+            };
+            analysis.regions.push(Region { range, kind });
+            start = next;
+        }
+    }
+
+    let overlaps_code = |target: &Range<usize>| target.clone().any(|byte| rom.contains(&byte) && is_code[byte - rom.start]);
+    for block in graph.blocks.values() {
+        let mut known = Constants::default();
+        for &(addr, instruction) in &block.instructions {
+            if let Some(len) = write_len(instruction) {
+                match known.i {
+                    Some(i) => {
+                        let target = i as usize..i as usize + len;
+                        if overlaps_code(&target) {
+                            analysis.code_writes.push(CodeWrite { pc: addr, target });
+                        }
+                    }
+                    None => analysis.unknown_writes.push(addr),
+                }
+            }
+            known.update(instruction);
+        }
+    }
+
+    analysis.runs_into_data.sort();
+    analysis.computed_jumps.sort();
+    analysis.unknown_writes.sort();
+    analysis.code_writes.sort_by_key(|write| write.pc);
+    analysis
+}
+
+// How many bytes an instruction writes at I, None for the ones that don't write memory
+fn write_len(instruction: Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::StoreBcd(_) => Some(3),
+        Instruction::StoreRegisters(x) => Some(x as usize + 1),
+        Instruction::StoreRange(x, y) => Some(x.abs_diff(y) as usize + 1),
+        _ => None,
+    }
+}
+
+// The registers (and I) whose values are known at some point in a basic block
+#[derive(Default)]
+struct Constants {
+    v: [Option<u8>; 16],
+    i: Option<u16>,
+}
+
+impl Constants {
+    // What's known after `instruction` runs
+    fn update(&mut self, instruction: Instruction) {
+        use Instruction::*;
+        match instruction {
+            LoadIndex(nnn) => self.i = Some(nnn),
+            LoadLongIndex(nnnn) => self.i = Some(nnnn),
+            AddIndex(x) => {
+                self.i = self.i.zip(self.v[x as usize]).map(|(i, v)| i.wrapping_add(v as u16));
+                // the index overflow quirk might have set VF
+                self.v[0xF] = None;
+            }
+            LoadByte(x, kk) => self.v[x as usize] = Some(kk),
+            AddByte(x, kk) => self.v[x as usize] = self.v[x as usize].map(|v| v.wrapping_add(kk)),
+            Move(x, y) => self.v[x as usize] = self.v[y as usize],
+            // ALU results aren't worth working out, and they all set VF
+            Or(x, _) | And(x, _) | Xor(x, _) | Add(x, _) | Sub(x, _) | SubReversed(x, _) | ShiftRight(x, _) | ShiftLeft(x, _) => {
+                self.v[x as usize] = None;
+                self.v[0xF] = None;
+            }
+            Random(x, _) | ReadDelay(x) | WaitForKey(x) => self.v[x as usize] = None,
+            Draw(..) => self.v[0xF] = None,
+            // these can move I along (a quirk), so I's a mystery afterwards
+            StoreRegisters(_) => self.i = None,
+            LoadRegisters(x) | LoadFlags(x) => {
+                self.v[..=x as usize].fill(None);
+                self.i = None;
+            }
+            LoadRange(x, y) => {
+                let (low, high) = (x.min(y) as usize, x.max(y) as usize);
+                self.v[low..=high].fill(None);
+            }
+            LoadFont(_) | LoadBigFont(_) => self.i = None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn analyze_rom(rom: &[u8]) -> Analysis {
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(rom).unwrap();
+        analyze(&cpu.memory, rom.len(), Variant::Chip8)
+    }
+
+    const MIXED: [u8; 20] = [
+        0xA2, 0x06, // 200 LD I, 0x206
+        0xD0, 0x12, // 202 DRW V0, V1, 2
+        0x12, 0x08, // 204 JP 0x208
+        0xF0, 0xF0, // 206 a sprite
+        0xA2, 0x00, // 208 LD I, 0x200
+        0xF0, 0x33, // 20A LD B, V0
+        0xF0, 0x65, // 20C LD V0, [I]
+        0xF0, 0x55, // 20E LD [I], V0
+        0xB3, 0x00, // 210 JP V0, 0x300
+        0xFF, 0xFF, // 212 nothing uses this
+    ];
+
+    #[test]
+    fn regions() {
+        let analysis = analyze_rom(&MIXED);
+        let region = |range, kind| Region { range, kind };
+        assert_eq!(
+            analysis.regions,
+            [
+                region(0x200..0x206, RegionKind::Code),
+                region(0x206..0x208, RegionKind::Data),
+                region(0x208..0x212, RegionKind::Code),
+                region(0x212..0x214, RegionKind::Unreachable),
+            ]
+        );
+        assert_eq!(analysis.embedded_data().collect::<Vec<_>>(), [&analysis.regions[1]]);
+        assert_eq!(analysis.computed_jumps, [0x210]);
+    }
+
+    #[test]
+    fn writes() {
+        let analysis = analyze_rom(&MIXED);
+        // FX33 at a known I, then FX65 loses track of it
+        assert_eq!(analysis.code_writes, [CodeWrite { pc: 0x20A, target: 0x200..0x203 }]);
+        assert_eq!(analysis.unknown_writes, [0x20E]);
+    }
+
+    #[test]
+    fn i_from_constants() {
+        let analysis = analyze_rom(&[
+            0xA2, 0x00, // 200 LD I, 0x200
+            0x61, 0x04, // 202 LD V1, 4
+            0xF1, 0x1E, // 204 ADD I, V1
+            0xF1, 0x55, // 206 LD [I], V1
+            0xA3, 0x00, // 208 LD I, 0x300
+            0xF1, 0x55, // 20A LD [I], V1, not code
+            0x12, 0x0C, // 20C JP 0x20C
+        ]);
+        assert_eq!(analysis.code_writes, [CodeWrite { pc: 0x206, target: 0x204..0x206 }]);
+        assert!(analysis.unknown_writes.is_empty());
+    }
+
+    #[test]
+    fn running_into_data() {
+        let analysis = analyze_rom(&[0x60, 0x01, 0xFF, 0xFF]);
+        assert_eq!(analysis.runs_into_data, [(0x202, 0xFFFF)]);
+        assert_eq!(analysis.regions, [Region { range: 0x200..0x204, kind: RegionKind::Code }]);
+        assert_eq!(analysis.embedded_data().count(), 0);
+    }
+}
@@ -0,0 +1,137 @@
+// The emulator as a tokio task.
+// Async GUIs and network servers don't want a loop of their own blocking a thread, so this
+// runs the CPU as a task instead: it wakes up 60 times a second to run a frame, and in between
+// waits on a channel of Commands. What happens comes back on a channel of Events.
+//
+//   let mut emulator = async_emulator::spawn(cpu);
+//   emulator.commands.send(Command::Key { key: 5, pressed: true }).await?;
+//   while let Some(event) = emulator.events.recv().await {
+//       match event {
+//           Event::Frame(frame) => draw(&frame),
+//           ..
+//       }
+//   }
+//
+// A Frame only goes out when the screen's changed, and if whoever's reading the events falls
+// behind, frames get dropped rather than holding the emulator up. Everything else always
+// gets through. The task finishes (handing back the Cpu) on Command::Quit, or once either
+// side of the channels has gone.
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::audio::Sound;
+use crate::clock::FRAME;
+use crate::cpu::Cpu;
+use crate::display::Frame;
+use crate::error::CpuError;
+use crate::halt::HaltReason;
+use crate::save_state::SaveState;
+
+// How many events can be waiting before frames start getting dropped
+const EVENT_QUEUE: usize = 64;
+
+/// Something for the emulator to do.
+#[derive(Debug)]
+pub enum Command {
+    /// Start a ROM on a fresh machine, the same variant and settings as now
+    LoadRom(Vec<u8>),
+    /// Press or release a key (0x0 to 0xF)
+    Key { key: u8, pressed: bool },
+    Pause,
+    Resume,
+    /// Send back the machine's state as it is between frames
+    SaveState(oneshot::Sender<SaveState>),
+    /// Stop the task
+    Quit,
+}
+
+/// Something that happened in the emulator.
+#[derive(Debug)]
+pub enum Event {
+    /// The screen changed, this is it now
+    Frame(Frame),
+    /// The sound changed (started, stopped, or XO-CHIP changed the pattern or pitch)
+    Sound(Sound),
+    /// The program stopped, and the error if it was a fault
+    Halted { reason: HaltReason, fault: Option<CpuError> },
+    /// A LoadRom didn't work, the old program's still there
+    LoadFailed(CpuError),
+}
+
+/// The two ends the frontend holds, and the task.
+pub struct EmulatorTask {
+    pub commands: mpsc::Sender<Command>,
+    pub events: mpsc::Receiver<Event>,
+    /// Finishes with the Cpu when the task does
+    pub task: JoinHandle<Cpu>,
+}
+
+/// Start running `cpu` as a task on the current tokio runtime.
+pub fn spawn(cpu: Cpu) -> EmulatorTask {
+    let (commands, command_rx) = mpsc::channel(EVENT_QUEUE);
+    let (event_tx, events) = mpsc::channel(EVENT_QUEUE);
+    let task = tokio::spawn(run(cpu, command_rx, event_tx));
+    EmulatorTask { commands, events, task }
+}
+
+/// The task itself, for running it some other way than spawn() (inside a select!, say).
+pub async fn run(mut cpu: Cpu, mut commands: mpsc::Receiver<Command>, events: mpsc::Sender<Event>) -> Cpu {
+    let mut ticks = time::interval(FRAME);
+    // a stall (the machine sleeping, a busy runtime) shouldn't be made up with a burst of frames
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut sound = cpu.sound();
+    // what Display::changes() was at the last Frame sent, None to send the next one anyway
+    let mut shown = None;
+    let mut halted = false;
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                None | Some(Command::Quit) => return cpu,
+                Some(Command::LoadRom(rom)) => match cpu.restart_with_rom(&rom) {
+                    Ok(()) => {
+                        shown = None;
+                        halted = false;
+                    }
+                    Err(e) => {
+                        if events.send(Event::LoadFailed(e)).await.is_err() {
+                            return cpu;
+                        }
+                    }
+                },
+                Some(Command::Key { key, pressed }) => cpu.set_key(key, pressed),
+                Some(Command::Pause) => cpu.pause(),
+                Some(Command::Resume) => cpu.resume(),
+                Some(Command::SaveState(reply)) => {
+                    // nobody waiting for it any more is their business
+                    let _ = reply.send(SaveState::capture(&cpu));
+                }
+            },
+            _ = ticks.tick() => {
+                cpu.run_frame();
+                if shown != Some(cpu.display.changes()) {
+                    match events.try_send(Event::Frame(cpu.display.frame())) {
+                        Ok(()) => shown = Some(cpu.display.changes()),
+                        Err(mpsc::error::TrySendError::Full(_)) => {}
+                        Err(mpsc::error::TrySendError::Closed(_)) => return cpu,
+                    }
+                }
+                if cpu.sound() != sound {
+                    sound = cpu.sound();
+                    if events.send(Event::Sound(sound.clone())).await.is_err() {
+                        return cpu;
+                    }
+                }
+                if let (false, Some(reason)) = (halted, cpu.halt_reason()) {
+                    halted = true;
+                    let fault = cpu.fault().cloned();
+                    if events.send(Event::Halted { reason, fault }).await.is_err() {
+                        return cpu;
+                    }
+                }
+            }
+        }
+    }
+}
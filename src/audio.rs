@@ -0,0 +1,340 @@
+// Audio.
+// Base CHIP-8 only has a buzzer: it sounds while the sound timer is above 0, and that's it.
+// XO-CHIP gave ROMs a 16 byte (128 bit) pattern buffer (FX02) that's played back as
+// 1-bit samples at a rate set by the pitch register (FX3A), so music is possible. Mega-Chip
+// went further and plays proper 8 bit samples out of memory (060N, see src/mega_chip.rs),
+// which play whatever the sound timer's doing, on top of the buzzer if that's on as well.
+// This module turns that state into actual samples, the audio backend just asks for buffers.
+//
+// What the buzzer sounds like is up to the emulator, so it's a Tone: a frequency and a
+// waveform, square (the usual, and the harshest), triangle, sine, or a sample from a WAV file
+// played over and over for as long as the buzzer's on. `chip8 run --beep-frequency 330
+// --waveform triangle`, or beep_frequency and waveform in config.toml. XO-CHIP patterns are
+// 1-bit samples already, so they stay square whatever the tone.
+//
+// The volume scales everything the engine makes, from 0 (silent) to 1, which is already well
+// short of full scale: a square wave at full scale is unpleasant. Muting is up to the
+// AudioSink (see src/frontend.rs), so unmuting goes back to the volume it was.
+
+use alloc::sync::Arc;
+
+/// Bytes in the XO-CHIP audio pattern buffer.
+pub const PATTERN_LEN: usize = 16;
+
+/// Pitch register value that plays the pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Tone used for the plain buzzer when no pattern has been loaded.
+pub const BEEP_FREQUENCY: f32 = 440.0;
+
+#[cfg(feature = "std")]
+const AMPLITUDE: f32 = 0.25;
+
+/// Everything needed to know what the machine sounds like right now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sound {
+    /// True while the sound timer is running
+    pub playing: bool,
+    /// The XO-CHIP pattern buffer, None until a ROM loads one
+    pub pattern: Option<[u8; PATTERN_LEN]>,
+    pub pitch: u8,
+    /// Mega-Chip's digitised sound, None unless one's been started
+    pub digitised: Option<Digitised>,
+}
+
+/// A Mega-Chip digitised sound (060N), copied out of memory when it started.
+#[derive(Debug, Clone)]
+pub struct Digitised {
+    /// Samples a second
+    pub sample_rate: u16,
+    /// Unsigned 8 bit, 128 is silence
+    pub samples: Arc<[u8]>,
+    pub looping: bool,
+}
+
+// The same sound started again is a new copy of the samples, and plays from the start again.
+// Comparing the copies rather than what's in them also keeps comparing Sounds every frame cheap
+impl PartialEq for Digitised {
+    fn eq(&self, other: &Self) -> bool {
+        self.sample_rate == other.sample_rate && self.looping == other.looping && Arc::ptr_eq(&self.samples, &other.samples)
+    }
+}
+
+impl Eq for Digitised {}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Sound {
+            playing: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            digitised: None,
+        }
+    }
+}
+
+// Turning state into samples needs floating point maths from std (powf)
+#[cfg(feature = "std")]
+impl Sound {
+    /// How many pattern bits play per second, 4000 * 2^((pitch - 64) / 48)
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// The shape of the buzzer's sound.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    /// A recording, looped at its own sample rate. The tone's frequency doesn't change it
+    Sample { sample_rate: u32, samples: Arc<[f32]> },
+}
+
+#[cfg(feature = "std")]
+impl Waveform {
+    /// square, triangle or sine.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    /// A sample from a WAV file (8 or 16 bit PCM, or 32 bit float), mixed down to mono.
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let (sample_rate, samples) = crate::wav::decode(&bytes)
+            .map_err(|reason| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), reason)))?;
+        if samples.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: there's no sound in it", path.display())));
+        }
+        Ok(Waveform::Sample { sample_rate, samples: samples.into() })
+    }
+
+    // One cycle of the wave, from -1 to 1, `phase` going from 0 to 1
+    fn at(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * core::f32::consts::TAU).sin(),
+            Waveform::Sample { .. } => 0.0,
+        }
+    }
+}
+
+/// What the buzzer sounds like, see the top of this file.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tone {
+    /// In Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+}
+
+#[cfg(feature = "std")]
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { frequency: BEEP_FREQUENCY, waveform: Waveform::Square }
+    }
+}
+
+#[cfg(feature = "std")]
+/// Turns `Sound` into samples. Keeps its position in the pattern between buffers so
+/// playback doesn't click every time the backend asks for more.
+pub struct AudioEngine {
+    sample_rate: f32,
+    tone: Tone,
+    volume: f32,
+    // position in the pattern (in bits), kept apart from the beep's so a ROM loading a pattern
+    // partway through a beep can't land past the end of it
+    pattern_phase: f32,
+    // position in the beep cycle (0 to 1), or in the beep's sample
+    beep_phase: f32,
+    // the digitised sound being played and how far through it (in samples) it's got
+    digitised: Option<(Arc<[u8]>, f32)>,
+}
+
+#[cfg(feature = "std")]
+impl AudioEngine {
+    pub fn new(sample_rate: u32) -> Self {
+        AudioEngine::with_tone(sample_rate, Tone::default())
+    }
+
+    /// An engine whose buzzer sounds like `tone`.
+    pub fn with_tone(sample_rate: u32, tone: Tone) -> Self {
+        AudioEngine {
+            sample_rate: sample_rate as f32,
+            tone,
+            volume: 1.0,
+            pattern_phase: 0.0,
+            beep_phase: 0.0,
+            digitised: None,
+        }
+    }
+
+    /// How loud to play, from 0.0 (silent) to 1.0 (the default).
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Fill `out` with mono samples between -1.0 and 1.0.
+    pub fn fill(&mut self, sound: &Sound, out: &mut [f32]) {
+        self.fill_buzzer(sound, out);
+        match &sound.digitised {
+            Some(digitised) => self.mix_digitised(digitised, out),
+            None => self.digitised = None,
+        }
+    }
+
+    // The sound timer's beep or the XO-CHIP pattern
+    fn fill_buzzer(&mut self, sound: &Sound, out: &mut [f32]) {
+        if !sound.playing {
+            out.fill(0.0);
+            self.pattern_phase = 0.0;
+            self.beep_phase = 0.0;
+            return;
+        }
+
+        let amplitude = AMPLITUDE * self.volume;
+        match sound.pattern {
+            Some(pattern) => {
+                let step = sound.playback_rate() / self.sample_rate;
+                let bits = (PATTERN_LEN * 8) as f32;
+                for sample in out.iter_mut() {
+                    let bit = self.pattern_phase as usize;
+                    let on = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    *sample = if on { amplitude } else { -amplitude };
+                    self.pattern_phase = (self.pattern_phase + step) % bits;
+                }
+            }
+            None => match &self.tone.waveform {
+                Waveform::Sample { sample_rate, samples } => {
+                    let step = *sample_rate as f32 / self.sample_rate;
+                    for sample in out.iter_mut() {
+                        *sample = samples[self.beep_phase as usize] * amplitude;
+                        self.beep_phase = (self.beep_phase + step) % samples.len() as f32;
+                    }
+                }
+                waveform => {
+                    let step = self.tone.frequency / self.sample_rate;
+                    for sample in out.iter_mut() {
+                        *sample = waveform.at(self.beep_phase) * amplitude;
+                        self.beep_phase = (self.beep_phase + step) % 1.0;
+                    }
+                }
+            },
+        }
+    }
+
+    // Add the digitised sound on top, carrying on from where the last buffer got to unless
+    // it's a different one (or the same one started again)
+    fn mix_digitised(&mut self, digitised: &Digitised, out: &mut [f32]) {
+        if !matches!(&self.digitised, Some((samples, _)) if Arc::ptr_eq(samples, &digitised.samples)) {
+            self.digitised = Some((Arc::clone(&digitised.samples), 0.0));
+        }
+        let amplitude = AMPLITUDE * self.volume;
+        let step = digitised.sample_rate as f32 / self.sample_rate;
+        let Some((samples, position)) = &mut self.digitised else {
+            return;
+        };
+        let len = samples.len() as f32;
+        for sample in out.iter_mut() {
+            if *position >= len {
+                // played once, it stays over until the ROM starts one again
+                if !digitised.looping || samples.is_empty() {
+                    return;
+                }
+                *position %= len;
+            }
+            *sample += (samples[*position as usize] as f32 - 128.0) / 128.0 * amplitude;
+            *position += step;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // a sample much longer than the pattern's 128 bits
+    fn long_sample() -> Tone {
+        Tone { frequency: BEEP_FREQUENCY, waveform: Waveform::Sample { sample_rate: 48_000, samples: vec![0.5; 1000].into() } }
+    }
+
+    #[test]
+    fn pattern_loaded_partway_through_a_sample() {
+        let mut engine = AudioEngine::with_tone(48_000, long_sample());
+        let mut out = [0.0; 800];
+        let beep = Sound { playing: true, ..Sound::default() };
+        engine.fill(&beep, &mut out);
+        assert!(out.iter().all(|&sample| sample == 0.5 * AMPLITUDE));
+
+        let pattern = Sound { pattern: Some([0xFF; PATTERN_LEN]), ..beep.clone() };
+        engine.fill(&pattern, &mut out);
+        assert!(out.iter().all(|&sample| sample == AMPLITUDE));
+
+        // and back, carrying on from where the sample was
+        engine.fill(&beep, &mut out);
+        assert!(out.iter().all(|&sample| sample == 0.5 * AMPLITUDE));
+    }
+
+    #[test]
+    fn silence_between_beeps() {
+        let mut engine = AudioEngine::new(48_000);
+        let mut out = [1.0; 100];
+        engine.fill(&Sound::default(), &mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn pattern_bits_are_the_samples() {
+        let mut engine = AudioEngine::new(8000);
+        let mut pattern = [0; PATTERN_LEN];
+        pattern[0] = 0x80;
+        // at 4000 bits a second each bit is 2 samples
+        let mut out = [0.0; 4];
+        engine.fill(&Sound { playing: true, pattern: Some(pattern), ..Sound::default() }, &mut out);
+        assert_eq!(out, [AMPLITUDE, AMPLITUDE, -AMPLITUDE, -AMPLITUDE]);
+    }
+
+    #[test]
+    fn volume_scales_the_beep() {
+        let mut engine = AudioEngine::new(48_000);
+        engine.set_volume(0.5);
+        let mut out = [0.0; 10];
+        engine.fill(&Sound { playing: true, ..Sound::default() }, &mut out);
+        assert_eq!(out[0], AMPLITUDE * 0.5);
+    }
+
+    #[test]
+    fn digitised_sound_plays_once_without_the_sound_timer() {
+        let mut engine = AudioEngine::new(48_000);
+        let digitised = Digitised { sample_rate: 48_000, samples: vec![255; 100].into(), looping: false };
+        let sound = Sound { digitised: Some(digitised.clone()), ..Sound::default() };
+        let mut out = [0.0; 150];
+        engine.fill(&sound, &mut out);
+        assert!(out[..100].iter().all(|&sample| sample > 0.0));
+        assert!(out[100..].iter().all(|&sample| sample == 0.0));
+
+        // and again from the start when the ROM starts it again
+        let again = Sound { digitised: Some(Digitised { samples: vec![255; 100].into(), ..digitised }), ..Sound::default() };
+        engine.fill(&again, &mut out);
+        assert!(out[0] > 0.0);
+    }
+
+    #[test]
+    fn looping_digitised_sound() {
+        let mut engine = AudioEngine::new(48_000);
+        let samples = [0, 255].repeat(5);
+        let digitised = Digitised { sample_rate: 48_000, samples: samples.into(), looping: true };
+        let mut out = [0.0; 25];
+        engine.fill(&Sound { digitised: Some(digitised), ..Sound::default() }, &mut out);
+        assert!(out[20] < 0.0 && out[21] > 0.0);
+    }
+}
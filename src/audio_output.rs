@@ -0,0 +1,83 @@
+// Plays the machine's sound through the default output device using cpal.
+// The emulator thread calls `update` every frame, the audio thread reads the latest
+// `Sound` (and the volume) whenever the device wants another buffer.
+
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::audio::{AudioEngine, Sound, Tone};
+use crate::frontend::AudioSink;
+
+pub struct AudioOutput {
+    shared: Arc<Mutex<Shared>>,
+    // playback stops when the stream is dropped, so hang on to it
+    _stream: cpal::Stream,
+}
+
+impl AudioOutput {
+    /// Open the default output device and start playing silence, with the buzzer sounding like
+    /// `tone` when it goes.
+    pub fn open(tone: Tone) -> Result<Self, String> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or("no audio output device")?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?;
+        if config.sample_format() != cpal::SampleFormat::F32 {
+            return Err(format!("unsupported sample format {:?}", config.sample_format()));
+        }
+
+        let channels = config.channels() as usize;
+        let mut engine = AudioEngine::with_tone(config.sample_rate().0, tone);
+        let shared = Arc::new(Mutex::new(Shared { sound: Sound::default(), volume: 1.0, muted: false }));
+        let playing = Arc::clone(&shared);
+        let mut mono = Vec::new();
+
+        let stream = device
+            .build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], _| {
+                    // the engine is mono, copy each sample to every channel
+                    mono.resize(data.len() / channels, 0.0);
+                    let current = playing.lock().unwrap().clone();
+                    engine.set_volume(if current.muted { 0.0 } else { current.volume });
+                    engine.fill(&current.sound, &mut mono);
+                    for (frame, sample) in data.chunks_mut(channels).zip(&mono) {
+                        frame.fill(*sample);
+                    }
+                },
+                |err| eprintln!("audio error: {}", err),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(AudioOutput { shared, _stream: stream })
+    }
+
+    /// Tell the audio thread what the machine sounds like now.
+    pub fn update(&self, sound: Sound) {
+        self.shared.lock().unwrap().sound = sound;
+    }
+}
+
+impl AudioSink for AudioOutput {
+    fn update(&mut self, sound: Sound) {
+        AudioOutput::update(self, sound)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.shared.lock().unwrap().volume = volume;
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.shared.lock().unwrap().muted = muted;
+    }
+}
+
+// What the emulator thread tells the audio thread
+#[derive(Clone)]
+struct Shared {
+    sound: Sound,
+    volume: f32,
+    muted: bool,
+}
@@ -0,0 +1,176 @@
+// Running a whole directory of ROMs.
+// `chip8 batch roms/ --cycles N` runs every ROM in a directory headless for up to N
+// instructions, as many at once as there are cores, and reports how each one finished and
+// what was on its screen. Good for finding the ROMs in a big collection that crash, or
+// checking a change to the core didn't break anything across all of them (the screen hashes
+// from before and after should match). Every ROM runs hardened, so a broken one can't take
+// the rest down with it.
+//
+// The report is a line per ROM, tab separated, in file name order:
+//
+//   # rom	variant	outcome	instructions	screen	detail
+//   brix.ch8	chip8	running	2000000	5E0C3A0D8E6A1B27
+//   broken.ch8	chip8	unknown-opcode	1408	0000000000000000	3AC: unknown opcode F0FF
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::cpu::Cpu;
+use crate::error::CpuError;
+use crate::halt::{HaltReason, LoopDetection};
+use crate::rom_format::{self, RomFormat};
+use crate::variant::{platform_hints, Variant};
+
+/// How to run each ROM.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchSettings {
+    /// Instructions to run before giving up and taking it as still running
+    pub cycles: u64,
+    /// The machine to run them on, None for whatever each one's opcodes need
+    pub variant: Option<Variant>,
+    /// Stop a ROM with an error after this many instructions (Cpu::watchdog)
+    pub watchdog: Option<u64>,
+}
+
+/// How a ROM finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// Ran every cycle it had and was still going, which is what games do
+    Running,
+    /// Stopped itself (0000, or SUPER-CHIP's 00FD)
+    Halted,
+    /// Stuck jumping to itself, how a lot of ROMs finish
+    InfiniteLoop,
+    /// Ran into an opcode the machine doesn't have
+    UnknownOpcode { pc: usize, opcode: u16 },
+    /// The watchdog stopped it
+    Watchdog,
+    /// Did something else broken: a bad memory access, a stack overflow
+    Crashed(Option<CpuError>),
+    /// Couldn't be loaded
+    LoadFailed(String),
+}
+
+impl BatchOutcome {
+    /// The outcome column of the report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchOutcome::Running => "running",
+            BatchOutcome::Halted => "halted",
+            BatchOutcome::InfiniteLoop => "infinite-loop",
+            BatchOutcome::UnknownOpcode { .. } => "unknown-opcode",
+            BatchOutcome::Watchdog => "watchdog",
+            BatchOutcome::Crashed(_) => "crashed",
+            BatchOutcome::LoadFailed(_) => "load-failed",
+        }
+    }
+
+    /// Broken rather than just finished.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, BatchOutcome::UnknownOpcode { .. } | BatchOutcome::Watchdog | BatchOutcome::Crashed(_) | BatchOutcome::LoadFailed(_))
+    }
+}
+
+/// One ROM's line in the report.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub rom: PathBuf,
+    pub variant: Variant,
+    pub outcome: BatchOutcome,
+    pub instructions: u64,
+    /// Display::hash() of the screen it finished on
+    pub screen_hash: u64,
+}
+
+impl fmt::Display for BatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.rom.file_name().unwrap_or_default().to_string_lossy();
+        write!(f, "{}\t{}\t{}\t{}\t{:016X}", name, self.variant.name(), self.outcome.name(), self.instructions, self.screen_hash)?;
+        match &self.outcome {
+            BatchOutcome::UnknownOpcode { pc, opcode } => write!(f, "\t{}", CpuError::UnknownOpcode { pc: *pc, opcode: *opcode }),
+            BatchOutcome::Crashed(Some(error)) => write!(f, "\t{}", error),
+            BatchOutcome::LoadFailed(reason) => write!(f, "\t{}", reason),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The report's first line, naming the columns.
+pub const REPORT_HEADER: &str = "# rom\tvariant\toutcome\tinstructions\tscreen\tdetail";
+
+/// Every ROM file in `dir` (by extension, see rom_format.rs), in name order.
+pub fn find_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let format = path.extension().and_then(|extension| extension.to_str()).and_then(RomFormat::from_extension);
+        // a .txt in with the ROMs is more likely a readme than a hex dump
+        if path.is_file() && format.is_some_and(|format| format != RomFormat::HexText) {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+/// Run one ROM.
+pub fn run_rom(path: &Path, settings: &BatchSettings) -> BatchResult {
+    let failed = |variant, reason: String| BatchResult {
+        rom: path.to_path_buf(),
+        variant,
+        outcome: BatchOutcome::LoadFailed(reason),
+        instructions: 0,
+        screen_hash: 0,
+    };
+    let rom = match rom_format::read(path) {
+        Ok(rom) => rom,
+        Err(e) => return failed(settings.variant.unwrap_or(Variant::Chip8), e.to_string()),
+    };
+    let variant = settings.variant.unwrap_or_else(|| platform_hints(&rom).variant());
+    let mut cpu = Cpu::with_variant(variant);
+    if let Err(e) = cpu.load_binary(&rom) {
+        return failed(variant, e.to_string());
+    }
+    cpu.hardened = true;
+    cpu.watchdog = settings.watchdog;
+    cpu.loop_detection = LoopDetection { jump_to_self: true, idle_window: None };
+    cpu.run_until_halt(settings.cycles);
+
+    let outcome = match (cpu.halt_reason(), cpu.fault()) {
+        (None, _) => BatchOutcome::Running,
+        (Some(HaltReason::Exit), _) => BatchOutcome::Halted,
+        (Some(HaltReason::InfiniteLoop), _) => BatchOutcome::InfiniteLoop,
+        (Some(HaltReason::Fault), Some(&CpuError::UnknownOpcode { pc, opcode })) => BatchOutcome::UnknownOpcode { pc, opcode },
+        (Some(HaltReason::Fault), Some(CpuError::Watchdog { .. })) => BatchOutcome::Watchdog,
+        (Some(HaltReason::Fault), error) => BatchOutcome::Crashed(error.cloned()),
+    };
+    BatchResult {
+        rom: path.to_path_buf(),
+        variant,
+        outcome,
+        instructions: cpu.instructions_retired(),
+        screen_hash: cpu.display.hash(),
+    }
+}
+
+/// Run every ROM in `roms`, `jobs` at a time. The results are in the same order as `roms`.
+pub fn run_all(roms: &[PathBuf], settings: &BatchSettings, jobs: usize) -> Vec<BatchResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; roms.len()]);
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, roms.len().max(1)) {
+            scope.spawn(|| loop {
+                let n = next.fetch_add(1, Ordering::Relaxed);
+                let Some(rom) = roms.get(n) else { break };
+                let result = run_rom(rom, settings);
+                results.lock().unwrap()[n] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|result| result.expect("every ROM gets run")).collect()
+}
@@ -0,0 +1,164 @@
+// Benchmarking the emulator.
+// `chip8 bench [rom]` runs a ROM flat out, frame after frame with no waiting between them, for
+// a set number of seconds and reports how many instructions and frames it got through. The
+// criterion benches in benches/ are better for comparing two builds of the core, this is for
+// seeing how fast the emulator is on your machine, with your settings and your ROM.
+//
+// Frames are emulated 60Hz frames, each one its share of the clock speed and a timer tick, so
+// the frame rate over 60 is how many times faster than real time it could run. At the usual
+// 700 IPS a frame is only a dozen instructions, so run with a big --ips to see what the
+// interpreter itself can do. A ROM that halts starts again, so it keeps going for the whole
+// run; one waiting for a key just spins through frames without running anything.
+//
+// Without a ROM it runs WORKLOAD, a loop of what games spend their time on: mostly arithmetic,
+// with a draw every few hundred instructions. CHIP-8 waits for the display after a draw, so
+// at a big --ips the draws set the pace unless the quirk's turned off (--variant schip):
+//
+//   200: LD I, 0x232      212: LD V6, 0x40      222: ADD V1, 0x01
+//   202: LD V0, 0x00      214: LD V2, V0        224: LD I, 0x238
+//   204: LD V1, 0x00      216: ADD V2, V1       226: LD B, V2
+//   206: DRW V0, V1, 5    218: XOR V3, V2       228: LD [I], V4
+//   208: SE VF, 0x00      21A: SHR V4, V3       22A: LD DT, V3
+//   20A: CLS              21C: ADD V6, 0xFF     22C: LD V5, DT
+//   20C: ADD V0, 0x05     21E: SE V6, 0x00      22E: LD I, 0x232
+//   20E: CALL 0x212       220: JP 0x214         230: RET
+//   210: JP 0x206
+//
+// with a sprite (a 0) at 232 and five bytes of scratch at 238.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::cpu::{BlockRunner, Cpu};
+use crate::error::CpuError;
+
+/// The ROM to run when there isn't one, see the top of this file.
+pub const WORKLOAD: &[u8] = &[
+    0xA2, 0x32, 0x60, 0x00, 0x61, 0x00, // set up
+    0xD0, 0x15, 0x3F, 0x00, 0x00, 0xE0, 0x70, 0x05, 0x22, 0x12, 0x12, 0x06, // draw and call
+    0x66, 0x40, 0x82, 0x00, 0x82, 0x14, 0x83, 0x23, 0x84, 0x36, 0x76, 0xFF, 0x36, 0x00, 0x12, 0x14, // arithmetic
+    0x71, 0x01, 0xA2, 0x38, 0xF2, 0x33, 0xF4, 0x55, 0xF3, 0x15, 0xF5, 0x07, 0xA2, 0x32, 0x00, 0xEE, // memory and timers
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x00, // the sprite
+    0x00, 0x00, 0x00, 0x00, 0x00, // scratch
+];
+
+// Looking at the clock costs about as much as a few instructions, so it's only done this often
+const FRAMES_PER_CHECK: u64 = 64;
+
+/// How a benchmark run went.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchResult {
+    /// Instructions run, across every restart
+    pub instructions: u64,
+    /// 60Hz frames emulated
+    pub frames: u64,
+    /// How long it really ran for
+    pub elapsed: Duration,
+    /// How many times the ROM halted and was started again
+    pub restarts: u64,
+}
+
+impl BenchResult {
+    /// Instructions per second.
+    pub fn ips(&self) -> f64 {
+        self.instructions as f64 / self.seconds()
+    }
+
+    /// Frames emulated per second.
+    pub fn fps(&self) -> f64 {
+        self.frames as f64 / self.seconds()
+    }
+
+    /// How many times faster than a real machine's 60 frames a second.
+    pub fn speedup(&self) -> f64 {
+        self.fps() / 60.0
+    }
+
+    fn seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} instructions and {} frames in {:.2}s", self.instructions, self.frames, self.elapsed.as_secs_f64())?;
+        writeln!(f, "{:.0} IPS", self.ips())?;
+        write!(f, "{:.0} frames/s, {:.1}x real time", self.fps(), self.speedup())?;
+        if self.restarts > 0 {
+            write!(f, "\nthe ROM halted and was restarted {} times", self.restarts)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `cpu` a frame at a time with no waiting for `duration`, handing blocks to `runner` if
+/// there is one (the JIT). Whenever it halts `restart` gets it going again, normally with
+/// Cpu::restart_with_rom(); an error from that ends the run.
+pub fn run(
+    cpu: &mut Cpu,
+    duration: Duration,
+    mut runner: Option<&mut dyn BlockRunner>,
+    mut restart: impl FnMut(&mut Cpu) -> Result<(), CpuError>,
+) -> Result<BenchResult, CpuError> {
+    // restarting starts the CPU's count again, so what it had got to is kept here
+    let mut instructions = 0;
+    let mut frames = 0;
+    let mut restarts = 0;
+    let started = Instant::now();
+    loop {
+        match runner.as_deref_mut() {
+            Some(runner) => cpu.run_frame_with(runner),
+            None => cpu.run_frame(),
+        }
+        frames += 1;
+        if cpu.is_halted() {
+            instructions += cpu.instructions_retired();
+            restart(cpu)?;
+            restarts += 1;
+        }
+        if frames % FRAMES_PER_CHECK == 0 && started.elapsed() >= duration {
+            break;
+        }
+    }
+    Ok(BenchResult {
+        instructions: instructions + cpu.instructions_retired(),
+        frames,
+        elapsed: started.elapsed(),
+        restarts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    #[test]
+    fn runs_the_workload() {
+        let mut cpu = Cpu::with_variant(Variant::SuperChip);
+        cpu.clock_speed = 60_000;
+        cpu.load_rom(WORKLOAD).unwrap();
+        let result = run(&mut cpu, Duration::from_millis(50), None, |cpu| cpu.restart_with_rom(WORKLOAD)).unwrap();
+        assert!(result.frames >= FRAMES_PER_CHECK);
+        // a thousand instructions a frame, it never halts or waits
+        assert_eq!(result.instructions, result.frames * 1000);
+        assert_eq!(result.restarts, 0);
+    }
+
+    #[test]
+    fn restarts_a_rom_that_halts() {
+        // 00FD, SUPER-CHIP's exit
+        let rom = [0x00, 0xFD];
+        let mut cpu = Cpu::with_variant(Variant::SuperChip);
+        cpu.load_rom(&rom).unwrap();
+        let result = run(&mut cpu, Duration::from_millis(10), None, |cpu| cpu.restart_with_rom(&rom)).unwrap();
+        assert_eq!(result.restarts, result.frames);
+        assert_eq!(result.instructions, result.frames);
+    }
+
+    #[test]
+    fn rates() {
+        let result = BenchResult { instructions: 7000, frames: 600, elapsed: Duration::from_secs(2), restarts: 0 };
+        assert_eq!((result.ips(), result.fps(), result.speedup()), (3500.0, 300.0, 5.0));
+    }
+}
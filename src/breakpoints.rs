@@ -0,0 +1,387 @@
+// Breakpoints.
+// Shared by everything that lets you stop a running program (the GDB stub, the debuggers).
+// Running with breakpoints means going one instruction at a time and checking PC in between,
+// which is slower than run_frame() but only matters while a debugger is attached.
+//
+// Besides addresses it can stop whenever a sprite draw collides (DXYN setting VF), since that's
+// where the game logic that's gone wrong usually is, and before any instruction matching an
+// opcode pattern like FX0A (any key wait) or DXX5 (any 5 row sprite), for when you know what
+// the ROM's going to do but not where.
+//
+// Step over and step out (a debugger's `next` and `finish`) are a one-off stop that goes away
+// once it's reached: over puts it at the return address of the CALL at PC, out stops at the
+// RET that leaves the current subroutine. Both go by stack depth too, so a recursive CALL
+// coming back through the same return address doesn't count.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::clock::TIMER_HZ;
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+/// Why running with breakpoints stopped early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// PC reached a breakpoint, the instruction there hasn't run yet
+    Breakpoint(usize),
+    /// The program halted
+    Halted,
+    /// The draw at this address set VF, with break on collision on. PC has moved past it.
+    Collision(usize),
+    /// PC reached an instruction matching an opcode pattern, (address, opcode). It hasn't run yet.
+    Opcode(usize, u16),
+    /// A step over or step out got where it was going, PC is here.
+    Returned(usize),
+}
+
+/// An opcode with wildcards, four characters: hex digits have to match and anything else
+/// (X, Y, N, K, ?) matches any nibble. FX0A is every key wait, DXY5 or Dxx5 every 5 row sprite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    // which bits have to match, and what they have to be
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    /// Read a pattern, None unless it's four characters.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.chars().count() != 4 {
+            return None;
+        }
+        let (mut mask, mut value) = (0, 0);
+        for c in text.chars() {
+            mask <<= 4;
+            value <<= 4;
+            if let Some(nibble) = c.to_digit(16) {
+                mask |= 0xF;
+                value |= nibble as u16;
+            }
+        }
+        Some(OpcodePattern { mask, value })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+// Wildcards come out as X
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            match (self.mask >> shift) & 0xF {
+                0 => write!(f, "X")?,
+                _ => write!(f, "{:X}", (self.value >> shift) & 0xF)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Where a step over or step out stops: once the stack's back down to `depth`, and at `addr`
+// if there is one
+#[derive(Debug, Clone, Copy)]
+struct Return {
+    addr: Option<usize>,
+    depth: usize,
+}
+
+/// A set of addresses to stop at.
+#[derive(Default)]
+pub struct Breakpoints {
+    addrs: BTreeSet<usize>,
+    patterns: Vec<OpcodePattern>,
+    on_collision: bool,
+    returning: Option<Return>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints::default()
+    }
+
+    /// Add a breakpoint, false if there already was one.
+    pub fn insert(&mut self, addr: usize) -> bool {
+        self.addrs.insert(addr)
+    }
+
+    /// Remove a breakpoint, false if there wasn't one.
+    pub fn remove(&mut self, addr: usize) -> bool {
+        self.addrs.remove(&addr)
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    /// Remove every address and pattern.
+    pub fn clear(&mut self) {
+        self.addrs.clear();
+        self.patterns.clear();
+    }
+
+    /// Stop before any instruction matching `pattern`, false if it was already there.
+    pub fn insert_pattern(&mut self, pattern: OpcodePattern) -> bool {
+        if self.patterns.contains(&pattern) {
+            return false;
+        }
+        self.patterns.push(pattern);
+        true
+    }
+
+    /// Stop stopping on `pattern`, false if it wasn't there.
+    pub fn remove_pattern(&mut self, pattern: OpcodePattern) -> bool {
+        let before = self.patterns.len();
+        self.patterns.retain(|&p| p != pattern);
+        self.patterns.len() != before
+    }
+
+    /// Opcode patterns, in the order they were added.
+    pub fn patterns(&self) -> impl Iterator<Item = OpcodePattern> + '_ {
+        self.patterns.iter().copied()
+    }
+
+    /// Stop after any draw that collides as well as at the addresses.
+    pub fn set_break_on_collision(&mut self, on: bool) {
+        self.on_collision = on;
+    }
+
+    pub fn break_on_collision(&self) -> bool {
+        self.on_collision
+    }
+
+    /// Set up a step over: if PC is at a CALL, the next run stops once it's returned, with
+    /// Stop::Returned at the instruction after it. False if it isn't a CALL, so just step.
+    pub fn step_over(&mut self, cpu: &Cpu) -> bool {
+        let pc = cpu.position_in_memory;
+        if !matches!(Instruction::decode_at(&cpu.memory, pc, cpu.variant), Instruction::Call(_)) {
+            return false;
+        }
+        self.returning = Some(Return { addr: Some(pc + 2), depth: cpu.stack_pointer });
+        true
+    }
+
+    /// Set up a step out: the next run stops once the subroutine PC is in returns, with
+    /// Stop::Returned wherever it went back to. False if it's not in one.
+    pub fn step_out(&mut self, cpu: &Cpu) -> bool {
+        let Some(depth) = cpu.stack_pointer.checked_sub(1) else { return false };
+        self.returning = Some(Return { addr: None, depth });
+        true
+    }
+
+    /// Forget a step over or out that hasn't got there yet. Any other stop forgets it anyway.
+    pub fn cancel_step(&mut self) {
+        self.returning = None;
+    }
+
+    /// Addresses in order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.addrs.iter().copied()
+    }
+
+    /// Run one frame's worth of instructions (timers tick as usual), stopping before any
+    /// instruction with a breakpoint on it.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        let budget = (cpu.clock_speed / TIMER_HZ).max(1);
+        for _ in 0..budget {
+            if cpu.is_halted() {
+                return Some(Stop::Halted);
+            }
+            if let Some(stop) = self.step(cpu) {
+                return Some(stop);
+            }
+        }
+        cpu.is_halted().then_some(Stop::Halted)
+    }
+
+    /// Get going again after stopping: the first instruction always runs, otherwise resuming
+    /// from a breakpoint would stop straight away on that same breakpoint.
+    pub fn resume(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        let stop = self.step(cpu);
+        if cpu.is_halted() { Some(Stop::Halted) } else { stop }
+    }
+
+    // Run one instruction and see if it should stop there
+    fn step(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        let (pc, ran) = (cpu.position_in_memory, cpu.instructions_retired());
+        cpu.run_for(1);
+        let drew = cpu.instructions_retired() != ran && matches!(Instruction::decode_at(&cpu.memory, pc, cpu.variant), Instruction::Draw(..));
+        let stop = if self.on_collision && drew && cpu.registers[0xF] != 0 {
+            Some(Stop::Collision(pc))
+        } else if self.returned(cpu) {
+            Some(Stop::Returned(cpu.position_in_memory))
+        } else if self.contains(cpu.position_in_memory) {
+            Some(Stop::Breakpoint(cpu.position_in_memory))
+        } else {
+            self.matching_opcode(cpu)
+        };
+        // whatever it stopped for, a step over or out is done with
+        if stop.is_some() || cpu.is_halted() {
+            self.returning = None;
+        }
+        stop
+    }
+
+    // Has a step over or out got there
+    fn returned(&self, cpu: &Cpu) -> bool {
+        self.returning.is_some_and(|target| {
+            cpu.stack_pointer <= target.depth && target.addr.is_none_or(|addr| addr == cpu.position_in_memory)
+        })
+    }
+
+    // The instruction at PC as Stop::Opcode, if it matches a pattern
+    fn matching_opcode(&self, cpu: &Cpu) -> Option<Stop> {
+        if self.patterns.is_empty() {
+            return None;
+        }
+        let pc = cpu.position_in_memory;
+        let byte = |addr: usize| cpu.memory.get(addr).copied().unwrap_or(0) as u16;
+        let opcode = byte(pc) << 8 | byte(pc + 1);
+        self.patterns.iter().any(|pattern| pattern.matches(opcode)).then_some(Stop::Opcode(pc, opcode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use crate::variant::Variant;
+
+    // Draws the same 0 twice, the second time colliding with the first, then loops
+    const DRAW_TWICE: [u8; 17] = [
+        0x60, 0x05, // 200 LD V0, 5
+        0x61, 0x03, // 202 LD V1, 3
+        0xA2, 0x0C, // 204 LD I, 0x20C
+        0xD0, 0x15, // 206 DRW V0, V1, 5
+        0xD0, 0x15, // 208 DRW V0, V1, 5
+        0x12, 0x0A, // 20A JP 0x20A
+        0xF0, 0x90, 0x90, 0x90, 0xF0,
+    ];
+
+    fn machine(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(program).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn patterns() {
+        let key_wait = OpcodePattern::parse("FX0A").unwrap();
+        assert!(key_wait.matches(0xF30A) && !key_wait.matches(0xF30B));
+        let five_rows = OpcodePattern::parse(" dxy5 ").unwrap();
+        assert!(five_rows.matches(0xD125) && !five_rows.matches(0xD126));
+        assert_eq!(five_rows.to_string(), "DXX5");
+        assert_eq!(OpcodePattern::parse("D??5"), Some(five_rows));
+        assert!(OpcodePattern::parse("XXXX").unwrap().matches(0x1234));
+    }
+
+    #[test]
+    fn patterns_have_to_be_four_characters() {
+        assert_eq!(OpcodePattern::parse("DXY"), None);
+        assert_eq!(OpcodePattern::parse("DXY55"), None);
+        assert_eq!(OpcodePattern::parse(""), None);
+    }
+
+    #[test]
+    fn stops_before_matching_opcodes() {
+        let mut cpu = machine(&DRAW_TWICE);
+        let mut breakpoints = Breakpoints::new();
+        assert!(breakpoints.insert_pattern(OpcodePattern::parse("DXY5").unwrap()));
+        assert!(!breakpoints.insert_pattern(OpcodePattern::parse("dxx5").unwrap()));
+
+        assert_eq!(breakpoints.run_frame(&mut cpu), Some(Stop::Opcode(0x206, 0xD015)));
+        assert_eq!(cpu.position_in_memory, 0x206);
+        assert_eq!(breakpoints.resume(&mut cpu), Some(Stop::Opcode(0x208, 0xD015)));
+
+        assert!(breakpoints.remove_pattern(OpcodePattern::parse("DXY5").unwrap()));
+        assert_eq!(breakpoints.patterns().count(), 0);
+        assert_eq!(breakpoints.resume(&mut cpu), None);
+    }
+
+    #[test]
+    fn addresses_and_collisions() {
+        let mut cpu = machine(&DRAW_TWICE);
+        let mut breakpoints = Breakpoints::new();
+        assert!(breakpoints.insert(0x204));
+        assert!(!breakpoints.insert(0x204));
+        breakpoints.set_break_on_collision(true);
+
+        assert_eq!(breakpoints.run_frame(&mut cpu), Some(Stop::Breakpoint(0x204)));
+        // the first draw doesn't collide with anything
+        assert_eq!(breakpoints.run_frame(&mut cpu), Some(Stop::Collision(0x208)));
+        assert_eq!(cpu.position_in_memory, 0x20A);
+        assert!(breakpoints.remove(0x204) && !breakpoints.contains(0x204));
+    }
+
+    #[test]
+    fn halting_stops_too() {
+        let mut cpu = machine(&[0x60, 0x01, 0x00, 0x00]);
+        assert_eq!(Breakpoints::new().run_frame(&mut cpu), Some(Stop::Halted));
+    }
+
+    // Calls itself until V0 is 3, then returns all the way back out
+    const RECURSIVE: [u8; 12] = [
+        0x22, 0x04, // 200 CALL 0x204
+        0x00, 0x00, // 202 HALT
+        0x70, 0x01, // 204 ADD V0, 1
+        0x30, 0x03, // 206 SE V0, 3
+        0x22, 0x04, // 208 CALL 0x204
+        0x00, 0xEE, // 20A RET
+    ];
+
+    // Frames until something stops it, a frame's only 11 instructions
+    fn run(breakpoints: &mut Breakpoints, cpu: &mut Cpu) -> Option<Stop> {
+        (0..10).find_map(|_| breakpoints.run_frame(cpu))
+    }
+
+    #[test]
+    fn step_over_a_call() {
+        let mut cpu = machine(&RECURSIVE);
+        let mut breakpoints = Breakpoints::new();
+        assert!(breakpoints.step_over(&cpu));
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Returned(0x202)));
+        assert_eq!((cpu.registers[0], cpu.stack_pointer), (3, 0));
+        // not a CALL, there's nothing to step over
+        assert!(!breakpoints.step_over(&cpu));
+    }
+
+    #[test]
+    fn recursion_back_through_the_same_address_doesnt_count() {
+        let mut cpu = machine(&RECURSIVE);
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(0x208);
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Breakpoint(0x208)));
+        breakpoints.remove(0x208);
+
+        // the innermost call returns to 20A too, but a level deeper
+        assert!(breakpoints.step_over(&cpu));
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Returned(0x20A)));
+        assert_eq!((cpu.registers[0], cpu.stack_pointer), (3, 1));
+    }
+
+    #[test]
+    fn step_out_of_a_subroutine() {
+        let mut cpu = machine(&RECURSIVE);
+        let mut breakpoints = Breakpoints::new();
+        assert!(!breakpoints.step_out(&cpu));
+        cpu.run_for(1);
+        assert!(breakpoints.step_out(&cpu));
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Returned(0x202)));
+    }
+
+    #[test]
+    fn other_stops_cancel_a_step() {
+        let mut cpu = machine(&RECURSIVE);
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.insert(0x206);
+        assert!(breakpoints.step_over(&cpu));
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Breakpoint(0x206)));
+        breakpoints.clear();
+        assert_eq!(run(&mut breakpoints, &mut cpu), Some(Stop::Halted));
+    }
+}
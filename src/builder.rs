@@ -0,0 +1,281 @@
+// Building a CPU.
+// Cpu::new() and Cpu::with_variant() give you a stock machine, and everything else gets set
+// afterwards, in an order that matters (set_variant() undoes set_memory_size(), load_binary()
+// moves PC). CpuBuilder takes all of it up front and puts it together in the right order,
+// checking the settings make sense together before there's a Cpu to get wrong:
+//
+//   let cpu = CpuBuilder::new(Variant::SuperChip)
+//       .clock_speed(1000)
+//       .seed(42)
+//       .rom(&rom)
+//       .build()?;
+//
+// Anything not set is whatever Cpu::with_variant() would have given you.
+
+use alloc::vec::Vec;
+
+use crate::cpu::{Cpu, MAX_MEMORY_SIZE, MAX_STACK_DEPTH, MIN_MEMORY_SIZE};
+use crate::error::CpuError;
+use crate::font::{BIG_FONT, BIG_FONT_ADDR, SMALL_FONT, SMALL_FONT_ADDR};
+use crate::quirks::Quirks;
+use crate::variant::Variant;
+use crate::vip_timing::Timing;
+
+/// Settings for a new Cpu, see the top of this file.
+#[derive(Debug, Clone)]
+pub struct CpuBuilder {
+    variant: Variant,
+    quirks: Option<Quirks>,
+    clock_speed: Option<u32>,
+    timing: Option<Timing>,
+    seed: Option<u64>,
+    memory_size: Option<usize>,
+    stack_depth: Option<usize>,
+    lores_height: Option<usize>,
+    small_font: Option<[u8; SMALL_FONT.len()]>,
+    big_font: Option<[u8; BIG_FONT.len()]>,
+    rom: Option<Vec<u8>>,
+    initial_pc: Option<usize>,
+}
+
+impl CpuBuilder {
+    /// Start from the stock `variant`, with the quirks its ROMs usually expect.
+    pub fn new(variant: Variant) -> Self {
+        CpuBuilder {
+            variant,
+            quirks: None,
+            clock_speed: None,
+            timing: None,
+            seed: None,
+            memory_size: None,
+            stack_depth: None,
+            lores_height: None,
+            small_font: None,
+            big_font: None,
+            rom: None,
+            initial_pc: None,
+        }
+    }
+
+    /// Follow these quirks instead of the variant's.
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Instructions per second.
+    pub fn clock_speed(mut self, clock_speed: u32) -> Self {
+        self.clock_speed = Some(clock_speed);
+        self
+    }
+
+    /// What instructions cost, see vip_timing.rs.
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = Some(timing);
+        self
+    }
+
+    /// Seed the random number generator, see Cpu::seed_rng().
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Bytes of memory instead of the variant's own amount, see Cpu::set_memory_size().
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = Some(size);
+        self
+    }
+
+    /// How deep CALLs can nest, see Cpu::set_stack_depth().
+    pub fn stack_depth(mut self, depth: usize) -> Self {
+        self.stack_depth = Some(depth);
+        self
+    }
+
+    /// Rows on the ETI-660's lo-res screen, 48 (its own) or 64, see Display::set_lores_height().
+    pub fn lores_height(mut self, rows: usize) -> Self {
+        self.lores_height = Some(rows);
+        self
+    }
+
+    /// Glyphs for FX29 instead of the built in ones, 5 bytes for each of 0-F.
+    pub fn small_font(mut self, font: [u8; SMALL_FONT.len()]) -> Self {
+        self.small_font = Some(font);
+        self
+    }
+
+    /// Glyphs for SUPER-CHIP's FX30 instead of the built in ones, 10 bytes for each of 0-F.
+    pub fn big_font(mut self, font: [u8; BIG_FONT.len()]) -> Self {
+        self.big_font = Some(font);
+        self
+    }
+
+    /// Load a ROM (already a binary, not hex) at 0x200.
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.rom = Some(rom.to_vec());
+        self
+    }
+
+    /// Start running at `pc` instead of 0x200.
+    pub fn initial_pc(mut self, pc: usize) -> Self {
+        self.initial_pc = Some(pc);
+        self
+    }
+
+    /// Check the settings go together and make the Cpu.
+    pub fn build(self) -> Result<Cpu, CpuError> {
+        let memory_size = self.memory_size.unwrap_or(self.variant.memory_size());
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&memory_size) {
+            return Err(CpuError::InvalidMemorySize { size: memory_size });
+        }
+        if let Some(depth) = self.stack_depth {
+            if !(1..=MAX_STACK_DEPTH).contains(&depth) {
+                return Err(CpuError::InvalidStackDepth { depth });
+            }
+        }
+        if self.clock_speed == Some(0) {
+            return Err(CpuError::InvalidConfig { reason: "the clock speed has to be at least 1 instruction a second" });
+        }
+        if self.initial_pc.is_some_and(|pc| pc + 1 >= memory_size) {
+            return Err(CpuError::InvalidConfig { reason: "the initial PC is past the end of memory" });
+        }
+        match self.lores_height {
+            Some(_) if self.variant != Variant::Eti660 => {
+                return Err(CpuError::InvalidConfig { reason: "only the ETI-660's screen comes in different heights" });
+            }
+            Some(rows) if rows != 48 && rows != 64 => {
+                return Err(CpuError::InvalidConfig { reason: "the ETI-660's screen is 48 or 64 rows" });
+            }
+            _ => {}
+        }
+        if self.big_font.is_some() && self.variant == Variant::Chip8 {
+            return Err(CpuError::InvalidConfig { reason: "plain CHIP-8 has no big font, FX30 is a SUPER-CHIP instruction" });
+        }
+
+        let mut cpu = Cpu::with_variant(self.variant);
+        cpu.set_memory_size(memory_size)?;
+        if let Some(depth) = self.stack_depth {
+            cpu.set_stack_depth(depth)?;
+        }
+        if let Some(quirks) = self.quirks {
+            cpu.set_quirks(quirks);
+        }
+        if let Some(rows) = self.lores_height {
+            cpu.display.set_lores_height(rows);
+        }
+        if let Some(clock_speed) = self.clock_speed {
+            cpu.clock_speed = clock_speed;
+        }
+        if let Some(timing) = self.timing {
+            cpu.timing = timing;
+        }
+        if let Some(seed) = self.seed {
+            cpu.seed_rng(seed);
+        }
+        if let Some(font) = self.small_font {
+            cpu.memory[SMALL_FONT_ADDR..SMALL_FONT_ADDR + font.len()].copy_from_slice(&font);
+        }
+        if let Some(font) = self.big_font {
+            cpu.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + font.len()].copy_from_slice(&font);
+        }
+        if let Some(rom) = &self.rom {
+            cpu.load_binary(rom)?;
+        }
+        if let Some(pc) = self.initial_pc {
+            cpu.position_in_memory = pc;
+        }
+        Ok(cpu)
+    }
+}
+
+impl Cpu {
+    /// A CpuBuilder for `variant`.
+    pub fn builder(variant: Variant) -> CpuBuilder {
+        CpuBuilder::new(variant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARIANTS: [Variant; 7] =
+        [Variant::Chip8, Variant::SuperChip, Variant::XoChip, Variant::Chip8X, Variant::MegaChip, Variant::Eti660, Variant::Chip8Hires];
+
+    #[test]
+    fn nothing_set_is_with_variant() {
+        for variant in VARIANTS {
+            let built = CpuBuilder::new(variant).build().unwrap();
+            let stock = Cpu::with_variant(variant);
+            assert_eq!(built.variant, variant);
+            assert_eq!(built.quirks(), stock.quirks(), "{:?}", variant);
+            assert_eq!((built.clock_speed, built.timing), (stock.clock_speed, stock.timing), "{:?}", variant);
+            assert_eq!(built.stack_depth(), stock.stack_depth(), "{:?}", variant);
+            assert_eq!(built.position_in_memory, stock.position_in_memory, "{:?}", variant);
+            assert_eq!(built.display.height(), stock.display.height(), "{:?}", variant);
+            assert!(built.memory == stock.memory, "{:?}", variant);
+            assert_eq!(built.state_digest(), stock.state_digest(), "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn settings() {
+        let quirks = Quirks { wrap_sprites: true, ..Quirks::superchip() };
+        let cpu = CpuBuilder::new(Variant::SuperChip)
+            .quirks(quirks)
+            .clock_speed(1000)
+            .timing(Timing::CosmacVip)
+            .memory_size(0x2000)
+            .stack_depth(32)
+            .rom(&[0x12, 0x00])
+            .initial_pc(0x300)
+            .build()
+            .unwrap();
+        // the variant doesn't put its own quirks or memory size back
+        assert_eq!(cpu.quirks(), quirks);
+        assert_eq!(cpu.memory.len(), 0x2000);
+        assert_eq!((cpu.clock_speed, cpu.timing, cpu.stack_depth()), (1000, Timing::CosmacVip, 32));
+        // loading the ROM doesn't move PC back
+        assert_eq!(&cpu.memory[0x200..0x202], &[0x12, 0x00]);
+        assert_eq!(cpu.position_in_memory, 0x300);
+
+        let cpu = CpuBuilder::new(Variant::Eti660).lores_height(64).build().unwrap();
+        assert_eq!(cpu.display.height(), 64);
+
+        let mut font = [0; SMALL_FONT.len()];
+        font[0] = 0xAA;
+        let cpu = CpuBuilder::new(Variant::Chip8).small_font(font).build().unwrap();
+        assert_eq!(&cpu.memory[SMALL_FONT_ADDR..SMALL_FONT_ADDR + 2], &[0xAA, 0]);
+    }
+
+    #[test]
+    fn seeds_repeat() {
+        // RND V0, 0xFF over and over
+        let random = |seed| {
+            let mut cpu = CpuBuilder::new(Variant::Chip8).seed(seed).rom(&[0xC0, 0xFF, 0x12, 0x00]).build().unwrap();
+            (0..8)
+                .map(|_| {
+                    cpu.run_for(2);
+                    cpu.registers[0]
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(random(42), random(42));
+        assert_ne!(random(42), random(43));
+    }
+
+    #[test]
+    fn settings_that_dont_go_together() {
+        let fails = |builder: CpuBuilder| builder.build().err().unwrap();
+        assert_eq!(fails(CpuBuilder::new(Variant::Chip8).memory_size(0x10)), CpuError::InvalidMemorySize { size: 0x10 });
+        assert_eq!(fails(CpuBuilder::new(Variant::Chip8).stack_depth(0)), CpuError::InvalidStackDepth { depth: 0 });
+        assert!(matches!(fails(CpuBuilder::new(Variant::Chip8).clock_speed(0)), CpuError::InvalidConfig { .. }));
+        assert!(matches!(fails(CpuBuilder::new(Variant::Chip8).initial_pc(0xFFF)), CpuError::InvalidConfig { .. }));
+        assert!(matches!(fails(CpuBuilder::new(Variant::Chip8).lores_height(64)), CpuError::InvalidConfig { .. }));
+        assert!(matches!(fails(CpuBuilder::new(Variant::Eti660).lores_height(32)), CpuError::InvalidConfig { .. }));
+        assert!(matches!(fails(CpuBuilder::new(Variant::Chip8).big_font([0; BIG_FONT.len()])), CpuError::InvalidConfig { .. }));
+        // the bigger memory makes room for it
+        assert!(CpuBuilder::new(Variant::Chip8).memory_size(0x2000).initial_pc(0xFFF).build().is_ok());
+    }
+}
@@ -0,0 +1,49 @@
+// Built in ROMs.
+// A few small ROMs compiled into the binary, so there's something to run straight away
+// (`chip8 run --builtin ibm-logo`) and something to check a port or a new frontend with. They
+// were written for this emulator and are as free to use as the rest of it. The listings they
+// were assembled from are next to them in roms/, in the same mnemonics the disassembler uses.
+
+use crate::variant::Variant;
+
+/// A ROM that comes with the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinRom {
+    /// What to pass to --builtin
+    pub name: &'static str,
+    pub title: &'static str,
+    /// One line on what it does
+    pub description: &'static str,
+    pub variant: Variant,
+    pub bytes: &'static [u8],
+}
+
+/// Every built in ROM.
+pub static BUILTIN_ROMS: [BuiltinRom; 3] = [
+    BuiltinRom {
+        name: "ibm-logo",
+        title: "IBM Logo",
+        description: "draws the IBM logo, the first thing to get working in a new interpreter",
+        variant: Variant::Chip8,
+        bytes: include_bytes!("../roms/ibm-logo.ch8"),
+    },
+    BuiltinRom {
+        name: "opcode-test",
+        title: "Opcode Test",
+        description: "checks the basic instructions and shows OK, or E and the number of the test that failed",
+        variant: Variant::Chip8,
+        bytes: include_bytes!("../roms/opcode-test.ch8"),
+    },
+    BuiltinRom {
+        name: "catch",
+        title: "Catch",
+        description: "catch the falling balls with the paddle, 4 and 6 move it",
+        variant: Variant::Chip8,
+        bytes: include_bytes!("../roms/catch.ch8"),
+    },
+];
+
+/// The built in ROM called `name`.
+pub fn find(name: &str) -> Option<&'static BuiltinRom> {
+    BUILTIN_ROMS.iter().find(|rom| rom.name == name)
+}
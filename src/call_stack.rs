@@ -0,0 +1,23 @@
+// Call stack frames.
+// The CHIP-8 stack only holds return addresses, which is all RET needs. Debuggers want a bit
+// more: where each call came from and which function it went to, like a backtrace.
+
+use core::fmt;
+
+/// One CALL (2NNN) that hasn't returned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Address of the CALL instruction
+    pub call_site: usize,
+    /// The function that was called (NNN)
+    pub function: usize,
+    /// Where RET will carry on from, the instruction after the call
+    pub return_address: usize,
+}
+
+// How debuggers show a frame
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fn {:03X}  from {:03X}  ret {:03X}", self.function, self.call_site, self.return_address)
+    }
+}
@@ -0,0 +1,172 @@
+// The clock.
+// A real CHIP-8 ran a few hundred instructions a second, left to itself the run loop would
+// get through a whole game in microseconds. Everything is paced in 60Hz frames: each frame the
+// CPU runs its share of instructions (Cpu::run_frame), the timers tick once, and the frontend
+// presents the display. The clock's only job is to wait out the rest of each frame.
+// In turbo (fast-forward) mode frames come round faster, or as fast as the host can manage,
+// which speeds up everything including the timers, exactly like holding fast-forward on a VCR.
+
+// Keeping wall time needs std, the constants don't.
+
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Roughly what most ROMs are written for.
+pub const DEFAULT_CLOCK_SPEED: u32 = 700;
+
+/// About how fast CHIP-48 ran on an HP-48, 30 instructions a frame.
+pub const CHIP48_CLOCK_SPEED: u32 = 30 * TIMER_HZ;
+
+/// The delay and sound timers always count down at 60Hz, whatever the clock speed.
+pub const TIMER_HZ: u32 = 60;
+
+/// How long one frame lasts.
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64);
+
+// If the host stalls (debugger, suspended laptop) don't try to catch up on more than this
+#[cfg(feature = "std")]
+const MAX_BEHIND: Duration = Duration::from_millis(250);
+
+#[cfg(feature = "std")]
+pub struct Clock {
+    next_frame: Instant,
+    turbo: bool,
+    // how many times faster turbo runs, None means no cap at all
+    turbo_factor: Option<u32>,
+    // frames aren't all worth showing in turbo, this is when the next one should be
+    next_present: Instant,
+}
+
+#[cfg(feature = "std")]
+impl Clock {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Clock {
+            next_frame: now + FRAME,
+            turbo: false,
+            turbo_factor: None,
+            next_present: now,
+        }
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    pub fn toggle_turbo(&mut self) {
+        self.turbo = !self.turbo;
+    }
+
+    /// How much faster turbo runs than normal, None (the default) to run uncapped.
+    pub fn set_turbo_factor(&mut self, factor: Option<u32>) {
+        self.turbo_factor = factor.map(|f| f.max(1));
+    }
+
+    fn frame_length(&self) -> Duration {
+        match (self.turbo, self.turbo_factor) {
+            (false, _) => FRAME,
+            (true, Some(factor)) => FRAME / factor,
+            (true, None) => Duration::ZERO,
+        }
+    }
+
+    /// Whether this frame should be drawn. Always true at normal speed, in turbo it's at most
+    /// 60 times a (real) second so frontends don't spend all their time presenting.
+    pub fn should_present(&mut self) -> bool {
+        if !self.turbo {
+            return true;
+        }
+        let now = Instant::now();
+        if now < self.next_present {
+            return false;
+        }
+        self.next_present = now + FRAME;
+        true
+    }
+
+    /// Sleep until the next frame is due. Frames are scheduled from when the last one was due,
+    /// not from when we woke up, so small oversleeps don't make the whole thing drift slow.
+    pub fn wait_for_next_frame(&mut self) {
+        let now = Instant::now();
+        let frame = self.frame_length();
+        if frame.is_zero() {
+            self.next_frame = now;
+            return;
+        }
+
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > MAX_BEHIND {
+            // too far behind to catch up sensibly, start counting again from now
+            self.next_frame = now;
+        }
+        self.next_frame += frame;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_frame_length() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.frame_length(), FRAME);
+        // the factor only counts in turbo
+        clock.set_turbo_factor(Some(4));
+        assert_eq!(clock.frame_length(), FRAME);
+        clock.toggle_turbo();
+        assert_eq!(clock.frame_length(), FRAME / 4);
+        clock.set_turbo_factor(None);
+        assert_eq!(clock.frame_length(), Duration::ZERO);
+        // 0 would be a frame that never ends
+        clock.set_turbo_factor(Some(0));
+        assert_eq!(clock.frame_length(), FRAME);
+        clock.toggle_turbo();
+        assert!(!clock.is_turbo());
+    }
+
+    #[test]
+    fn turbo_presents_at_most_60_times_a_second() {
+        let mut clock = Clock::new();
+        assert!(clock.should_present() && clock.should_present());
+        clock.set_turbo(true);
+        assert!(clock.should_present());
+        assert!(!clock.should_present());
+    }
+
+    #[test]
+    fn uncapped_turbo_doesnt_wait() {
+        let mut clock = Clock::new();
+        clock.set_turbo(true);
+        let started = Instant::now();
+        for _ in 0..100 {
+            clock.wait_for_next_frame();
+        }
+        assert!(started.elapsed() < FRAME * 10);
+    }
+
+    #[test]
+    fn normal_speed_waits_out_the_frame() {
+        let mut clock = Clock::new();
+        let started = Instant::now();
+        for _ in 0..3 {
+            clock.wait_for_next_frame();
+        }
+        assert!(started.elapsed() >= FRAME * 3);
+    }
+}
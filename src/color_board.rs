@@ -0,0 +1,113 @@
+// The CHIP-8X colour board.
+// CHIP-8X was RCA's own extension of CHIP-8 for a VIP fitted with the VP-590 colour board. The
+// screen is still 64x32 and one bit per pixel, the colour comes from a separate map: the whole
+// background is one of four colours, and every lit pixel takes the foreground colour of the zone
+// it's in. Zones are 8 pixels wide, so there are 8 across, and 1 pixel high, though BXY0 colours
+// them in blocks of 4 rows (which was as fine as the board's own zones went).
+//
+//   02A0   next background colour: blue, black, green, red, back round to blue
+//   BXY0   colour a block of zones VY's colour: VX's low nibble is the first zone across and its
+//          high nibble how many more, V(X+1) the same for blocks of 4 rows down
+//   BXYN   colour an 8 pixel wide strip N rows high starting at pixel (VX, V(X+1)) VY's colour
+//
+// Foreground colours are 0 black, 1 red, 2 blue, 3 violet, 4 green, 5 yellow, 6 aqua, 7 white,
+// and everything starts red on blue. The map isn't part of the display's pixels (hash(),
+// to_text() and collisions don't see it), only of what rgb_pixels() turns them into.
+
+use crate::display::{HEIGHT, WIDTH};
+
+/// Pixels across one zone.
+pub const ZONE_WIDTH: usize = 8;
+/// Rows in the blocks BXY0 colours.
+pub const BLOCK_HEIGHT: usize = 4;
+const ZONES_ACROSS: usize = WIDTH / ZONE_WIDTH;
+
+/// RGB for the background colours, in the order 02A0 steps through them.
+pub const BACKGROUNDS: [[u8; 3]; 4] = [[0x00, 0x00, 0x80], [0x00, 0x00, 0x00], [0x00, 0x80, 0x00], [0x80, 0x00, 0x00]];
+/// RGB for the foreground colours, by number.
+pub const FOREGROUNDS: [[u8; 3]; 8] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0x00, 0x00],
+    [0x00, 0x00, 0xFF],
+    [0xFF, 0x00, 0xFF],
+    [0x00, 0xFF, 0x00],
+    [0xFF, 0xFF, 0x00],
+    [0x00, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xFF],
+];
+
+/// The colour map, see the top of this file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorBoard {
+    // index into BACKGROUNDS
+    background: u8,
+    // foreground colour of each zone, a row of them per pixel row
+    zones: [[u8; ZONES_ACROSS]; HEIGHT],
+}
+
+impl ColorBoard {
+    /// Red on blue, how the board comes up.
+    pub fn new() -> Self {
+        ColorBoard { background: 0, zones: [[1; ZONES_ACROSS]; HEIGHT] }
+    }
+
+    /// Which of BACKGROUNDS it is.
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: u8) {
+        self.background = background % BACKGROUNDS.len() as u8;
+    }
+
+    /// 02A0
+    pub fn cycle_background(&mut self) {
+        self.set_background(self.background + 1);
+    }
+
+    /// The foreground colour number of the zone pixel (x, y) is in.
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        self.zones[y % HEIGHT][(x % WIDTH) / ZONE_WIDTH]
+    }
+
+    /// Colour one zone, (x, y) in zones across and pixel rows down.
+    pub fn set_zone(&mut self, x: usize, y: usize, color: u8) {
+        self.zones[y % HEIGHT][x % ZONES_ACROSS] = color & 7;
+    }
+
+    /// BXY0, `horizontal` and `vertical` being VX and V(X+1).
+    pub fn color_blocks(&mut self, horizontal: u8, vertical: u8, color: u8) {
+        let across = (horizontal & 0xF) as usize..=((horizontal & 0xF) + (horizontal >> 4)) as usize;
+        let down = (vertical & 0xF) as usize..=((vertical & 0xF) + (vertical >> 4)) as usize;
+        for block in down.take_while(|&block| block * BLOCK_HEIGHT < HEIGHT) {
+            for y in block * BLOCK_HEIGHT..(block + 1) * BLOCK_HEIGHT {
+                for x in across.clone().take_while(|&x| x < ZONES_ACROSS) {
+                    self.set_zone(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// BXYN, (x, y) in pixels.
+    pub fn color_strip(&mut self, x: usize, y: usize, rows: usize, color: u8) {
+        for y in (y % HEIGHT..).take(rows).take_while(|&y| y < HEIGHT) {
+            self.set_zone((x % WIDTH) / ZONE_WIDTH, y, color);
+        }
+    }
+
+    /// RGB for the background.
+    pub fn background_rgb(&self) -> [u8; 3] {
+        BACKGROUNDS[self.background as usize]
+    }
+
+    /// RGB for a lit pixel at (x, y).
+    pub fn foreground_rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        FOREGROUNDS[self.foreground(x, y) as usize]
+    }
+}
+
+impl Default for ColorBoard {
+    fn default() -> Self {
+        ColorBoard::new()
+    }
+}
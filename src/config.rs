@@ -0,0 +1,297 @@
+// Where the emulator keeps its files between runs, and the settings file that lives there.
+//
+// config.toml holds defaults for the command line, so the same flags don't need passing every
+// time. Everything is optional and flags given on the command line always win:
+//   ips = 1000
+//   variant = "schip"          # chip8, schip, xochip, chip8x, megachip, eti660 or chip8hires
+//   quirks = "chip48"          # vip, chip48, schip or xochip, instead of the variant's usual ones
+//   screen_height = 64         # the ETI-660's, 48 or 64 rows
+//   palette = "amber"          # anything --palette takes
+//   background = "101010"
+//   foreground = "FFB000"
+//   phosphor = 4
+//   renderer = "half-blocks"   # anything --renderer takes
+//   keymap = "dvorak"          # anything --keymap takes
+//   volume = 50                # 0 to 100
+//   beep_frequency = 330
+//   waveform = "triangle"      # square, triangle, sine, or a WAV file to loop
+//   metadata = "/home/me/chip8Archive/programs.json"   # for ROMs that don't have one nearby
+//
+//   [keys]                     # CHIP-8 key = keyboard key, on top of the keymap
+//   5 = "k"
+//   8 = "j"
+//
+//   [gamepad]                  # button = CHIP-8 key, on top of the default mapping
+//   south = "5"                # (button names are in src/gamepad.rs)
+//
+//   [rom.brix]                 # just for brix.ch8 (the file name, without the extension)
+//   keymap = "qwerty"
+//   keys = { 4 = "a", 6 = "d" }
+//   gamepad = { dpad-left = "4", dpad-right = "6" }
+//
+//   [rom.pong]                 # two players on one keyboard (see src/keymap.rs), keys is
+//   keymap = "split"           # player 1's and player2 the other player's
+//   keys = { 1 = "w", 4 = "s" }
+//   player2 = { C = "i", D = "k" }
+//
+// roms.txt next to it adds to the ROM database, see src/rom_db.rs, and shared libraries in
+// plugins/ next to it get loaded by chip8 run, see src/plugin.rs.
+
+use std::env;
+use std::path::PathBuf;
+
+#[cfg(feature = "config-file")]
+use std::collections::BTreeMap;
+#[cfg(feature = "config-file")]
+use std::fs;
+#[cfg(feature = "config-file")]
+use std::io;
+#[cfg(feature = "config-file")]
+use std::path::Path;
+
+#[cfg(feature = "config-file")]
+use serde::Deserialize;
+
+#[cfg(feature = "config-file")]
+use crate::keymap::{Keymap, Player};
+
+/// `$XDG_CONFIG_HOME/chip8`, falling back to `~/.config/chip8`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip8"))
+}
+
+/// `config.toml` in the config directory.
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// The settings from config.toml. Names are checked by whoever uses them (the command line
+/// parses them the same way as its flags), only the file's shape is checked here.
+#[cfg(feature = "config-file")]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub ips: Option<u32>,
+    pub variant: Option<String>,
+    pub quirks: Option<String>,
+    pub screen_height: Option<usize>,
+    pub palette: Option<String>,
+    pub background: Option<String>,
+    pub foreground: Option<String>,
+    pub phosphor: Option<u8>,
+    pub renderer: Option<String>,
+    pub keymap: Option<String>,
+    pub volume: Option<u8>,
+    pub beep_frequency: Option<f32>,
+    pub waveform: Option<String>,
+    /// A CHIP-8 Archive programs.json to look ROMs up in
+    pub metadata: Option<PathBuf>,
+    /// CHIP-8 key (as a hex digit) to keyboard key
+    pub keys: BTreeMap<String, char>,
+    /// The same for player 2's keys, in a split keymap
+    pub player2: BTreeMap<String, char>,
+    /// Gamepad button name to CHIP-8 key (as a hex digit)
+    pub gamepad: BTreeMap<String, String>,
+    /// Overrides for single ROMs, by file name without the extension
+    pub rom: BTreeMap<String, RomConfig>,
+}
+
+/// A [rom.<name>] section, keys and buttons for one game.
+#[cfg(feature = "config-file")]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub keymap: Option<String>,
+    pub keys: BTreeMap<String, char>,
+    pub player2: BTreeMap<String, char>,
+    pub gamepad: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "config-file")]
+impl Config {
+    /// Read a config file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Read config.toml from the config directory, all defaults if there isn't one.
+    pub fn load_default() -> io::Result<Self> {
+        match config_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// The keymap for a ROM (its file name without the extension, case doesn't matter): the
+    /// ROM's own keymap or the general one, then the general [keys] and [player2], then the
+    /// ROM's.
+    pub fn keymap(&self, rom: Option<&str>) -> io::Result<Keymap> {
+        let rom_config = self.rom_config(rom);
+
+        let layout = rom_config.and_then(|(_, config)| config.keymap.as_ref()).or(self.keymap.as_ref());
+        let mut keymap = match layout {
+            Some(text) => Keymap::parse(text).ok_or_else(|| invalid(format!("keymap {:?} isn't a layout, 16 different keys or two of those split with |", text)))?,
+            None => Keymap::default(),
+        };
+        bind_keys(&mut keymap, Player::One, &self.keys)?;
+        bind_keys(&mut keymap, Player::Two, &self.player2)?;
+        if let Some((_, config)) = rom_config {
+            bind_keys(&mut keymap, Player::One, &config.keys)?;
+            bind_keys(&mut keymap, Player::Two, &config.player2)?;
+        }
+        Ok(keymap)
+    }
+
+    /// The gamepad buttons to rebind for a ROM, as (button name, CHIP-8 key): the [gamepad]
+    /// table and then the ROM's own, so the ROM's come last and win.
+    pub fn gamepad_buttons(&self, rom: Option<&str>) -> io::Result<Vec<(String, u8)>> {
+        let rom_buttons = self.rom_config(rom).map(|(_, config)| &config.gamepad);
+        let mut buttons = Vec::new();
+        for (button, key) in self.gamepad.iter().chain(rom_buttons.into_iter().flatten()) {
+            buttons.push((button.clone(), parse_key(key)?));
+        }
+        Ok(buttons)
+    }
+
+    fn rom_config(&self, rom: Option<&str>) -> Option<(&String, &RomConfig)> {
+        rom.and_then(|rom| self.rom.iter().find(|(name, _)| name.eq_ignore_ascii_case(rom)))
+    }
+}
+
+// A CHIP-8 key as a hex digit
+#[cfg(feature = "config-file")]
+fn parse_key(key: &str) -> io::Result<u8> {
+    match u8::from_str_radix(key, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(invalid(format!("{:?} isn't a CHIP-8 key, they go from 0 to F", key))),
+    }
+}
+
+#[cfg(feature = "config-file")]
+fn bind_keys(keymap: &mut Keymap, player: Player, keys: &BTreeMap<String, char>) -> io::Result<()> {
+    for (key, &c) in keys {
+        keymap.bind_for(player, parse_key(key)?, c);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "config-file")]
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("config.toml: {}", message))
+}
+
+#[cfg(all(test, feature = "config-file"))]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn everything_is_optional() {
+        let config = parse("");
+        assert_eq!(config.ips, None);
+        assert!(config.keys.is_empty() && config.rom.is_empty());
+        assert_eq!(config.keymap(Some("brix")).unwrap(), Keymap::default());
+    }
+
+    #[test]
+    fn settings() {
+        let config = parse(
+            r#"
+            ips = 1000
+            variant = "schip"
+            volume = 50
+            beep_frequency = 330
+            metadata = "/tmp/programs.json"
+            "#,
+        );
+        assert_eq!(config.ips, Some(1000));
+        assert_eq!(config.variant.as_deref(), Some("schip"));
+        assert_eq!(config.volume, Some(50));
+        assert_eq!(config.beep_frequency, Some(330.0));
+        assert_eq!(config.metadata, Some(PathBuf::from("/tmp/programs.json")));
+    }
+
+    #[test]
+    fn bad_files() {
+        // unknown names, the wrong types, and out of range numbers are all caught
+        assert!(toml::from_str::<Config>("speed = 1000").is_err());
+        assert!(toml::from_str::<Config>("ips = \"fast\"").is_err());
+        assert!(toml::from_str::<Config>("volume = 300").is_err());
+        assert!(toml::from_str::<Config>("[keys]\n5 = \"kk\"").is_err());
+        assert!(toml::from_str::<Config>("[rom.brix]\nspeed = 10").is_err());
+        assert!(toml::from_str::<Config>("ips = ").is_err());
+    }
+
+    #[test]
+    fn keys_on_top_of_the_keymap_then_the_roms() {
+        let config = parse(
+            r#"
+            keymap = "dvorak"
+            [keys]
+            5 = "k"
+            [rom.Brix]
+            keymap = "qwerty"
+            keys = { 4 = "j" }
+            "#,
+        );
+        let general = config.keymap(Some("pong")).unwrap();
+        assert_eq!(general.key_for('k'), Some(0x5));
+        assert_eq!(general.key_for('a'), Some(0x7));
+
+        let brix = config.keymap(Some("brix")).unwrap();
+        assert_eq!(brix.key_for('k'), Some(0x5));
+        assert_eq!(brix.key_for('j'), Some(0x4));
+        assert_eq!(brix.key_for('a'), Some(0x7));
+        assert_eq!(brix.key_for('q'), None);
+    }
+
+    #[test]
+    fn bad_keys() {
+        let error = |text| parse(text).keymap(None).unwrap_err().to_string();
+        assert_eq!(error("keymap = \"workman\""), "config.toml: keymap \"workman\" isn't a layout, 16 different keys or two of those split with |");
+        assert_eq!(error("[keys]\n10 = \"k\""), "config.toml: \"10\" isn't a CHIP-8 key, they go from 0 to F");
+        assert_eq!(error("[keys]\nG = \"k\""), "config.toml: \"G\" isn't a CHIP-8 key, they go from 0 to F");
+    }
+
+    #[test]
+    fn gamepad_buttons_rom_last() {
+        let config = parse(
+            r#"
+            [gamepad]
+            south = "5"
+            [rom.brix]
+            gamepad = { south = "6", dpad-left = "4" }
+            "#,
+        );
+        let buttons = config.gamepad_buttons(Some("BRIX")).unwrap();
+        assert_eq!(buttons, [("south".to_string(), 5), ("dpad-left".to_string(), 4), ("south".to_string(), 6)]);
+        assert_eq!(config.gamepad_buttons(None).unwrap(), [("south".to_string(), 5)]);
+        assert!(parse("[gamepad]\nsouth = \"x\"").gamepad_buttons(None).is_err());
+    }
+
+    #[test]
+    fn player_two_keys() {
+        let config = parse(
+            r#"
+            [rom.pong]
+            keymap = "split"
+            keys = { 1 = "w", 4 = "s" }
+            player2 = { C = "i", D = "k" }
+            "#,
+        );
+        let pong = config.keymap(Some("pong")).unwrap();
+        assert_eq!(pong.player_key_for('w'), Some((Player::One, 0x1)));
+        assert_eq!(pong.player_key_for('s'), Some((Player::One, 0x4)));
+        assert_eq!(pong.player_key_for('i'), Some((Player::Two, 0xC)));
+        assert_eq!(pong.player_key_for('k'), Some((Player::Two, 0xD)));
+        assert!(!config.keymap(Some("brix")).unwrap().is_split());
+    }
+}
@@ -0,0 +1,177 @@
+// Execution coverage.
+// Counts how many times each address ran, so you can see which parts of a game you've played
+// through and which branches you've never got to. Add a Coverage as an observer (hooks.rs):
+//
+//   chip8 run game.ch8 --coverage game.cov      play, the counts get written when you quit
+//   chip8 coverage game.ch8 game.cov            the ROM as a heatmap, and the code that never ran
+//
+// The terminal debugger keeps its own counts and colours the disassembly's addresses with them.
+//
+// The file is plain text, one executed address per line with its count, so it's easy to
+// pick apart or add up across several play sessions:
+//
+//     # address count
+//     0x200 1
+//     0x202 58211
+//
+// An instruction waiting on FX0A counts once per try, so expect big numbers there.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::cpu::Cpu;
+use crate::hooks::Observer;
+
+/// How many times each address has run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    // indexed by address, grown as higher addresses run
+    counts: Vec<u64>,
+    max: u64,
+}
+
+/// A coverage file line that didn't parse. `line` counts from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl core::error::Error for CoverageError {}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Count one more run of `addr`.
+    pub fn record(&mut self, addr: usize) {
+        self.add(addr, 1);
+    }
+
+    fn add(&mut self, addr: usize, count: u64) {
+        if addr >= self.counts.len() {
+            self.counts.resize(addr + 1, 0);
+        }
+        self.counts[addr] = self.counts[addr].saturating_add(count);
+        self.max = self.max.max(self.counts[addr]);
+    }
+
+    /// How many times `addr` has run.
+    pub fn count(&self, addr: usize) -> u64 {
+        self.counts.get(addr).copied().unwrap_or(0)
+    }
+
+    /// (address, count) for every address that's run, lowest first.
+    pub fn executed(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts.iter().enumerate().filter(|(_, &count)| count > 0).map(|(addr, &count)| (addr, count))
+    }
+
+    /// The highest count.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// How hot `addr` is, from 0 (never ran) to 1 (ran as often as the hottest address). It's
+    /// on a log scale, or a tight loop would leave everything else looking cold. Needs std for
+    /// the log.
+    #[cfg(feature = "std")]
+    pub fn heat(&self, addr: usize) -> f32 {
+        if self.max == 0 {
+            return 0.0;
+        }
+        (self.count(addr) as f32 + 1.0).log2() / (self.max as f32 + 1.0).log2()
+    }
+
+    /// Add another run's counts to these.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (addr, count) in other.executed() {
+            self.add(addr, count);
+        }
+    }
+
+    /// Forget every count.
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.max = 0;
+    }
+
+    /// The counts in the file format at the top of this file.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# address count\n");
+        for (addr, count) in self.executed() {
+            let _ = writeln!(text, "0x{:03X} {}", addr, count);
+        }
+        text
+    }
+
+    /// Read counts written by to_text(). An address that's in there twice gets both counts.
+    pub fn parse(text: &str) -> Result<Self, CoverageError> {
+        let mut coverage = Coverage::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason| CoverageError { line: n + 1, reason };
+            let mut fields = line.split_whitespace();
+            let addr = fields.next().unwrap_or("");
+            let addr = addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")).unwrap_or(addr);
+            let addr = usize::from_str_radix(addr, 16).map_err(|_| error("expected a hex address"))?;
+            // 64K is as much memory as there is, don't allocate gigabytes for a typo
+            if addr > 0xFFFF {
+                return Err(error("the address is past 64K"));
+            }
+            let count = fields.next().and_then(|count| count.parse().ok()).ok_or_else(|| error("expected a count after the address"))?;
+            if fields.next().is_some() {
+                return Err(error("expected just an address and a count"));
+            }
+            coverage.add(addr, count);
+        }
+        Ok(coverage)
+    }
+
+    /// Read a coverage file from disk.
+    #[cfg(feature = "std")]
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Coverage::parse(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Write the counts to disk.
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+}
+
+impl Observer for Coverage {
+    fn before_instruction(&mut self, _cpu: &Cpu, pc: usize) {
+        self.record(pc);
+    }
+}
+
+/// The colour for a heat() value, going from dark blue through red to yellow. Never run (0)
+/// is dark grey.
+pub fn heat_color(heat: f32) -> [u8; 3] {
+    if heat <= 0.0 {
+        return [48, 48, 48];
+    }
+    let heat = heat.min(1.0);
+    // blue to red over the first half, red to yellow over the second
+    let (r, g, b) = if heat < 0.5 {
+        let t = heat * 2.0;
+        (40.0 + 215.0 * t, 0.0, 160.0 * (1.0 - t))
+    } else {
+        let t = (heat - 0.5) * 2.0;
+        (255.0, 230.0 * t, 0.0)
+    };
+    [r as u8, g as u8, b as u8]
+}
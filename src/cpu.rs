@@ -0,0 +1,1813 @@
+// Decoding CHIP-8 opcodes.
+// they're u16 values made up of 4 nibbles (half a byte / 4 bits)
+// rust has no 4 bit type so we just wrap them all together in a 16bit type and then awkwardly
+// filter out the bits we dont care about to access each individual nibble
+
+// CHIP-8 breaks opcodes down into 2 bytes, high byte, low byte, then nibbles, high nibble, low nibble
+// (layout of the bytes is called endianness, cpu manufacturers decide this)
+// 0xAB12
+// high byte = AB
+  // high nibble A
+  // low nibble B
+// low byte = 12
+  // high nibble 1
+  // low nibble 2
+
+// Note that control flow in a CPU is done by comparing values in a register
+// then modifying position_in_memory, depending on the outcome. There are no while
+// or for loops in the CPU, thats the job of the programming languages compiler.
+use core::fmt;
+use core::mem;
+use core::panic;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::audio::{Digitised, Sound, DEFAULT_PITCH, PATTERN_LEN};
+use crate::call_stack::CallFrame;
+#[cfg(feature = "std")]
+use crate::clock::Clock;
+use crate::clock::{DEFAULT_CLOCK_SPEED, TIMER_HZ};
+use crate::color_board::ColorBoard;
+use crate::display::Display;
+use crate::error::CpuError;
+use crate::font::{BIG_FONT, BIG_FONT_ADDR, SMALL_FONT, SMALL_FONT_ADDR};
+use crate::fnv::fnv1a;
+use crate::halt::{HaltReason, LoopDetection, UnknownOpcodePolicy};
+use crate::hooks::{Observer, ObserverId};
+use crate::instruction::Instruction;
+use crate::mega_chip::{DigitisedSound, MegaScreen, SOUND_HEADER_LEN};
+use crate::memory_protection::MemoryProtection;
+use crate::quirks::Quirks;
+use crate::rom_format::{self, RomFormat};
+use crate::rpl_flags::RPL_FLAG_COUNT;
+use crate::variant::Variant;
+use crate::vip_timing::{self, Timing, VIP_CYCLES_PER_FRAME};
+
+#[cfg(feature = "dispatch-table")]
+mod dispatch;
+
+// ROMs are loaded after the 512 bytes the original interpreter reserved for itself
+pub const PROGRAM_START: usize = 0x200;
+
+/// How deep CALLs can nest unless set_stack_depth() says otherwise, 16 like most interpreters.
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+/// The most memory set_memory_size() allows, as much as Mega-Chip's 24 bit I can reach.
+pub const MAX_MEMORY_SIZE: usize = 0x100_0000;
+/// The least memory set_memory_size() allows, room for one instruction after the reserved part.
+pub const MIN_MEMORY_SIZE: usize = PROGRAM_START + 2;
+/// The deepest stack set_stack_depth() allows. Return addresses are 16 bits, so anything past
+/// this would just be a lot of the same addresses.
+pub const MAX_STACK_DEPTH: usize = 0x10000;
+
+// Only the first 64K gets its decoded instructions kept. Jumps can't go past 4K, so code out
+// past 64K (and only Mega-Chip has memory there) is very rare, and a cache entry for every byte
+// of 16MB is a lot of memory for nothing
+pub(crate) const DECODE_CACHE_SIZE: usize = 0x10000;
+
+// Any non-zero value will do, this one is just easy to spot in a debugger
+const DEFAULT_SEED: u64 = 0xC8C8_C8C8_C8C8_C8C8;
+
+// What idle loop detection compares between instructions. Timers aren't included,
+// them counting down doesn't mean the program is getting anywhere.
+#[derive(Default, PartialEq)]
+struct IdleSnapshot {
+    registers: [u8; 16],
+    index_register: u32,
+    stack_pointer: usize,
+    memory_writes: u64,
+    display_changes: u64,
+}
+
+/// Runs straight-line blocks of instructions in one go instead of a step() at a time, which is
+/// how the JIT and transpiled ROMs plug into the CPU (see Cpu::run_for_with).
+pub trait BlockRunner {
+    /// Run up to `max` instructions starting at PC, leaving PC after the last one, and return
+    /// how many ran. Returning 0 leaves the next instruction to the interpreter. Blocks have to
+    /// stop before anything that touches memory, the stack, the display, the keypad or the
+    /// timers, so only registers, I and PC ever change. The last instruction can be a jump or a
+    /// skip, as long as it isn't a jump to itself (that's how infinite loops get caught).
+    fn run_block(&mut self, cpu: &mut Cpu, max: u32) -> u32;
+}
+
+// The plain interpreter, which never takes a block
+struct Interpreter;
+
+impl BlockRunner for Interpreter {
+    fn run_block(&mut self, _cpu: &mut Cpu, _max: u32) -> u32 {
+        0
+    }
+}
+
+// All CHIP-8 opcodes are U16 values, defined by who makes the architecture
+pub struct Cpu {
+    // Moved now to 16 registers. Means that a single hex num (0 to F) can address these,
+    // let's all opcodes be compactly represented as u16 values.
+    pub registers: [u8; 16],
+    // Usually called 'program counter' but this naming makes it obvious
+    pub position_in_memory: usize, // diverges from original spec, but rust lets us use this for indexing
+    // 0x1000 is hex for 4096 (4kb), the amount of bytes of RAM a CHIP-8 had.
+    // The chip-8 usize equiv basically, only 2^12 (12 bits = 4096)
+    // In original spec, the first 512 bytes (0x100) are reserved for the system, others are for programs
+    // XO-CHIP has 64kb, so the size depends on the variant (or set_memory_size())
+    pub memory: Vec<u8>,
+
+    // ~ The stack ~ specialised memory for CALL and RETURN opcodes
+    // stacks maximum height is 16 by default, after 16 nested function calls we say its a stack overflow
+    // set_stack_depth() changes it, it's a Vec so it can
+    pub stack: Vec<u16>,
+    pub stack_pointer: usize, // giving the stack_pointer usize makes it easier to index values cause rust
+    // the function each stack entry called, RET doesn't need it but debuggers do
+    pub(crate) call_targets: Vec<u16>,
+
+    // Usually just called 'I'. A 16 bit register that holds memory addresses,
+    // it's the only way opcodes can point at data (sprites, saved registers, etc.)
+    // Mega-Chip's is 24 bits, so it's kept in a u32 (see Variant::index_mask())
+    pub index_register: u32,
+
+    // Two timers that count down to 0 at 60Hz. The delay timer is just for the program to read,
+    // the sound timer beeps for as long as it's above 0.
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+
+    // XO-CHIP audio, a 128 bit sample pattern (FX02) and its playback pitch (FX3A)
+    pub audio_pattern: Option<[u8; PATTERN_LEN]>,
+    pub audio_pitch: u8,
+    // Mega-Chip's digitised sound (060N), None when nothing's playing
+    pub digitised_sound: Option<DigitisedSound>,
+    // and a copy of its samples for the audio backend, made when it starts (see sound())
+    digitised_samples: Option<Arc<[u8]>>,
+
+    pub display: Display,
+
+    // HP-48 user flags (FX75/FX85). They're meant to outlive the program, so whoever runs the
+    // CPU should load them before starting and save them when rpl_flags_dirty gets set
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+    pub rpl_flags_dirty: bool,
+
+    // The 16 key hex keypad (0-9, A-F), true while a key is held down
+    pub keypad: [bool; 16],
+    // CHIP-8X's second keypad, the same again (EXF2/EXF5)
+    pub keypad2: [bool; 16],
+    // CHIP-8X's I/O port: the last byte FXF8 wrote, and what FXFB reads. Nothing's plugged
+    // into it, so a frontend with something to plug in sets port_input
+    pub port_output: u8,
+    pub port_input: u8,
+
+    // State for the random number generator behind CXKK
+    pub(crate) rng_state: u64,
+
+    // Set by opcode 0x0000 (or loop detection), run() stops once there's a reason
+    pub(crate) halt_reason: Option<HaltReason>,
+    // Halt with a CpuError on anything a broken program does (running off the end of memory,
+    // recursing too deep) instead of panicking. For ROMs you don't trust. Unknown opcodes
+    // halt either way, see UnknownOpcodePolicy.
+    pub hardened: bool,
+    // What a hardened CPU (or the watchdog, or an unknown opcode) halted on
+    pub(crate) fault: Option<CpuError>,
+    // Which never-ending loops count as halting
+    pub loop_detection: LoopDetection,
+    // Stop with CpuError::Watchdog once this many instructions have run (counting from when
+    // the CPU was made), for running ROMs that might never finish. Works hardened or not.
+    pub watchdog: Option<u64>,
+    // Which reads and writes are errors, see memory_protection.rs
+    pub memory_protection: MemoryProtection,
+    // Which bytes of memory have been written (font, ROM or program), for the strict read check
+    initialized: Vec<bool>,
+    // What opcodes the variant doesn't have do, see set_unknown_opcode_policy()
+    unknown_opcode_policy: UnknownOpcodePolicy,
+    // Instructions in a row that haven't changed anything, and what "unchanged" looks like
+    idle_cycles: u64,
+    idle_snapshot: IdleSnapshot,
+    // Bumped on every write to memory, cheap way to notice memory changing
+    memory_writes: u64,
+    // Instructions already decoded, by address. Writes to memory throw away the entries they
+    // touch, so self-modifying code still works. Only valid for the variant it was decoded for.
+    decoded: Vec<Option<Instruction>>,
+    decoded_for: Variant,
+    // The span of memory written since take_code_writes() was last called, start..end
+    code_writes: Option<(usize, usize)>,
+    // With the display_wait quirk a draw blocks the CPU until the next timer tick
+    pub waiting_for_vblank: bool,
+    // With the wait_for_key_release quirk, the key FX0A saw go down and is waiting to come up
+    pub held_key: Option<u8>,
+    // Frozen by the user, run_frame() does nothing until resumed
+    paused: bool,
+    // Whoever's watching the program run (see hooks.rs), and the id the next one gets
+    pub(crate) observers: Vec<(ObserverId, Box<dyn Observer>)>,
+    next_observer: u64,
+
+    // Which interpreter's behaviour the ambiguous opcodes should follow
+    pub quirks: Quirks,
+    // Which instruction set is available
+    pub variant: Variant,
+    // How many instructions are executed per second, spread evenly over 60Hz frames
+    pub clock_speed: u32,
+    // What an instruction costs, see vip_timing.rs. With VIP timing the two below are in 1802
+    // machine cycles rather than instructions
+    pub timing: Timing,
+    // clock_speed rarely divides by 60, this carries the leftover (in 60ths of an instruction).
+    // With VIP timing it's how far the last instruction ran on past the end of its frame
+    pub(crate) frame_remainder: u32,
+    // instructions left in the current frame before the timers tick
+    pub(crate) frame_cycles_left: u32,
+    // Counted for Stats (stats.rs): instructions run and 60Hz ticks since the CPU was made
+    pub(crate) instructions_retired: u64,
+    pub(crate) timer_ticks: u64,
+}
+
+impl Cpu {
+    pub fn new(quirks: Quirks) -> Self {
+        let mut cpu = Cpu {
+            // repeat expressions [x; N], which produces an array with N copies of x
+            registers: [0; 16],
+            memory: vec![0; Variant::Chip8.memory_size()],
+            position_in_memory: 0,
+            stack: vec![0; DEFAULT_STACK_DEPTH],
+            stack_pointer: 0,
+            call_targets: vec![0; DEFAULT_STACK_DEPTH],
+            index_register: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            audio_pattern: None,
+            audio_pitch: DEFAULT_PITCH,
+            digitised_sound: None,
+            digitised_samples: None,
+            display: Display::new(),
+            rpl_flags: [0; RPL_FLAG_COUNT],
+            rpl_flags_dirty: false,
+            keypad: [false; 16],
+            keypad2: [false; 16],
+            port_output: 0,
+            port_input: 0,
+            rng_state: DEFAULT_SEED,
+            halt_reason: None,
+            hardened: false,
+            fault: None,
+            loop_detection: LoopDetection::off(),
+            watchdog: None,
+            unknown_opcode_policy: UnknownOpcodePolicy::Error,
+            memory_protection: MemoryProtection::off(),
+            initialized: vec![false; Variant::Chip8.memory_size()],
+            idle_cycles: 0,
+            idle_snapshot: IdleSnapshot::default(),
+            memory_writes: 0,
+            decoded: Vec::new(),
+            decoded_for: Variant::Chip8,
+            code_writes: None,
+            waiting_for_vblank: false,
+            held_key: None,
+            paused: false,
+            observers: Vec::new(),
+            next_observer: 0,
+            quirks,
+            variant: Variant::Chip8,
+            clock_speed: DEFAULT_CLOCK_SPEED,
+            timing: Timing::Instructions,
+            frame_remainder: 0,
+            frame_cycles_left: 0,
+            instructions_retired: 0,
+            timer_ticks: 0,
+        };
+
+        // the fonts live in the interpreter's reserved memory
+        cpu.memory[SMALL_FONT_ADDR..SMALL_FONT_ADDR + SMALL_FONT.len()].copy_from_slice(&SMALL_FONT);
+        cpu.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+        cpu.mark_initialized(SMALL_FONT_ADDR, SMALL_FONT.len());
+        cpu.mark_initialized(BIG_FONT_ADDR, BIG_FONT.len());
+
+        cpu
+    }
+
+    /// A CPU for the given machine, using the quirks that machine's ROMs usually expect.
+    pub fn with_variant(variant: Variant) -> Self {
+        let mut cpu = Cpu::new(variant.default_quirks());
+        cpu.set_variant(variant);
+        cpu
+    }
+
+    /// Copies a ROM into memory at 0x200 (program_start()) and points the program counter at it
+    /// (past the patch, for two-page hi-res). The ROM's always taken as a binary, load_rom_as()
+    /// loads the text formats.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), CpuError> {
+        self.load_binary(rom)
+    }
+
+    /// load_rom() for a ROM in `format`: Intel HEX and hex text dumps are turned into bytes
+    /// first (see rom_format.rs).
+    pub fn load_rom_as(&mut self, rom: &[u8], format: RomFormat) -> Result<(), CpuError> {
+        let rom = rom_format::decode(rom, format).map_err(CpuError::InvalidRomFile)?;
+        self.load_binary(&rom)
+    }
+
+    /// Swap this machine for a fresh one running `rom`, with the same variant, settings (quirks,
+    /// clock speed and timing, memory size and stack depth, screen height, hardening and the
+    /// other checks, phosphor decay) and observers. Left as it was if the ROM doesn't load.
+    pub fn restart_with_rom(&mut self, rom: &[u8]) -> Result<(), CpuError> {
+        let mut fresh = Cpu::with_variant(self.variant);
+        fresh.set_quirks(self.quirks);
+        fresh.clock_speed = self.clock_speed;
+        fresh.timing = self.timing;
+        fresh.set_memory_size(self.memory.len())?;
+        fresh.set_stack_depth(self.stack.len())?;
+        fresh.display.set_lores_height(self.display.lores_height());
+        fresh.hardened = self.hardened;
+        fresh.loop_detection = self.loop_detection;
+        fresh.watchdog = self.watchdog;
+        fresh.memory_protection = self.memory_protection;
+        fresh.display.set_phosphor_decay(self.display.phosphor_decay());
+        fresh.load_rom(rom)?;
+        fresh.unknown_opcode_policy = mem::take(&mut self.unknown_opcode_policy);
+        fresh.observers = mem::take(&mut self.observers);
+        *self = fresh;
+        Ok(())
+    }
+
+    /// The same as load_rom().
+    pub fn load_binary(&mut self, rom: &[u8]) -> Result<(), CpuError> {
+        let start = self.program_start();
+        let capacity = self.memory.len().saturating_sub(start);
+        if rom.len() > capacity {
+            return Err(CpuError::RomTooLarge { size: rom.len(), capacity });
+        }
+
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.mark_initialized(start, rom.len());
+        self.memory_writes += 1;
+        self.flush_decoded();
+        self.position_in_memory = self.variant.entry_point();
+        Ok(())
+    }
+
+    /// Press or release one of the 16 keys (0x0 to 0xF).
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keypad[(key & 0xF) as usize] = pressed;
+    }
+
+    /// set_key() for CHIP-8X's second keypad.
+    pub fn set_key2(&mut self, key: u8, pressed: bool) {
+        self.keypad2[(key & 0xF) as usize] = pressed;
+    }
+
+    /// Reseed the random number generator, the same seed always gives the same CXKK results.
+    pub fn seed_rng(&mut self, seed: u64) {
+        // xorshift gets stuck on 0 forever
+        self.rng_state = if seed == 0 { DEFAULT_SEED } else { seed };
+    }
+
+    /// Switch machine, memory grows or shrinks to fit (anything past the new size is lost).
+    /// That includes a size set with set_memory_size(), so set the variant first. Switching
+    /// to CHIP-8X adds its colour board to the display, switching away takes it off again, and
+    /// the same goes for Mega-Chip's screen.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.resize_memory(variant.memory_size());
+        let chip8x = variant == Variant::Chip8X;
+        if chip8x != self.display.color_board().is_some() {
+            self.display.set_color_board(chip8x.then(ColorBoard::new));
+        }
+        let mega = variant == Variant::MegaChip;
+        if mega != self.display.mega_screen().is_some() {
+            self.display.set_mega_screen(mega.then(MegaScreen::new));
+        }
+        if variant.lores_height() != self.display.lores_height() {
+            self.display.set_lores_height(variant.lores_height());
+        }
+    }
+
+    /// Where the machine loads programs, 0x200 except on CHIP-8X and the ETI-660.
+    pub fn program_start(&self) -> usize {
+        self.variant.program_start()
+    }
+
+    /// Give the CPU `size` bytes of memory instead of the variant's own amount, less for a
+    /// machine like the ETI-660 or more to give a CHIP-8 ROM XO-CHIP's 64K. Anything past the
+    /// new size is lost. It has to be between MIN_MEMORY_SIZE and MAX_MEMORY_SIZE.
+    pub fn set_memory_size(&mut self, size: usize) -> Result<(), CpuError> {
+        if !(MIN_MEMORY_SIZE..=MAX_MEMORY_SIZE).contains(&size) {
+            return Err(CpuError::InvalidMemorySize { size });
+        }
+        self.resize_memory(size);
+        Ok(())
+    }
+
+    fn resize_memory(&mut self, size: usize) {
+        self.memory.resize(size, 0);
+        self.initialized.resize(size, false);
+        if self.position_in_memory >= size {
+            self.position_in_memory = self.program_start();
+        }
+        self.flush_decoded();
+    }
+
+    /// How many CALLs can be nested before a stack overflow, DEFAULT_STACK_DEPTH unless
+    /// changed. Raise it for ROMs that recurse deeply, or lower it to match an interpreter with
+    /// a smaller stack (the VIP's had room for 12). Shrinking it below what's on the stack now
+    /// drops the innermost calls. It has to be between 1 and MAX_STACK_DEPTH.
+    pub fn set_stack_depth(&mut self, depth: usize) -> Result<(), CpuError> {
+        if !(1..=MAX_STACK_DEPTH).contains(&depth) {
+            return Err(CpuError::InvalidStackDepth { depth });
+        }
+        self.stack.resize(depth, 0);
+        self.call_targets.resize(depth, 0);
+        self.stack_pointer = self.stack_pointer.min(depth);
+        Ok(())
+    }
+
+    /// How many CALLs can be nested, see set_stack_depth().
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Count `len` bytes at `addr` as written for MemoryProtection::uninitialized_reads, after
+    /// putting something there through `memory` directly.
+    pub fn mark_initialized(&mut self, addr: usize, len: usize) {
+        let end = (addr + len).min(self.initialized.len());
+        if addr < end {
+            self.initialized[addr..end].fill(true);
+        }
+    }
+
+    /// Forget every decoded instruction. Writes made by running instructions take care of this
+    /// themselves, but anything that changes `memory` directly while a program is running
+    /// (a debugger poking bytes, say) needs to call this afterwards.
+    pub fn flush_decoded(&mut self) {
+        self.decoded.clear();
+        self.decoded.resize(self.memory.len().min(DECODE_CACHE_SIZE), None);
+        self.decoded_for = self.variant;
+        self.code_writes = Some((0, self.memory.len()));
+    }
+
+    /// The span of memory (start..end) written since the last call, if any. This is the hook for
+    /// anything that keeps its own copy of the program, like the JIT, to notice self-modifying
+    /// code. A flush_decoded() counts as all of memory being written.
+    pub fn take_code_writes(&mut self) -> Option<(usize, usize)> {
+        self.code_writes.take()
+    }
+
+    /// Start calling `observer` as the program runs, see hooks.rs.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) -> ObserverId {
+        let id = ObserverId(self.next_observer);
+        self.next_observer += 1;
+        self.observers.push((id, observer));
+        id
+    }
+
+    /// Stop calling an observer and hand it back, None if it's already gone.
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Box<dyn Observer>> {
+        let n = self.observers.iter().position(|(other, _)| *other == id)?;
+        Some(self.observers.remove(n).1)
+    }
+
+    // Call every observer. They're taken out of the CPU while they run so they can borrow it.
+    fn notify(&mut self, mut event: impl FnMut(&mut dyn Observer, &Cpu)) {
+        let mut observers = core::mem::take(&mut self.observers);
+        for (_, observer) in &mut observers {
+            event(observer.as_mut(), self);
+        }
+        self.observers = observers;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Swap quirks at runtime, takes effect from the next instruction.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt_reason.is_some()
+    }
+
+    /// Why the CPU stopped, None while it's still running.
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    /// Stop the CPU, the first reason given sticks.
+    pub fn halt(&mut self, reason: HaltReason) {
+        #[cfg(feature = "tracing")]
+        if self.halt_reason.is_none() {
+            tracing::debug!(?reason, pc = %format_args!("{:03X}", self.position_in_memory), "halted");
+        }
+        self.halt_reason.get_or_insert(reason);
+    }
+
+    /// What went wrong, if a hardened CPU, the watchdog or an unknown opcode halted with
+    /// HaltReason::Fault.
+    pub fn fault(&self) -> Option<&CpuError> {
+        self.fault.as_ref()
+    }
+
+    // The program did something broken. Hardened CPUs halt with the error, everything else panics.
+    fn raise(&mut self, error: CpuError) {
+        if !self.hardened {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%error, "program fault");
+            // with the state, so a crash report says where the program had got to
+            panic!("{}\n{}", error, self);
+        }
+        self.halt_with_fault(error);
+    }
+
+    // Halt with HaltReason::Fault, keeping `error` for fault(), hardened or not
+    fn halt_with_fault(&mut self, error: CpuError) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(%error, "program fault");
+        if !self.is_halted() {
+            self.fault = Some(error);
+            self.halt(HaltReason::Fault);
+        }
+    }
+
+    /// Choose what opcodes the variant doesn't have do: stop (the default), get skipped, or
+    /// go to a handler of your own. See UnknownOpcodePolicy.
+    pub fn set_unknown_opcode_policy(&mut self, policy: UnknownOpcodePolicy) {
+        self.unknown_opcode_policy = policy;
+    }
+
+    // An opcode the variant doesn't have, PC has already moved past it
+    pub(crate) fn unknown_opcode(&mut self, opcode: u16) {
+        let pc = self.position_in_memory - 2;
+        let handled = match &mut self.unknown_opcode_policy {
+            UnknownOpcodePolicy::Error => false,
+            UnknownOpcodePolicy::Skip => true,
+            UnknownOpcodePolicy::Handler(_) => {
+                // taken out while it runs so it can have the CPU, unless it set a new policy
+                let mut policy = core::mem::take(&mut self.unknown_opcode_policy);
+                let UnknownOpcodePolicy::Handler(handler) = &mut policy else { unreachable!() };
+                let handled = handler(self, pc, opcode);
+                if matches!(self.unknown_opcode_policy, UnknownOpcodePolicy::Error) {
+                    self.unknown_opcode_policy = policy;
+                }
+                handled
+            }
+        };
+        if !handled {
+            self.halt_with_fault(CpuError::UnknownOpcode { pc, opcode });
+        }
+    }
+
+    // Whether `len` bytes at `addr` are all in memory, raising a fault if they aren't.
+    // Called before an instruction touches memory so it either does everything or nothing.
+    fn check_memory(&mut self, addr: usize, len: usize) -> bool {
+        if addr + len <= self.memory.len() {
+            return true;
+        }
+        // PC has already moved past the instruction
+        let pc = self.position_in_memory - 2;
+        self.raise(CpuError::MemoryOutOfBounds { pc, addr, len });
+        false
+    }
+
+    // check_memory() for an instruction reading memory, which also checks it's been written
+    fn check_read(&mut self, addr: usize, len: usize) -> bool {
+        if !self.check_memory(addr, len) {
+            return false;
+        }
+        if self.memory_protection.uninitialized_reads {
+            if let Some(unwritten) = (addr..addr + len).find(|&addr| !self.initialized[addr]) {
+                let pc = self.position_in_memory - 2;
+                self.raise(CpuError::UninitializedRead { pc, addr: unwritten });
+                return false;
+            }
+        }
+        true
+    }
+
+    // check_memory() for an instruction writing memory, which also keeps it out of the reserved part
+    fn check_write(&mut self, addr: usize, len: usize) -> bool {
+        if !self.check_memory(addr, len) {
+            return false;
+        }
+        if self.memory_protection.reserved && addr < self.program_start() {
+            let pc = self.position_in_memory - 2;
+            self.raise(CpuError::ProtectedWrite { pc, addr });
+            return false;
+        }
+        true
+    }
+
+    // All opcode writes to memory go through here so we can keep track of them
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.initialized[addr] = true;
+        self.memory_writes += 1;
+        // the byte could be part of an instruction starting up to 3 bytes earlier (F000 NNNN)
+        for start in addr.saturating_sub(3)..=addr {
+            if let Some(entry) = self.decoded.get_mut(start) {
+                *entry = None;
+            }
+        }
+        self.code_writes = Some(match self.code_writes {
+            Some((start, end)) => (start.min(addr), end.max(addr + 1)),
+            None => (addr, addr + 1),
+        });
+        if !self.observers.is_empty() {
+            self.notify(|observer, cpu| observer.memory_write(cpu, addr, value));
+        }
+    }
+
+    // The instruction at PC, decoding it only if it hasn't been seen before
+    #[cfg(not(feature = "dispatch-table"))]
+    fn fetch(&mut self) -> Instruction {
+        // the variant field is public, so it may have changed under us
+        if self.decoded_for != self.variant || self.decoded.len() != self.memory.len().min(DECODE_CACHE_SIZE) {
+            self.flush_decoded();
+        }
+
+        let pc = self.position_in_memory;
+        if let Some(Some(instruction)) = self.decoded.get(pc) {
+            return *instruction;
+        }
+        let instruction = Instruction::decode_at(&self.memory, pc, self.variant);
+        if let Some(entry) = self.decoded.get_mut(pc) {
+            *entry = Some(instruction);
+        }
+        instruction
+    }
+
+    fn read_opcode(&self) -> u16 {
+        // combine 2 u8 into a single u16
+        // past the end of memory reads as 0, the same as Instruction::decode_at()
+        let p = self.position_in_memory;
+        let op_byte1 = self.memory.get(p).copied().unwrap_or(0) as u16;
+        let op_byte2 = self.memory.get(p + 1).copied().unwrap_or(0) as u16;
+
+        // to create a u16 opcode, combine two values from memory with logical OR
+        // they need to be cast as u16 to start with; otherwise,
+        // the left shift sets all of the bits to 0
+        // left shift to ignore the right most 8 bits, we're adding op_byte2 to get those.
+        op_byte1 << 8 | op_byte2
+    }
+
+    /// The calls that haven't returned yet, outermost first (so the index is the nesting depth).
+    pub fn call_stack(&self) -> impl Iterator<Item = CallFrame> + '_ {
+        let depth = self.stack_pointer.min(self.stack.len());
+        self.stack[..depth].iter().zip(&self.call_targets).map(|(&return_address, &function)| CallFrame {
+            // CALL is always 2 bytes, so it sits just before where it returns to
+            call_site: (return_address as usize).wrapping_sub(2),
+            function: function as usize,
+            return_address: return_address as usize,
+        })
+    }
+
+    /// A digest of the whole machine, for checking a run against a known good one (or another
+    /// core running the same thing) without keeping or sending the whole state. It's 64-bit
+    /// FNV-1a, like Display::hash(), over these in order, numbers little endian:
+    ///
+    ///   V0-VF, PC (4 bytes), I (2 bytes, 3 on Mega-Chip), SP (4 bytes), the stack up to SP (2 bytes each),
+    ///   DT, ST, the memory size (4 bytes), all of memory, then Display::hash() (8 bytes)
+    ///
+    /// so the same state digests the same on every platform and every version. Anything not in
+    /// that list (the keypad, quirks, counters, halting) doesn't change it.
+    pub fn state_digest(&self) -> u64 {
+        let index_len = if self.variant == Variant::MegaChip { 3 } else { 2 };
+        let stack = &self.stack[..self.stack_pointer.min(self.stack.len())];
+        fnv1a(
+            self.registers
+                .iter()
+                .copied()
+                .chain((self.position_in_memory as u32).to_le_bytes())
+                .chain(self.index_register.to_le_bytes().into_iter().take(index_len))
+                .chain((self.stack_pointer as u32).to_le_bytes())
+                .chain(stack.iter().flat_map(|return_address| return_address.to_le_bytes()))
+                .chain([self.delay_timer, self.sound_timer])
+                .chain((self.memory.len() as u32).to_le_bytes())
+                .chain(self.memory.iter().copied())
+                .chain(self.display.hash().to_le_bytes()),
+        )
+    }
+
+    /// The registers, PC, I, SP, timers and stack as text, the same as Display. The format's
+    /// fixed so dumps can be diffed:
+    ///
+    ///   PC 0206  I 0100  SP 1  DT 0  ST 0
+    ///   V0 05  V1 00  ...  VF 00
+    ///   stack 1/16  0204
+    ///
+    /// The stack line lists the return addresses outermost first, only the innermost 16 of
+    /// them for a deeper stack.
+    pub fn dump(&self) -> alloc::string::String {
+        alloc::format!("{}", self)
+    }
+
+    /// Main CPU loop, runs until a HALT (0x0000), one frame every 60th of a second.
+    /// Needs std to keep time, without it drive the CPU with run_frame() from your own timer.
+    #[cfg(feature = "std")]
+    pub fn run(&mut self) {
+        let mut clock = Clock::new();
+        while !self.is_halted() {
+            self.run_frame();
+            clock.wait_for_next_frame();
+        }
+    }
+
+    /// Freeze the machine, timers included. Frontends keep presenting so the screen can be inspected.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Emulate one 60Hz frame: this frame's share of `clock_speed` instructions, then a timer tick.
+    /// Doesn't wait for anything, so frontends can call it from their own render loop and
+    /// present the display after it returns. Does nothing while paused.
+    pub fn run_frame(&mut self) {
+        if !self.paused {
+            self.advance_frame();
+        }
+    }
+
+    /// run_frame(), but handing blocks of instructions to `runner` like run_for_with() does.
+    /// Blocks are kept inside the frame so the timers tick at the same point they always would.
+    pub fn run_frame_with<R: BlockRunner + ?Sized>(&mut self, runner: &mut R) {
+        if self.paused {
+            return;
+        }
+        // blocks are counted in instructions, which VIP timing doesn't go by
+        if self.timing == Timing::CosmacVip {
+            self.advance_frame();
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("frame", pc = %format_args!("{:03X}", self.position_in_memory)).entered();
+        if self.frame_cycles_left == 0 {
+            self.frame_cycles_left = self.next_frame_share();
+        }
+        if self.frame_cycles_left == 0 {
+            // slower than one instruction a frame
+            self.tick_timers();
+            return;
+        }
+        self.run_for_with(self.frame_cycles_left as u64, runner);
+        if self.frame_cycles_left > 0 {
+            // halted part way through
+            self.frame_cycles_left = 0;
+            self.tick_timers();
+        }
+    }
+
+    /// Emulate exactly one frame even if paused, for stepping through a ROM a frame at a time.
+    /// If run_for() stopped part way through a frame, this finishes that frame.
+    pub fn advance_frame(&mut self) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("frame", pc = %format_args!("{:03X}", self.position_in_memory)).entered();
+        if self.frame_cycles_left == 0 {
+            self.frame_cycles_left = self.next_frame_share();
+        }
+        while self.frame_cycles_left > 0 && !self.is_halted() {
+            let cycles = self.next_instruction_cycles();
+            self.step();
+            self.charge(cycles);
+        }
+        self.frame_cycles_left = 0;
+        self.tick_timers();
+    }
+
+    // How many instructions (or machine cycles) the next frame gets
+    fn next_frame_share(&mut self) -> u32 {
+        if self.timing == Timing::CosmacVip {
+            let share = VIP_CYCLES_PER_FRAME.saturating_sub(self.frame_remainder);
+            self.frame_remainder = self.frame_remainder.saturating_sub(VIP_CYCLES_PER_FRAME);
+            return share;
+        }
+        let owed = self.clock_speed + self.frame_remainder;
+        self.frame_remainder = owed % TIMER_HZ;
+        owed / TIMER_HZ
+    }
+
+    // What the instruction at PC will take out of the frame
+    fn next_instruction_cycles(&self) -> u32 {
+        match self.timing {
+            Timing::Instructions => 1,
+            // waiting for the display burns the rest of the frame
+            Timing::CosmacVip if self.waiting_for_vblank => self.frame_cycles_left.max(1),
+            Timing::CosmacVip => {
+                let pc = self.position_in_memory;
+                if pc + 1 >= self.memory.len() {
+                    return 1;
+                }
+                vip_timing::instruction_cycles(self, Instruction::decode_at(&self.memory, pc, self.variant))
+            }
+        }
+    }
+
+    // Take an instruction out of the frame. With VIP timing one can cost more than the frame
+    // has left, and the rest comes out of the frames after
+    fn charge(&mut self, cycles: u32) {
+        if cycles > self.frame_cycles_left {
+            self.frame_remainder += cycles - self.frame_cycles_left;
+            self.frame_cycles_left = 0;
+        } else {
+            self.frame_cycles_left -= cycles;
+        }
+    }
+
+    /// Execute up to `cycles` instructions as fast as possible, no waiting. The timers still tick
+    /// once per frame's worth of instructions so the ROM sees the same timing it would at
+    /// `clock_speed`. Returns how many cycles ran, fewer than asked if the program halted.
+    pub fn run_for(&mut self, cycles: u64) -> u64 {
+        self.run_for_with(cycles, &mut Interpreter)
+    }
+
+    /// run_for(), but handing blocks of instructions to `runner` (the JIT) where it can take
+    /// them. Idle loop detection and observers need to see every instruction, so they turn the
+    /// runner off.
+    pub fn run_for_with<R: BlockRunner + ?Sized>(&mut self, cycles: u64, runner: &mut R) -> u64 {
+        let mut ran = 0;
+        while ran < cycles && !self.is_halted() {
+            if self.frame_cycles_left == 0 {
+                self.frame_cycles_left = self.next_frame_share();
+                if self.frame_cycles_left == 0 {
+                    // slower than one instruction a frame
+                    self.tick_timers();
+                    continue;
+                }
+            }
+
+            let mut max = (cycles - ran).min(u32::MAX as u64) as u32;
+            // blocks mustn't run past the watchdog, step() stops it exactly there
+            if let Some(limit) = self.watchdog {
+                max = max.min(limit.saturating_sub(self.instructions_retired).min(u32::MAX as u64) as u32);
+            }
+            let mut block = 0;
+            if !self.waiting_for_vblank && self.loop_detection.idle_window.is_none() && self.observers.is_empty() && self.timing == Timing::Instructions {
+                block = runner.run_block(self, max).min(max);
+            }
+            if block == 0 {
+                let cycles = self.next_instruction_cycles();
+                self.step();
+                ran += 1;
+                self.charge(cycles);
+                if self.frame_cycles_left == 0 {
+                    self.tick_timers();
+                }
+                continue;
+            }
+            self.instructions_retired += block as u64;
+            ran += block as u64;
+
+            // A block can run on past the end of the frame. Nothing in one looks at the timers,
+            // so ticking them afterwards for every frame it crossed is the same as ticking them
+            // partway through.
+            let mut left = block;
+            while left >= self.frame_cycles_left {
+                left -= self.frame_cycles_left;
+                self.frame_cycles_left = 0;
+                self.tick_timers();
+                if left == 0 {
+                    break;
+                }
+                self.frame_cycles_left = self.next_frame_share();
+            }
+            self.frame_cycles_left -= left;
+        }
+        ran
+    }
+
+    /// Execute `n` instructions back to back and nothing else: no frames, no timers, no block
+    /// runner. It's the interpreter's inner loop on its own, so changes to it can be measured
+    /// (see benches/interpreter.rs). A CPU waiting for the display never gets released, since
+    /// that takes a timer tick. Returns how many ran, fewer than `n` if the program halted.
+    pub fn run_batch(&mut self, n: u64) -> u64 {
+        for ran in 0..n {
+            if self.is_halted() {
+                return ran;
+            }
+            self.step();
+        }
+        n
+    }
+
+    /// run_for() for a hardened CPU: the error it faulted on, or how many cycles ran.
+    pub fn run_checked(&mut self, cycles: u64) -> Result<u64, CpuError> {
+        let ran = self.run_for(cycles);
+        match &self.fault {
+            Some(error) => Err(error.clone()),
+            None => Ok(ran),
+        }
+    }
+
+    /// Run until the program halts, giving up after `max_cycles`. True if it halted.
+    pub fn run_until_halt(&mut self, max_cycles: u64) -> bool {
+        self.run_for(max_cycles);
+        self.is_halted()
+    }
+
+    /// The 60Hz tick. Counts both timers down towards 0, releases a CPU waiting on the display
+    /// and moves phosphor decay on a frame.
+    pub fn tick_timers(&mut self) {
+        self.timer_ticks += 1;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.waiting_for_vblank = false;
+        self.display.end_frame();
+    }
+
+    /// How many instructions have run since the CPU was made, including ones the JIT ran.
+    pub fn instructions_retired(&self) -> u64 {
+        self.instructions_retired
+    }
+
+    /// How many times the 60Hz timers have ticked since the CPU was made.
+    pub fn timer_ticks(&self) -> u64 {
+        self.timer_ticks
+    }
+
+    /// What the machine should sound like right now, for the audio backend.
+    pub fn sound(&self) -> Sound {
+        Sound {
+            playing: self.sound_timer > 0 || self.held_key.is_some(),
+            pattern: self.audio_pattern,
+            pitch: self.audio_pitch,
+            digitised: self.digitised_sound.zip(self.digitised_samples.clone()).map(|(sound, samples)| Digitised {
+                sample_rate: sound.sample_rate,
+                samples,
+                looping: sound.looping,
+            }),
+        }
+    }
+
+    /// Executes a single instruction
+    /// 1. Read u16 opcode from values in memory (2 u8 values, the high byte and low byte)
+    /// 2. Decodes instructions
+    /// 3. Matches decoded instructions to known opcodes
+    /// 4. dispatches execution of the operation to a specific function
+    ///
+    /// Does nothing while halted or waiting for a vertical blank.
+    pub fn step(&mut self) {
+        if self.is_halted() || self.waiting_for_vblank {
+            return;
+        }
+        // otherwise running off the end reads as 0000 and halts like the program exited
+        if self.hardened && self.position_in_memory + 1 >= self.memory.len() {
+            self.raise(CpuError::PcOutOfBounds { pc: self.position_in_memory });
+            return;
+        }
+        // running code counts as reading it, for the strict memory check
+        if self.memory_protection.uninitialized_reads {
+            let pc = self.position_in_memory;
+            if let Some(addr) = (pc..pc + 2).find(|&addr| !self.initialized.get(addr).copied().unwrap_or(true)) {
+                self.raise(CpuError::UninitializedRead { pc, addr });
+                return;
+            }
+        }
+
+        let pc = self.position_in_memory;
+        if self.watchdog.is_some_and(|limit| self.instructions_retired >= limit) {
+            self.fault = Some(CpuError::Watchdog { pc, cycles: self.instructions_retired });
+            self.halt(HaltReason::Fault);
+            return;
+        }
+        let observed = !self.observers.is_empty();
+        if observed {
+            self.notify(|observer, cpu| observer.before_instruction(cpu, pc));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(pc = %format_args!("{:03X}", pc), opcode = %format_args!("{:04X}", self.read_opcode()), "instruction");
+
+        // Normally the opcode is decoded into an Instruction (or found already decoded) and
+        // matched on. With the dispatch-table feature the raw opcode is looked up in a table
+        // of function pointers instead.
+        #[cfg(not(feature = "dispatch-table"))]
+        let instruction = self.fetch();
+        #[cfg(feature = "dispatch-table")]
+        let opcode = self.read_opcode();
+
+        // we've read and loaded the instruction from memory; point to next instruction
+        // Increment in twos because when we create the opcodes
+        // we combine 2 values from memory (whatever values we want to add together for example)
+        self.position_in_memory += 2;
+        self.instructions_retired += 1;
+
+        #[cfg(not(feature = "dispatch-table"))]
+        self.execute(instruction);
+        #[cfg(feature = "dispatch-table")]
+        dispatch::execute(self, opcode);
+
+        if let Some(window) = self.loop_detection.idle_window {
+            self.check_idle(window);
+        }
+        if observed {
+            self.notify(|observer, cpu| observer.after_instruction(cpu, pc));
+        }
+    }
+
+    // Run a decoded instruction
+    #[cfg(not(feature = "dispatch-table"))]
+    fn execute(&mut self, instruction: Instruction) {
+        use Instruction::*;
+
+        let reg = |n: u8| self.registers[n as usize];
+
+        match instruction {
+            Halt => self.halt(HaltReason::Exit), // terminate execution when opcode 0x0000 is encountered
+            MegaOff => self.display.update_mega_screen(|screen| screen.set_enabled(false)),
+            MegaOn => self.display.update_mega_screen(|screen| screen.set_enabled(true)),
+            ScrollDown(n) => self.display.scroll_down(n as usize),
+            ScrollUp(n) => self.display.scroll_up(n as usize),
+            Clear => self.display.clear(),
+            CycleBackground => self.display.update_color_board(ColorBoard::cycle_background),
+            LoadHugeIndex(nnnnnn) => {
+                self.index_register = nnnnnn;
+                // skip over the rest of the address
+                self.position_in_memory += 2;
+            }
+            LoadPalette(n) => self.load_palette(n),
+            SpriteWidth(n) => self.display.update_mega_screen(|screen| screen.set_sprite_width(n)),
+            SpriteHeight(n) => self.display.update_mega_screen(|screen| screen.set_sprite_height(n)),
+            ScreenAlpha(n) => self.display.update_mega_screen(|screen| screen.set_alpha(n)),
+            PlaySound(n) => self.play_digitised_sound(n != 1),
+            StopSound => self.digitised_sound = None,
+            BlendMode(n) => self.display.update_mega_screen(|screen| screen.set_blend_mode(n)),
+            CollisionColor(n) => self.display.update_mega_screen(|screen| screen.set_collision_color(n)),
+            Return => self.ret(),
+            ScrollRight => self.display.scroll_right(),
+            ScrollLeft => self.display.scroll_left(),
+            Exit => self.halt(HaltReason::Exit), // EXIT the interpreter
+            LowRes => self.display.set_hires(false),
+            HighRes => self.display.set_hires(true),
+            Jump(nnn) => self.jump(nnn),
+            Call(nnn) => self.call(nnn),
+            SkipIfEqual(x, kk) => self.skip_if(reg(x) == kk),
+            SkipIfNotEqual(x, kk) => self.skip_if(reg(x) != kk),
+            SkipIfRegistersEqual(x, y) => self.skip_if(reg(x) == reg(y)),
+            AddNibbles(x, y) => self.registers[x as usize] = Self::add_nibbles(reg(x), reg(y)),
+            StoreRange(x, y) => self.store_register_range(x, y),
+            LoadRange(x, y) => self.load_register_range(x, y),
+            LoadByte(x, kk) => self.registers[x as usize] = kk,
+            AddByte(x, kk) => self.registers[x as usize] = reg(x).wrapping_add(kk), // no carry flag for this one
+            Move(x, y) => self.registers[x as usize] = reg(y),
+            Or(x, y) => self.registers[x as usize] = reg(x) | reg(y),
+            And(x, y) => self.registers[x as usize] = reg(x) & reg(y),
+            Xor(x, y) => self.registers[x as usize] = reg(x) ^ reg(y),
+            Add(x, y) => self.add_xy(x, y),
+            Sub(x, y) => self.sub_xy(x, reg(x), reg(y)),
+            ShiftRight(x, y) => self.shr_xy(x, y),
+            SubReversed(x, y) => self.sub_xy(x, reg(y), reg(x)),
+            ShiftLeft(x, y) => self.shl_xy(x, y),
+            SkipIfRegistersDiffer(x, y) => self.skip_if(reg(x) != reg(y)),
+            LoadIndex(nnn) => self.index_register = nnn as u32,
+            JumpWithOffset(x, nnn) => self.jump_with_offset(x, nnn),
+            ColorBlocks(x, y) => self.color_blocks(x, y),
+            ColorStrip(x, y, n) => self.color_strip(x, y, n),
+            Random(x, kk) => self.registers[x as usize] = self.random_byte() & kk,
+            Draw(x, y, n) => self.draw(x, y, n),
+            SkipIfKey(x) => self.skip_if(self.keypad[(reg(x) & 0xF) as usize]),
+            SkipIfNotKey(x) => self.skip_if(!self.keypad[(reg(x) & 0xF) as usize]),
+            SkipIfKey2(x) => self.skip_if(self.keypad2[(reg(x) & 0xF) as usize]),
+            SkipIfNotKey2(x) => self.skip_if(!self.keypad2[(reg(x) & 0xF) as usize]),
+            LoadLongIndex(nnnn) => {
+                self.index_register = nnnn as u32;
+                // skip over the address
+                self.position_in_memory += 2;
+            }
+            SelectPlanes(n) => self.display.select_planes(n),
+            LoadAudioPattern => self.load_audio_pattern(),
+            ReadDelay(x) => self.registers[x as usize] = self.delay_timer,
+            WaitForKey(x) => self.wait_for_key(x),
+            SetDelay(x) => self.delay_timer = reg(x),
+            SetSound(x) => self.sound_timer = reg(x),
+            AddIndex(x) => self.add_register_to_index(x),
+            LoadFont(x) => self.index_register = (SMALL_FONT_ADDR + (reg(x) & 0xF) as usize * 5) as u32,
+            LoadBigFont(x) => self.index_register = (BIG_FONT_ADDR + (reg(x) & 0xF) as usize * 10) as u32,
+            StoreBcd(x) => self.store_bcd(reg(x)),
+            SetPitch(x) => self.audio_pitch = reg(x),
+            StoreRegisters(x) => self.store_registers(x),
+            LoadRegisters(x) => self.load_registers(x),
+            StoreFlags(x) => self.store_rpl_flags(x),
+            LoadFlags(x) => self.load_rpl_flags(x),
+            OutputPort(x) => self.port_output = reg(x),
+            InputPort(x) => self.registers[x as usize] = self.port_input,
+            Unknown(opcode) => self.unknown_opcode(opcode),
+        }
+    }
+
+    // Counts instructions that left the machine exactly as it was, halting once there's a window's worth
+    fn check_idle(&mut self, window: u64) {
+        let snapshot = IdleSnapshot {
+            registers: self.registers,
+            index_register: self.index_register,
+            stack_pointer: self.stack_pointer,
+            memory_writes: self.memory_writes,
+            display_changes: self.display.changes(),
+        };
+
+        if snapshot == self.idle_snapshot {
+            self.idle_cycles += 1;
+            if self.idle_cycles >= window {
+                self.halt(HaltReason::InfiniteLoop);
+            }
+        } else {
+            self.idle_snapshot = snapshot;
+            self.idle_cycles = 0;
+        }
+    }
+
+    // ADD_XY: Add y to x register
+    fn add_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+
+        // overflowing add returns a tuple, the value and boolean letting us know if overflow was detected
+        let (val, overflow) = arg1.overflowing_add(arg2);
+        self.registers[x as usize] = val;
+
+        // within CHIP-8, the last register is a 'carry flag'. When set it indiciates
+        // that an operation has overflowed the u8 register size.
+        if overflow {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // Which register the shift opcodes read from depends on the interpreter.
+    // The VIP shifts VY and stores the result in VX, CHIP-48 just shifts VX in place.
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_vx_in_place {
+            self.registers[x as usize]
+        } else {
+            self.registers[y as usize]
+        }
+    }
+
+    // SHR_XY: opcode 0x8xy6, shift right by one. VF gets the bit that fell off the end.
+    fn shr_xy(&mut self, x: u8, y: u8) {
+        let val = self.shift_source(x, y);
+        self.registers[x as usize] = val >> 1;
+        // set the flag last, so VF as the destination still ends up holding the flag
+        self.registers[0xF] = val & 0x1;
+    }
+
+    // SHL_XY: opcode 0x8xyE, shift left by one. VF gets the most significant bit that was shifted out.
+    fn shl_xy(&mut self, x: u8, y: u8) {
+        let val = self.shift_source(x, y);
+        self.registers[x as usize] = val << 1;
+        self.registers[0xF] = (val & 0x80) >> 7;
+    }
+
+    // JUMP: opcode 0x1nnn, no stack involved, just move position_in_memory
+    fn jump(&mut self, addr: u16) {
+        // position_in_memory has already moved past this instruction
+        if self.loop_detection.jump_to_self && addr as usize == self.position_in_memory - 2 {
+            self.halt(HaltReason::InfiniteLoop);
+        }
+        self.position_in_memory = addr as usize;
+    }
+
+    // JUMP_OFFSET: opcode 0xBnnn, jump to nnn plus a register.
+    // The VIP always adds V0. CHIP-48 accidentally read it as BXNN, adding VX
+    // (where X happens to be the top nibble of the address) and some ROMs rely on that.
+    fn jump_with_offset(&mut self, x: u8, nnn: u16) {
+        let offset = if self.quirks.jump_offset_uses_vx {
+            self.registers[x as usize]
+        } else {
+            self.registers[0]
+        };
+        self.jump(nnn + offset as u16);
+    }
+
+    // SKIP: the conditional opcodes (3xkk, 4xkk, 5xy0, 9xy0, Ex9E, ExA1) all just hop over the next instruction
+    // On XO-CHIP the instruction being skipped might be the 4 byte F000 NNNN, so hop over all of it.
+    // The same goes for Mega-Chip's 01NN NNNN.
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            let long = match self.variant {
+                Variant::XoChip => self.read_opcode() == 0xF000,
+                Variant::MegaChip => self.read_opcode() & 0xFF00 == 0x0100,
+                _ => false,
+            };
+            self.position_in_memory += if long { 4 } else { 2 };
+        }
+    }
+
+    // LONG_I: opcode 0xF000 0xNNNN (XO-CHIP), the address is the whole next word so it can reach all 64K.
+    #[cfg(feature = "dispatch-table")]
+    fn load_long_index(&mut self) {
+        self.index_register = self.read_opcode() as u32;
+        self.position_in_memory += 2;
+    }
+
+    // LDHI: opcode 0x01nn 0xNNNN (Mega-Chip), nn is the top 8 bits of a 24 bit address
+    #[cfg(feature = "dispatch-table")]
+    fn load_huge_index(&mut self, high: u8) {
+        self.index_register = (high as u32) << 16 | self.read_opcode() as u32;
+        self.position_in_memory += 2;
+    }
+
+    // ADD_I: opcode 0xFx1E. With the index_overflow_sets_vf quirk VF says whether I went past
+    // 0xFFF, worked out before VX gets overwritten (when X is F)
+    fn add_register_to_index(&mut self, x: u8) {
+        let vx = self.registers[x as usize] as u32;
+        if self.quirks.index_overflow_sets_vf {
+            self.registers[0xF] = (self.index_register + vx > 0xFFF) as u8;
+        }
+        self.add_to_index(vx);
+    }
+
+    // I += n, wrapping round at the top of what I can hold
+    fn add_to_index(&mut self, n: u32) {
+        self.index_register = self.index_register.wrapping_add(n) & self.variant.index_mask();
+    }
+
+    // LDPAL: opcode 0x02nn (Mega-Chip), nn colours from I into the palette (see mega_chip.rs)
+    fn load_palette(&mut self, n: u8) {
+        let start = self.index_register as usize;
+        let len = n as usize * 4;
+        if !self.check_read(start, len) {
+            return;
+        }
+        let colors = &self.memory[start..start + len];
+        self.display.update_mega_screen(|screen| screen.load_palette(colors));
+    }
+
+    // DIGISND: opcode 0x060n (Mega-Chip), start the digitised sound at I
+    fn play_digitised_sound(&mut self, looping: bool) {
+        let start = self.index_register as usize;
+        if !self.check_read(start, SOUND_HEADER_LEN) {
+            return;
+        }
+        let mut header = [0; SOUND_HEADER_LEN];
+        header.copy_from_slice(&self.memory[start..start + SOUND_HEADER_LEN]);
+        self.digitised_sound = Some(DigitisedSound::parse(start, &header, looping));
+        self.copy_digitised_samples();
+    }
+
+    // Copy the digitised sound's samples out of memory for sound() to hand out, once rather
+    // than every frame as a sound can be megabytes
+    pub(crate) fn copy_digitised_samples(&mut self) {
+        self.digitised_samples = self.digitised_sound.map(|sound| {
+            // a length running off the end of memory plays what there is
+            let end = sound.start.saturating_add(sound.len).min(self.memory.len());
+            self.memory[sound.start.min(end)..end].into()
+        });
+    }
+
+    // AUDIO: opcode 0xF002 (XO-CHIP), copy the 16 bytes at I into the audio pattern buffer
+    fn load_audio_pattern(&mut self) {
+        let start = self.index_register as usize;
+        if !self.check_read(start, PATTERN_LEN) {
+            return;
+        }
+        let mut pattern = [0; PATTERN_LEN];
+        pattern.copy_from_slice(&self.memory[start..start + PATTERN_LEN]);
+        self.audio_pattern = Some(pattern);
+    }
+
+    // COLOR: opcode 0xBxy0 (CHIP-8X), colour blocks of zones, Vx and Vx+1 say which (see color_board.rs)
+    fn color_blocks(&mut self, x: u8, y: u8) {
+        let horizontal = self.registers[x as usize];
+        let vertical = self.registers[(x as usize + 1) & 0xF];
+        let color = self.registers[y as usize];
+        self.display.update_color_board(|board| board.color_blocks(horizontal, vertical, color));
+    }
+
+    // COLOR: opcode 0xBxyn (CHIP-8X), colour an 8 pixel wide strip n rows high at (Vx, Vx+1)
+    fn color_strip(&mut self, x: u8, y: u8, n: u8) {
+        let px = self.registers[x as usize] as usize;
+        let py = self.registers[(x as usize + 1) & 0xF] as usize;
+        let color = self.registers[y as usize];
+        self.display.update_color_board(|board| board.color_strip(px, py, n as usize, color));
+    }
+
+    // Register ranges for 5xy2/5xy3 go from Vx to Vy, backwards if x > y
+    // Yields (offset from I, register number) pairs
+    fn register_range(x: u8, y: u8) -> impl Iterator<Item = (usize, usize)> {
+        let (x, y) = (x as usize, y as usize);
+        (0..=x.abs_diff(y)).map(move |offset| (offset, if x <= y { x + offset } else { x - offset }))
+    }
+
+    // SAVE_RANGE: opcode 0x5xy2 (XO-CHIP), write Vx..Vy to memory at I. I isn't changed.
+    fn store_register_range(&mut self, x: u8, y: u8) {
+        let start = self.index_register as usize;
+        if !self.check_write(start, x.abs_diff(y) as usize + 1) {
+            return;
+        }
+        for (offset, reg) in Self::register_range(x, y) {
+            self.write_memory(start + offset, self.registers[reg]);
+        }
+    }
+
+    // LOAD_RANGE: opcode 0x5xy3 (XO-CHIP), read memory at I into Vx..Vy. I isn't changed.
+    fn load_register_range(&mut self, x: u8, y: u8) {
+        let start = self.index_register as usize;
+        if !self.check_read(start, x.abs_diff(y) as usize + 1) {
+            return;
+        }
+        for (offset, reg) in Self::register_range(x, y) {
+            self.registers[reg] = self.memory[start + offset];
+        }
+    }
+
+    // SUB: opcodes 0x8xy5 (Vx - Vy) and 0x8xy7 (Vy - Vx), result always goes in Vx.
+    // VF is set to 1 when there was NO borrow, the opposite of what you'd guess.
+    fn sub_xy(&mut self, x: u8, lhs: u8, rhs: u8) {
+        let (val, borrow) = lhs.overflowing_sub(rhs);
+        self.registers[x as usize] = val;
+        self.registers[0xF] = !borrow as u8;
+    }
+
+    // ADD_NIBBLES: opcode 0x5xy1 (CHIP-8X), add Vy to Vx a nibble at a time, each nibble
+    // wrapping round at 8 rather than carrying. No flag either. Handy for moving packed
+    // coordinates (x in one nibble, y in the other) around the colour board's 8x8 zones
+    fn add_nibbles(vx: u8, vy: u8) -> u8 {
+        let high = ((vx >> 4) + (vy >> 4)) & 0x7;
+        let low = ((vx & 0xF) + (vy & 0xF)) & 0x7;
+        high << 4 | low
+    }
+
+    // RND: opcode 0xCxkk. xorshift, plenty random enough for games and it means no dependencies
+    fn random_byte(&mut self) -> u8 {
+        let mut s = self.rng_state;
+        s ^= s << 13;
+        s ^= s >> 7;
+        s ^= s << 17;
+        self.rng_state = s;
+        (s >> 32) as u8
+    }
+
+    // WAIT_KEY: opcode 0xFx0A, block until a key is pressed and put it in Vx.
+    // Blocking is done by stepping position_in_memory back so this instruction runs again.
+    // The VIP waited for the key to be let go as well, beeping until it was, so a key held
+    // down doesn't get read twice by the next FX0A.
+    fn wait_for_key(&mut self, x: u8) {
+        if self.quirks.wait_for_key_release {
+            match self.held_key {
+                Some(key) if !self.keypad[key as usize] => {
+                    self.registers[x as usize] = key;
+                    self.held_key = None;
+                    return;
+                }
+                Some(_) => {}
+                None => self.held_key = self.keypad.iter().position(|&pressed| pressed).map(|key| key as u8),
+            }
+            self.position_in_memory -= 2;
+            if self.held_key.is_none() && !self.observers.is_empty() {
+                self.notify(|observer, cpu| observer.key_wait(cpu, x));
+            }
+            return;
+        }
+        match self.keypad.iter().position(|&pressed| pressed) {
+            Some(key) => self.registers[x as usize] = key as u8,
+            None => {
+                self.position_in_memory -= 2;
+                if !self.observers.is_empty() {
+                    self.notify(|observer, cpu| observer.key_wait(cpu, x));
+                }
+            }
+        }
+    }
+
+    // BCD: opcode 0xFx33, store the decimal digits of Vx at I, I+1 and I+2 (hundreds, tens, ones)
+    fn store_bcd(&mut self, vx: u8) {
+        let i = self.index_register as usize;
+        if !self.check_write(i, 3) {
+            return;
+        }
+        self.write_memory(i, vx / 100);
+        self.write_memory(i + 1, (vx / 10) % 10);
+        self.write_memory(i + 2, vx % 10);
+    }
+
+    // DRAW: opcode 0xDxyn, draw the n byte sprite at I to the screen at (Vx, Vy).
+    // VF is set to 1 if any pixels were erased, 0 otherwise.
+    // On SUPER-CHIP n = 0 draws a big sprite instead: 16x16 in hi-res, 8x16 in lo-res.
+    // XO-CHIP always draws 16x16, and with both planes selected reads a second sprite for plane 2.
+    // In Mega-Chip mode it's a blit instead, see mega_chip.rs.
+    fn draw(&mut self, x: u8, y: u8, n: u8) {
+        let vx = self.registers[x as usize] as usize;
+        let vy = self.registers[y as usize] as usize;
+        let drawn = if self.display.is_mega_mode() { self.blit(vx, vy, n) } else { self.draw_on_planes(vx, vy, n) };
+        let Some(collision) = drawn else { return };
+        self.registers[0xF] = collision as u8;
+        if !self.observers.is_empty() {
+            self.notify(|observer, cpu| observer.draw(cpu, vx, vy, collision));
+        }
+
+        // the VIP only drew during the vertical blank interrupt, so a ROM can't draw
+        // more than once a frame. Stall until the next tick to get the same pacing.
+        if self.quirks.display_wait {
+            self.waiting_for_vblank = true;
+        }
+    }
+
+    // The usual XOR drawing, returning whether there was a collision (None if the sprite
+    // wasn't all in memory)
+    fn draw_on_planes(&mut self, vx: usize, vy: usize, n: u8) -> Option<bool> {
+        let start = self.index_register as usize;
+        let wrap = self.quirks.wrap_sprites;
+        let planes = self.display.selected_planes().count_ones().max(1) as usize;
+
+        let wide = n == 0
+            && (self.variant == Variant::XoChip
+                || (self.variant.has_superchip_opcodes() && self.display.is_hires()));
+        let rows = if n == 0 && self.variant.has_superchip_opcodes() { 16 } else { n as usize };
+        let len = if wide { 32 * planes } else { rows * planes };
+        if !self.check_read(start, len) {
+            return None;
+        }
+        let sprite = &self.memory[start..start + len];
+        Some(if wide {
+            self.display.draw_wide_sprite(vx, vy, sprite, wrap)
+        } else {
+            self.display.draw_sprite(vx, vy, sprite, wrap)
+        })
+    }
+
+    // DXYN in Mega-Chip mode: a sprite_size() sprite of colours, or 1 bit text when I's pointing
+    // at the font (N rows, or SUPER-CHIP's 16x16 for N = 0)
+    fn blit(&mut self, vx: usize, vy: usize, n: u8) -> Option<bool> {
+        let start = self.index_register as usize;
+        let wrap = self.quirks.wrap_sprites;
+        if start < PROGRAM_START {
+            let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+            let len = rows * bytes_per_row;
+            if !self.check_read(start, len) {
+                return None;
+            }
+            return Some(self.display.blit_text(vx, vy, &self.memory[start..start + len], bytes_per_row, wrap));
+        }
+        let (width, height) = self.display.mega_screen().map_or((0, 0), MegaScreen::sprite_size);
+        let len = width * height;
+        if !self.check_read(start, len) {
+            return None;
+        }
+        Some(self.display.blit_sprite(vx, vy, &self.memory[start..start + len], wrap))
+    }
+
+    // STORE: opcode 0xFx55, write V0 through Vx (inclusive) to memory starting at I.
+    fn store_registers(&mut self, x: u8) {
+        let start = self.index_register as usize;
+        if !self.check_write(start, x as usize + 1) {
+            return;
+        }
+        for n in 0..=x as usize {
+            self.write_memory(start + n, self.registers[n]);
+        }
+        self.bump_index_after_load_store(x);
+    }
+
+    // LOAD: opcode 0xFx65, read memory starting at I into V0 through Vx (inclusive).
+    fn load_registers(&mut self, x: u8) {
+        let start = self.index_register as usize;
+        if !self.check_read(start, x as usize + 1) {
+            return;
+        }
+        for n in 0..=x as usize {
+            self.registers[n] = self.memory[start + n];
+        }
+        self.bump_index_after_load_store(x);
+    }
+
+    // SAVE_FLAGS: opcode 0xFx75 (SUPER-CHIP), copy V0 through Vx into the RPL user flags
+    fn store_rpl_flags(&mut self, x: u8) {
+        let n = x as usize + 1;
+        self.rpl_flags[..n].copy_from_slice(&self.registers[..n]);
+        self.rpl_flags_dirty = true;
+    }
+
+    // LOAD_FLAGS: opcode 0xFx85 (SUPER-CHIP), copy the RPL user flags back into V0 through Vx
+    fn load_rpl_flags(&mut self, x: u8) {
+        let n = x as usize + 1;
+        self.registers[..n].copy_from_slice(&self.rpl_flags[..n]);
+    }
+
+    // The VIP walked I along as it copied, so it's left pointing just past the last register.
+    // CHIP-48 stopped one short, on the last register, and SUPER-CHIP used a temporary and left
+    // I alone.
+    fn bump_index_after_load_store(&mut self, x: u8) {
+        if self.quirks.load_store_increments_index {
+            self.add_to_index(x as u32 + !self.quirks.load_store_index_short as u32);
+        }
+    }
+
+    // CALL: opcode 0x2nnn sets position_in_memory to nnn, the address of the function
+    // Each CALL opcode adds an address to the stack by incrementing the stack pointer
+    // and writing nnn to that position in the stack.
+
+    fn call(&mut self, addr: u16) {
+        let sp = self.stack_pointer;   
+        if sp >= self.stack.len() {
+            let pc = self.position_in_memory - 2;
+            self.raise(CpuError::StackOverflow { pc });
+            return;
+        }
+        let stack = &mut self.stack;
+
+        // add current position in memory to stack
+        // memory address is two bytes higher than calling location as it is incremented within the body of run()
+        stack[sp] = self.position_in_memory as u16;
+        self.call_targets[sp] = addr;
+        self.stack_pointer += 1;
+
+        // modify position in memory to affect jumping to that address
+        self.position_in_memory = addr as usize;
+    }
+
+    // RETURN: opcode 0x00EE sets position_in_memory to the memory address of the previous CALL opcode
+    // Each RETURN opcode removes the top address by decrementing the stack pointer.
+    fn ret(&mut self) {
+        if self.stack_pointer == 0 {
+            let pc = self.position_in_memory - 2;
+            self.raise(CpuError::StackUnderflow { pc });
+            return;
+        }
+
+        self.stack_pointer -= 1;
+        // jump to position in memory where an earlier call was made
+        let call_addr = self.stack[self.stack_pointer];
+        self.position_in_memory = call_addr as usize;
+    }
+}
+
+impl Default for Cpu {
+    /// A plain CHIP-8, the same as Cpu::with_variant(Variant::Chip8).
+    fn default() -> Self {
+        Cpu::with_variant(Variant::Chip8)
+    }
+}
+
+// Memory, the display and the observers are far too much to print, so just the state that
+// describes where the program's got to
+impl fmt::Debug for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let depth = self.stack_pointer.min(self.stack.len());
+        f.debug_struct("Cpu")
+            .field("variant", &self.variant)
+            .field("registers", &self.registers)
+            .field("position_in_memory", &self.position_in_memory)
+            .field("index_register", &self.index_register)
+            .field("stack", &&self.stack[..depth])
+            .field("stack_pointer", &self.stack_pointer)
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("memory_size", &self.memory.len())
+            .field("quirks", &self.quirks)
+            .field("clock_speed", &self.clock_speed)
+            .field("halt_reason", &self.halt_reason)
+            .field("fault", &self.fault)
+            .finish_non_exhaustive()
+    }
+}
+
+// See dump() for the format
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "PC {:04X}  I {:04X}  SP {}  DT {}  ST {}",
+            self.position_in_memory, self.index_register, self.stack_pointer, self.delay_timer, self.sound_timer
+        )?;
+        for (n, v) in self.registers.iter().enumerate() {
+            let gap = if n == 0 { "" } else { "  " };
+            write!(f, "{}V{:X} {:02X}", gap, n, v)?;
+        }
+        writeln!(f)?;
+        write!(f, "stack {}/{}", self.stack_pointer, self.stack.len())?;
+        // a deep stack (set_stack_depth()) only shows its innermost 16
+        let depth = self.stack_pointer.min(self.stack.len());
+        let shown = depth.saturating_sub(16);
+        if shown > 0 {
+            write!(f, "  ...")?;
+        }
+        for return_address in &self.stack[shown..depth] {
+            write!(f, "  {:04X}", return_address)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CPU with `quirks`, V0 onwards set to `registers` and `program` loaded
+    fn machine(quirks: Quirks, registers: &[u8], program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(quirks);
+        cpu.registers[..registers.len()].copy_from_slice(registers);
+        cpu.load_rom(program).unwrap();
+        cpu
+    }
+
+    // and run it up to the 0000 after the program
+    fn run(quirks: Quirks, registers: &[u8], program: &[u8]) -> Cpu {
+        let mut cpu = machine(quirks, registers, program);
+        while !cpu.is_halted() {
+            cpu.run_frame();
+        }
+        cpu
+    }
+
+    #[test]
+    fn shift_in_place() {
+        // SHR V0, V1 with V0 = 4 and V1 = 3
+        let program = [0x80, 0x16];
+        let cpu = run(Quirks { shift_vx_in_place: true, ..Quirks::cosmac_vip() }, &[4, 3], &program);
+        assert_eq!((cpu.registers[0], cpu.registers[0xF]), (2, 0));
+        let cpu = run(Quirks { shift_vx_in_place: false, ..Quirks::cosmac_vip() }, &[4, 3], &program);
+        assert_eq!((cpu.registers[0], cpu.registers[0xF]), (1, 1));
+    }
+
+    #[test]
+    fn load_store_moves_the_index() {
+        // I = 0x300, store V0-V2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+        let cpu = run(Quirks { load_store_increments_index: true, ..Quirks::cosmac_vip() }, &[1, 2, 3], &program);
+        assert_eq!(&cpu.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(cpu.index_register, 0x303);
+        let cpu = run(Quirks::chip48(), &[1, 2, 3], &program);
+        assert_eq!(cpu.index_register, 0x302);
+        let cpu = run(Quirks { load_store_increments_index: false, ..Quirks::cosmac_vip() }, &[1, 2, 3], &program);
+        assert_eq!(cpu.index_register, 0x300);
+    }
+
+    #[test]
+    fn jump_offset_uses_vx() {
+        // JP V0, 0x120 with V0 = 4 and V1 = 0x10, stopping on the 0000 it lands on
+        let program = [0xB1, 0x20];
+        let cpu = run(Quirks { jump_offset_uses_vx: true, ..Quirks::cosmac_vip() }, &[4, 0x10], &program);
+        assert_eq!(cpu.position_in_memory, 0x130 + 2);
+        let cpu = run(Quirks { jump_offset_uses_vx: false, ..Quirks::cosmac_vip() }, &[4, 0x10], &program);
+        assert_eq!(cpu.position_in_memory, 0x124 + 2);
+    }
+
+    #[test]
+    fn display_wait() {
+        // draw the one row sprite at 0x206, then SHR V0, V0 with V0 = 4
+        let program = [0xA2, 0x06, 0xD1, 0x11, 0x80, 0x06, 0xFF];
+        let mut cpu = machine(Quirks { display_wait: true, ..Quirks::cosmac_vip() }, &[4], &program);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!((cpu.position_in_memory, cpu.registers[0]), (0x204, 4));
+        cpu.tick_timers();
+        cpu.step();
+        assert_eq!(cpu.registers[0], 2);
+
+        let mut cpu = machine(Quirks { display_wait: false, ..Quirks::cosmac_vip() }, &[4], &program);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers[0], 2);
+    }
+
+    #[test]
+    fn sprite_wrap() {
+        // a row of 8 pixels at (62, 0), two on screen and six off the right edge
+        let program = [0xA2, 0x06, 0xD0, 0x11, 0x00, 0x00, 0xFF];
+        let cpu = run(Quirks { wrap_sprites: true, ..Quirks::cosmac_vip() }, &[62, 0], &program);
+        assert!(cpu.display.pixel(63, 0) && cpu.display.pixel(0, 0));
+        let cpu = run(Quirks { wrap_sprites: false, ..Quirks::cosmac_vip() }, &[62, 0], &program);
+        assert!(cpu.display.pixel(63, 0) && !cpu.display.pixel(0, 0));
+    }
+
+    #[test]
+    fn index_overflow_sets_vf() {
+        // VF = 5, I = 0xFFF, V0 = 2, ADD I, V0
+        let program = [0x6F, 0x05, 0xAF, 0xFF, 0x60, 0x02, 0xF0, 0x1E];
+        let cpu = run(Quirks { index_overflow_sets_vf: true, ..Quirks::cosmac_vip() }, &[], &program);
+        assert_eq!((cpu.index_register, cpu.registers[0xF]), (0x1001, 1));
+        let cpu = run(Quirks { index_overflow_sets_vf: false, ..Quirks::cosmac_vip() }, &[], &program);
+        assert_eq!((cpu.index_register, cpu.registers[0xF]), (0x1001, 5));
+        // and it's cleared when I doesn't go past
+        let cpu = run(Quirks { index_overflow_sets_vf: true, ..Quirks::cosmac_vip() }, &[], &[0x6F, 0x05, 0xAF, 0x00, 0xF0, 0x1E]);
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
+    #[test]
+    fn wait_for_key_release() {
+        // LD V0, K
+        let program = [0xF0, 0x0A];
+        let mut cpu = Cpu::new(Quirks { wait_for_key_release: true, ..Quirks::cosmac_vip() });
+        cpu.load_binary(&program).unwrap();
+        cpu.step();
+        assert_eq!(cpu.position_in_memory, 0x200);
+        cpu.set_key(7, true);
+        cpu.step();
+        assert_eq!(cpu.position_in_memory, 0x200);
+        assert!(cpu.sound().playing, "beeps while the key's held");
+        cpu.set_key(7, false);
+        cpu.step();
+        assert_eq!((cpu.position_in_memory, cpu.registers[0]), (0x202, 7));
+        assert!(!cpu.sound().playing);
+
+        let mut cpu = Cpu::new(Quirks { wait_for_key_release: false, ..Quirks::cosmac_vip() });
+        cpu.load_binary(&program).unwrap();
+        cpu.set_key(7, true);
+        cpu.step();
+        assert_eq!((cpu.position_in_memory, cpu.registers[0]), (0x202, 7));
+    }
+
+    #[test]
+    fn digitised_sound_is_copied_for_the_audio_backend() {
+        let mut cpu = Cpu::with_variant(Variant::MegaChip);
+        cpu.load_binary(&[
+            0x01, 0x00, 0x02, 0x0A, // I = 0x20A
+            0x06, 0x00,             // play it, looping
+            0x12, 0x06,             // and wait
+            0x00, 0x00,
+            0x1F, 0x40, 0x00, 0x00, 0x03, 0x00, // 8000Hz, 3 samples
+            0x10, 0x80, 0xF0,
+        ])
+        .unwrap();
+        assert_eq!(cpu.sound().digitised, None);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        let digitised = cpu.sound().digitised.unwrap();
+        assert_eq!((digitised.sample_rate, &*digitised.samples, digitised.looping), (8000, &[0x10, 0x80, 0xF0][..], true));
+        // the same copy every frame, so it isn't started again
+        assert_eq!(cpu.sound(), cpu.sound());
+
+        cpu.memory[0x204] = 0x07;
+        cpu.position_in_memory = 0x204;
+        cpu.flush_decoded();
+        cpu.step();
+        assert_eq!(cpu.sound().digitised, None);
+    }
+
+    // 5XY1 is only an instruction on XO-CHIP
+    const UNKNOWN: [u8; 4] = [0x50, 0x01, 0x61, 0x07];
+
+    #[test]
+    fn unknown_opcodes_halt_by_default() {
+        // not hardened, it used to panic
+        let mut cpu = machine(Quirks::default(), &[], &UNKNOWN);
+        cpu.step();
+        assert_eq!(cpu.halt_reason(), Some(HaltReason::Fault));
+        assert_eq!(cpu.fault(), Some(&CpuError::UnknownOpcode { pc: 0x200, opcode: 0x5001 }));
+    }
+
+    #[test]
+    fn unknown_opcodes_skipped() {
+        let mut cpu = machine(Quirks::default(), &[], &UNKNOWN);
+        cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Skip);
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.registers[1], 7);
+    }
+
+    #[test]
+    fn unknown_opcode_handlers() {
+        let mut cpu = machine(Quirks::default(), &[], &UNKNOWN);
+        cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Handler(Box::new(|cpu, pc, opcode| {
+            cpu.registers[2] = (pc >> 8) as u8;
+            opcode == 0x5001
+        })));
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.registers[2], 2);
+
+        // a handler saying no halts the same as Error
+        let mut cpu = machine(Quirks::default(), &[], &UNKNOWN);
+        cpu.set_unknown_opcode_policy(UnknownOpcodePolicy::Handler(Box::new(|_, _, _| false)));
+        cpu.step();
+        assert_eq!(cpu.fault(), Some(&CpuError::UnknownOpcode { pc: 0x200, opcode: 0x5001 }));
+    }
+
+    // ADD V0, 1 then jump back, forever. V0 changes, so loop detection leaves it alone
+    const COUNT_FOREVER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn watchdog_stops_on_the_limit() {
+        for hardened in [false, true] {
+            let mut cpu = machine(Quirks::default(), &[], &COUNT_FOREVER);
+            cpu.hardened = hardened;
+            cpu.watchdog = Some(101);
+            for _ in 0..20 {
+                cpu.run_frame();
+            }
+            assert_eq!(cpu.halt_reason(), Some(HaltReason::Fault));
+            assert_eq!(cpu.fault(), Some(&CpuError::Watchdog { pc: 0x202, cycles: 101 }));
+            assert_eq!((cpu.instructions_retired(), cpu.registers[0]), (101, 51));
+        }
+    }
+
+    #[test]
+    fn watchdog_counts_from_the_start() {
+        let mut cpu = machine(Quirks::default(), &[], &COUNT_FOREVER);
+        cpu.run_for(50);
+        // set late, the 50 already run count
+        cpu.watchdog = Some(60);
+        cpu.run_for(50);
+        assert_eq!(cpu.fault(), Some(&CpuError::Watchdog { pc: 0x200, cycles: 60 }));
+        assert_eq!(cpu.registers[0], 30);
+
+        let mut cpu = machine(Quirks::default(), &[], &COUNT_FOREVER);
+        cpu.run_for(1000);
+        assert!(!cpu.is_halted(), "off by default");
+    }
+}
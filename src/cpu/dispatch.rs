@@ -0,0 +1,217 @@
+// Function pointer dispatch.
+// Instead of one big match on all four nibbles, the high nibble indexes a table of 16 handlers
+// and each handler only sorts out its own family of opcodes. Whether that beats the match
+// depends on what the compiler makes of the match (often a jump table anyway), so both are
+// kept and this one is picked with the dispatch-table feature, to compare them on long runs:
+//   cargo build --release --features dispatch-table
+
+use super::Cpu;
+use crate::font::{BIG_FONT_ADDR, SMALL_FONT_ADDR};
+use crate::halt::HaltReason;
+use crate::color_board::ColorBoard;
+use crate::variant::Variant;
+
+// The pieces of an opcode, pulled apart once before dispatching
+#[derive(Clone, Copy)]
+struct Operands {
+    opcode: u16,
+    x: u8,
+    y: u8,
+    d: u8,
+    nnn: u16,
+    kk: u8,
+}
+
+type Handler = fn(&mut Cpu, Operands);
+
+// Indexed by the high nibble
+static TABLE: [Handler; 16] = [
+    system, // 0nnn
+    jump, // 1nnn
+    call, // 2nnn
+    skip_if_equal, // 3xkk
+    skip_if_not_equal, // 4xkk
+    register_compare, // 5xy_
+    load_byte, // 6xkk
+    add_byte, // 7xkk
+    arithmetic, // 8xy_
+    skip_if_registers_differ, // 9xy0
+    load_index, // Annn
+    jump_with_offset, // Bnnn
+    random, // Cxkk
+    draw, // Dxyn
+    keypad, // Ex__
+    misc, // Fx__
+];
+
+pub(super) fn execute(cpu: &mut Cpu, opcode: u16) {
+    let operands = Operands {
+        opcode,
+        x: ((opcode & 0x0F00) >> 8) as u8,
+        y: ((opcode & 0x00F0) >> 4) as u8,
+        d: (opcode & 0x000F) as u8,
+        nnn: opcode & 0x0FFF,
+        kk: (opcode & 0x00FF) as u8,
+    };
+    TABLE[(opcode >> 12) as usize](cpu, operands);
+}
+
+fn unknown(cpu: &mut Cpu, op: Operands) {
+    cpu.unknown_opcode(op.opcode);
+}
+
+fn system(cpu: &mut Cpu, op: Operands) {
+    let schip = cpu.variant.has_superchip_opcodes();
+    let xo = cpu.variant == Variant::XoChip;
+    let mega = cpu.variant == Variant::MegaChip;
+    match op.opcode {
+        0x0000 => cpu.halt(HaltReason::Exit),
+        0x0010 if mega => cpu.display.update_mega_screen(|screen| screen.set_enabled(false)),
+        0x0011 if mega => cpu.display.update_mega_screen(|screen| screen.set_enabled(true)),
+        0x00B0..=0x00BF if mega => cpu.display.scroll_up(op.d as usize),
+        0x00C0..=0x00CF if schip => cpu.display.scroll_down(op.d as usize),
+        0x00D0..=0x00DF if xo => cpu.display.scroll_up(op.d as usize),
+        0x00E0 => cpu.display.clear(),
+        0x0230 if cpu.variant == Variant::Chip8Hires => cpu.display.clear(),
+        0x02A0 if cpu.variant == Variant::Chip8X => cpu.display.update_color_board(ColorBoard::cycle_background),
+        0x00EE => cpu.ret(),
+        0x00FB if schip => cpu.display.scroll_right(),
+        0x00FC if schip => cpu.display.scroll_left(),
+        0x00FD if schip => cpu.halt(HaltReason::Exit),
+        0x00FE if schip => cpu.display.set_hires(false),
+        0x00FF if schip => cpu.display.set_hires(true),
+        0x0100..=0x01FF if mega => cpu.load_huge_index(op.kk),
+        0x0200..=0x02FF if mega => cpu.load_palette(op.kk),
+        0x0300..=0x03FF if mega => cpu.display.update_mega_screen(|screen| screen.set_sprite_width(op.kk)),
+        0x0400..=0x04FF if mega => cpu.display.update_mega_screen(|screen| screen.set_sprite_height(op.kk)),
+        0x0500..=0x05FF if mega => cpu.display.update_mega_screen(|screen| screen.set_alpha(op.kk)),
+        0x0600..=0x060F if mega => cpu.play_digitised_sound(op.d != 1),
+        0x0700 if mega => cpu.digitised_sound = None,
+        0x0800..=0x080F if mega => cpu.display.update_mega_screen(|screen| screen.set_blend_mode(op.d)),
+        0x0900..=0x09FF if mega => cpu.display.update_mega_screen(|screen| screen.set_collision_color(op.kk)),
+        _ => unknown(cpu, op),
+    }
+}
+
+fn jump(cpu: &mut Cpu, op: Operands) {
+    cpu.jump(op.nnn);
+}
+
+fn call(cpu: &mut Cpu, op: Operands) {
+    cpu.call(op.nnn);
+}
+
+fn skip_if_equal(cpu: &mut Cpu, op: Operands) {
+    cpu.skip_if(cpu.registers[op.x as usize] == op.kk);
+}
+
+fn skip_if_not_equal(cpu: &mut Cpu, op: Operands) {
+    cpu.skip_if(cpu.registers[op.x as usize] != op.kk);
+}
+
+fn register_compare(cpu: &mut Cpu, op: Operands) {
+    let xo = cpu.variant == Variant::XoChip;
+    match op.d {
+        0x0 => cpu.skip_if(cpu.registers[op.x as usize] == cpu.registers[op.y as usize]),
+        0x1 if cpu.variant == Variant::Chip8X => cpu.registers[op.x as usize] = Cpu::add_nibbles(cpu.registers[op.x as usize], cpu.registers[op.y as usize]),
+        0x2 if xo => cpu.store_register_range(op.x, op.y),
+        0x3 if xo => cpu.load_register_range(op.x, op.y),
+        _ => unknown(cpu, op),
+    }
+}
+
+fn load_byte(cpu: &mut Cpu, op: Operands) {
+    cpu.registers[op.x as usize] = op.kk;
+}
+
+fn add_byte(cpu: &mut Cpu, op: Operands) {
+    let vx = cpu.registers[op.x as usize];
+    cpu.registers[op.x as usize] = vx.wrapping_add(op.kk);
+}
+
+fn arithmetic(cpu: &mut Cpu, op: Operands) {
+    let (x, y) = (op.x, op.y);
+    let vx = cpu.registers[x as usize];
+    let vy = cpu.registers[y as usize];
+    match op.d {
+        0x0 => cpu.registers[x as usize] = vy,
+        0x1 => cpu.registers[x as usize] = vx | vy,
+        0x2 => cpu.registers[x as usize] = vx & vy,
+        0x3 => cpu.registers[x as usize] = vx ^ vy,
+        0x4 => cpu.add_xy(x, y),
+        0x5 => cpu.sub_xy(x, vx, vy),
+        0x6 => cpu.shr_xy(x, y),
+        0x7 => cpu.sub_xy(x, vy, vx),
+        0xE => cpu.shl_xy(x, y),
+        _ => unknown(cpu, op),
+    }
+}
+
+fn skip_if_registers_differ(cpu: &mut Cpu, op: Operands) {
+    match op.d {
+        0x0 => cpu.skip_if(cpu.registers[op.x as usize] != cpu.registers[op.y as usize]),
+        _ => unknown(cpu, op),
+    }
+}
+
+fn load_index(cpu: &mut Cpu, op: Operands) {
+    cpu.index_register = op.nnn as u32;
+}
+
+// CHIP-8X has colour instead
+fn jump_with_offset(cpu: &mut Cpu, op: Operands) {
+    match op.d {
+        _ if cpu.variant != Variant::Chip8X => cpu.jump_with_offset(op.x, op.nnn),
+        0 => cpu.color_blocks(op.x, op.y),
+        n => cpu.color_strip(op.x, op.y, n),
+    }
+}
+
+fn random(cpu: &mut Cpu, op: Operands) {
+    cpu.registers[op.x as usize] = cpu.random_byte() & op.kk;
+}
+
+fn draw(cpu: &mut Cpu, op: Operands) {
+    cpu.draw(op.x, op.y, op.d);
+}
+
+fn keypad(cpu: &mut Cpu, op: Operands) {
+    let key = (cpu.registers[op.x as usize] & 0xF) as usize;
+    let (pressed, pressed2) = (cpu.keypad[key], cpu.keypad2[key]);
+    let x8x = cpu.variant == Variant::Chip8X;
+    match op.kk {
+        0x9E => cpu.skip_if(pressed),
+        0xA1 => cpu.skip_if(!pressed),
+        0xF2 if x8x => cpu.skip_if(pressed2),
+        0xF5 if x8x => cpu.skip_if(!pressed2),
+        _ => unknown(cpu, op),
+    }
+}
+
+fn misc(cpu: &mut Cpu, op: Operands) {
+    let schip = cpu.variant.has_superchip_opcodes();
+    let xo = cpu.variant == Variant::XoChip;
+    let x = op.x;
+    let vx = cpu.registers[x as usize];
+    match op.kk {
+        0x00 if xo && x == 0 => cpu.load_long_index(),
+        0x01 if xo => cpu.display.select_planes(x),
+        0x02 if xo && x == 0 => cpu.load_audio_pattern(),
+        0x07 => cpu.registers[x as usize] = cpu.delay_timer,
+        0x0A => cpu.wait_for_key(x),
+        0x15 => cpu.delay_timer = vx,
+        0x18 => cpu.sound_timer = vx,
+        0x1E => cpu.add_register_to_index(x),
+        0x29 => cpu.index_register = (SMALL_FONT_ADDR + (vx & 0xF) as usize * 5) as u32,
+        0x30 if schip => cpu.index_register = (BIG_FONT_ADDR + (vx & 0xF) as usize * 10) as u32,
+        0x33 => cpu.store_bcd(vx),
+        0x3A if xo => cpu.audio_pitch = vx,
+        0x55 => cpu.store_registers(x),
+        0x65 => cpu.load_registers(x),
+        0x75 if schip => cpu.store_rpl_flags(x),
+        0x85 if schip => cpu.load_rpl_flags(x),
+        0xF8 if cpu.variant == Variant::Chip8X => cpu.port_output = vx,
+        0xFB if cpu.variant == Variant::Chip8X => cpu.registers[x as usize] = cpu.port_input,
+        _ => unknown(cpu, op),
+    }
+}
@@ -0,0 +1,109 @@
+// CRT look.
+// A CPU approximation of what the screen looked like on a TV: dark gaps between scanlines, the
+// picture bulging out towards the middle of the tube, and lit pixels bleeding light onto their
+// neighbours. It works on the RGB a frontend was going to draw anyway (Display::rgb_pixels())
+// and gives back a bigger image, since scanlines need more than one output row per pixel.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+// The output is this wide whatever the resolution, hires just gets fewer rows per pixel
+const OUTPUT_WIDTH: usize = 512;
+// How far the corners get pulled in, bigger is more curved
+const CURVATURE: f32 = 0.06;
+// How bright the gap between scanlines is, compared to the middle of a line
+const SCANLINE_GAP: f32 = 0.45;
+// How much of the neighbours' light gets added
+const BLOOM: f32 = 0.35;
+
+/// Which CRT effects to draw. All off (the default) is just the screen scaled up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CrtEffects {
+    pub scanlines: bool,
+    pub curvature: bool,
+    pub bloom: bool,
+}
+
+/// A processed frame.
+pub struct CrtImage {
+    pub width: usize,
+    pub height: usize,
+    /// RGB, row by row
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl CrtEffects {
+    pub fn any(&self) -> bool {
+        self.scanlines || self.curvature || self.bloom
+    }
+
+    /// Draw a `width` x `height` screen (`pixels` row by row) with the effects.
+    pub fn apply(&self, width: usize, height: usize, pixels: &[[u8; 3]]) -> CrtImage {
+        let scale = (OUTPUT_WIDTH / width).max(1);
+        let (out_width, out_height) = (width * scale, height * scale);
+        let glow = if self.bloom { bloom(width, height, pixels) } else { Vec::new() };
+
+        let mut out = vec![[0; 3]; out_width * out_height];
+        for out_y in 0..out_height {
+            for out_x in 0..out_width {
+                // where in the screen this output pixel comes from, in screen pixels
+                let (mut x, mut y) = ((out_x as f32 + 0.5) / scale as f32, (out_y as f32 + 0.5) / scale as f32);
+                if self.curvature {
+                    match curve(x / width as f32, y / height as f32) {
+                        Some((u, v)) => (x, y) = (u * width as f32, v * height as f32),
+                        None => continue, // off the edge of the tube, stays black
+                    }
+                }
+                let (pixel_x, pixel_y) = ((x as usize).min(width - 1), (y as usize).min(height - 1));
+                let n = pixel_y * width + pixel_x;
+
+                let mut rgb = pixels[n].map(|c| c as f32);
+                if self.bloom {
+                    rgb = core::array::from_fn(|c| rgb[c] + glow[n][c] * BLOOM);
+                }
+                if self.scanlines {
+                    // brightest in the middle of a pixel's rows, darkest at the top and bottom
+                    let across = y - pixel_y as f32;
+                    let from_middle = (across - 0.5).abs() * 2.0;
+                    let brightness = 1.0 - (1.0 - SCANLINE_GAP) * from_middle * from_middle;
+                    rgb = rgb.map(|c| c * brightness);
+                }
+                out[out_y * out_width + out_x] = rgb.map(|c| c.clamp(0.0, 255.0) as u8);
+            }
+        }
+        CrtImage { width: out_width, height: out_height, pixels: out }
+    }
+}
+
+// Barrel distortion, (u, v) from 0 to 1 across the screen. None if it lands outside the screen
+fn curve(u: f32, v: f32) -> Option<(f32, f32)> {
+    let (x, y) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+    let stretch = 1.0 + CURVATURE * (x * x + y * y);
+    let (x, y) = (x * stretch, y * stretch);
+    let inside = (-1.0..=1.0).contains(&x) && (-1.0..=1.0).contains(&y);
+    inside.then(|| ((x + 1.0) / 2.0, (y + 1.0) / 2.0))
+}
+
+// How much brighter each pixel's 8 neighbours are on average than it is, so light spills onto
+// darker pixels and doesn't just wash everything out
+fn bloom(width: usize, height: usize, pixels: &[[u8; 3]]) -> Vec<[f32; 3]> {
+    let mut glow = vec![[0.0; 3]; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+                let neighbour = pixels[ny as usize * width + nx as usize];
+                for c in 0..3 {
+                    sum[c] += neighbour[c] as f32;
+                }
+            }
+            let own = pixels[y * width + x];
+            glow[y * width + x] = core::array::from_fn(|c| (sum[c] / 8.0 - own[c] as f32).max(0.0));
+        }
+    }
+    glow
+}
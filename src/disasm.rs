@@ -0,0 +1,118 @@
+// Disassembler.
+// Turns opcodes back into the assembly mnemonics from Cowgod's CHIP-8 reference
+// (plus the SUPER-CHIP and XO-CHIP extensions), for debuggers to show what's about to run.
+// It uses the same decoder as Cpu::step(), anything step() wouldn't recognise for the
+// given variant comes out as raw data.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::symbols::Symbols;
+use crate::variant::Variant;
+
+/// One decoded instruction.
+pub struct Disassembly {
+    /// Where it lives in memory
+    pub addr: usize,
+    /// The raw opcode (the first two bytes for 4 byte instructions)
+    pub opcode: u16,
+    /// How many bytes it takes up, 4 for XO-CHIP's F000 NNNN and 2 for everything else
+    pub len: usize,
+    pub instruction: Instruction,
+    pub text: String,
+    // what the text was rendered with, for with_symbols()
+    quirks: Quirks,
+}
+
+impl Disassembly {
+    /// Show the address the instruction uses by name, if `symbols` has one for it.
+    pub fn with_symbols(mut self, symbols: &Symbols) -> Self {
+        self.text = symbols.format(&self.instruction, &self.quirks);
+        self
+    }
+}
+
+/// Disassemble the instruction at `addr`. Runs off the end of memory are shown as 0 bytes.
+/// `quirks` is what it'll run with, which changes how BNNN reads.
+pub fn disassemble_at(memory: &[u8], addr: usize, variant: Variant, quirks: &Quirks) -> Disassembly {
+    let byte = |offset: usize| memory.get(addr + offset).copied().unwrap_or(0) as u16;
+    let instruction = Instruction::decode_at(memory, addr, variant);
+    Disassembly {
+        addr,
+        opcode: byte(0) << 8 | byte(1),
+        len: instruction.size(),
+        instruction,
+        text: instruction.disassemble(quirks),
+        quirks: *quirks,
+    }
+}
+
+/// The mnemonic for a single 2 byte opcode.
+pub fn disassemble(opcode: u16, variant: Variant, quirks: &Quirks) -> String {
+    Instruction::decode(opcode, variant).disassemble(quirks)
+}
+
+/// `lines` instructions with `center` in the middle, for a disassembly view that follows PC.
+/// Instructions can't be decoded backwards, so the ones before `center` are decoded forwards from
+/// a little earlier, cutting any that would overlap `center` short so it always lines up.
+pub fn disassemble_around(memory: &[u8], center: usize, variant: Variant, quirks: &Quirks, lines: usize) -> Vec<Disassembly> {
+    let before = lines / 2;
+    let mut listing = Vec::with_capacity(lines);
+
+    let mut addr = center.saturating_sub(before * 2);
+    while addr < center {
+        let mut instruction = disassemble_at(memory, addr, variant, quirks);
+        if addr + instruction.len > center {
+            let data = Instruction::Unknown(instruction.opcode);
+            instruction = Disassembly {
+                addr,
+                opcode: instruction.opcode,
+                len: 2,
+                instruction: data,
+                text: data.to_string(),
+                quirks: *quirks,
+            };
+        }
+        addr += instruction.len;
+        listing.push(instruction);
+    }
+
+    while listing.len() < lines && addr < memory.len() {
+        let instruction = disassemble_at(memory, addr, variant, quirks);
+        addr += instruction.len;
+        listing.push(instruction);
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // B345 then B210
+    const JUMPS: [u8; 4] = [0xB3, 0x45, 0xB2, 0x10];
+
+    #[test]
+    fn jump_with_offset_follows_the_quirk() {
+        let vip = Quirks::cosmac_vip();
+        let schip = Quirks::superchip();
+        assert_eq!(disassemble(0xB345, Variant::Chip8, &vip), "JP V0, 0x345");
+        assert_eq!(disassemble(0xB345, Variant::Chip8, &schip), "JP V3, 0x345");
+
+        let listing = disassemble_around(&JUMPS, 0, Variant::Chip8, &schip, 2);
+        let text: Vec<&str> = listing.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["JP V3, 0x345", "JP V2, 0x210"]);
+    }
+
+    #[test]
+    fn symbols_keep_the_register() {
+        let mut symbols = Symbols::new();
+        symbols.insert("table", 0x345);
+        let vip = disassemble_at(&JUMPS, 0, Variant::Chip8, &Quirks::cosmac_vip()).with_symbols(&symbols);
+        let schip = disassemble_at(&JUMPS, 0, Variant::Chip8, &Quirks::superchip()).with_symbols(&symbols);
+        assert_eq!(vip.text, "JP V0, table");
+        assert_eq!(schip.text, "JP V3, table");
+    }
+}
@@ -0,0 +1,522 @@
+// The display.
+// CHIP-8 has a monochrome 64x32 screen. Nothing is ever "set" directly, sprites are
+// XOR'd onto the screen, so drawing the same sprite twice in the same place erases it.
+// That's also how games detect collisions: if a draw turns a lit pixel off, VF gets set.
+// SUPER-CHIP added a 128x64 hi-res mode, the buffer is always big enough for that and
+// we just use the top left corner of it in lo-res. The ETI-660's lo-res screen was taller,
+// 48 or 64 rows, which fits in the same buffer.
+// XO-CHIP added a second bit-plane, so each pixel is really 2 bits (4 colours).
+// Opcodes only touch the planes that are currently selected (FN01), plane 1 by default.
+//
+// XOR drawing means a moving sprite is erased and redrawn, and on a real CRT the phosphor kept
+// glowing for a moment so it never looked like it went away. Phosphor decay fakes that: a pixel
+// that goes out fades to the background over a few frames in rgb_pixels(). It's only in what
+// frontends show, pixel(), hash() and collisions all see the real screen.
+//
+// Mega-Chip grows past bit-planes altogether: in its mode the screen is 256x192 with a byte of
+// colour per pixel (mega_chip.rs), and everything here that reads the screen (pixel_color(),
+// rows(), hash(), rgb_pixels()) reads that one instead. The planes are still there underneath
+// for when the ROM switches back.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::color_board::ColorBoard;
+use crate::fnv::fnv1a;
+use crate::mega_chip::{MegaScreen, MEGA_HEIGHT, MEGA_WIDTH};
+use crate::palette::Palette;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Bitmask covering every plane
+pub const ALL_PLANES: u8 = 0b11;
+
+pub struct Display {
+    // row major, each pixel holds one bit per plane. 0 means unlit on every plane
+    pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    hires: bool,
+    // rows in lo-res, HEIGHT except on the ETI-660
+    lores_height: usize,
+    // bitmask of the planes drawing/clearing/scrolling apply to
+    selected_planes: u8,
+    // bumped whenever anything is done to the screen
+    changes: u64,
+    // what changes was at when each row was last drawn on, scrolled or cleared
+    row_changes: [u64; MEGA_HEIGHT],
+    // None unless phosphor decay is on
+    phosphor: Option<Box<Phosphor>>,
+    // CHIP-8X only
+    color_board: Option<Box<ColorBoard>>,
+    // Mega-Chip only, whether or not it's in Mega-Chip mode
+    mega: Option<Box<MegaScreen>>,
+}
+
+/// A copy of what's on screen, for handing to something that can't borrow the Display (another
+/// thread, an async task).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Colour indexes like Display::pixel_color(), row by row
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn pixel_color(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+// How brightly each pixel is still glowing
+struct Phosphor {
+    frames: u8,
+    // how much brightness a pixel loses each frame once it's out
+    fade: u8,
+    // per pixel: brightness (255 while lit) and the colour it was last lit in
+    glow: [[(u8, u8); HIRES_WIDTH]; HIRES_HEIGHT],
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            pixels: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+            lores_height: HEIGHT,
+            selected_planes: 0b01,
+            changes: 0,
+            row_changes: [0; MEGA_HEIGHT],
+            phosphor: None,
+            color_board: None,
+            mega: None,
+        }
+    }
+
+    /// Give the display CHIP-8X's colour board, or take it away with None. With one, its colours
+    /// are used instead of the palette's.
+    pub fn set_color_board(&mut self, board: Option<ColorBoard>) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        self.color_board = board.map(Box::new);
+    }
+
+    pub fn color_board(&self) -> Option<&ColorBoard> {
+        self.color_board.as_deref()
+    }
+
+    /// Change the colour board, if there is one. Every row counts as changed.
+    pub fn update_color_board(&mut self, update: impl FnOnce(&mut ColorBoard)) {
+        if let Some(board) = &mut self.color_board {
+            update(board);
+            self.changes += 1;
+            self.row_changes = [self.changes; MEGA_HEIGHT];
+        }
+    }
+
+    /// Give the display Mega-Chip's screen (switched off until 0011), or take it away with None.
+    pub fn set_mega_screen(&mut self, screen: Option<MegaScreen>) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        self.mega = screen.map(Box::new);
+    }
+
+    pub fn mega_screen(&self) -> Option<&MegaScreen> {
+        self.mega.as_deref()
+    }
+
+    /// Change the Mega-Chip screen or its settings, if there is one. Every row counts as changed.
+    pub fn update_mega_screen(&mut self, update: impl FnOnce(&mut MegaScreen)) {
+        if let Some(screen) = &mut self.mega {
+            update(screen);
+            self.changes += 1;
+            self.row_changes = [self.changes; MEGA_HEIGHT];
+        }
+    }
+
+    /// True in Mega-Chip mode, when the 256x192 screen is the one showing.
+    pub fn is_mega_mode(&self) -> bool {
+        self.mega.as_ref().is_some_and(|screen| screen.is_enabled())
+    }
+
+    // The Mega-Chip screen, while it's the one showing
+    fn mega_mode(&mut self) -> Option<&mut MegaScreen> {
+        self.mega.as_deref_mut().filter(|screen| screen.is_enabled())
+    }
+
+    /// Make pixels that go out fade away over `frames` frames instead of vanishing, 0 (or 1)
+    /// turns it off.
+    pub fn set_phosphor_decay(&mut self, frames: u8) {
+        self.phosphor = (frames > 1).then(|| {
+            Box::new(Phosphor { frames, fade: 255u8.div_ceil(frames), glow: [[(0, 0); HIRES_WIDTH]; HIRES_HEIGHT] })
+        });
+    }
+
+    /// How many frames phosphor decay lasts, 0 if it's off.
+    pub fn phosphor_decay(&self) -> u8 {
+        self.phosphor.as_ref().map_or(0, |phosphor| phosphor.frames)
+    }
+
+    /// Once per 60Hz frame, the CPU does it when it ticks the timers. Lit pixels glow at full
+    /// brightness, ones that went out get dimmer.
+    pub fn end_frame(&mut self) {
+        let Some(phosphor) = &mut self.phosphor else { return };
+        for (glow_row, row) in phosphor.glow.iter_mut().zip(&self.pixels) {
+            for (glow, &color) in glow_row.iter_mut().zip(row) {
+                *glow = match color {
+                    0 => (glow.0.saturating_sub(phosphor.fade), glow.1),
+                    _ => (255, color),
+                };
+            }
+        }
+    }
+
+    /// CLS: opcode 0x00E0, only clears the selected planes. In Mega-Chip mode it shows what's
+    /// been drawn since the last one and starts again on a blank screen.
+    pub fn clear(&mut self) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        if let Some(screen) = self.mega_mode() {
+            screen.present();
+            return;
+        }
+        let keep = !self.selected_planes;
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= keep;
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match (self.is_mega_mode(), self.hires) {
+            (true, _) => MEGA_WIDTH,
+            (false, true) => HIRES_WIDTH,
+            (false, false) => WIDTH,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match (self.is_mega_mode(), self.hires) {
+            (true, _) => MEGA_HEIGHT,
+            (false, true) => HIRES_HEIGHT,
+            (false, false) => self.lores_height,
+        }
+    }
+
+    /// Rows on the lo-res screen, HEIGHT unless set_lores_height() changed it.
+    pub fn lores_height(&self) -> usize {
+        self.lores_height
+    }
+
+    /// Make the lo-res screen taller, 48 or 64 rows for the ETI-660 (anything past HIRES_HEIGHT
+    /// is cut down to it). Clears the screen.
+    pub fn set_lores_height(&mut self, height: usize) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        self.lores_height = height.clamp(1, HIRES_HEIGHT);
+        self.pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+    }
+
+    /// Counts every operation on the screen, if it hasn't moved nothing can have changed.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// The rows (top to bottom) that might look different now than when changes() was `since`,
+    /// so a frontend can redraw just those. Remember changes() when drawing, pass it next time.
+    /// A change of resolution counts as every row changing.
+    pub fn rows_changed_since(&self, since: u64) -> impl Iterator<Item = usize> + '_ {
+        (0..self.height()).filter(move |&y| self.row_changes[y] > since)
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// LOW/HIGH: opcodes 0x00FE/0x00FF. Switching resolution clears the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        self.hires = hires;
+        self.pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// PLANE: opcode 0xFN01, pick which planes (bitmask, 0 to 3) later opcodes act on.
+    pub fn select_planes(&mut self, planes: u8) {
+        self.changes += 1;
+        self.selected_planes = planes & ALL_PLANES;
+    }
+
+    /// True if the pixel is lit on any plane (or isn't colour 0, in Mega-Chip mode).
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixel_color(x, y) != 0
+    }
+
+    /// The colour index (0 to 3) of a pixel, bit 0 is plane 1 and bit 1 is plane 2. In
+    /// Mega-Chip mode it's the palette colour (0 to 255).
+    pub fn pixel_color(&self, x: usize, y: usize) -> u8 {
+        match &self.mega {
+            Some(screen) if screen.is_enabled() => screen.pixel(x, y),
+            _ => self.pixels[y][x],
+        }
+    }
+
+    /// The colour index of a pixel on the planes, even in Mega-Chip mode when they aren't
+    /// what's showing. Save states keep the planes with this and set_pixel_color().
+    pub fn plane_pixel_color(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+
+    /// Set a pixel's colour index on the planes directly, for putting a saved screen back.
+    /// Programs draw with draw_sprite().
+    pub fn set_pixel_color(&mut self, x: usize, y: usize, color: u8) {
+        self.changes += 1;
+        self.row_changes[y] = self.changes;
+        self.pixels[y][x] = color & ALL_PLANES;
+    }
+
+    /// Rows of the screen, top to bottom, each as wide as the current resolution.
+    /// Values are colour indexes, anything non-zero is lit.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let width = self.width();
+        (0..self.height()).map(move |y| match &self.mega {
+            Some(screen) if screen.is_enabled() => screen.row(y),
+            _ => &self.pixels[y][..width],
+        })
+    }
+
+    /// Copy the screen out.
+    pub fn frame(&self) -> Frame {
+        Frame {
+            width: self.width(),
+            height: self.height(),
+            pixels: self.rows().flatten().copied().collect(),
+        }
+    }
+
+    /// Every pixel's RGB in `palette`, row by row, for frontends to copy into whatever
+    /// they draw with. Pixels still fading out with phosphor decay are blended towards the
+    /// background. With a colour board (CHIP-8X) the colours are the board's, and in Mega-Chip
+    /// mode they're the ROM's own palette (no phosphor decay there).
+    pub fn rgb_pixels<'a>(&'a self, palette: &'a Palette) -> impl Iterator<Item = [u8; 3]> + 'a {
+        let mega = self.mega.as_deref().filter(|screen| screen.is_enabled());
+        let rgb = move |x: usize, y: usize, color: u8| match &self.color_board {
+            Some(board) if color == 0 => board.background_rgb(),
+            Some(board) => board.foreground_rgb(x, y),
+            None => palette.rgb(color),
+        };
+        self.rows().enumerate().flat_map(move |(y, row)| {
+            row.iter().enumerate().map(move |(x, &color)| match (mega, &self.phosphor, color) {
+                (Some(screen), _, _) => screen.rgb(x, y),
+                (None, Some(phosphor), 0) => {
+                    let (brightness, lit) = phosphor.glow[y][x];
+                    blend(rgb(x, y, 0), rgb(x, y, lit), brightness)
+                }
+                _ => rgb(x, y, color),
+            })
+        })
+    }
+
+    /// A digest of what's on screen, for checking a screen without keeping all of it (tests,
+    /// test ROM suites). It's 64-bit FNV-1a over the width and height (2 bytes each, little
+    /// endian) and then every pixel's colour a byte at a time, row by row, so the same screen
+    /// hashes the same on every platform and every version.
+    pub fn hash(&self) -> u64 {
+        let size = [(self.width() as u16).to_le_bytes(), (self.height() as u16).to_le_bytes()];
+        let pixels = self.rows().flatten();
+        fnv1a(size.iter().flatten().chain(pixels).copied())
+    }
+
+    /// The screen as text, a line per row: `.` for off and `#` for lit. XO-CHIP's other colours
+    /// are `+` (plane 2 only) and `@` (both planes), and Mega-Chip's colours past 3 are all `@`
+    /// too. Headless runs print this, and test ROM
+    /// suites store their expected screens in it.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.rows() {
+            text.extend(row.iter().map(|&color| match color {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            }));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// XOR an 8 pixel wide sprite onto the screen, one byte per row.
+    /// The starting position always wraps around the screen. Parts of the sprite that hang off
+    /// the edge are clipped, or wrapped around to the other side when `wrap` is set.
+    /// With more than one plane selected `sprite` holds the data for each plane back to back.
+    /// Returns true if any lit pixel was turned off (a collision).
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, sprite, 1, wrap)
+    }
+
+    /// Same as `draw_sprite` but 16 pixels wide, two bytes per row (SUPER-CHIP DXY0).
+    pub fn draw_wide_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, sprite, 2, wrap)
+    }
+
+    /// Mega-Chip's DXYN: blit a sprite, a byte of colour per pixel, onto the screen being drawn
+    /// (see MegaScreen::blit). Does nothing outside Mega-Chip mode.
+    pub fn blit_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        self.changes += 1;
+        self.mega_mode().is_some_and(|screen| screen.blit(x, y, sprite, wrap))
+    }
+
+    /// draw_sprite() in Mega-Chip mode, for text: the 1 bit sprite is blitted in TEXT_COLOR.
+    pub fn blit_text(&mut self, x: usize, y: usize, sprite: &[u8], bytes_per_row: usize, wrap: bool) -> bool {
+        self.changes += 1;
+        self.mega_mode().is_some_and(|screen| screen.blit_bits(x, y, sprite, bytes_per_row, wrap))
+    }
+
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], bytes_per_row: usize, wrap: bool) -> bool {
+        self.changes += 1;
+        let planes = self.selected_planes.count_ones() as usize;
+        if planes == 0 {
+            return false;
+        }
+
+        let per_plane = sprite.len() / planes;
+        let mut collision = false;
+        let mut data = sprite.chunks(per_plane.max(1));
+        for plane in [0b01, 0b10] {
+            if self.selected_planes & plane == 0 {
+                continue;
+            }
+            if let Some(plane_sprite) = data.next() {
+                collision |= self.draw_plane(plane, x, y, plane_sprite, bytes_per_row, wrap);
+            }
+        }
+        collision
+    }
+
+    fn draw_plane(&mut self, plane: u8, x: usize, y: usize, sprite: &[u8], bytes_per_row: usize, wrap: bool) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let x = x % width;
+        let y = y % height;
+        let mut collision = false;
+
+        for (row, bytes) in sprite.chunks(bytes_per_row).enumerate() {
+            let mut py = y + row;
+            if py >= height {
+                if !wrap {
+                    break;
+                }
+                py %= height;
+            }
+            for col in 0..bytes.len() * 8 {
+                let mut px = x + col;
+                if px >= width {
+                    if !wrap {
+                        break;
+                    }
+                    px %= width;
+                }
+                // walk each byte from the most significant bit, that's the leftmost pixel
+                if bytes[col / 8] & (0x80 >> (col % 8)) == 0 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[py][px];
+                collision |= *pixel & plane != 0;
+                *pixel ^= plane;
+            }
+            self.row_changes[py] = self.changes;
+        }
+
+        collision
+    }
+
+    /// SCD: opcode 0x00CN, scroll the selected planes down n rows. Rows scrolled in at the top are blank.
+    /// Scrolling in Mega-Chip mode moves what's being drawn instead.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// SCU: opcode 0x00DN (XO-CHIP) or 0x00BN (Mega-Chip), scroll the selected planes up n rows.
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// SCR: opcode 0x00FB, scroll the selected planes right 4 pixels.
+    pub fn scroll_right(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    /// SCL: opcode 0x00FC, scroll the selected planes left 4 pixels.
+    pub fn scroll_left(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    // moves the selected planes by (dx, dy), whatever gets uncovered is blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.changes += 1;
+        self.row_changes = [self.changes; MEGA_HEIGHT];
+        if let Some(screen) = self.mega_mode() {
+            screen.scroll(dx, dy);
+            return;
+        }
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let mask = self.selected_planes;
+        let before = self.pixels;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    before[sy as usize][sx as usize] & mask
+                } else {
+                    0
+                };
+                let pixel = &mut self.pixels[y as usize][x as usize];
+                *pixel = (*pixel & !mask) | moved;
+            }
+        }
+    }
+}
+
+// `amount` of the way (out of 255) from one colour to another
+fn blend(from: [u8; 3], to: [u8; 3], amount: u8) -> [u8; 3] {
+    core::array::from_fn(|n| ((from[n] as u16 * (255 - amount) as u16 + to[n] as u16 * amount as u16) / 255) as u8)
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_off_the_bottom_corner() {
+        // a 2x2 block with its top left on the last pixel
+        let sprite = [0xC0, 0xC0];
+        let mut display = Display::new();
+        display.draw_sprite(WIDTH - 1, HEIGHT - 1, &sprite, true);
+        assert!([(WIDTH - 1, HEIGHT - 1), (0, HEIGHT - 1), (WIDTH - 1, 0), (0, 0)].iter().all(|&(x, y)| display.pixel(x, y)));
+
+        let mut display = Display::new();
+        display.draw_sprite(WIDTH - 1, HEIGHT - 1, &sprite, false);
+        assert!(display.pixel(WIDTH - 1, HEIGHT - 1));
+        assert!(![(0, HEIGHT - 1), (WIDTH - 1, 0), (0, 0)].iter().any(|&(x, y)| display.pixel(x, y)));
+    }
+
+    #[test]
+    fn the_start_always_wraps() {
+        let mut display = Display::new();
+        display.draw_sprite(WIDTH + 2, HEIGHT + 1, &[0x80], false);
+        assert!(display.pixel(2, 1));
+    }
+}
@@ -0,0 +1,183 @@
+// The emulator on a thread of its own.
+// A frontend that runs the CPU between drawing frames stutters whenever drawing (or anything
+// else on the UI thread) takes too long, and the game slows down with it. EmulatorHandle moves
+// the CPU onto its own thread, which keeps 60 frames a second however the UI's doing. Input
+// goes in over a channel, and the screen comes back through a mailbox that only ever holds
+// the newest frame: a UI that stalls just skips the frames it missed, it never falls behind.
+//
+//   let emulator = EmulatorHandle::spawn(cpu);
+//   loop {
+//       emulator.set_key(5, keyboard.is_down(Key::W));
+//       if let Some(frame) = emulator.latest_frame() {
+//           draw(&frame);
+//       }
+//       for event in emulator.events() { .. }
+//   }
+//
+// Dropping the handle stops the thread, stop() does too and hands the Cpu back.
+
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::audio::Sound;
+use crate::clock::Clock;
+use crate::cpu::Cpu;
+use crate::display::Frame;
+use crate::error::CpuError;
+use crate::halt::HaltReason;
+use crate::save_state::SaveState;
+
+/// Something that happened on the emulator thread. The screen doesn't come this way, see
+/// EmulatorHandle::latest_frame().
+#[derive(Debug)]
+pub enum Event {
+    /// The sound changed (started, stopped, or XO-CHIP changed the pattern or pitch)
+    Sound(Sound),
+    /// The program stopped, and the error if it was a fault
+    Halted { reason: HaltReason, fault: Option<CpuError> },
+    /// A load_rom() didn't work, the old program's still there
+    LoadFailed(CpuError),
+}
+
+// What the handle asks the thread to do
+enum Command {
+    LoadRom(Vec<u8>),
+    Key(u8, bool),
+    Pause,
+    Resume,
+    SaveState(Sender<SaveState>),
+    Quit,
+}
+
+/// A CPU running on its own thread, see the top of this file.
+pub struct EmulatorHandle {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    // the newest frame the UI hasn't taken yet
+    frame: Arc<Mutex<Option<Frame>>>,
+    thread: Option<JoinHandle<Cpu>>,
+}
+
+impl EmulatorHandle {
+    /// Start running `cpu` in real time on a new thread.
+    pub fn spawn(cpu: Cpu) -> Self {
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let frame = Arc::new(Mutex::new(None));
+        let mailbox = frame.clone();
+        let thread = thread::Builder::new()
+            .name("chip8".to_string())
+            .spawn(move || run(cpu, command_rx, event_tx, mailbox))
+            .expect("couldn't start the emulator thread");
+        EmulatorHandle { commands, events, frame, thread: Some(thread) }
+    }
+
+    /// The screen, if it's changed since the last time this was called.
+    pub fn latest_frame(&self) -> Option<Frame> {
+        self.frame.lock().unwrap().take()
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        self.events.try_iter()
+    }
+
+    /// Press or release a key (0x0 to 0xF).
+    pub fn set_key(&self, key: u8, pressed: bool) {
+        self.send(Command::Key(key, pressed));
+    }
+
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Start a ROM on a fresh machine, the same variant and settings as now. If it doesn't
+    /// load an Event::LoadFailed says why.
+    pub fn load_rom(&self, rom: &[u8]) {
+        self.send(Command::LoadRom(rom.to_vec()));
+    }
+
+    /// The machine's state between two frames. Waits for the thread to get to it, at most a frame.
+    pub fn save_state(&self) -> Option<SaveState> {
+        let (reply, state) = mpsc::channel();
+        self.send(Command::SaveState(reply));
+        state.recv().ok()
+    }
+
+    /// Stop the thread and take the Cpu back.
+    pub fn stop(mut self) -> Cpu {
+        self.send(Command::Quit);
+        let thread = self.thread.take().unwrap();
+        thread.join().expect("the emulator thread panicked")
+    }
+
+    // A thread that's gone has either been stopped or panicked, and the panic comes out of
+    // stop() or drop, so there's nothing to do about a failed send
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+impl Drop for EmulatorHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.send(Command::Quit);
+            let _ = thread.join();
+        }
+    }
+}
+
+// The emulator thread: commands, a frame, and sleep until the next one
+fn run(mut cpu: Cpu, commands: Receiver<Command>, events: Sender<Event>, frame: Arc<Mutex<Option<Frame>>>) -> Cpu {
+    let mut clock = Clock::new();
+    let mut sound = cpu.sound();
+    // what Display::changes() was at the last frame put out, None to put the next one out anyway
+    let mut shown = None;
+    let mut halted = false;
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Command::LoadRom(rom)) => match cpu.restart_with_rom(&rom) {
+                    Ok(()) => {
+                        shown = None;
+                        halted = false;
+                    }
+                    Err(e) => {
+                        let _ = events.send(Event::LoadFailed(e));
+                    }
+                },
+                Ok(Command::Key(key, pressed)) => cpu.set_key(key, pressed),
+                Ok(Command::Pause) => cpu.pause(),
+                Ok(Command::Resume) => cpu.resume(),
+                Ok(Command::SaveState(reply)) => {
+                    let _ = reply.send(SaveState::capture(&cpu));
+                }
+                Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return cpu,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        cpu.run_frame();
+        if shown != Some(cpu.display.changes()) {
+            // replacing a frame the UI never took is the point
+            *frame.lock().unwrap() = Some(cpu.display.frame());
+            shown = Some(cpu.display.changes());
+        }
+        // events nobody's reading are dropped with the channel
+        if cpu.sound() != sound {
+            sound = cpu.sound();
+            let _ = events.send(Event::Sound(sound.clone()));
+        }
+        if let (false, Some(reason)) = (halted, cpu.halt_reason()) {
+            halted = true;
+            let _ = events.send(Event::Halted { reason, fault: cpu.fault().cloned() });
+        }
+        clock.wait_for_next_frame();
+    }
+}
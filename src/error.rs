@@ -0,0 +1,68 @@
+use core::fmt;
+
+use crate::cpu::{MAX_MEMORY_SIZE, MAX_STACK_DEPTH, MIN_MEMORY_SIZE};
+use crate::rom_format::RomFormatError;
+
+/// Everything that can go wrong driving the CPU from the outside, and (for a hardened CPU)
+/// everything a broken program can do wrong. `pc` is the address of the offending instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// The ROM doesn't fit between the load address and the end of memory.
+    RomTooLarge { size: usize, capacity: usize },
+    /// The ROM was loaded as Intel HEX or hex text but didn't parse.
+    InvalidRomFile(RomFormatError),
+    /// Cpu::set_memory_size() was asked for less than MIN_MEMORY_SIZE or more than MAX_MEMORY_SIZE.
+    InvalidMemorySize { size: usize },
+    /// Cpu::set_stack_depth() was asked for no stack or more than MAX_STACK_DEPTH.
+    InvalidStackDepth { depth: usize },
+    /// CpuBuilder was given settings that don't go together.
+    InvalidConfig { reason: &'static str },
+    /// An instruction read or wrote `len` bytes at `addr`, which runs past the end of memory.
+    MemoryOutOfBounds { pc: usize, addr: usize, len: usize },
+    /// PC went past the end of memory, there's no instruction there to run.
+    PcOutOfBounds { pc: usize },
+    /// A CALL with every stack slot already in use, usually runaway recursion.
+    StackOverflow { pc: usize },
+    /// A RET without a CALL to return to.
+    StackUnderflow { pc: usize },
+    /// An opcode the CPU's variant doesn't have.
+    UnknownOpcode { pc: usize, opcode: u16 },
+    /// A write to `addr`, in the interpreter's reserved memory below 0x200, with
+    /// MemoryProtection::reserved on.
+    ProtectedWrite { pc: usize, addr: usize },
+    /// A read of `addr`, which nothing has written, with MemoryProtection::uninitialized_reads on.
+    UninitializedRead { pc: usize, addr: usize },
+    /// The CPU ran the `cycles` instructions its watchdog allows (Cpu::watchdog) without the
+    /// program finishing. `pc` is the instruction that would have run next.
+    Watchdog { pc: usize, cycles: u64 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::RomTooLarge { size, capacity } => {
+                write!(f, "ROM is {} bytes but only {} bytes of memory are free", size, capacity)
+            }
+            CpuError::InvalidRomFile(e) => write!(f, "{}", e),
+            CpuError::InvalidMemorySize { size } => {
+                write!(f, "{} bytes of memory won't do, it has to be between {} and {}", size, MIN_MEMORY_SIZE, MAX_MEMORY_SIZE)
+            }
+            CpuError::InvalidStackDepth { depth } => {
+                write!(f, "a stack {} deep won't do, it has to be between 1 and {}", depth, MAX_STACK_DEPTH)
+            }
+            CpuError::InvalidConfig { reason } => write!(f, "{}", reason),
+            CpuError::MemoryOutOfBounds { pc, addr, len } => {
+                write!(f, "{:03X}: {} bytes at {:03X} runs past the end of memory", pc, len, addr)
+            }
+            CpuError::PcOutOfBounds { pc } => write!(f, "PC {:03X} is past the end of memory", pc),
+            CpuError::StackOverflow { pc } => write!(f, "{:03X}: stack overflow", pc),
+            CpuError::StackUnderflow { pc } => write!(f, "{:03X}: stack underflow, RET without a CALL", pc),
+            CpuError::UnknownOpcode { pc, opcode } => write!(f, "{:03X}: unknown opcode {:04X}", pc, opcode),
+            CpuError::ProtectedWrite { pc, addr } => write!(f, "{:03X}: write to {:03X}, in the interpreter's memory below 0x200", pc, addr),
+            CpuError::UninitializedRead { pc, addr } => write!(f, "{:03X}: read of {:03X}, which nothing has written", pc, addr),
+            CpuError::Watchdog { pc, cycles } => write!(f, "{:03X}: watchdog stopped it after {} instructions", pc, cycles),
+        }
+    }
+}
+
+impl core::error::Error for CpuError {}
@@ -0,0 +1,144 @@
+// C bindings.
+// Lets non-Rust frontends embed the core. The machine is an opaque pointer from chip8_new
+// that must be released with chip8_free. The declarations live in include/chip8.h.
+// Build a library to link against with:
+//   cargo rustc --lib --release --no-default-features --features ffi --crate-type cdylib
+// (or --crate-type staticlib)
+
+use core::ptr;
+use core::slice;
+
+use alloc::boxed::Box;
+
+use crate::cpu::Cpu;
+use crate::variant::Variant;
+
+pub const CHIP8_VARIANT_CHIP8: u32 = 0;
+pub const CHIP8_VARIANT_SCHIP: u32 = 1;
+pub const CHIP8_VARIANT_XOCHIP: u32 = 2;
+pub const CHIP8_VARIANT_CHIP8X: u32 = 3;
+pub const CHIP8_VARIANT_MEGACHIP: u32 = 4;
+pub const CHIP8_VARIANT_ETI660: u32 = 5;
+pub const CHIP8_VARIANT_CHIP8HIRES: u32 = 6;
+
+/// A new machine of the given variant (CHIP8_VARIANT_*), null for an unknown variant.
+#[no_mangle]
+pub extern "C" fn chip8_new(variant: u32) -> *mut Cpu {
+    let variant = match variant {
+        CHIP8_VARIANT_CHIP8 => Variant::Chip8,
+        CHIP8_VARIANT_SCHIP => Variant::SuperChip,
+        CHIP8_VARIANT_XOCHIP => Variant::XoChip,
+        CHIP8_VARIANT_CHIP8X => Variant::Chip8X,
+        CHIP8_VARIANT_MEGACHIP => Variant::MegaChip,
+        CHIP8_VARIANT_ETI660 => Variant::Eti660,
+        CHIP8_VARIANT_CHIP8HIRES => Variant::Chip8Hires,
+        _ => return ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(Cpu::with_variant(variant)))
+}
+
+/// # Safety
+/// `cpu` must come from chip8_new (or be null) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(cpu: *mut Cpu) {
+    if !cpu.is_null() {
+        drop(Box::from_raw(cpu));
+    }
+}
+
+/// Copy `len` bytes of ROM into memory at 0x200. Returns 0 on success, -1 if it doesn't fit.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new and `rom` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(cpu: *mut Cpu, rom: *const u8, len: usize) -> i32 {
+    let cpu = &mut *cpu;
+    match cpu.load_rom(slice::from_raw_parts(rom, len)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Execute a single instruction.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(cpu: *mut Cpu) {
+    (*cpu).step();
+}
+
+/// Run one 60Hz frame: the frame's share of instructions then a timer tick.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(cpu: *mut Cpu) {
+    (*cpu).run_frame();
+}
+
+/// Tick the delay and sound timers, for callers that drive chip8_step themselves at 60Hz.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_tick_timers(cpu: *mut Cpu) {
+    (*cpu).tick_timers();
+}
+
+/// Copy the screen into `out`, one byte per pixel row by row (0 is unlit, XO-CHIP uses 0 to 3).
+/// Writes the resolution to `width` and `height` (either may be null) and returns how many
+/// bytes the whole screen needs. Nothing is copied if `len` is smaller than that.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new, `out` must have room for `len` bytes (or be null),
+/// `width` and `height` must be writable or null.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(cpu: *const Cpu, out: *mut u8, len: usize, width: *mut usize, height: *mut usize) -> usize {
+    let display = &(*cpu).display;
+    let (w, h) = (display.width(), display.height());
+    if !width.is_null() {
+        *width = w;
+    }
+    if !height.is_null() {
+        *height = h;
+    }
+
+    let needed = w * h;
+    if out.is_null() || len < needed {
+        return needed;
+    }
+
+    let out = slice::from_raw_parts_mut(out, needed);
+    for (dst, row) in out.chunks_mut(w).zip(display.rows()) {
+        dst.copy_from_slice(row);
+    }
+    needed
+}
+
+/// Press (non-zero `pressed`) or release a keypad key, 0x0 to 0xF.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_key_event(cpu: *mut Cpu, key: u8, pressed: i32) {
+    (*cpu).set_key(key, pressed != 0);
+}
+
+/// 1 while the buzzer should be sounding, 0 otherwise.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_playing(cpu: *const Cpu) -> i32 {
+    (*cpu).sound().playing as i32
+}
+
+/// 1 once the program has halted, 0 while it's running.
+///
+/// # Safety
+/// `cpu` must be a live pointer from chip8_new.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_is_halted(cpu: *const Cpu) -> i32 {
+    (*cpu).is_halted() as i32
+}
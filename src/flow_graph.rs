@@ -0,0 +1,277 @@
+// Control flow graphs.
+// `chip8 cfg` follows a ROM's control flow from its entry point the same way the transpiler
+// does (jumps, calls, both sides of every skip) and splits what it reaches into basic blocks:
+// runs of instructions that are always executed start to finish. Blocks end at anything that
+// can go somewhere other than the next instruction, and wherever something else jumps into the
+// middle of them. to_dot() writes the result out for Graphviz:
+//
+//   chip8 cfg game.ch8 -o game.dot && dot -Tsvg game.dot -o game.svg
+//
+// Calls are drawn dashed to the subroutine, with a plain edge on to the instruction after the
+// call (where the RET comes back to). BNNN's computed jumps can't be followed, those blocks
+// just end, and so does anything that isn't an instruction (usually the walk running into data).
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::symbols::Symbols;
+use crate::variant::Variant;
+
+/// How control gets from one block to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Straight on to the next instruction, including after a call returns
+    Next,
+    Jump,
+    Call,
+    /// A skip instruction's condition was true and it skipped the next instruction
+    Skip,
+}
+
+/// An edge out of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that always execute together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// (address, instruction), in order
+    pub instructions: Vec<(usize, Instruction)>,
+    pub exits: Vec<Edge>,
+}
+
+/// Every block reachable from the entry point, by start address.
+#[derive(Debug, Clone, Default)]
+pub struct FlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    /// Where the walk started, the variant's entry point
+    pub entry: usize,
+}
+
+impl FlowGraph {
+    /// Follow the code in `memory` (a CPU's memory with the ROM loaded) from `variant`'s entry
+    /// point. Jumps to below where programs load or off the end of memory are kept as edges
+    /// but not followed.
+    pub fn build(memory: &[u8], variant: Variant) -> Self {
+        let start = variant.program_start();
+        let entry = variant.entry_point();
+        let in_program = |addr: usize| addr >= start && addr + 1 < memory.len();
+
+        // first find every instruction that runs and every address something branches to
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut to_visit = vec![entry];
+        while let Some(addr) = to_visit.pop() {
+            if !in_program(addr) || instructions.contains_key(&addr) {
+                continue;
+            }
+            let instruction = Instruction::decode_at(memory, addr, variant);
+            instructions.insert(addr, instruction);
+            let exits = exits(memory, addr, instruction, variant);
+            // a branch, so whatever it goes to starts a block
+            if exits.len() != 1 || exits[0].kind != EdgeKind::Next {
+                leaders.extend(exits.iter().map(|edge| edge.to));
+            }
+            to_visit.extend(exits.iter().map(|edge| edge.to));
+        }
+
+        // then cut the instructions into blocks at the leaders and the branches
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|&&addr| instructions.contains_key(&addr)) {
+            let mut block = BasicBlock { start, instructions: Vec::new(), exits: Vec::new() };
+            let mut addr = start;
+            while let Some(&instruction) = instructions.get(&addr) {
+                block.instructions.push((addr, instruction));
+                let exits = exits(memory, addr, instruction, variant);
+                let next = addr + instruction.size();
+                let falls_through = exits.len() == 1 && exits[0].kind == EdgeKind::Next;
+                if !falls_through || leaders.contains(&next) || !instructions.contains_key(&next) {
+                    block.exits = exits;
+                    break;
+                }
+                addr = next;
+            }
+            blocks.insert(start, block);
+        }
+        FlowGraph { blocks, entry }
+    }
+
+    /// The graph in Graphviz's dot language, one box per block with its disassembly. Names from
+    /// `symbols` are used for block titles and in the instructions, `quirks` for how BNNN reads.
+    pub fn to_dot(&self, name: &str, symbols: &Symbols, quirks: &Quirks) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph \"{}\" {{", escape(name));
+        let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+        let title = |addr: usize| match symbols.name(addr) {
+            Some(name) => format!("{} (0x{:03X})", name, addr),
+            None => format!("0x{:03X}", addr),
+        };
+
+        for block in self.blocks.values() {
+            // \l ends a left-aligned line
+            let mut label = format!("{}:\\l", escape(&title(block.start)));
+            for &(addr, instruction) in &block.instructions {
+                let _ = write!(label, "{:03X}  {}\\l", addr, escape(&symbols.format(&instruction, quirks)));
+            }
+            let style = if block.start == self.entry { ", style=bold" } else { "" };
+            let _ = writeln!(dot, "    b{:03X} [label=\"{}\"{}];", block.start, label, style);
+        }
+
+        // edges to places the walk didn't follow still get a node, so they're visible
+        let outside: BTreeSet<usize> = self.edges().map(|(_, edge)| edge.to).filter(|to| !self.blocks.contains_key(to)).collect();
+        for addr in outside {
+            let _ = writeln!(dot, "    b{:03X} [label=\"{}\\nnot followed\", style=dashed];", addr, escape(&title(addr)));
+        }
+
+        for (from, edge) in self.edges() {
+            let attributes = match edge.kind {
+                EdgeKind::Next => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Call => " [label=\"call\", style=dashed]",
+                EdgeKind::Skip => " [label=\"skip\", color=blue]",
+            };
+            let _ = writeln!(dot, "    b{:03X} -> b{:03X}{};", from, edge.to, attributes);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // (block start, edge) for every edge
+    fn edges(&self) -> impl Iterator<Item = (usize, Edge)> + '_ {
+        self.blocks.values().flat_map(|block| block.exits.iter().map(move |&edge| (block.start, edge)))
+    }
+}
+
+// Where control can go after the instruction at `addr`
+fn exits(memory: &[u8], addr: usize, instruction: Instruction, variant: Variant) -> Vec<Edge> {
+    use Instruction::*;
+    let next = addr + instruction.size();
+    let edge = |to: usize, kind| Edge { to, kind };
+    match instruction {
+        // nowhere to go, or nowhere we can know about before it runs
+        Halt | Exit | Return | JumpWithOffset(..) | Unknown(_) => Vec::new(),
+        Jump(nnn) => vec![edge(nnn as usize, EdgeKind::Jump)],
+        Call(nnn) => vec![edge(nnn as usize, EdgeKind::Call), edge(next, EdgeKind::Next)],
+        SkipIfEqual(..) | SkipIfNotEqual(..) | SkipIfRegistersEqual(..) | SkipIfRegistersDiffer(..) | SkipIfKey(_) | SkipIfNotKey(_) => {
+            let skipped = Instruction::decode_at(memory, next, variant).size();
+            vec![edge(next, EdgeKind::Next), edge(next + skipped, EdgeKind::Skip)]
+        }
+        _ => vec![edge(next, EdgeKind::Next)],
+    }
+}
+
+// The inside of a dot string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn starts_where_the_variant_loads_programs() {
+        let mut cpu = Cpu::with_variant(Variant::Eti660);
+        // V6 = 1, jump back to the start
+        cpu.load_binary(&[0x66, 0x01, 0x16, 0x00]).unwrap();
+        let graph = FlowGraph::build(&cpu.memory, Variant::Eti660);
+        assert_eq!(graph.entry, 0x600);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), [0x600]);
+        let dot = graph.to_dot("eti", &Symbols::new(), &Variant::Eti660.default_quirks());
+        assert!(dot.contains("b600 [label=") && dot.contains(", style=bold]"), "{}", dot);
+    }
+
+    #[test]
+    fn two_page_hires_starts_past_the_patch() {
+        let mut rom = vec![0; 0xC2];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        // jump to itself
+        rom[0xC0..].copy_from_slice(&[0x12, 0xC0]);
+        let mut cpu = Cpu::with_variant(Variant::Chip8Hires);
+        cpu.load_binary(&rom).unwrap();
+        let graph = FlowGraph::build(&cpu.memory, Variant::Chip8Hires);
+        assert_eq!(graph.entry, 0x2C0);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), [0x2C0]);
+    }
+
+    const LOOP: [u8; 14] = [
+        0x60, 0x00, // 200 LD V0, 0
+        0x22, 0x0A, // 202 CALL 0x20A
+        0x30, 0x01, // 204 SE V0, 1
+        0x12, 0x02, // 206 JP 0x202
+        0x00, 0x00, // 208 HALT
+        0x70, 0x01, // 20A ADD V0, 1
+        0x00, 0xEE, // 20C RET
+    ];
+
+    fn graph(rom: &[u8]) -> FlowGraph {
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(rom).unwrap();
+        FlowGraph::build(&cpu.memory, Variant::Chip8)
+    }
+
+    #[test]
+    fn blocks_end_at_branches_and_where_branches_land() {
+        let graph = graph(&LOOP);
+        let edge = |to, kind| Edge { to, kind };
+        let exits = |start: usize| graph.blocks[&start].exits.clone();
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]);
+        // the jump back to 202 splits the first two instructions up
+        assert_eq!(exits(0x200), [edge(0x202, EdgeKind::Next)]);
+        assert_eq!(exits(0x202), [edge(0x20A, EdgeKind::Call), edge(0x204, EdgeKind::Next)]);
+        assert_eq!(exits(0x204), [edge(0x206, EdgeKind::Next), edge(0x208, EdgeKind::Skip)]);
+        assert_eq!(exits(0x206), [edge(0x202, EdgeKind::Jump)]);
+        assert!(exits(0x208).is_empty() && exits(0x20A).is_empty());
+        assert_eq!(graph.blocks[&0x20A].instructions, [(0x20A, Instruction::AddByte(0, 1)), (0x20C, Instruction::Return)]);
+    }
+
+    #[test]
+    fn computed_jumps_and_data_end_blocks() {
+        let graph = graph(&[
+            0x60, 0x02, // 200 LD V0, 2
+            0x22, 0x08, // 202 CALL 0x208
+            0x12, 0x0A, // 204 JP 0x20A
+            0x00, 0x00, // 206
+            0xB3, 0x00, // 208 JP V0, 0x300
+            0xFF, 0xFF, // 20A not an instruction
+        ]);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), [0x200, 0x204, 0x208, 0x20A]);
+        assert!(graph.blocks[&0x208].exits.is_empty());
+        assert_eq!(graph.blocks[&0x20A].instructions, [(0x20A, Instruction::Unknown(0xFFFF))]);
+        assert!(graph.blocks[&0x20A].exits.is_empty());
+    }
+
+    #[test]
+    fn dot() {
+        let symbols = Symbols::parse("20A add_one").unwrap();
+        let dot = graph(&LOOP).to_dot("loop \"1\"", &symbols, &Quirks::cosmac_vip());
+        assert!(dot.starts_with("digraph \"loop \\\"1\\\"\" {\n"), "{}", dot);
+        assert!(dot.contains("    b20A [label=\"add_one (0x20A):\\l20A  ADD V0, 0x01\\l20C  RET\\l\"];\n"), "{}", dot);
+        assert!(dot.contains("202  CALL add_one"), "{}", dot);
+        assert!(dot.contains("    b202 -> b20A [label=\"call\", style=dashed];\n"), "{}", dot);
+        assert!(dot.contains("    b204 -> b208 [label=\"skip\", color=blue];\n"), "{}", dot);
+        assert!(dot.contains("    b206 -> b202 [label=\"jump\"];\n"), "{}", dot);
+        assert!(dot.contains("    b200 -> b202;\n"), "{}", dot);
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn jumps_outside_the_program_arent_followed() {
+        let graph = graph(&[0x10, 0x50]);
+        assert_eq!(graph.blocks[&0x200].exits, [Edge { to: 0x050, kind: EdgeKind::Jump }]);
+        assert_eq!(graph.blocks.len(), 1);
+        let dot = graph.to_dot("out", &Symbols::new(), &Quirks::cosmac_vip());
+        assert!(dot.contains("    b050 [label=\"0x050\\nnot followed\", style=dashed];"), "{}", dot);
+    }
+}
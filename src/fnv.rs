@@ -0,0 +1,25 @@
+// FNV-1a.
+// The 64-bit version, for the digests that have to come out the same on every platform and
+// every version (RPL flag file names, Display::hash(), Cpu::state_digest()). It isn't much of a
+// hash, but it's tiny and stable, and that's all these need.
+
+const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// 64-bit FNV-1a over `bytes`, in order.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from the reference implementation's test suite
+    #[test]
+    fn known_vectors() {
+        assert_eq!(fnv1a(*b""), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(fnv1a(*b"a"), 0xAF63_DC4C_8601_EC8C);
+        assert_eq!(fnv1a(*b"foobar"), 0x8594_4171_F739_67E8);
+    }
+}
@@ -0,0 +1,163 @@
+// Frontend traits.
+// Everything outside the CPU goes one of three ways: the screen goes out to a DisplaySink, keys
+// come in from an InputSource, and the sound state goes out to an AudioSink. Anything that
+// implements them can be driven by play() (or a loop of your own), so an LED matrix, a canvas
+// or a test double plugs in without touching the core. The terminal frontend implements the
+// first two and AudioOutput the third.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::audio::Sound;
+use crate::cpu::Cpu;
+use crate::display::Display;
+use crate::keymap::Hotkey;
+
+/// Whatever went wrong in a frontend, they all have their own error types.
+pub type FrontendError = Box<dyn core::error::Error>;
+
+/// Shows the screen.
+pub trait DisplaySink {
+    /// Show the screen as it is now. Not necessarily called every frame, fast-forward skips some.
+    fn present(&mut self, display: &Display) -> Result<(), FrontendError>;
+
+    /// Draw `lines` of text over the top left corner of the screen, after present(). None takes
+    /// it off again. Frontends that can't show text can leave it doing nothing.
+    fn overlay(&mut self, _lines: Option<&[String]>) -> Result<(), FrontendError> {
+        Ok(())
+    }
+}
+
+/// Reads the keypad (and hotkeys) from somewhere.
+pub trait InputSource {
+    /// Called once per frame before it runs: press and release keys with Cpu::set_key() and
+    /// return the hotkeys pressed since last time.
+    fn poll(&mut self, cpu: &mut Cpu) -> Result<Vec<Hotkey>, FrontendError>;
+
+    /// Called once per frame after it runs, for inputs that have to time key releases themselves.
+    fn end_frame(&mut self, _cpu: &mut Cpu) {}
+}
+
+/// Plays the machine's sound.
+pub trait AudioSink {
+    /// What the machine sounds like now, called once per frame.
+    fn update(&mut self, sound: Sound);
+
+    /// How loud to play, from 0.0 (silent) to 1.0. Sinks without a volume ignore it.
+    fn set_volume(&mut self, _volume: f32) {}
+
+    /// Go quiet, or back to the volume it was, without the machine knowing.
+    fn set_muted(&mut self, _muted: bool) {}
+}
+
+/// An AudioSink that doesn't make any sound.
+pub struct Silent;
+
+impl AudioSink for Silent {
+    fn update(&mut self, _sound: Sound) {}
+}
+
+impl<T: DisplaySink + ?Sized> DisplaySink for &mut T {
+    fn present(&mut self, display: &Display) -> Result<(), FrontendError> {
+        (**self).present(display)
+    }
+
+    fn overlay(&mut self, lines: Option<&[String]>) -> Result<(), FrontendError> {
+        (**self).overlay(lines)
+    }
+}
+
+impl<T: InputSource + ?Sized> InputSource for &mut T {
+    fn poll(&mut self, cpu: &mut Cpu) -> Result<Vec<Hotkey>, FrontendError> {
+        (**self).poll(cpu)
+    }
+
+    fn end_frame(&mut self, cpu: &mut Cpu) {
+        (**self).end_frame(cpu)
+    }
+}
+
+impl<T: AudioSink + ?Sized> AudioSink for &mut T {
+    fn update(&mut self, sound: Sound) {
+        (**self).update(sound)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        (**self).set_volume(volume)
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        (**self).set_muted(muted)
+    }
+}
+
+/// A separate DisplaySink and InputSource used as one frontend, for play().
+pub struct Split<D, I> {
+    pub display: D,
+    pub input: I,
+}
+
+impl<D: DisplaySink, I> DisplaySink for Split<D, I> {
+    fn present(&mut self, display: &Display) -> Result<(), FrontendError> {
+        self.display.present(display)
+    }
+
+    fn overlay(&mut self, lines: Option<&[String]>) -> Result<(), FrontendError> {
+        self.display.overlay(lines)
+    }
+}
+
+impl<D, I: InputSource> InputSource for Split<D, I> {
+    fn poll(&mut self, cpu: &mut Cpu) -> Result<Vec<Hotkey>, FrontendError> {
+        self.input.poll(cpu)
+    }
+
+    fn end_frame(&mut self, cpu: &mut Cpu) {
+        self.input.end_frame(cpu)
+    }
+}
+
+/// Play `cpu` in real time until the program halts or the frontend asks to quit. Handles the
+/// hotkeys every frontend shares (quit, pause, frame advance, fast-forward, the debug overlay
+/// and mute) and ignores the rest. One object usually does both display and input (a window, a terminal), use Split
+/// when they're separate.
+#[cfg(feature = "std")]
+pub fn play<F>(cpu: &mut Cpu, frontend: &mut F, audio: &mut dyn AudioSink) -> Result<(), FrontendError>
+where
+    F: DisplaySink + InputSource + ?Sized,
+{
+    let mut clock = crate::clock::Clock::new();
+    let mut meter = crate::stats::StatsMeter::new(cpu);
+    let mut show_overlay = false;
+    let mut muted = false;
+    while !cpu.is_halted() {
+        for hotkey in frontend.poll(cpu)? {
+            match hotkey {
+                Hotkey::Quit => return Ok(()),
+                Hotkey::ToggleTurbo => clock.toggle_turbo(),
+                Hotkey::TogglePause if cpu.is_paused() => cpu.resume(),
+                Hotkey::TogglePause => cpu.pause(),
+                Hotkey::AdvanceFrame if cpu.is_paused() => cpu.advance_frame(),
+                Hotkey::ToggleOverlay => show_overlay = !show_overlay,
+                Hotkey::ToggleMute => {
+                    muted = !muted;
+                    audio.set_muted(muted);
+                }
+                _ => {}
+            }
+        }
+
+        cpu.run_frame();
+        frontend.end_frame(cpu);
+        if clock.should_present() {
+            frontend.present(&cpu.display)?;
+            meter.frame_presented();
+            let ips = meter.current(cpu).ips;
+            frontend.overlay(show_overlay.then(|| crate::overlay::overlay_lines(cpu, ips)).as_deref())?;
+        }
+        audio.update(cpu.sound());
+        clock.wait_for_next_frame();
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+// Gamepads.
+// Any controller gilrs knows about (which is most of them, it has SDL's mapping database) can
+// drive the keypad. Buttons and the d-pad press keypad keys, the left stick works as a second
+// d-pad, Start pauses and Select toggles fast-forward. Every connected pad drives the same
+// keypad, so two people can share one game.
+//
+// The default mapping is the same as the libretro core's: the d-pad is 2/8/4/6 (up, down, left,
+// right), which is what most CHIP-8 games move with, and the face buttons are 5, 0, A and B.
+// Buttons are named after where they are on the pad, so "south" is A on an Xbox pad and cross
+// on a PlayStation one:
+//   south east north west c z left-trigger left-trigger2 right-trigger right-trigger2
+//   left-thumb right-thumb dpad-up dpad-down dpad-left dpad-right
+
+use std::fmt;
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+use crate::cpu::Cpu;
+use crate::frontend::{FrontendError, InputSource};
+use crate::keymap::Hotkey;
+
+// How far the stick has to be pushed to count as a d-pad press
+const STICK_THRESHOLD: f32 = 0.5;
+
+const BUTTON_NAMES: [(&str, Button); 16] = [
+    ("south", Button::South),
+    ("east", Button::East),
+    ("north", Button::North),
+    ("west", Button::West),
+    ("c", Button::C),
+    ("z", Button::Z),
+    ("left-trigger", Button::LeftTrigger),
+    ("left-trigger2", Button::LeftTrigger2),
+    ("right-trigger", Button::RightTrigger),
+    ("right-trigger2", Button::RightTrigger2),
+    ("left-thumb", Button::LeftThumb),
+    ("right-thumb", Button::RightThumb),
+    ("dpad-up", Button::DPadUp),
+    ("dpad-down", Button::DPadDown),
+    ("dpad-left", Button::DPadLeft),
+    ("dpad-right", Button::DPadRight),
+];
+
+/// Which keypad key each button presses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GamepadMap {
+    buttons: Vec<(Button, u8)>,
+}
+
+impl GamepadMap {
+    /// No buttons bound at all.
+    pub fn empty() -> Self {
+        GamepadMap { buttons: Vec::new() }
+    }
+
+    /// Make `button` press `key`, replacing whatever it pressed before.
+    pub fn bind(&mut self, button: Button, key: u8) {
+        self.buttons.retain(|&(other, _)| other != button);
+        self.buttons.push((button, key & 0xF));
+    }
+
+    /// bind(), with the button by name (see the top of this file). False if there's no such button.
+    pub fn bind_named(&mut self, name: &str, key: u8) -> bool {
+        match BUTTON_NAMES.iter().find(|(other, _)| other.eq_ignore_ascii_case(name)) {
+            Some(&(_, button)) => {
+                self.bind(button, key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The keypad key a button presses, if any.
+    pub fn key_for(&self, button: Button) -> Option<u8> {
+        self.buttons.iter().find(|&&(other, _)| other == button).map(|&(_, key)| key)
+    }
+}
+
+impl Default for GamepadMap {
+    fn default() -> Self {
+        let mut map = GamepadMap::empty();
+        for (button, key) in [
+            (Button::DPadUp, 0x2),
+            (Button::DPadDown, 0x8),
+            (Button::DPadLeft, 0x4),
+            (Button::DPadRight, 0x6),
+            (Button::East, 0x5),
+            (Button::South, 0x0),
+            (Button::North, 0xA),
+            (Button::West, 0xB),
+        ] {
+            map.bind(button, key);
+        }
+        map
+    }
+}
+
+/// Every connected gamepad, as an InputSource. Pads can come and go while it's open.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    map: GamepadMap,
+    // which way the left stick is pushed on each axis, -1, 0 or 1
+    stick: [i8; 2],
+}
+
+/// Why gamepads couldn't be opened.
+#[derive(Debug)]
+pub struct GamepadError(String);
+
+impl fmt::Display for GamepadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GamepadError {}
+
+impl Gamepads {
+    pub fn open(map: GamepadMap) -> Result<Self, GamepadError> {
+        // gilrs' own error can hold a whole (dummy) context, just keep what it says
+        let gilrs = Gilrs::new().map_err(|e| GamepadError(e.to_string()))?;
+        Ok(Gamepads { gilrs, map, stick: [0; 2] })
+    }
+
+    /// How many pads are plugged in.
+    pub fn connected(&self) -> usize {
+        self.gilrs.gamepads().count()
+    }
+
+    fn set_button(&self, cpu: &mut Cpu, button: Button, pressed: bool) {
+        if let Some(key) = self.map.key_for(button) {
+            cpu.set_key(key, pressed);
+        }
+    }
+
+    // The stick moved along one axis, let go of the direction it was pushed and press the new one
+    fn move_stick(&mut self, cpu: &mut Cpu, axis: usize, value: f32) {
+        let direction = if value > STICK_THRESHOLD {
+            1
+        } else if value < -STICK_THRESHOLD {
+            -1
+        } else {
+            0
+        };
+        if direction == self.stick[axis] {
+            return;
+        }
+        // gilrs has up as positive
+        let button = |direction: i8| match (axis, direction) {
+            (0, -1) => Some(Button::DPadLeft),
+            (0, 1) => Some(Button::DPadRight),
+            (1, -1) => Some(Button::DPadDown),
+            (1, 1) => Some(Button::DPadUp),
+            _ => None,
+        };
+        if let Some(old) = button(self.stick[axis]) {
+            self.set_button(cpu, old, false);
+        }
+        if let Some(new) = button(direction) {
+            self.set_button(cpu, new, true);
+        }
+        self.stick[axis] = direction;
+    }
+}
+
+impl InputSource for Gamepads {
+    fn poll(&mut self, cpu: &mut Cpu) -> Result<Vec<Hotkey>, FrontendError> {
+        let mut hotkeys = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(Button::Start, _) => hotkeys.push(Hotkey::TogglePause),
+                EventType::ButtonPressed(Button::Select, _) => hotkeys.push(Hotkey::ToggleTurbo),
+                EventType::ButtonPressed(button, _) => self.set_button(cpu, button, true),
+                EventType::ButtonReleased(button, _) => self.set_button(cpu, button, false),
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.move_stick(cpu, 0, value),
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => self.move_stick(cpu, 1, value),
+                _ => {}
+            }
+        }
+        Ok(hotkeys)
+    }
+}
@@ -0,0 +1,499 @@
+// GDB remote debugging.
+// Speaks enough of the GDB remote serial protocol for gdb (or an IDE driving gdb) to attach over
+// TCP, read and write registers and memory, set breakpoints, and step or continue a running ROM.
+//   chip8 run --gdb 127.0.0.1:1234 game.ch8
+//   (gdb) target remote 127.0.0.1:1234
+// Packets look like $<data>#<two hex digit checksum>, and every packet gets acked with a +.
+// The one exception is a raw 0x03 byte, which is gdb asking us to stop (Ctrl-C).
+//
+// gdb has no idea what a CHIP-8 is, so we describe the registers in a target.xml:
+// V0 to VF, then I, PC and SP. Addresses are plain offsets into CPU memory.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::breakpoints::{Breakpoints, Stop};
+use crate::cpu::Cpu;
+
+// gdb's SIGTRAP, what every stop is reported as
+const SIGTRAP: u8 = 5;
+// The byte gdb sends outside of a packet to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+// V0-VF, I, PC, SP
+const REGISTER_COUNT: usize = 19;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// A gdb remote stub driving a Cpu. The frontend calls run_frame() on it instead of on the CPU,
+/// and the debugger decides whether anything actually runs.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    // bytes read from the client that don't make a whole packet yet
+    pending: Vec<u8>,
+    breakpoints: Breakpoints,
+    // false while gdb has the CPU stopped
+    running: bool,
+}
+
+impl GdbServer {
+    /// Listen for gdb on `addr`, e.g. "127.0.0.1:1234".
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(GdbServer {
+            listener: TcpListener::bind(addr)?,
+            client: None,
+            pending: Vec::new(),
+            breakpoints: Breakpoints::new(),
+            running: true,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Block until gdb connects. The CPU starts out stopped so breakpoints can be set first.
+    pub fn wait_for_client(&mut self) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        self.client = Some(stream);
+        self.pending.clear();
+        self.running = false;
+        Ok(())
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Handle whatever gdb has sent, then run one frame's worth of instructions if gdb lets us,
+    /// stopping early at a breakpoint. Once gdb detaches the CPU just runs normally.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        if self.client.is_none() {
+            cpu.run_frame();
+            return Ok(());
+        }
+
+        self.poll(cpu)?;
+        if !self.running || cpu.is_paused() {
+            return Ok(());
+        }
+
+        let stop = self.breakpoints.run_frame(cpu);
+        self.report(stop)
+    }
+
+    // Let gdb know if the program stopped
+    fn report(&mut self, stop: Option<Stop>) -> io::Result<()> {
+        match stop {
+            Some(Stop::Breakpoint(_) | Stop::Collision(_) | Stop::Opcode(..) | Stop::Returned(_)) => self.stop(SIGTRAP),
+            Some(Stop::Halted) => {
+                // tell gdb the program exited, it'll hang up after that
+                self.running = false;
+                self.send("W00")
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Read everything gdb has sent without blocking and answer each packet
+    fn poll(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        let Some(client) = self.client.as_mut() else { return Ok(()) };
+
+        client.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let mut hung_up = false;
+        loop {
+            match client.read(&mut buffer) {
+                Ok(0) => {
+                    hung_up = true;
+                    break;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    client.set_nonblocking(false)?;
+                    return Err(e);
+                }
+            }
+        }
+        client.set_nonblocking(false)?;
+
+        while let Some(packet) = self.next_packet()? {
+            self.handle(&packet, cpu)?;
+        }
+        if hung_up {
+            self.detach();
+        }
+        Ok(())
+    }
+
+    // Pull the next complete packet out of `pending`, acking it. Interrupts come back as "\x03".
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let Some(&first) = self.pending.first() else { return Ok(None) };
+            match first {
+                INTERRUPT => {
+                    self.pending.remove(0);
+                    return Ok(Some("\x03".to_string()));
+                }
+                b'$' => break,
+                // acks (+), nacks (-) and line noise before a packet
+                _ => {
+                    self.pending.remove(0);
+                }
+            }
+        }
+
+        let Some(hash) = self.pending.iter().position(|&b| b == b'#') else { return Ok(None) };
+        if self.pending.len() < hash + 3 {
+            return Ok(None);
+        }
+        let data = String::from_utf8_lossy(&self.pending[1..hash]).into_owned();
+        self.pending.drain(..hash + 3);
+        self.write_raw(b"+")?;
+        Ok(Some(data))
+    }
+
+    fn handle(&mut self, packet: &str, cpu: &mut Cpu) -> io::Result<()> {
+        if packet == "\x03" {
+            if self.running {
+                self.stop(SIGTRAP)?;
+            }
+            return Ok(());
+        }
+
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match command {
+            "?" => self.send(&format!("S{:02x}", SIGTRAP)),
+            "g" => {
+                let registers: String = (0..REGISTER_COUNT).map(|n| read_register(cpu, n)).collect();
+                self.send(&registers)
+            }
+            "G" => {
+                let mut offset = 0;
+                for n in 0..REGISTER_COUNT {
+                    let width = register_width(n) * 2;
+                    let Some(value) = args.get(offset..offset + width) else { break };
+                    write_register(cpu, n, value);
+                    offset += width;
+                }
+                self.send("OK")
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_COUNT => self.send(&read_register(cpu, n)),
+                _ => self.send("E01"),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| Some((usize::from_str_radix(n, 16).ok()?, value)));
+                match parsed {
+                    Some((n, value)) if n < REGISTER_COUNT => {
+                        write_register(cpu, n, value);
+                        self.send("OK")
+                    }
+                    _ => self.send("E01"),
+                }
+            }
+            "m" => match parse_range(args).and_then(|(addr, len)| cpu.memory.get(addr..addr.checked_add(len)?)) {
+                Some(bytes) => self.send(&to_hex(bytes)),
+                None => self.send("E01"),
+            },
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = from_hex(data)?;
+                    let target = cpu.memory.get_mut(addr..addr.checked_add(len)?)?;
+                    (bytes.len() == len).then(|| target.copy_from_slice(&bytes))
+                });
+                if written.is_some() {
+                    cpu.flush_decoded();
+                }
+                self.send(if written.is_some() { "OK" } else { "E01" })
+            }
+            "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.position_in_memory = addr;
+                }
+                self.running = true;
+                let stop = self.breakpoints.resume(cpu);
+                self.report(stop)
+            }
+            "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.position_in_memory = addr;
+                }
+                cpu.run_for(1);
+                self.send(&format!("S{:02x}", SIGTRAP))
+            }
+            // Z0 software, Z1 hardware, it's all the same to us
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(parse_hex);
+                match (kind, addr) {
+                    (Some("0" | "1"), Some(addr)) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(addr);
+                        }
+                        self.send("OK")
+                    }
+                    // watchpoints aren't supported, an empty reply says so
+                    _ => self.send(""),
+                }
+            }
+            "q" => self.handle_query(args),
+            // there's only one thread, whichever gdb picks is fine
+            "H" => self.send("OK"),
+            "T" => self.send("OK"),
+            "D" => {
+                self.send("OK")?;
+                self.detach();
+                Ok(())
+            }
+            "k" => {
+                self.detach();
+                Ok(())
+            }
+            _ => self.send(""),
+        }
+    }
+
+    fn handle_query(&mut self, query: &str) -> io::Result<()> {
+        if query.starts_with("Supported") {
+            return self.send("PacketSize=4000;qXfer:features:read+");
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, len)) = parse_range(range) else { return self.send("E01") };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(len).min(xml.len());
+            // m means there's more to come, l means that's the last of it
+            let marker = if end < xml.len() { 'm' } else { 'l' };
+            return self.send(&format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end])));
+        }
+        match query {
+            "Attached" => self.send("1"),
+            "C" => self.send("QC1"),
+            "fThreadInfo" => self.send("m1"),
+            "sThreadInfo" => self.send("l"),
+            _ => self.send(""),
+        }
+    }
+
+    // Stop running and tell gdb why
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.running = false;
+        self.send(&format!("S{:02x}", signal))
+    }
+
+    // gdb is gone, breakpoints go with it and the program carries on by itself
+    fn detach(&mut self) {
+        self.client = None;
+        self.pending.clear();
+        self.breakpoints.clear();
+        self.running = true;
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let data = escape(data);
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write_raw(format!("${}#{:02x}", data, checksum).as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.client.as_mut() {
+            Some(client) => client.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+// Registers go over the wire as little endian hex
+fn read_register(cpu: &Cpu, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", cpu.registers[n]),
+        // only I's bottom 16 bits fit, which is all of it except on Mega-Chip
+        REG_I => to_hex(&(cpu.index_register as u16).to_le_bytes()),
+        REG_PC => to_hex(&(cpu.position_in_memory as u16).to_le_bytes()),
+        REG_SP => format!("{:02x}", cpu.stack_pointer as u8),
+        _ => String::new(),
+    }
+}
+
+fn write_register(cpu: &mut Cpu, n: usize, hex: &str) {
+    let Some(bytes) = from_hex(hex) else { return };
+    let value = bytes.iter().rev().fold(0u16, |value, &b| value << 8 | b as u16);
+    match n {
+        0..=15 => cpu.registers[n] = value as u8,
+        REG_I => cpu.index_register = value as u32,
+        REG_PC => cpu.position_in_memory = value as usize,
+        REG_SP => cpu.stack_pointer = (value as usize).min(cpu.stack.len()),
+        _ => {}
+    }
+}
+
+// In bytes
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "addr,length", both in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    // an odd length leaves half a byte at the end, which get() turns into None
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// $, #, } and * mean something in the protocol, they're sent as } followed by the byte xor 0x20
+fn escape(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        if matches!(c, '$' | '#' | '}' | '*') {
+            escaped.push('}');
+            escaped.push((c as u8 ^ 0x20) as char);
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // A stub with gdb attached, and gdb's end of the connection
+    fn attached() -> (GdbServer, TcpStream) {
+        let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+        server.wait_for_client().unwrap();
+        (server, client)
+    }
+
+    fn packet(data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${}#{:02x}", data, checksum)
+    }
+
+    // Send `data` from gdb and give the stub frames until it's answered with a whole packet,
+    // returning everything it sent back
+    fn exchange(server: &mut GdbServer, cpu: &mut Cpu, client: &mut TcpStream, data: &[u8]) -> String {
+        client.write_all(data).unwrap();
+        let mut received = String::new();
+        let mut buffer = [0; 4096];
+        for _ in 0..500 {
+            server.run_frame(cpu).unwrap();
+            if let Ok(n) = client.read(&mut buffer) {
+                received.push_str(&String::from_utf8_lossy(&buffer[..n]));
+            }
+            if received.find('#').is_some_and(|at| at + 2 < received.len()) {
+                return received;
+            }
+        }
+        panic!("no answer to {:?}, got {:?}", String::from_utf8_lossy(data), received);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(packet("?"), "$?#3f");
+        assert_eq!(packet("S05"), "$S05#b8");
+        assert_eq!(packet(""), "$#00");
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(escape("a$b#c}d*e"), "a}\x04b}\x03c}]d}\x0ae");
+        assert_eq!(escape("OK"), "OK");
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(from_hex("0a1B"), Some(vec![0x0A, 0x1B]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(to_hex(&[0x00, 0xFF]), "00ff");
+        assert_eq!(parse_range("200,10"), Some((0x200, 0x10)));
+        assert_eq!(parse_range("200"), None);
+    }
+
+    #[test]
+    fn packets_are_acked_and_answered() {
+        let (mut server, mut client) = attached();
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(&[0x60, 0x2A]).unwrap();
+        let mut send = |cpu: &mut Cpu, data: &str| exchange(&mut server, cpu, &mut client, data.as_bytes());
+
+        // an ack and some noise before the packet are skipped
+        assert_eq!(send(&mut cpu, &["+x", &packet("?")].concat()), ["+", &packet("S05")].concat());
+        assert_eq!(send(&mut cpu, &packet("m200,2")), ["+", &packet("602a")].concat());
+        assert_eq!(send(&mut cpu, &packet("P0=7f")), ["+", &packet("OK")].concat());
+        assert_eq!(cpu.registers[0], 0x7F);
+        // PC is 16 bits, little endian
+        assert_eq!(send(&mut cpu, &packet("p11")), ["+", &packet("0002")].concat());
+        assert_eq!(send(&mut cpu, &packet("m1000,1")), ["+", &packet("E01")].concat());
+        assert_eq!(send(&mut cpu, &packet("vMustReplyEmpty")), ["+", &packet("")].concat());
+    }
+
+    #[test]
+    fn packets_split_across_reads() {
+        let (mut server, mut client) = attached();
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        let read = packet("m200,2");
+        client.write_all(&read.as_bytes()[..4]).unwrap();
+        for _ in 0..10 {
+            server.run_frame(&mut cpu).unwrap();
+        }
+        assert!(client.read(&mut [0; 16]).is_err(), "answered half a packet");
+        assert_eq!(exchange(&mut server, &mut cpu, &mut client, &read.as_bytes()[4..]), ["+", &packet("0000")].concat());
+    }
+}
@@ -0,0 +1,591 @@
+// Graphical debugger.
+// An egui window with a panel for everything: the screen, registers, a memory hexdump (editable
+// while stopped), disassembly around PC, breakpoints, and timing controls. Panels are separate
+// windows that can be dragged around, collapsed, or closed and brought back from the View menu.
+// View has a keypad as well, off to begin with: the 16 keys laid out like the real one with the
+// keyboard key for each, lit up while they're held, that can be clicked (or tapped) to play
+// without learning the keymap.
+// Emulation runs at 60Hz (times the speed setting) off the wall clock, whatever rate egui
+// happens to repaint at.
+
+use std::time::Instant;
+
+use eframe::egui::{self, Color32, ColorImage, Key, RichText, TextureHandle, TextureOptions};
+
+use crate::breakpoints::{Breakpoints, OpcodePattern, Stop};
+use crate::call_stack::CallFrame;
+use crate::clock::FRAME;
+use crate::cpu::Cpu;
+use crate::disasm::disassemble_around;
+use crate::crt::CrtEffects;
+use crate::keymap::{Keymap, Player, KEYPAD_ORDER};
+use crate::palette::Palette;
+use crate::stats::StatsMeter;
+use crate::symbols::Symbols;
+use crate::variant::Variant;
+
+// Bytes per hexdump row
+const HEXDUMP_WIDTH: usize = 16;
+// How many bytes from I get highlighted, enough for the biggest sprite or a full register dump
+const I_HIGHLIGHT_LEN: usize = 16;
+// Instructions shown in the disassembly panel
+const DISASSEMBLY_LINES: usize = 32;
+// Never try to catch up on more than this many frames at once (after a stall, say)
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// Which panels are open
+struct Panels {
+    screen: bool,
+    registers: bool,
+    memory: bool,
+    disassembly: bool,
+    breakpoints: bool,
+    timing: bool,
+    keypad: bool,
+}
+
+pub struct GuiDebugger {
+    cpu: Cpu,
+    // kept to restart the program from the Timing panel
+    rom: Vec<u8>,
+    keymap: Keymap,
+    palette: Palette,
+    crt: CrtEffects,
+    breakpoints: Breakpoints,
+    symbols: Symbols,
+    running: bool,
+    // 1.0 is real time
+    speed: f32,
+    // frames owed, carried between repaints
+    frame_debt: f32,
+    last_update: Instant,
+    // how fast it's really going, for the Timing panel
+    meter: StatsMeter,
+    status: String,
+    panels: Panels,
+    screen: Option<TextureHandle>,
+    new_breakpoint: String,
+    new_pattern: String,
+    // the hexdump byte being edited, and what's been typed so far
+    editing_byte: Option<(usize, String)>,
+    // the keypad panel's key being held down with the mouse
+    clicked_key: Option<u8>,
+}
+
+impl GuiDebugger {
+    /// A debugger for `rom`, stopped at the first instruction.
+    pub fn new(cpu: Cpu, rom: Vec<u8>, keymap: Keymap) -> Self {
+        GuiDebugger {
+            meter: StatsMeter::new(&cpu),
+            cpu,
+            rom,
+            keymap,
+            palette: Palette::default(),
+            crt: CrtEffects::default(),
+            breakpoints: Breakpoints::new(),
+            symbols: Symbols::new(),
+            running: false,
+            speed: 1.0,
+            frame_debt: 0.0,
+            last_update: Instant::now(),
+            status: "stopped".to_string(),
+            panels: Panels {
+                screen: true,
+                registers: true,
+                memory: true,
+                disassembly: true,
+                breakpoints: true,
+                timing: true,
+                keypad: false,
+            },
+            screen: None,
+            new_breakpoint: String::new(),
+            new_pattern: String::new(),
+            editing_byte: None,
+            clicked_key: None,
+        }
+    }
+
+    /// Change the colours the screen is drawn in.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Names for addresses, for the disassembly and breakpoints.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Open the debugger window, returns once it's closed.
+    pub fn run(self) -> eframe::Result {
+        let options = eframe::NativeOptions {
+            viewport: egui::ViewportBuilder::default().with_inner_size([1100.0, 760.0]),
+            ..Default::default()
+        };
+        eframe::run_native("CHIP-8 debugger", options, Box::new(|_| Ok(Box::new(self))))
+    }
+
+    // Run however many frames are owed since the last repaint
+    fn emulate(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update);
+        self.last_update = now;
+        if !self.running {
+            self.frame_debt = 0.0;
+            return;
+        }
+
+        self.frame_debt += elapsed.as_secs_f32() / FRAME.as_secs_f32() * self.speed;
+        let frames = (self.frame_debt as u32).min(MAX_CATCH_UP_FRAMES);
+        self.frame_debt = self.frame_debt.fract();
+        for _ in 0..frames {
+            let stop = self.breakpoints.run_frame(&mut self.cpu);
+            if self.report(stop) {
+                break;
+            }
+        }
+    }
+
+    // Stop if running with breakpoints stopped, true if it did
+    fn report(&mut self, stop: Option<Stop>) -> bool {
+        match stop {
+            Some(Stop::Breakpoint(addr)) => match self.symbols.name(addr) {
+                Some(name) => self.status = format!("breakpoint at 0x{:03X} ({})", addr, name),
+                None => self.status = format!("breakpoint at 0x{:03X}", addr),
+            },
+            Some(Stop::Collision(addr)) => self.status = format!("collision drawing at 0x{:03X}", addr),
+            Some(Stop::Opcode(addr, opcode)) => self.status = format!("{:04X} at 0x{:03X}", opcode, addr),
+            Some(Stop::Returned(addr)) => self.status = format!("returned to 0x{:03X}", addr),
+            Some(Stop::Halted) => self.status = format!("halted: {:?}", self.cpu.halt_reason().unwrap()),
+            None => return false,
+        }
+        self.running = false;
+        true
+    }
+
+    fn resume(&mut self) {
+        self.running = true;
+        self.status = "running".to_string();
+        let stop = self.breakpoints.resume(&mut self.cpu);
+        self.report(stop);
+    }
+
+    fn stop(&mut self) {
+        self.running = false;
+        self.breakpoints.cancel_step();
+        self.status = "stopped".to_string();
+    }
+
+    fn step(&mut self) {
+        self.running = false;
+        self.breakpoints.cancel_step();
+        self.cpu.run_for(1);
+        self.status = format!("stepped to 0x{:03X}", self.cpu.position_in_memory);
+    }
+
+    // Step, but run a CALL until it comes back
+    fn step_over(&mut self) {
+        if self.breakpoints.step_over(&self.cpu) {
+            self.resume();
+        } else {
+            self.step();
+        }
+    }
+
+    // Run until the current subroutine returns
+    fn step_out(&mut self) {
+        if self.breakpoints.step_out(&self.cpu) {
+            self.resume();
+        } else {
+            self.status = "not in a subroutine".to_string();
+        }
+    }
+
+    // Start the program again on a fresh machine with the same settings
+    fn restart(&mut self) {
+        let mut cpu = Cpu::with_variant(self.cpu.variant);
+        cpu.quirks = self.cpu.quirks;
+        cpu.clock_speed = self.cpu.clock_speed;
+        cpu.rpl_flags = self.cpu.rpl_flags;
+        cpu.display.set_phosphor_decay(self.cpu.display.phosphor_decay());
+        if cpu.load_binary(&self.rom).is_ok() {
+            self.meter = StatsMeter::new(&cpu);
+            self.cpu = cpu;
+            self.stop();
+        }
+    }
+
+    // Keyboard to keypad, unless a text box has the keyboard, and the keypad panel's clicks
+    fn read_keypad(&mut self, ctx: &egui::Context) {
+        let typing = ctx.wants_keyboard_input();
+        // a split keymap has two keyboard keys for a CHIP-8 key, either one holds it down, unless
+        // it's CHIP-8X where player 2 has a keypad of their own
+        let second_keypad = self.cpu.variant == Variant::Chip8X;
+        let mut pressed = [[false; 16]; 2];
+        ctx.input(|input| {
+            for (key, c) in self.keymap.bindings() {
+                let down = Key::from_name(&c.to_ascii_uppercase().to_string()).is_some_and(|k| input.key_down(k));
+                let keypad = match self.keymap.player_key_for(c) {
+                    Some((Player::Two, _)) if second_keypad => 1,
+                    _ => 0,
+                };
+                pressed[keypad][key as usize] |= down && !typing;
+            }
+        });
+        if let Some(key) = self.clicked_key {
+            pressed[0][key as usize] = true;
+        }
+        for (key, (&down, &down2)) in pressed[0].iter().zip(&pressed[1]).enumerate() {
+            self.cpu.set_key(key as u8, down);
+            self.cpu.set_key2(key as u8, down2);
+        }
+    }
+
+    fn menu_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+            egui::MenuBar::new().ui(ui, |ui| {
+                ui.menu_button("View", |ui| {
+                    ui.checkbox(&mut self.panels.screen, "Screen");
+                    ui.checkbox(&mut self.panels.registers, "Registers");
+                    ui.checkbox(&mut self.panels.memory, "Memory");
+                    ui.checkbox(&mut self.panels.disassembly, "Disassembly");
+                    ui.checkbox(&mut self.panels.breakpoints, "Breakpoints");
+                    ui.checkbox(&mut self.panels.timing, "Timing");
+                    ui.checkbox(&mut self.panels.keypad, "Keypad");
+                });
+                ui.separator();
+                ui.label(&self.status);
+            });
+        });
+    }
+
+    fn screen_panel(&mut self, ctx: &egui::Context) {
+        let display = &self.cpu.display;
+        let (width, height) = (display.width(), display.height());
+        let to_color = |[r, g, b]: [u8; 3]| Color32::from_rgb(r, g, b);
+        let (image, options) = if self.crt.any() {
+            let rgb: Vec<[u8; 3]> = display.rgb_pixels(&self.palette).collect();
+            let crt = self.crt.apply(width, height, &rgb);
+            let pixels = crt.pixels.into_iter().map(to_color).collect();
+            // already scaled up, smooth the rest of the way so the scanlines don't alias
+            (ColorImage::new([crt.width, crt.height], pixels), TextureOptions::LINEAR)
+        } else {
+            let pixels = display.rgb_pixels(&self.palette).map(to_color).collect();
+            // nearest neighbour, so pixels stay sharp when scaled up
+            (ColorImage::new([width, height], pixels), TextureOptions::NEAREST)
+        };
+        match &mut self.screen {
+            Some(texture) => texture.set(image, options),
+            None => self.screen = Some(ctx.load_texture("screen", image, options)),
+        }
+
+        let Some(texture) = &self.screen else { return };
+        let crt = &mut self.crt;
+        egui::Window::new("Screen").open(&mut self.panels.screen).default_pos([10.0, 40.0]).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut crt.scanlines, "Scanlines");
+                ui.checkbox(&mut crt.curvature, "Curvature");
+                ui.checkbox(&mut crt.bloom, "Bloom");
+            });
+            // scale to fit whatever space the window has, keeping the 2:1 shape
+            let available = ui.available_width().max(128.0);
+            let size = egui::vec2(available, available / 2.0);
+            ui.image((texture.id(), size));
+        });
+    }
+
+    fn registers_panel(&mut self, ctx: &egui::Context) {
+        let cpu = &self.cpu;
+        egui::Window::new("Registers").open(&mut self.panels.registers).default_pos([560.0, 40.0]).show(ctx, |ui| {
+            egui::Grid::new("registers").striped(true).show(ui, |ui| {
+                for row in 0..4 {
+                    for n in row * 4..row * 4 + 4 {
+                        ui.monospace(format!("V{:X}", n));
+                        ui.monospace(format!("{:02X}", cpu.registers[n]));
+                    }
+                    ui.end_row();
+                }
+                ui.monospace("I");
+                ui.monospace(format!("{:04X}", cpu.index_register));
+                ui.monospace("PC");
+                ui.monospace(format!("{:04X}", cpu.position_in_memory));
+                ui.monospace("DT");
+                ui.monospace(format!("{:02X}", cpu.delay_timer));
+                ui.monospace("ST");
+                ui.monospace(format!("{:02X}", cpu.sound_timer));
+                ui.end_row();
+            });
+            ui.separator();
+            ui.label(format!("Stack ({})", cpu.stack_pointer));
+            let frames: Vec<CallFrame> = cpu.call_stack().collect();
+            for (depth, frame) in frames.iter().enumerate().rev() {
+                ui.monospace(format!("{:2}: {}", depth, frame));
+            }
+        });
+    }
+
+    fn memory_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.memory;
+        egui::Window::new("Memory").open(&mut open).default_pos([560.0, 330.0]).show(ctx, |ui| {
+            let pc = self.cpu.position_in_memory;
+            let index = self.cpu.index_register as usize;
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace) + ui.spacing().item_spacing.y;
+
+            let mut scroll_to = None;
+            ui.horizontal(|ui| {
+                if ui.button("Go to PC").clicked() {
+                    scroll_to = Some(pc);
+                }
+                if ui.button("Go to I").clicked() {
+                    scroll_to = Some(index);
+                }
+                ui.label(if self.running { "stop to edit" } else { "click a byte to edit it" });
+            });
+
+            let mut scroll = egui::ScrollArea::vertical().max_height(300.0);
+            if let Some(addr) = scroll_to {
+                scroll = scroll.vertical_scroll_offset((addr / HEXDUMP_WIDTH) as f32 * row_height);
+            }
+            let rows = self.cpu.memory.len().div_ceil(HEXDUMP_WIDTH);
+            scroll.show_rows(ui, row_height, rows, |ui, range| {
+                for row in range {
+                    let start = row * HEXDUMP_WIDTH;
+                    let end = (start + HEXDUMP_WIDTH).min(self.cpu.memory.len());
+                    ui.horizontal(|ui| {
+                        ui.spacing_mut().item_spacing.x = 4.0;
+                        ui.monospace(format!("{:04X} ", start));
+                        for addr in start..end {
+                            self.memory_byte(ui, addr, pc, index);
+                        }
+                        let ascii: String = self.cpu.memory[start..end]
+                            .iter()
+                            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                            .collect();
+                        ui.monospace(format!(" {}", ascii));
+                    });
+                }
+            });
+        });
+        self.panels.memory = open;
+    }
+
+    // One byte of the hexdump. PC's instruction and the bytes at I stand out, and while
+    // stopped clicking a byte turns it into a text box for typing a new value.
+    fn memory_byte(&mut self, ui: &mut egui::Ui, addr: usize, pc: usize, index: usize) {
+        if let Some((editing, text)) = &mut self.editing_byte {
+            if *editing == addr {
+                let response = ui.add(egui::TextEdit::singleline(text).desired_width(18.0).char_limit(2).font(egui::TextStyle::Monospace));
+                response.request_focus();
+                if response.lost_focus() {
+                    if ui.input(|input| input.key_pressed(Key::Enter)) {
+                        if let Ok(value) = u8::from_str_radix(text, 16) {
+                            self.cpu.memory[addr] = value;
+                            self.cpu.flush_decoded();
+                        }
+                    }
+                    self.editing_byte = None;
+                }
+                return;
+            }
+        }
+
+        let mut text = RichText::new(format!("{:02X}", self.cpu.memory[addr])).monospace();
+        if (pc..pc + 2).contains(&addr) {
+            text = text.color(Color32::BLACK).background_color(Color32::YELLOW);
+        } else if (index..index + I_HIGHLIGHT_LEN).contains(&addr) {
+            text = text.color(Color32::BLACK).background_color(Color32::LIGHT_BLUE);
+        }
+        let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+        if response.clicked() && !self.running {
+            self.editing_byte = Some((addr, format!("{:02X}", self.cpu.memory[addr])));
+        }
+    }
+
+    fn disassembly_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.disassembly;
+        egui::Window::new("Disassembly").open(&mut open).default_pos([850.0, 40.0]).show(ctx, |ui| {
+            ui.label("click a line to toggle a breakpoint");
+            let pc = self.cpu.position_in_memory;
+            // centred on PC, so it scrolls along as the program runs
+            for instruction in disassemble_around(&self.cpu.memory, pc, self.cpu.variant, &self.cpu.quirks(), DISASSEMBLY_LINES) {
+                let instruction = instruction.with_symbols(&self.symbols);
+                let addr = instruction.addr;
+                if let Some(name) = self.symbols.name(addr) {
+                    ui.label(RichText::new(format!("{}:", name)).monospace().color(Color32::LIGHT_BLUE));
+                }
+                let marker = if self.breakpoints.contains(addr) { "●" } else { " " };
+                let arrow = if addr == pc { ">" } else { " " };
+                let mut text = RichText::new(format!(
+                    "{}{} {:03X}  {:04X}  {}",
+                    marker, arrow, addr, instruction.opcode, instruction.text
+                ))
+                .monospace();
+                if addr == pc {
+                    text = text.color(Color32::BLACK).background_color(Color32::YELLOW);
+                } else if self.breakpoints.contains(addr) {
+                    text = text.color(Color32::LIGHT_RED);
+                }
+                if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() && !self.breakpoints.remove(addr) {
+                    self.breakpoints.insert(addr);
+                }
+            }
+        });
+        self.panels.disassembly = open;
+    }
+
+    fn breakpoints_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.breakpoints;
+        egui::Window::new("Breakpoints").open(&mut open).default_pos([850.0, 560.0]).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.new_breakpoint).hint_text("address (hex) or symbol").desired_width(100.0));
+                if ui.button("Add").clicked() {
+                    let text = self.new_breakpoint.trim();
+                    let addr = self.symbols.addr(text).or_else(|| usize::from_str_radix(text.trim_start_matches("0x"), 16).ok());
+                    match addr {
+                        Some(addr) => {
+                            self.breakpoints.insert(addr);
+                            self.new_breakpoint.clear();
+                        }
+                        None => self.status = format!("not an address: {}", self.new_breakpoint),
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.new_pattern).hint_text("opcode, like FX0A").desired_width(100.0));
+                if ui.button("Add").clicked() {
+                    match OpcodePattern::parse(&self.new_pattern) {
+                        Some(pattern) => {
+                            self.breakpoints.insert_pattern(pattern);
+                            self.new_pattern.clear();
+                        }
+                        None => self.status = format!("not an opcode pattern: {}", self.new_pattern),
+                    }
+                }
+            });
+            let mut on_collision = self.breakpoints.break_on_collision();
+            if ui.checkbox(&mut on_collision, "Stop when a draw collides").changed() {
+                self.breakpoints.set_break_on_collision(on_collision);
+            }
+            let mut removed = None;
+            for addr in self.breakpoints.iter() {
+                ui.horizontal(|ui| {
+                    match self.symbols.name(addr) {
+                        Some(name) => ui.monospace(format!("0x{:03X} {}", addr, name)),
+                        None => ui.monospace(format!("0x{:03X}", addr)),
+                    };
+                    if ui.small_button("remove").clicked() {
+                        removed = Some(addr);
+                    }
+                });
+            }
+            if let Some(addr) = removed {
+                self.breakpoints.remove(addr);
+            }
+            let mut removed = None;
+            for pattern in self.breakpoints.patterns() {
+                ui.horizontal(|ui| {
+                    ui.monospace(format!("{} anywhere", pattern));
+                    if ui.small_button("remove").clicked() {
+                        removed = Some(pattern);
+                    }
+                });
+            }
+            if let Some(pattern) = removed {
+                self.breakpoints.remove_pattern(pattern);
+            }
+        });
+        self.panels.breakpoints = open;
+    }
+
+    fn timing_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.timing;
+        egui::Window::new("Timing").open(&mut open).default_pos([10.0, 420.0]).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.running {
+                    if ui.button("Stop").clicked() {
+                        self.stop();
+                    }
+                } else if ui.button("Run").clicked() {
+                    self.resume();
+                }
+                if ui.button("Step").clicked() {
+                    self.step();
+                }
+                if ui.button("Step over").clicked() {
+                    self.step_over();
+                }
+                if ui.button("Step out").clicked() {
+                    self.step_out();
+                }
+                if ui.button("Frame").clicked() {
+                    self.running = false;
+                    self.breakpoints.cancel_step();
+                    self.cpu.advance_frame();
+                }
+                if ui.button("Restart").clicked() {
+                    self.restart();
+                }
+            });
+            ui.add(egui::Slider::new(&mut self.cpu.clock_speed, 60..=20_000).logarithmic(true).text("instructions/s"));
+            ui.add(egui::Slider::new(&mut self.speed, 0.1..=8.0).logarithmic(true).text("speed"));
+            let variant = match self.cpu.variant {
+                Variant::Chip8 => "CHIP-8",
+                Variant::SuperChip => "SUPER-CHIP",
+                Variant::XoChip => "XO-CHIP",
+                Variant::Chip8X => "CHIP-8X",
+                Variant::MegaChip => "Mega-Chip",
+                Variant::Eti660 => "ETI-660",
+                Variant::Chip8Hires => "CHIP-8 two-page hi-res",
+            };
+            ui.label(format!("{}, {}x{}", variant, self.cpu.display.width(), self.cpu.display.height()));
+            let stats = self.meter.current(&self.cpu);
+            ui.label(format!("{:.0} instructions/s, {:.1} frames/s", stats.ips, stats.fps));
+            ui.label(format!("{} instructions, {} timer ticks", stats.instructions, stats.timer_ticks));
+        });
+        self.panels.timing = open;
+    }
+
+    fn keypad_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.panels.keypad;
+        let mut held = None;
+        egui::Window::new("Keypad").open(&mut open).default_pos([10.0, 600.0]).resizable(false).show(ctx, |ui| {
+            egui::Grid::new("keypad").spacing([4.0, 4.0]).show(ui, |ui| {
+                for (n, &key) in KEYPAD_ORDER.iter().enumerate() {
+                    // player 1's key, the first one bound
+                    let bound = self.keymap.bindings().find(|&(k, _)| k == key).map(|(_, c)| c.to_ascii_uppercase());
+                    let text = RichText::new(format!("{:X}\n{}", key, bound.unwrap_or(' '))).monospace();
+                    let button = egui::Button::new(text).min_size(egui::vec2(40.0, 40.0)).selected(self.cpu.keypad[key as usize]);
+                    if ui.add(button).is_pointer_button_down_on() {
+                        held = Some(key);
+                    }
+                    if n % 4 == 3 {
+                        ui.end_row();
+                    }
+                }
+            });
+        });
+        self.panels.keypad = open;
+        self.clicked_key = held;
+    }
+}
+
+impl eframe::App for GuiDebugger {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.read_keypad(ctx);
+        self.emulate();
+
+        self.menu_bar(ctx);
+        egui::CentralPanel::default().show(ctx, |_| {});
+        self.screen_panel(ctx);
+        self.registers_panel(ctx);
+        self.memory_panel(ctx);
+        self.disassembly_panel(ctx);
+        self.breakpoints_panel(ctx);
+        self.timing_panel(ctx);
+        self.keypad_panel(ctx);
+        self.meter.frame_presented();
+
+        // keep the frames coming, egui would otherwise only repaint on input
+        ctx.request_repaint();
+    }
+}
@@ -0,0 +1,76 @@
+// Why the CPU stopped, and how hard it should look for programs that will never stop on their own.
+// Lots of ROMs finish with a jump to themselves (JP self) rather than HALT, since the original
+// interpreter had no way to exit. Interactively that's fine, the last screen stays up, but a
+// headless run would spin until its cycle budget runs out.
+// Junk is the other thing that stops programs: plenty of ROMs have data in with the code that
+// gets jumped over on real hardware, but one wrong turn runs it. UnknownOpcodePolicy says
+// whether that stops the CPU or not.
+
+use alloc::boxed::Box;
+use core::fmt;
+
+use crate::cpu::Cpu;
+
+/// Why the CPU halted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// 0x0000, or SUPER-CHIP's EXIT (00FD)
+    Exit,
+    /// The program got stuck in a loop it can never leave
+    InfiniteLoop,
+    /// A hardened CPU hit a broken instruction, an unknown opcode stopped it or the watchdog
+    /// ran out, Cpu::fault() says what
+    Fault,
+}
+
+/// Which stuck loops to halt on. Everything is off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoopDetection {
+    /// Halt on a jump to the jump instruction itself.
+    pub jump_to_self: bool,
+    /// Halt after this many instructions in a row that didn't change any registers,
+    /// memory or the display. Catches loops like waiting for a key that will never be pressed.
+    pub idle_window: Option<u64>,
+}
+
+impl LoopDetection {
+    /// Nothing detected, the program runs until it halts itself.
+    pub const fn off() -> Self {
+        LoopDetection {
+            jump_to_self: false,
+            idle_window: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.jump_to_self || self.idle_window.is_some()
+    }
+}
+
+/// A handler for UnknownOpcodePolicy::Handler, called with the CPU, the address and the opcode.
+pub type UnknownOpcodeHandler = Box<dyn FnMut(&mut Cpu, usize, u16) -> bool + Send + Sync>;
+
+/// What to do with an opcode the CPU's variant doesn't have.
+#[derive(Default)]
+pub enum UnknownOpcodePolicy {
+    /// Halt with HaltReason::Fault and CpuError::UnknownOpcode as the fault, hardened or not.
+    /// The default.
+    #[default]
+    Error,
+    /// Treat it as doing nothing and carry on with the next instruction.
+    Skip,
+    /// Call a handler with the CPU, the address and the opcode. It can do whatever the opcode should
+    /// do (PC already points at the next instruction) and return true, or return false to
+    /// stop like Error does.
+    Handler(UnknownOpcodeHandler),
+}
+
+impl fmt::Debug for UnknownOpcodePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownOpcodePolicy::Error => write!(f, "Error"),
+            UnknownOpcodePolicy::Skip => write!(f, "Skip"),
+            UnknownOpcodePolicy::Handler(_) => write!(f, "Handler(..)"),
+        }
+    }
+}
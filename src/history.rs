@@ -0,0 +1,281 @@
+// Execution history, for stepping backwards.
+// Keeping a snapshot of every instruction would eat memory fast, so History keeps one every
+// `interval` instructions (a ring buffer of the last `capacity` of them), plus a log of
+// everything from outside the CPU that changed what it did in between: timer ticks and keys
+// going up and down. Stepping back restores the newest snapshot from before where it's going
+// and runs forward again from there, replaying the log, so it lands on exactly the state the
+// machine was in. Debugging an XOR glitch is a lot easier when you can watch the draw again.
+//
+// Add it as an observer (hooks.rs) to start recording. It can only go back as far as its
+// oldest snapshot, by default that's somewhere between 64K and 65K instructions.
+
+use alloc::collections::VecDeque;
+use core::mem;
+
+use crate::cpu::Cpu;
+use crate::hooks::Observer;
+use crate::save_state::SaveState;
+
+/// How many instructions apart snapshots are, unless History::with_interval() says otherwise.
+pub const DEFAULT_INTERVAL: u64 = 1024;
+/// How many snapshots are kept by default.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+// A snapshot, and the counts that go with it (SaveState leaves them out)
+struct Keyframe {
+    instructions: u64,
+    timer_ticks: u64,
+    keypad: [bool; 16],
+    state: SaveState,
+}
+
+/// Recent snapshots and what happened between them, see the top of this file.
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    keyframes: VecDeque<Keyframe>,
+    // (instruction count, how many) for timer ticks that happened before that instruction ran
+    ticks: VecDeque<(u64, u64)>,
+    // (instruction count, the keypad) for key changes before that instruction ran
+    keys: VecDeque<(u64, [bool; 16])>,
+    // what the timers and keys were at the last instruction, to spot them changing
+    last_ticks: Option<u64>,
+    last_keypad: [bool; 16],
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::with_interval(DEFAULT_INTERVAL, DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Snapshot every `interval` instructions and keep the last `capacity` snapshots. A shorter
+    /// interval means less to run again on every step back, but more memory for the same reach.
+    pub fn with_interval(interval: u64, capacity: usize) -> Self {
+        History {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            keyframes: VecDeque::new(),
+            ticks: VecDeque::new(),
+            keys: VecDeque::new(),
+            last_ticks: None,
+            last_keypad: [false; 16],
+        }
+    }
+
+    /// How many instructions back step_back() can go from where `cpu` is now.
+    pub fn reach(&self, cpu: &Cpu) -> u64 {
+        self.keyframes.front().map_or(0, |oldest| cpu.instructions_retired().saturating_sub(oldest.instructions))
+    }
+
+    /// Forget everything, for when the program's been reloaded or changed by hand.
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.ticks.clear();
+        self.keys.clear();
+        self.last_ticks = None;
+    }
+
+    /// Put `cpu` back to how it was `count` instructions ago. Returns false, leaving it alone,
+    /// if that's further back than the history goes. Observers don't see the instructions that
+    /// get run again.
+    pub fn step_back(&mut self, cpu: &mut Cpu, count: u64) -> bool {
+        let Some(target) = cpu.instructions_retired().checked_sub(count) else { return false };
+        let Some(keyframe) = self.keyframes.iter().rev().find(|keyframe| keyframe.instructions <= target) else {
+            return false;
+        };
+        if keyframe.state.restore(cpu).is_err() {
+            return false;
+        }
+        cpu.instructions_retired = keyframe.instructions;
+        cpu.timer_ticks = keyframe.timer_ticks;
+        cpu.keypad = keyframe.keypad;
+
+        // run forward to the target without the observers (this one included) seeing it all again
+        let observers = mem::take(&mut cpu.observers);
+        let start = keyframe.instructions;
+        let mut ticks = self.ticks.iter().filter(|&&(at, _)| at > start).peekable();
+        let mut keys = self.keys.iter().filter(|&&(at, _)| at > start).peekable();
+        loop {
+            let now = cpu.instructions_retired();
+            while let Some(&(_, keypad)) = keys.next_if(|&&(at, _)| at == now) {
+                cpu.keypad = keypad;
+            }
+            while let Some(&(_, count)) = ticks.next_if(|&&(at, _)| at == now) {
+                for _ in 0..count {
+                    cpu.tick_timers();
+                }
+            }
+            if now >= target {
+                break;
+            }
+            cpu.step();
+            // stuck (halted, or waiting on a tick the log doesn't have), which shouldn't happen
+            if cpu.instructions_retired() == now {
+                break;
+            }
+        }
+        cpu.observers = observers;
+
+        // what came after is gone, it'll get recorded again if the program gets there
+        let now = cpu.instructions_retired();
+        self.keyframes.retain(|keyframe| keyframe.instructions <= now);
+        self.ticks.retain(|&(at, _)| at <= now);
+        self.keys.retain(|&(at, _)| at <= now);
+        self.last_ticks = Some(cpu.timer_ticks());
+        self.last_keypad = cpu.keypad;
+        true
+    }
+}
+
+impl Observer for History {
+    fn before_instruction(&mut self, cpu: &Cpu, _pc: usize) {
+        let now = cpu.instructions_retired();
+        if let Some(last) = self.last_ticks {
+            if cpu.timer_ticks() > last {
+                self.ticks.push_back((now, cpu.timer_ticks() - last));
+            }
+        }
+        self.last_ticks = Some(cpu.timer_ticks());
+        if cpu.keypad != self.last_keypad {
+            self.keys.push_back((now, cpu.keypad));
+            self.last_keypad = cpu.keypad;
+        }
+
+        if self.keyframes.back().is_none_or(|newest| now >= newest.instructions + self.interval) {
+            self.keyframes.push_back(Keyframe {
+                instructions: now,
+                timer_ticks: cpu.timer_ticks(),
+                keypad: cpu.keypad,
+                state: SaveState::capture(cpu),
+            });
+            if self.keyframes.len() > self.capacity {
+                self.keyframes.pop_front();
+                // the log before the oldest snapshot can't be replayed any more
+                let oldest = self.keyframes.front().map_or(now, |oldest| oldest.instructions);
+                self.ticks.retain(|&(at, _)| at > oldest);
+                self.keys.retain(|&(at, _)| at > oldest);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use std::sync::{Arc, Mutex};
+
+    use crate::quirks::Quirks;
+
+    // Counts in V0, adds up DT in V2 and counts in V4 while key 0 is held, setting DT
+    // again whenever it runs out, so going back right needs the ticks and keys replayed
+    const TIMERS_AND_KEYS: [u8; 20] = [
+        0x70, 0x01, // 200: ADD V0, 1
+        0xF1, 0x07, // 202: LD V1, DT
+        0x82, 0x14, // 204: ADD V2, V1
+        0xE3, 0xA1, // 206: SKNP V3
+        0x74, 0x01, // 208: ADD V4, 1
+        0x31, 0x00, // 20A: SE V1, 0
+        0x12, 0x00, // 20C: JP 200
+        0x65, 0x20, // 20E: LD V5, 0x20
+        0xF5, 0x15, // 210: LD DT, V5
+        0x12, 0x00, // 212: JP 200
+    ];
+
+    // A machine with a History recording it, run for `frames` with key 0 held on every third
+    // frame. Returns what the instruction count and digest were after each frame.
+    fn recorded(history: History, frames: usize) -> (Cpu, Arc<Mutex<History>>, Vec<(u64, u64)>) {
+        let history = Arc::new(Mutex::new(history));
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.load_rom(&TIMERS_AND_KEYS).unwrap();
+        cpu.add_observer(Box::new(history.clone()));
+        let mut after = Vec::new();
+        for frame in 0..frames {
+            cpu.set_key(0, frame % 3 == 0);
+            cpu.run_frame();
+            after.push((cpu.instructions_retired(), cpu.state_digest()));
+        }
+        (cpu, history, after)
+    }
+
+    #[test]
+    fn back_to_exactly_where_it_was() {
+        let (mut cpu, history, after) = recorded(History::with_interval(16, 64), 60);
+        let mut history = history.lock().unwrap();
+        // newest first, each step back from where the last one left it
+        for &(instructions, digest) in after.iter().rev().skip(1).step_by(7) {
+            let count = cpu.instructions_retired() - instructions;
+            assert!(history.step_back(&mut cpu, count));
+            assert_eq!((cpu.instructions_retired(), cpu.state_digest()), (instructions, digest));
+        }
+    }
+
+    #[test]
+    fn not_further_than_the_oldest_snapshot() {
+        let (mut cpu, history, _) = recorded(History::with_interval(16, 4), 60);
+        let mut history = history.lock().unwrap();
+        let reach = history.reach(&cpu);
+        assert!((16 * 3..16 * 5).contains(&reach), "{}", reach);
+        let (instructions, digest) = (cpu.instructions_retired(), cpu.state_digest());
+        assert!(!history.step_back(&mut cpu, reach + 1));
+        assert_eq!((cpu.instructions_retired(), cpu.state_digest()), (instructions, digest));
+        assert!(history.step_back(&mut cpu, reach));
+        assert_eq!(history.reach(&cpu), 0);
+    }
+
+    // The digest before every instruction, by instruction count
+    #[derive(Default)]
+    struct Digests(Vec<u64>);
+
+    impl Observer for Digests {
+        fn before_instruction(&mut self, cpu: &Cpu, _pc: usize) {
+            let now = cpu.instructions_retired() as usize;
+            self.0.truncate(now);
+            self.0.push(cpu.state_digest());
+        }
+    }
+
+    #[test]
+    fn one_instruction_at_a_time() {
+        let (mut cpu, history, _) = recorded(History::with_interval(16, 64), 0);
+        let digests = Arc::new(Mutex::new(Digests::default()));
+        cpu.add_observer(Box::new(digests.clone()));
+        for frame in 0..10 {
+            cpu.set_key(0, frame % 3 == 0);
+            cpu.run_frame();
+        }
+        let digests = &digests.lock().unwrap().0;
+        let mut history = history.lock().unwrap();
+        while cpu.instructions_retired() > 0 {
+            assert!(history.step_back(&mut cpu, 1));
+            assert_eq!(cpu.state_digest(), digests[cpu.instructions_retired() as usize], "{}", cpu.instructions_retired());
+        }
+    }
+
+    #[test]
+    fn what_came_after_is_forgotten() {
+        let (mut cpu, history, after) = recorded(History::with_interval(16, 64), 30);
+        let count = cpu.instructions_retired() - after[19].0;
+        assert!(history.lock().unwrap().step_back(&mut cpu, count));
+        // key 0 held the whole time this go, the old log's key changes mustn't get replayed
+        let mut again = Vec::new();
+        for _ in 0..10 {
+            cpu.set_key(0, true);
+            cpu.run_frame();
+            again.push((cpu.instructions_retired(), cpu.state_digest()));
+        }
+        let count = cpu.instructions_retired() - again[4].0;
+        assert!(history.lock().unwrap().step_back(&mut cpu, count));
+        assert_eq!((cpu.instructions_retired(), cpu.state_digest()), again[4]);
+        let count = cpu.instructions_retired() - after[9].0;
+        assert!(history.lock().unwrap().step_back(&mut cpu, count));
+        assert_eq!((cpu.instructions_retired(), cpu.state_digest()), after[9]);
+    }
+}
@@ -0,0 +1,153 @@
+// Execution hooks.
+// Tracers, visualisers and scripts want to see what the program is doing as it happens, without
+// a copy of the interpreter loop of their own. An Observer registered with Cpu::add_observer()
+// gets called around every instruction and whenever the program writes memory, draws or sits
+// waiting for a key. Every callback gets the CPU read-only, so observers can look but can't
+// change what the program does.
+//
+// Observers need to see every instruction, so while any are registered the JIT is turned off
+// (the same as idle loop detection). With none registered it costs one check per instruction.
+
+use crate::cpu::Cpu;
+
+/// Callbacks for things happening in a running CPU. Everything does nothing by default, so
+/// only implement the ones you're interested in. Observers have to be Send and Sync like the
+/// rest of the CPU, so it can still move between threads (and into Python).
+pub trait Observer: Send + Sync {
+    /// The instruction at `pc` is about to run.
+    fn before_instruction(&mut self, _cpu: &Cpu, _pc: usize) {}
+
+    /// The instruction at `pc` just ran. PC has already moved on to whatever runs next.
+    fn after_instruction(&mut self, _cpu: &Cpu, _pc: usize) {}
+
+    /// The program wrote `value` to `addr` (FX33, FX55 and XO-CHIP's 5XY2). Called after the write.
+    fn memory_write(&mut self, _cpu: &Cpu, _addr: usize, _value: u8) {}
+
+    /// A sprite was drawn at (`x`, `y`), `collision` is what went in VF. The sprite is at I.
+    fn draw(&mut self, _cpu: &Cpu, _x: usize, _y: usize, _collision: bool) {}
+
+    /// FX0A found no key held down and will try again, so this gets called every time it
+    /// runs until a key is pressed. `register` is the X it'll put the key in.
+    fn key_wait(&mut self, _cpu: &Cpu, _register: u8) {}
+}
+
+/// What add_observer() gives back, to take the observer off again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(pub(crate) u64);
+
+/// A shared observer, so whoever added it can keep a handle and get at it afterwards
+/// (a trace to flush, counts to read).
+#[cfg(feature = "std")]
+impl<T: Observer> Observer for std::sync::Arc<std::sync::Mutex<T>> {
+    fn before_instruction(&mut self, cpu: &Cpu, pc: usize) {
+        self.lock().unwrap().before_instruction(cpu, pc)
+    }
+
+    fn after_instruction(&mut self, cpu: &Cpu, pc: usize) {
+        self.lock().unwrap().after_instruction(cpu, pc)
+    }
+
+    fn memory_write(&mut self, cpu: &Cpu, addr: usize, value: u8) {
+        self.lock().unwrap().memory_write(cpu, addr, value)
+    }
+
+    fn draw(&mut self, cpu: &Cpu, x: usize, y: usize, collision: bool) {
+        self.lock().unwrap().draw(cpu, x, y, collision)
+    }
+
+    fn key_wait(&mut self, cpu: &Cpu, register: u8) {
+        self.lock().unwrap().key_wait(cpu, register)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::quirks::Quirks;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        Before(usize),
+        After(usize),
+        Write(usize, u8),
+        Draw(usize, usize, bool),
+        KeyWait(u8),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl Observer for Recorder {
+        fn before_instruction(&mut self, _cpu: &Cpu, pc: usize) {
+            self.0.push(Event::Before(pc));
+        }
+
+        fn after_instruction(&mut self, _cpu: &Cpu, pc: usize) {
+            self.0.push(Event::After(pc));
+        }
+
+        fn memory_write(&mut self, _cpu: &Cpu, addr: usize, value: u8) {
+            self.0.push(Event::Write(addr, value));
+        }
+
+        fn draw(&mut self, _cpu: &Cpu, x: usize, y: usize, collision: bool) {
+            self.0.push(Event::Draw(x, y, collision));
+        }
+
+        fn key_wait(&mut self, _cpu: &Cpu, register: u8) {
+            self.0.push(Event::KeyWait(register));
+        }
+    }
+
+    const PROGRAM: [u8; 10] = [
+        0x60, 0x05, // 200 LD V0, 5
+        0xA3, 0x00, // 202 LD I, 0x300
+        0xF0, 0x33, // 204 LD B, V0
+        0xD0, 0x01, // 206 DRW V0, V0, 1
+        0xF1, 0x0A, // 208 LD V1, K
+    ];
+
+    fn machine() -> Cpu {
+        // without the VIP's wait for the next frame after drawing
+        let mut cpu = Cpu::new(Quirks { display_wait: false, ..Quirks::cosmac_vip() });
+        cpu.load_rom(&PROGRAM).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn events_in_order() {
+        let mut cpu = machine();
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        cpu.add_observer(Box::new(recorder.clone()));
+        cpu.run_for(6);
+
+        use Event::*;
+        let expected = [
+            Before(0x200), After(0x200),
+            Before(0x202), After(0x202),
+            Before(0x204), Write(0x300, 0), Write(0x301, 0), Write(0x302, 5), After(0x204),
+            Before(0x206), Draw(5, 5, false), After(0x206),
+            // every time it looks and there's no key
+            Before(0x208), KeyWait(1), After(0x208),
+            Before(0x208), KeyWait(1), After(0x208),
+        ];
+        assert_eq!(recorder.lock().unwrap().0, expected);
+    }
+
+    #[test]
+    fn removed_observers_hear_nothing_more() {
+        let mut cpu = machine();
+        let (first, second) = (Arc::new(Mutex::new(Recorder::default())), Arc::new(Mutex::new(Recorder::default())));
+        let id = cpu.add_observer(Box::new(first.clone()));
+        let other = cpu.add_observer(Box::new(second.clone()));
+        assert_ne!(id, other);
+        cpu.run_for(1);
+        assert!(cpu.remove_observer(id).is_some());
+        assert!(cpu.remove_observer(id).is_none());
+        cpu.run_for(1);
+        assert_eq!(first.lock().unwrap().0.len(), 2);
+        assert_eq!(second.lock().unwrap().0.len(), 4);
+    }
+}
@@ -0,0 +1,406 @@
+// Decoded instructions.
+// Pulling an opcode apart into nibbles and working out which instruction it is happens once,
+// here, and the result is a typed Instruction the CPU can run (and cache, so hot loops skip
+// decoding altogether) and the disassembler can print.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use crate::quirks::Quirks;
+use crate::variant::Variant;
+
+/// One CHIP-8 instruction with its operands. Register operands are register numbers (0x0-0xF).
+/// Names follow what the instruction does; the comment on each is the opcode it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0000
+    Halt,
+    /// 0010 (Mega-Chip)
+    MegaOff,
+    /// 0011 (Mega-Chip)
+    MegaOn,
+
+    /// 00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00DN (XO-CHIP), 00BN (Mega-Chip)
+    ScrollUp(u8),
+    /// 00E0, and 0230 on two-page hi-res
+    Clear,
+    /// 02A0 (CHIP-8X)
+    CycleBackground,
+    /// 01NN NNNN (Mega-Chip), 4 bytes like F000 NNNN
+    LoadHugeIndex(u32),
+    /// 02NN (Mega-Chip)
+    LoadPalette(u8),
+    /// 03NN (Mega-Chip)
+    SpriteWidth(u8),
+    /// 04NN (Mega-Chip)
+    SpriteHeight(u8),
+    /// 05NN (Mega-Chip)
+    ScreenAlpha(u8),
+    /// 060N (Mega-Chip)
+    PlaySound(u8),
+    /// 0700 (Mega-Chip)
+    StopSound,
+    /// 080N (Mega-Chip)
+    BlendMode(u8),
+    /// 09NN (Mega-Chip)
+    CollisionColor(u8),
+    /// 00EE
+    Return,
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    LowRes,
+    /// 00FF (SUPER-CHIP)
+    HighRes,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XKK
+    SkipIfEqual(u8, u8),
+    /// 4XKK
+    SkipIfNotEqual(u8, u8),
+    /// 5XY0
+    SkipIfRegistersEqual(u8, u8),
+    /// 5XY1 (CHIP-8X)
+    AddNibbles(u8, u8),
+    /// 5XY2 (XO-CHIP)
+    StoreRange(u8, u8),
+    /// 5XY3 (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6XKK
+    LoadByte(u8, u8),
+    /// 7XKK
+    AddByte(u8, u8),
+    /// 8XY0
+    Move(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    Add(u8, u8),
+    /// 8XY5
+    Sub(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7
+    SubReversed(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0
+    SkipIfRegistersDiffer(u8, u8),
+    /// ANNN
+    LoadIndex(u16),
+    /// BNNN
+    JumpWithOffset(u8, u16),
+    /// BXY0 (CHIP-8X, instead of BNNN)
+    ColorBlocks(u8, u8),
+    /// BXYN (CHIP-8X, instead of BNNN)
+    ColorStrip(u8, u8, u8),
+    /// CXKK
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// EXF2 (CHIP-8X)
+    SkipIfKey2(u8),
+    /// EXF5 (CHIP-8X)
+    SkipIfNotKey2(u8),
+    /// F000 NNNN (XO-CHIP), 4 bytes
+    LoadLongIndex(u16),
+    /// FX01 (XO-CHIP)
+    SelectPlanes(u8),
+    /// F002 (XO-CHIP)
+    LoadAudioPattern,
+    /// FX07
+    ReadDelay(u8),
+    /// FX0A
+    WaitForKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddIndex(u8),
+    /// FX29
+    LoadFont(u8),
+    /// FX30 (SUPER-CHIP)
+    LoadBigFont(u8),
+    /// FX33
+    StoreBcd(u8),
+    /// FX3A (XO-CHIP)
+    SetPitch(u8),
+    /// FX55
+    StoreRegisters(u8),
+    /// FX65
+    LoadRegisters(u8),
+    /// FX75 (SUPER-CHIP)
+    StoreFlags(u8),
+    /// FX85 (SUPER-CHIP)
+    LoadFlags(u8),
+    /// FXF8 (CHIP-8X)
+    OutputPort(u8),
+    /// FXFB (CHIP-8X)
+    InputPort(u8),
+    /// Anything that isn't an instruction on this machine, probably data
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decode the instruction at `addr`. Bytes past the end of memory read as 0.
+    pub fn decode_at(memory: &[u8], addr: usize, variant: Variant) -> Self {
+        let word = |offset: usize| {
+            let byte = |n: usize| memory.get(addr + offset + n).copied().unwrap_or(0) as u16;
+            // combine 2 u8 into a single u16, high byte first
+            byte(0) << 8 | byte(1)
+        };
+        let opcode = word(0);
+        if variant == Variant::XoChip && opcode == 0xF000 {
+            // the address is the whole next word
+            return Instruction::LoadLongIndex(word(2));
+        }
+        if variant == Variant::MegaChip && opcode & 0xFF00 == 0x0100 {
+            // and Mega-Chip's is this one's low byte and then the whole next word
+            return Instruction::LoadHugeIndex(((opcode & 0xFF) as u32) << 16 | word(2) as u32);
+        }
+        Instruction::decode(opcode, variant)
+    }
+
+    /// Decode a 2 byte opcode. F000 and 01NN need the word after them, use decode_at() for those.
+    pub fn decode(opcode: u16, variant: Variant) -> Self {
+        // Extract nibbles from bytes.
+        // filter with & bit AND operator.
+        // then shift to move the bits to the lowest significant place
+        // hex is convenient cause each hex represents 4 bits
+        // cast cause otherwise it leaves them as u16 from opcode and we want nibbles.
+        // Variable definitions can be found in page 161 table 5.2
+        let c = ((opcode & 0xF000) >> 12) as u8;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let d = (opcode & 0x000F) as u8;
+
+        // You can select multiple nibbles by increasing the width of the filter.
+        // we dont need to bit shift them cause they're already in lowest significant place
+
+        // To support functions
+        let nnn = opcode & 0x0FFF;
+        // A byte sized constant, for comparing against or loading into registers
+        let kk = (opcode & 0x00FF) as u8;
+
+        // SUPER-CHIP, XO-CHIP, CHIP-8X, Mega-Chip and two-page hi-res opcodes only exist when we're emulating those machines
+        let schip = variant.has_superchip_opcodes();
+        let xo = variant == Variant::XoChip;
+        let x8x = variant == Variant::Chip8X;
+        let mega = variant == Variant::MegaChip;
+        let two_page = variant == Variant::Chip8Hires;
+
+        use Instruction::*;
+        match (c, x, y, d) {
+            (0, 0, 0, 0) => Halt,
+            (0, 0, 0x1, 0x0) if mega => MegaOff,
+            (0, 0, 0x1, 0x1) if mega => MegaOn,
+            (0, 0, 0xB, _) if mega => ScrollUp(d),
+            (0, 0, 0xC, _) if schip => ScrollDown(d),
+            (0, 0, 0xD, _) if xo => ScrollUp(d),
+            (0, 0, 0xE, 0x0) => Clear,
+            (0, 0x2, 0x3, 0x0) if two_page => Clear,
+            (0, 0x2, 0xA, 0x0) if x8x => CycleBackground,
+            (0, 0x1, _, _) if mega => LoadHugeIndex((kk as u32) << 16),
+            (0, 0x2, _, _) if mega => LoadPalette(kk),
+            (0, 0x3, _, _) if mega => SpriteWidth(kk),
+            (0, 0x4, _, _) if mega => SpriteHeight(kk),
+            (0, 0x5, _, _) if mega => ScreenAlpha(kk),
+            (0, 0x6, 0x0, _) if mega => PlaySound(d),
+            (0, 0x7, 0x0, 0x0) if mega => StopSound,
+            (0, 0x8, 0x0, _) if mega => BlendMode(d),
+            (0, 0x9, _, _) if mega => CollisionColor(kk),
+            (0, 0, 0xE, 0xE) => Return,
+            (0, 0, 0xF, 0xB) if schip => ScrollRight,
+            (0, 0, 0xF, 0xC) if schip => ScrollLeft,
+            (0, 0, 0xF, 0xD) if schip => Exit,
+            (0, 0, 0xF, 0xE) if schip => LowRes,
+            (0, 0, 0xF, 0xF) if schip => HighRes,
+            (0x1, _, _, _) => Jump(nnn),
+            (0x2, _, _, _) => Call(nnn),
+            (0x3, _, _, _) => SkipIfEqual(x, kk),
+            (0x4, _, _, _) => SkipIfNotEqual(x, kk),
+            (0x5, _, _, 0x0) => SkipIfRegistersEqual(x, y),
+            (0x5, _, _, 0x1) if x8x => AddNibbles(x, y),
+            (0x5, _, _, 0x2) if xo => StoreRange(x, y),
+            (0x5, _, _, 0x3) if xo => LoadRange(x, y),
+            (0x6, _, _, _) => LoadByte(x, kk),
+            (0x7, _, _, _) => AddByte(x, kk),
+            (0x8, _, _, 0x0) => Move(x, y),
+            (0x8, _, _, 0x1) => Or(x, y),
+            (0x8, _, _, 0x2) => And(x, y),
+            (0x8, _, _, 0x3) => Xor(x, y),
+            (0x8, _, _, 0x4) => Add(x, y),
+            (0x8, _, _, 0x5) => Sub(x, y),
+            (0x8, _, _, 0x6) => ShiftRight(x, y),
+            (0x8, _, _, 0x7) => SubReversed(x, y),
+            (0x8, _, _, 0xE) => ShiftLeft(x, y),
+            (0x9, _, _, 0x0) => SkipIfRegistersDiffer(x, y),
+            (0xA, _, _, _) => LoadIndex(nnn),
+            (0xB, _, _, 0x0) if x8x => ColorBlocks(x, y),
+            (0xB, _, _, _) if x8x => ColorStrip(x, y, d),
+            (0xB, _, _, _) => JumpWithOffset(x, nnn),
+            (0xC, _, _, _) => Random(x, kk),
+            (0xD, _, _, _) => Draw(x, y, d),
+            (0xE, _, 0x9, 0xE) => SkipIfKey(x),
+            (0xE, _, 0xA, 0x1) => SkipIfNotKey(x),
+            (0xE, _, 0xF, 0x2) if x8x => SkipIfKey2(x),
+            (0xE, _, 0xF, 0x5) if x8x => SkipIfNotKey2(x),
+            (0xF, _, 0x0, 0x1) if xo => SelectPlanes(x),
+            (0xF, 0, 0x0, 0x2) if xo => LoadAudioPattern,
+            (0xF, _, 0x0, 0x7) => ReadDelay(x),
+            (0xF, _, 0x0, 0xA) => WaitForKey(x),
+            (0xF, _, 0x1, 0x5) => SetDelay(x),
+            (0xF, _, 0x1, 0x8) => SetSound(x),
+            (0xF, _, 0x1, 0xE) => AddIndex(x),
+            (0xF, _, 0x2, 0x9) => LoadFont(x),
+            (0xF, _, 0x3, 0x0) if schip => LoadBigFont(x),
+            (0xF, _, 0x3, 0x3) => StoreBcd(x),
+            (0xF, _, 0x3, 0xA) if xo => SetPitch(x),
+            (0xF, _, 0x5, 0x5) => StoreRegisters(x),
+            (0xF, _, 0x6, 0x5) => LoadRegisters(x),
+            (0xF, _, 0x7, 0x5) if schip => StoreFlags(x),
+            (0xF, _, 0x8, 0x5) if schip => LoadFlags(x),
+            (0xF, _, 0xF, 0x8) if x8x => OutputPort(x),
+            (0xF, _, 0xF, 0xB) if x8x => InputPort(x),
+            _ => Unknown(opcode),
+        }
+    }
+
+    /// How many bytes it takes up in memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadLongIndex(_) | Instruction::LoadHugeIndex(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// The mnemonic as `quirks` would run it. Display always shows BNNN the way the VIP reads it,
+    /// jumping to NNN + V0, with jump_offset_uses_vx on it's BXNN and jumps to XNN + VX instead.
+    pub fn disassemble(&self, quirks: &Quirks) -> String {
+        match *self {
+            Instruction::JumpWithOffset(x, nnn) if quirks.jump_offset_uses_vx => format!("JP V{:X}, 0x{:03X}", x, nnn),
+            _ => self.to_string(),
+        }
+    }
+
+    /// True for the instructions that only read and write V0-VF and I (LD, ADD, the 8XYN ALU
+    /// ops, LD I and ADD I). These are what the JIT and the transpiler turn into native code.
+    pub fn only_touches_registers(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            LoadByte(..)
+                | AddByte(..)
+                | Move(..)
+                | Or(..)
+                | And(..)
+                | Xor(..)
+                | Add(..)
+                | Sub(..)
+                | SubReversed(..)
+                | ShiftRight(..)
+                | ShiftLeft(..)
+                | LoadIndex(..)
+                | AddIndex(..)
+        )
+    }
+}
+
+// Assembly mnemonics from Cowgod's CHIP-8 reference, plus the SUPER-CHIP, XO-CHIP, CHIP-8X and
+// Mega-Chip extensions
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Halt => write!(f, "HALT"),
+            MegaOff => write!(f, "MEGAOFF"),
+            MegaOn => write!(f, "MEGAON"),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            Clear => write!(f, "CLS"),
+            CycleBackground => write!(f, "BGCOL"),
+            LoadHugeIndex(nnnnnn) => write!(f, "LDHI I, 0x{:06X}", nnnnnn),
+            LoadPalette(n) => write!(f, "LDPAL {}", n),
+            SpriteWidth(n) => write!(f, "SPRW {}", n),
+            SpriteHeight(n) => write!(f, "SPRH {}", n),
+            ScreenAlpha(n) => write!(f, "ALPHA {}", n),
+            PlaySound(n) => write!(f, "DIGISND {}", n),
+            StopSound => write!(f, "STOPSND"),
+            BlendMode(n) => write!(f, "BMODE {}", n),
+            CollisionColor(n) => write!(f, "CCOL {}", n),
+            Return => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            SkipIfEqual(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            SkipIfNotEqual(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SkipIfRegistersEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            AddNibbles(x, y) => write!(f, "ADDN V{:X}, V{:X}", x, y),
+            StoreRange(x, y) => write!(f, "SAVE V{:X}-V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}-V{:X}", x, y),
+            LoadByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubReversed(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipIfRegistersDiffer(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadIndex(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            JumpWithOffset(_, nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            ColorBlocks(x, y) => write!(f, "COL V{:X}, V{:X}", x, y),
+            ColorStrip(x, y, n) => write!(f, "COL V{:X}, V{:X}, {}", x, y, n),
+            Random(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipIfKey(x) => write!(f, "SKP V{:X}", x),
+            SkipIfNotKey(x) => write!(f, "SKNP V{:X}", x),
+            SkipIfKey2(x) => write!(f, "SKP2 V{:X}", x),
+            SkipIfNotKey2(x) => write!(f, "SKNP2 V{:X}", x),
+            LoadLongIndex(nnnn) => write!(f, "LD I, long 0x{:04X}", nnnn),
+            SelectPlanes(n) => write!(f, "PLANE {}", n),
+            LoadAudioPattern => write!(f, "AUDIO"),
+            ReadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            WaitForKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            LoadBigFont(x) => write!(f, "LD HF, V{:X}", x),
+            StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            SetPitch(x) => write!(f, "PITCH V{:X}", x),
+            StoreRegisters(x) => write!(f, "LD [I], V{:X}", x),
+            LoadRegisters(x) => write!(f, "LD V{:X}, [I]", x),
+            StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            OutputPort(x) => write!(f, "OUT V{:X}", x),
+            InputPort(x) => write!(f, "IN V{:X}", x),
+            Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+// Experimental JIT.
+// Most of a CHIP-8 program's time goes on runs of register arithmetic between the jumps, skips
+// and draws. This finds those straight-line runs (basic blocks, more or less), compiles each one
+// to a native function with Cranelift the first time PC lands on it, and calls that instead of
+// stepping through them one at a time. Anything else, and any block smaller than MIN_BLOCK, is
+// left to the interpreter through Cpu::run_for_with().
+//
+// Compiled code doesn't notice if the bytes it came from change, so after every block the CPU's
+// code write hook is checked. A block that's been written over is thrown away and its address is
+// never compiled again, self-modifying code just runs in the interpreter.
+
+use std::mem;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlagsData, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::cpu::{BlockRunner, Cpu, DECODE_CACHE_SIZE};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::variant::Variant;
+
+// Calling into native code isn't free, shorter runs are quicker in the interpreter
+const MIN_BLOCK: usize = 3;
+// Don't let one block get silly, a 4K ROM of nothing but arithmetic would be one function
+const MAX_BLOCK: usize = 256;
+
+// A compiled block takes pointers to V0-VF and I
+type BlockFn = unsafe extern "C" fn(*mut u8, *mut u32);
+
+#[derive(Clone, Copy)]
+struct Block {
+    // None when the run at this address was too short to be worth compiling
+    func: Option<BlockFn>,
+    // how many instructions it covers, each 2 bytes
+    len: u32,
+}
+
+/// Runs ROMs with straight-line blocks compiled to native code.
+pub struct Jit {
+    module: JITModule,
+    ctx: Context,
+    builder_ctx: FunctionBuilderContext,
+    // indexed by address, like the CPU's decode cache
+    blocks: Vec<Option<Block>>,
+    self_modified: Vec<bool>,
+    // the decode and the shift quirk get baked into the code, so a change means starting over
+    compiled_for: Option<(Variant, Quirks)>,
+    compiled: usize,
+    failed: usize,
+}
+
+impl Jit {
+    /// Set up Cranelift for the host CPU. Fails if the host isn't one Cranelift supports.
+    pub fn new() -> Result<Self, String> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+        flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+        flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+        let isa = cranelift_native::builder()?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        Ok(Jit {
+            ctx: module.make_context(),
+            module,
+            builder_ctx: FunctionBuilderContext::new(),
+            blocks: Vec::new(),
+            self_modified: Vec::new(),
+            compiled_for: None,
+            compiled: 0,
+            failed: 0,
+        })
+    }
+
+    /// Cpu::run_for(), with the JIT.
+    pub fn run_for(&mut self, cpu: &mut Cpu, cycles: u64) -> u64 {
+        cpu.run_for_with(cycles, self)
+    }
+
+    /// How many blocks have been compiled to native code so far.
+    pub fn compiled_blocks(&self) -> usize {
+        self.compiled
+    }
+
+    /// How many blocks Cranelift couldn't compile, which were left to the interpreter. Should
+    /// be 0, anything else is a bug in the JIT.
+    pub fn failed_blocks(&self) -> usize {
+        self.failed
+    }
+
+    // Drop anything compiled from memory that's been written since last time
+    fn invalidate(&mut self, cpu: &mut Cpu) {
+        let settings = (cpu.variant, cpu.quirks);
+        if self.compiled_for != Some(settings) {
+            self.compiled_for = Some(settings);
+            self.reset(cpu.memory.len());
+        }
+
+        let Some((start, end)) = cpu.take_code_writes() else {
+            return;
+        };
+        if start == 0 && end >= cpu.memory.len() {
+            // a new ROM (or something like it), not self-modifying code
+            self.reset(cpu.memory.len());
+            return;
+        }
+        // a block covering a written byte can start at most MAX_BLOCK instructions earlier
+        for addr in start.saturating_sub(MAX_BLOCK * 2)..end.min(self.blocks.len()) {
+            let Some(block) = &self.blocks[addr] else {
+                continue;
+            };
+            if addr + block.len as usize * 2 > start {
+                // runs too short to compile can just be looked at again
+                self.self_modified[addr] = block.func.is_some();
+                self.blocks[addr] = None;
+            }
+        }
+    }
+
+    fn reset(&mut self, memory_len: usize) {
+        // Mega-Chip's 16MB is data, code stays in the first 64K
+        let memory_len = memory_len.min(DECODE_CACHE_SIZE);
+        self.blocks.clear();
+        self.blocks.resize_with(memory_len, || None);
+        self.self_modified.clear();
+        self.self_modified.resize(memory_len, false);
+    }
+
+    // The run of instructions starting at `addr` that a block can cover
+    fn find_block(cpu: &Cpu, addr: usize) -> Vec<Instruction> {
+        let mut block = Vec::new();
+        let mut pc = addr;
+        while block.len() < MAX_BLOCK && pc + 1 < cpu.memory.len() {
+            let instruction = Instruction::decode_at(&cpu.memory, pc, cpu.variant);
+            if !instruction.only_touches_registers() {
+                break;
+            }
+            block.push(instruction);
+            pc += 2;
+        }
+        block
+    }
+
+    fn compile(&mut self, instructions: &[Instruction], quirks: Quirks, index_mask: u32) -> Result<BlockFn, String> {
+        let target = self.module.target_config();
+        let pointer = target.pointer_type();
+        self.module.clear_context(&mut self.ctx);
+        self.ctx.func.signature.params.push(AbiParam::new(pointer));
+        self.ctx.func.signature.params.push(AbiParam::new(pointer));
+
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_ctx);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let registers_ptr = builder.block_params(entry)[0];
+        let index_ptr = builder.block_params(entry)[1];
+
+        // Keep everything in SSA values while the block runs and only write back what changed
+        let flags = MemFlagsData::trusted();
+        let loaded: [Value; 16] =
+            core::array::from_fn(|n| builder.ins().load(types::I8, flags, registers_ptr, n as i32));
+        let loaded_index = builder.ins().load(types::I32, flags, index_ptr, 0);
+        let mut v = loaded;
+        let mut index = loaded_index;
+
+        for &instruction in instructions {
+            use Instruction::*;
+
+            match instruction {
+                LoadByte(x, kk) => v[x as usize] = builder.ins().iconst(types::I8, kk as i64),
+                AddByte(x, kk) => v[x as usize] = builder.ins().iadd_imm_u(v[x as usize], kk as i64),
+                Move(x, y) => v[x as usize] = v[y as usize],
+                Or(x, y) => v[x as usize] = builder.ins().bor(v[x as usize], v[y as usize]),
+                And(x, y) => v[x as usize] = builder.ins().band(v[x as usize], v[y as usize]),
+                Xor(x, y) => v[x as usize] = builder.ins().bxor(v[x as usize], v[y as usize]),
+                Add(x, y) => {
+                    let vx = v[x as usize];
+                    let sum = builder.ins().iadd(vx, v[y as usize]);
+                    // it wrapped if the sum came out smaller
+                    let carry = builder.ins().icmp(IntCC::UnsignedLessThan, sum, vx);
+                    v[x as usize] = sum;
+                    v[0xF] = carry;
+                }
+                Sub(x, y) | SubReversed(x, y) => {
+                    let (lhs, rhs) = match instruction {
+                        Sub(..) => (v[x as usize], v[y as usize]),
+                        _ => (v[y as usize], v[x as usize]),
+                    };
+                    let difference = builder.ins().isub(lhs, rhs);
+                    let no_borrow = builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, lhs, rhs);
+                    v[x as usize] = difference;
+                    v[0xF] = no_borrow;
+                }
+                ShiftRight(x, y) | ShiftLeft(x, y) => {
+                    let source = v[if quirks.shift_vx_in_place { x } else { y } as usize];
+                    let (shifted, flag) = match instruction {
+                        ShiftRight(..) => (builder.ins().ushr_imm_u(source, 1), builder.ins().band_imm_u(source, 1)),
+                        _ => (builder.ins().ishl_imm_u(source, 1), builder.ins().ushr_imm_u(source, 7)),
+                    };
+                    v[x as usize] = shifted;
+                    v[0xF] = flag;
+                }
+                LoadIndex(nnn) => index = builder.ins().iconst(types::I32, nnn as i64),
+                AddIndex(x) => {
+                    let vx = builder.ins().uextend(types::I32, v[x as usize]);
+                    let sum = builder.ins().iadd(index, vx);
+                    if quirks.index_overflow_sets_vf {
+                        v[0xF] = builder.ins().icmp_imm_u(IntCC::UnsignedGreaterThan, sum, 0xFFF);
+                    }
+                    index = builder.ins().band_imm_u(sum, index_mask as i64);
+                }
+                _ => unreachable!("{} isn't compilable", instruction),
+            }
+        }
+
+        for n in 0..16 {
+            if v[n] != loaded[n] {
+                builder.ins().store(flags, v[n], registers_ptr, n as i32);
+            }
+        }
+        if index != loaded_index {
+            builder.ins().store(flags, index, index_ptr, 0);
+        }
+        builder.ins().return_(&[]);
+        builder.finalize(target);
+
+        let id = self
+            .module
+            .declare_anonymous_function(&self.ctx.func.signature)
+            .map_err(|e| e.to_string())?;
+        self.module.define_function(id, &mut self.ctx).map_err(|e| e.to_string())?;
+        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: the function was built with exactly BlockFn's signature above
+        Ok(unsafe { mem::transmute::<*const u8, BlockFn>(code) })
+    }
+}
+
+impl BlockRunner for Jit {
+    fn run_block(&mut self, cpu: &mut Cpu, max: u32) -> u32 {
+        self.invalidate(cpu);
+
+        let pc = cpu.position_in_memory;
+        if pc >= self.blocks.len() || self.self_modified[pc] {
+            return 0;
+        }
+        if self.blocks[pc].is_none() {
+            let instructions = Self::find_block(cpu, pc);
+            let mut block = Block { func: None, len: instructions.len() as u32 };
+            if instructions.len() >= MIN_BLOCK {
+                match self.compile(&instructions, cpu.quirks, cpu.variant.index_mask()) {
+                    Ok(func) => {
+                        block.func = Some(func);
+                        self.compiled += 1;
+                    }
+                    // not worth stopping for, the interpreter can run it
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(error = %_e, pc = %format_args!("{:03X}", pc), "jit couldn't compile block");
+                        self.failed += 1;
+                    }
+                }
+            }
+            self.blocks[pc] = Some(block);
+        }
+
+        let Some(Block { func: Some(func), len }) = self.blocks[pc] else {
+            return 0;
+        };
+        // too close to the end of the run
+        if len > max {
+            return 0;
+        }
+        // SAFETY: the pointers are to the CPU's own registers and I, which is all a block touches
+        unsafe { func(cpu.registers.as_mut_ptr(), &mut cpu.index_register) };
+        cpu.position_in_memory = pc + len as usize * 2;
+        len
+    }
+}
+
+#[cfg(all(test, feature = "builtin-roms"))]
+mod tests {
+    use super::*;
+    use crate::builtin_roms::BUILTIN_ROMS;
+    use crate::error::CpuError;
+
+    // 10 seconds, enough for catch to drop a few balls
+    const FRAMES: usize = 600;
+
+    #[test]
+    fn same_state_as_the_interpreter() {
+        for rom in &BUILTIN_ROMS {
+            let mut jit = Jit::new().unwrap();
+            let mut interpreted = Cpu::with_variant(rom.variant);
+            let mut compiled = Cpu::with_variant(rom.variant);
+            interpreted.load_rom(rom.bytes).unwrap();
+            compiled.load_rom(rom.bytes).unwrap();
+
+            for frame in 0..FRAMES {
+                // hold 6 for a bit so catch's paddle moves
+                let held = (100..200).contains(&frame);
+                interpreted.set_key(0x6, held);
+                compiled.set_key(0x6, held);
+                interpreted.run_frame();
+                compiled.run_frame_with(&mut jit);
+                assert_eq!(interpreted.state_digest(), compiled.state_digest(), "{} frame {}", rom.name, frame);
+            }
+            assert_eq!(jit.failed_blocks(), 0, "{}", rom.name);
+            // or this is only checking the interpreter against itself
+            assert!(jit.compiled_blocks() > 0, "{}", rom.name);
+        }
+    }
+
+    #[test]
+    fn blocks_stop_at_the_watchdog() {
+        // three ADD V0, 1 then jump back, forever. The limit lands partway into the block, so
+        // the last two have to be interpreted
+        let mut jit = Jit::new().unwrap();
+        let mut cpu = Cpu::new(Quirks::default());
+        cpu.load_rom(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu.watchdog = Some(102);
+        for _ in 0..20 {
+            cpu.run_frame_with(&mut jit);
+        }
+        assert_eq!(cpu.fault(), Some(&CpuError::Watchdog { pc: 0x204, cycles: 102 }));
+        assert_eq!(cpu.registers[0], 77);
+        assert!(jit.compiled_blocks() > 0);
+    }
+}
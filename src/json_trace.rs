@@ -0,0 +1,85 @@
+// JSON lines trace.
+// One JSON object per executed instruction, one per line, for scripts to pick apart. Each line
+// is the machine as it was just before the instruction ran:
+//   {"cycle":0,"pc":512,"opcode":24586,"mnemonic":"LD V0, 0x0A","registers":[0,...],"i":0,"sp":0}
+// cycle counts instructions from when the trace was added, starting at 0. Numbers are plain
+// decimal and the keys always come in this order, so the format only ever gains keys at the end.
+
+use std::io::{self, Write};
+
+use crate::cpu::Cpu;
+use crate::disasm::disassemble_at;
+use crate::hooks::Observer;
+
+/// An Observer that writes every instruction to `W` as a line of JSON. Wrap files in a
+/// BufWriter, there's a write per instruction.
+pub struct JsonTrace<W: Write> {
+    out: W,
+    cycle: u64,
+    // the first write that failed, everything after it is dropped
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonTrace<W> {
+    pub fn new(out: W) -> Self {
+        JsonTrace { out, cycle: 0, error: None }
+    }
+
+    /// How many instructions have been written.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Flush what's been written, or give back the error that stopped the trace.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+
+    fn write_line(&mut self, cpu: &Cpu, pc: usize) -> io::Result<()> {
+        let instruction = disassemble_at(&cpu.memory, pc, cpu.variant, &cpu.quirks());
+        write!(
+            self.out,
+            "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"registers\":[",
+            self.cycle,
+            pc,
+            instruction.opcode,
+            escape(&instruction.text)
+        )?;
+        for (n, value) in cpu.registers.iter().enumerate() {
+            if n > 0 {
+                self.out.write_all(b",")?;
+            }
+            write!(self.out, "{}", value)?;
+        }
+        writeln!(self.out, "],\"i\":{},\"sp\":{}}}", cpu.index_register, cpu.stack_pointer)
+    }
+}
+
+impl<W: Write + Send + Sync> Observer for JsonTrace<W> {
+    fn before_instruction(&mut self, cpu: &Cpu, pc: usize) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(error) = self.write_line(cpu, pc) {
+            self.error = Some(error);
+        }
+        self.cycle += 1;
+    }
+}
+
+// A JSON string's insides. Mnemonics are plain ASCII, but there's no harm in being careful
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,292 @@
+// Keypad mapping.
+// The original machines had a 4x4 hex keypad laid out like this:
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+// Every emulator maps it onto the left hand side of a QWERTY keyboard so the shape is kept:
+//   1 2 3 4
+//   Q W E R
+//   A S D F
+//   Z X C V
+// Other keyboard layouts get the keys in the same place rather than the same letters. A keymap
+// can also be written out as 16 keyboard keys in the order the keypad reads, row by row, so
+// QWERTY is "1234qwerasdfzxcv".
+//
+// Plenty of games are for two players sharing the one keypad (Pong has 1 and 4 for the left
+// paddle, C and D for the right one), which is a squeeze on one corner of the keyboard. A split
+// keymap gives the second player a keypad of their own on the other half, both reaching the
+// same 16 keys, and each half can be remapped without touching the other. "split" is QWERTY
+// with player 2 on
+//   6 7 8 9
+//   Y U I O
+//   H J K L
+//   N M , .
+// and any two keymaps can be split as "left|right", like "dvorak|6789fgcrdhtnbmwv". The
+// terminal keeps N for stepping a frame, so player 2's A needs moving for games that use it.
+// On CHIP-8X, which had a second keypad, player 2's keys are that keypad's.
+
+/// Keys frontends handle themselves rather than passing to the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Esc
+    Quit,
+    /// Tab, switches fast-forward on and off
+    ToggleTurbo,
+    /// P, pause or resume
+    TogglePause,
+    /// N, run a single frame (while paused)
+    AdvanceFrame,
+    /// F2, switch between the text renderers (blocks, half blocks and braille)
+    NextTextRenderer,
+    /// F3, show or hide the debug overlay (see overlay.rs)
+    ToggleOverlay,
+    /// F4, turn the sound off and on
+    ToggleMute,
+    /// F10, start or stop recording a GIF
+    ToggleRecording,
+    /// F12, save the screen as a PNG
+    Screenshot,
+}
+
+/// One of the two players sharing a split keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+/// The CHIP-8 keys in the order the keypad reads, top left to bottom right.
+pub const KEYPAD_ORDER: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+/// Which keyboard key is bound to each CHIP-8 key, indexed by CHIP-8 key (0x0 to 0xF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [char; 16],
+    // player 2's keys in a split keymap, '\0' for a CHIP-8 key they don't have
+    player2: Option<[char; 16]>,
+}
+
+impl Keymap {
+    /// The layouts by name.
+    pub const LAYOUTS: [(&'static str, Keymap); 5] = [
+        ("qwerty", Keymap::qwerty()),
+        ("azerty", Keymap::azerty()),
+        ("dvorak", Keymap::dvorak()),
+        ("colemak", Keymap::colemak()),
+        ("split", Keymap::split()),
+    ];
+
+    pub const fn qwerty() -> Self {
+        Keymap::from_layout(['1', '2', '3', '4', 'q', 'w', 'e', 'r', 'a', 's', 'd', 'f', 'z', 'x', 'c', 'v'])
+    }
+
+    /// The number row is whatever those keys type without shift.
+    pub const fn azerty() -> Self {
+        Keymap::from_layout(['&', 'é', '"', '\'', 'a', 'z', 'e', 'r', 'q', 's', 'd', 'f', 'w', 'x', 'c', 'v'])
+    }
+
+    pub const fn dvorak() -> Self {
+        Keymap::from_layout(['1', '2', '3', '4', '\'', ',', '.', 'p', 'a', 'o', 'e', 'u', ';', 'q', 'j', 'k'])
+    }
+
+    pub const fn colemak() -> Self {
+        Keymap::from_layout(['1', '2', '3', '4', 'q', 'w', 'f', 'p', 'a', 'r', 's', 't', 'z', 'x', 'c', 'd'])
+    }
+
+    /// Two players on QWERTY, see the top of this file.
+    pub const fn split() -> Self {
+        let right = Keymap::from_layout(['6', '7', '8', '9', 'y', 'u', 'i', 'o', 'h', 'j', 'k', 'l', 'n', 'm', ',', '.']);
+        Keymap { keys: Keymap::qwerty().keys, player2: Some(right.keys) }
+    }
+
+    /// Player 1 on `left`'s keys and player 2 on `right`'s. None if they have a keyboard key in
+    /// common, or either's already split.
+    pub fn split_between(left: Keymap, right: Keymap) -> Option<Self> {
+        if left.is_split() || right.is_split() || left.keys.iter().any(|c| right.keys.contains(c)) {
+            return None;
+        }
+        Some(Keymap { keys: left.keys, player2: Some(right.keys) })
+    }
+
+    // 16 keyboard keys in keypad order
+    const fn from_layout(layout: [char; 16]) -> Self {
+        let mut keys = ['\0'; 16];
+        let mut n = 0;
+        while n < 16 {
+            keys[KEYPAD_ORDER[n] as usize] = layout[n];
+            n += 1;
+        }
+        Keymap { keys, player2: None }
+    }
+
+    /// A layout name, 16 different keys in keypad order, or two of those split as "left|right"
+    /// (see the top of this file).
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if let Some((left, right)) = text.split_once('|') {
+            return Keymap::split_between(Keymap::parse(left)?, Keymap::parse(right)?);
+        }
+        if let Some(&(_, keymap)) = Self::LAYOUTS.iter().find(|(name, _)| name.eq_ignore_ascii_case(text)) {
+            return Some(keymap);
+        }
+
+        let mut layout = ['\0'; 16];
+        let mut count = 0;
+        for c in text.chars().map(|c| c.to_ascii_lowercase()) {
+            if layout[..count].contains(&c) {
+                return None;
+            }
+            *layout.get_mut(count)? = c;
+            count += 1;
+        }
+        (count == 16).then(|| Keymap::from_layout(layout))
+    }
+
+    /// Whether there's a second player on the other half of the keyboard.
+    pub fn is_split(&self) -> bool {
+        self.player2.is_some()
+    }
+
+    /// The CHIP-8 key a keyboard key is bound to, if any, for either player. Case insensitive.
+    pub fn key_for(&self, c: char) -> Option<u8> {
+        self.player_key_for(c).map(|(_, key)| key)
+    }
+
+    /// key_for(), and which player's key it is.
+    pub fn player_key_for(&self, c: char) -> Option<(Player, u8)> {
+        let c = c.to_ascii_lowercase();
+        let key = self.keys.iter().chain(self.player2.iter().flatten()).position(|&k| k == c)?;
+        let player = if key < 16 { Player::One } else { Player::Two };
+        Some((player, key as u8 % 16))
+    }
+
+    /// Bind a keyboard key to a CHIP-8 key. If it was already bound to another one, that key
+    /// gets this one's old key instead, so every CHIP-8 key always has something. In a split
+    /// keymap this is player 1's keys.
+    pub fn bind(&mut self, key: u8, c: char) {
+        self.bind_for(Player::One, key, c);
+    }
+
+    /// bind() for one player's keys. A key the other player had is taken off them (a keyboard
+    /// key can only do one thing), and binding player 2's keys splits a keymap that wasn't,
+    /// player 2 starting with just the keys bound this way.
+    pub fn bind_for(&mut self, player: Player, key: u8, c: char) {
+        let key = (key & 0xF) as usize;
+        let c = c.to_ascii_lowercase();
+        let player2 = self.player2.get_or_insert(['\0'; 16]);
+        let (keys, other) = match player {
+            Player::One => (&mut self.keys, player2),
+            Player::Two => (player2, &mut self.keys),
+        };
+        for k in other.iter_mut().filter(|k| **k == c) {
+            *k = '\0';
+        }
+        if let Some(previous) = keys.iter().position(|&k| k == c) {
+            keys[previous] = keys[key];
+        }
+        keys[key] = c;
+        if self.player2 == Some(['\0'; 16]) {
+            self.player2 = None;
+        }
+    }
+
+    /// Every CHIP-8 key with the keyboard key bound to it, 0x0 first, and then player 2's in a
+    /// split keymap. A key can be in there twice.
+    pub fn bindings(&self) -> impl Iterator<Item = (u8, char)> + '_ {
+        let player2 = self.player2.iter().flat_map(|keys| keys.iter().enumerate());
+        self.keys.iter().enumerate().chain(player2).filter(|(_, &c)| c != '\0').map(|(key, &c)| (key as u8, c))
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::qwerty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_keep_the_keypad_shape() {
+        let qwerty = Keymap::qwerty();
+        assert_eq!(qwerty.key_for('1'), Some(0x1));
+        assert_eq!(qwerty.key_for('4'), Some(0xC));
+        assert_eq!(qwerty.key_for('X'), Some(0x0));
+        assert_eq!(qwerty.key_for('v'), Some(0xF));
+        assert_eq!(qwerty.key_for('t'), None);
+        assert_eq!(Keymap::azerty().key_for('a'), Some(0x4));
+        assert_eq!(Keymap::dvorak().key_for('o'), Some(0x8));
+        assert_eq!(Keymap::colemak().key_for('t'), Some(0xE));
+        for (_, keymap) in Keymap::LAYOUTS {
+            assert_eq!(keymap.bindings().count(), if keymap.is_split() { 32 } else { 16 });
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Keymap::parse(" Dvorak "), Some(Keymap::dvorak()));
+        assert_eq!(Keymap::parse("1234QWERasdfzxcv"), Some(Keymap::qwerty()));
+        // too short, too long, a key twice
+        assert_eq!(Keymap::parse("1234qwerasdfzxc"), None);
+        assert_eq!(Keymap::parse("1234qwerasdfzxcvb"), None);
+        assert_eq!(Keymap::parse("1234qwerasdfzxcc"), None);
+        assert_eq!(Keymap::parse("workman"), None);
+        assert_eq!(Keymap::parse(""), None);
+    }
+
+    #[test]
+    fn binding_swaps_keys() {
+        let mut keymap = Keymap::qwerty();
+        // W was 0x5, so 0x5 gets A's old key
+        keymap.bind(0x7, 'W');
+        assert_eq!(keymap.key_for('w'), Some(0x7));
+        assert_eq!(keymap.key_for('a'), Some(0x5));
+        keymap.bind(0x0, 'm');
+        assert_eq!(keymap.key_for('m'), Some(0x0));
+        assert_eq!(keymap.key_for('x'), None);
+        assert!(!keymap.is_split());
+    }
+
+    #[test]
+    fn split_keymaps() {
+        let split = Keymap::split();
+        assert!(split.is_split());
+        assert_eq!(split.player_key_for('q'), Some((Player::One, 0x4)));
+        assert_eq!(split.player_key_for('Y'), Some((Player::Two, 0x4)));
+        assert_eq!(split.player_key_for('.'), Some((Player::Two, 0xF)));
+
+        assert_eq!(Keymap::parse("qwerty|6789yuiohjklnm,."), Some(split));
+        let custom = Keymap::parse("dvorak|6789fgcrdhtnbmwv").unwrap();
+        assert_eq!(custom.player_key_for('f'), Some((Player::Two, 0x4)));
+        // keys in common, or splitting something already split
+        assert_eq!(Keymap::parse("qwerty|colemak"), None);
+        assert_eq!(Keymap::parse("split|dvorak"), None);
+        assert_eq!(Keymap::parse("qwerty|"), None);
+    }
+
+    #[test]
+    fn remapping_one_player() {
+        let mut keymap = Keymap::split();
+        keymap.bind_for(Player::Two, 0xA, 'b');
+        assert_eq!(keymap.player_key_for('b'), Some((Player::Two, 0xA)));
+        assert_eq!(keymap.player_key_for('n'), None);
+        assert_eq!(keymap.player_key_for('z'), Some((Player::One, 0xA)));
+
+        // a key player 1 takes is taken off player 2
+        keymap.bind_for(Player::One, 0x5, 'u');
+        assert_eq!(keymap.player_key_for('u'), Some((Player::One, 0x5)));
+        assert!(!keymap.bindings().skip(16).any(|(_, c)| c == 'u'));
+    }
+
+    #[test]
+    fn binding_player_two_splits() {
+        let mut keymap = Keymap::qwerty();
+        keymap.bind_for(Player::Two, 0xC, 'i');
+        assert!(keymap.is_split());
+        assert_eq!(keymap.bindings().count(), 17);
+        assert_eq!(keymap.player_key_for('i'), Some((Player::Two, 0xC)));
+    }
+}
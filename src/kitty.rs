@@ -0,0 +1,73 @@
+// Kitty graphics protocol.
+// kitty (and WezTerm, Ghostty and Konsole) can show images sent as escape codes: an APC
+// `ESC _ G <keys> ; <base64 payload> ESC \`, with the payload split into 4096 byte chunks and
+// m=1 on every chunk but the last. The screen goes over as a PNG blown up with solid square
+// pixels, which is small (CHIP-8 screens compress to almost nothing) and stays sharp. Every
+// frame is sent with the same image and placement ids so it replaces the last one.
+
+use std::env;
+use std::fmt::Write;
+use std::io;
+
+use crate::display::Display;
+use crate::palette::Palette;
+
+// The image is this many pixels across whatever the resolution, the same as the sixel one
+const IMAGE_WIDTH: usize = 512;
+// Base64 bytes per escape code, the protocol's limit
+const CHUNK: usize = 4096;
+
+/// Whether the terminal looks like one that speaks the kitty graphics protocol, going by what
+/// it sets in the environment.
+pub fn is_supported() -> bool {
+    let term = env::var("TERM").unwrap_or_default();
+    let program = env::var("TERM_PROGRAM").unwrap_or_default();
+    env::var_os("KITTY_WINDOW_ID").is_some()
+        || term == "xterm-kitty"
+        || term == "xterm-ghostty"
+        || program == "WezTerm"
+        || program == "ghostty"
+}
+
+/// The escape codes that draw the screen at the cursor, replacing the last frame.
+pub fn encode(display: &Display, palette: &Palette) -> io::Result<String> {
+    let scale = (IMAGE_WIDTH / display.width()).max(1) as u32;
+    let mut image = Vec::new();
+    display.write_png(&mut image, scale, palette)?;
+    let payload = base64(&image);
+
+    let mut out = String::with_capacity(payload.len() + payload.len() / CHUNK * 8 + 64);
+    for start in (0..payload.len()).step_by(CHUNK) {
+        let end = (start + CHUNK).min(payload.len());
+        let more = (end < payload.len()) as u8;
+        if start == 0 {
+            // transmit and show a PNG, don't move the cursor, don't answer, and go under any
+            // text on top (the debug overlay)
+            let _ = write!(out, "\x1b_Ga=T,f=100,i=1,p=1,C=1,z=-1,q=2,m={};", more);
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        out.push_str(&payload[start..end]);
+        out.push_str("\x1b\\");
+    }
+    Ok(out)
+}
+
+/// The escape code that takes the image off the screen again.
+pub const CLEAR: &str = "\x1b_Ga=d,d=I,i=1,q=2\x1b\\";
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let n = group.iter().enumerate().fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
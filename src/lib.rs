@@ -0,0 +1,124 @@
+// Ref: rust in action
+
+// CHIP-8 Emulator.
+// The core lives in this library so other frontends can drive it, main.rs is just a runner.
+// Without the std feature the core (CPU, decoder, display) is no_std and only needs alloc,
+// so it can run on a microcontroller. Frontends and anything touching files or time need std.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod analysis;
+#[cfg(feature = "tokio")]
+pub mod async_emulator;
+pub mod audio;
+#[cfg(feature = "audio")]
+pub mod audio_output;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod breakpoints;
+pub mod builder;
+#[cfg(feature = "builtin-roms")]
+pub mod builtin_roms;
+pub mod call_stack;
+pub mod clock;
+pub mod color_board;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod crt;
+pub mod display;
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod emulator_thread;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flow_graph;
+pub mod font;
+mod fnv;
+pub mod frontend;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+#[cfg(feature = "egui")]
+pub mod gui_debugger;
+pub mod halt;
+pub mod history;
+pub mod hooks;
+pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "std")]
+pub mod json_trace;
+pub mod keymap;
+#[cfg(feature = "terminal")]
+pub mod kitty;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod lockstep;
+pub mod mega_chip;
+#[cfg(feature = "metadata")]
+pub mod metadata;
+pub mod memory_protection;
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod netplay;
+pub mod overlay;
+pub mod palette;
+pub mod patch;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quirks;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "std")]
+pub mod rom_db;
+pub mod rom_format;
+pub mod rpl_flags;
+pub mod save_state;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "screenshot")]
+pub mod screenshot;
+pub mod sha1;
+#[cfg(feature = "terminal")]
+pub mod sixel;
+pub mod stats;
+pub mod symbols;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+#[cfg(feature = "std")]
+pub mod test_roms;
+pub mod transpile;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod variant;
+#[cfg(feature = "std")]
+pub mod video;
+pub mod vip_timing;
+#[cfg(feature = "std")]
+pub mod watch;
+#[cfg(feature = "std")]
+pub mod wav;
+#[cfg(feature = "wasm")]
+pub mod web;
+
+pub use builder::CpuBuilder;
+pub use cpu::Cpu;
+pub use display::Display;
+pub use error::CpuError;
+pub use halt::{HaltReason, LoopDetection, UnknownOpcodePolicy};
+pub use instruction::Instruction;
+pub use memory_protection::MemoryProtection;
+pub use quirks::Quirks;
+pub use variant::Variant;
@@ -0,0 +1,459 @@
+// libretro core.
+// Implements the libretro C API so RetroArch (or any libretro frontend) can load the emulator
+// and provide video, audio, input, shaders, recording etc. for us. The frontend calls
+// retro_run() once per frame, everything else here is plumbing around Cpu::run_frame.
+// Build with:
+//   cargo rustc --lib --release --no-default-features --features libretro --crate-type cdylib
+// and load the library as chip8_libretro.so.
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use crate::audio::AudioEngine;
+use crate::clock::TIMER_HZ;
+use crate::cpu::Cpu;
+use crate::display::{HEIGHT, WIDTH};
+use crate::mega_chip::{MEGA_HEIGHT, MEGA_WIDTH};
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::variant::Variant;
+
+// The bits of libretro.h we need
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_Y: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+const RETRO_DEVICE_ID_JOYPAD_X: c_uint = 9;
+
+// Most games steer with 2/4/6/8 and act with 5, so the joypad gets those.
+// The keyboard gets the whole keypad through the usual QWERTY layout.
+const JOYPAD_MAP: [(c_uint, u8); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0xA),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0xB),
+];
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / TIMER_HZ) as usize;
+
+// Core options, shown in the frontend's menu. The first choice is the default
+const PALETTE_KEY: &CStr = c"chip8_palette";
+const PALETTE_CHOICES: &CStr = c"Palette; monochrome|green|amber|paper";
+const PHOSPHOR_KEY: &CStr = c"chip8_phosphor";
+const PHOSPHOR_CHOICES: &CStr = c"Phosphor decay (frames); 0|2|4|8|16";
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    // the loaded game, kept so retro_reset can start it again
+    rom: Vec<u8>,
+    variant: Variant,
+    cpu: Option<Cpu>,
+    audio: AudioEngine,
+    video: Vec<u32>,
+    palette: Palette,
+    phosphor_decay: u8,
+    samples: Vec<f32>,
+    stereo: Vec<i16>,
+}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+// Run `f` on the core, doing nothing before retro_init/after retro_deinit
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> Option<T> {
+    CORE.lock().unwrap().as_mut().map(f)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    *CORE.lock().unwrap() = Some(Core {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+        rom: Vec::new(),
+        variant: Variant::Chip8,
+        cpu: None,
+        audio: AudioEngine::new(SAMPLE_RATE),
+        video: Vec::new(),
+        palette: Palette::default(),
+        phosphor_decay: 0,
+        samples: vec![0.0; SAMPLES_PER_FRAME],
+        stereo: vec![0; SAMPLES_PER_FRAME * 2],
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"CHIP-8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8|xo8|c8x|mc8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_av_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            // Mega-Chip's screen is the biggest
+            max_width: MEGA_WIDTH as c_uint,
+            max_height: MEGA_HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: RetroSystemTiming {
+            fps: TIMER_HZ as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    let variables = [
+        RetroVariable { key: PALETTE_KEY.as_ptr(), value: PALETTE_CHOICES.as_ptr() },
+        RetroVariable { key: PHOSPHOR_KEY.as_ptr(), value: PHOSPHOR_CHOICES.as_ptr() },
+        RetroVariable { key: ptr::null(), value: ptr::null() },
+    ];
+    // SAFETY: SET_VARIABLES takes a null terminated array of retro_variable, which the frontend
+    // copies before returning
+    unsafe { callback(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void) };
+    with_core(|core| core.environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    with_core(|core| core.video_refresh = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {
+    // we always hand over a whole frame of audio with the batch callback
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    with_core(|core| core.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    with_core(|core| core.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    with_core(|core| core.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// # Safety
+/// `game` must be null or point to a valid retro_game_info whose data is `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let game = &*game;
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+
+    // no other way to tell which machine a ROM is for, so go by the extension like everyone else
+    let variant = if game.path.is_null() {
+        Variant::Chip8
+    } else {
+        let path = CStr::from_ptr(game.path).to_string_lossy();
+        match Path::new(path.as_ref()).extension().and_then(|e| e.to_str()) {
+            Some("sc8") => Variant::SuperChip,
+            Some("xo8") => Variant::XoChip,
+            Some("c8x") => Variant::Chip8X,
+            Some("mc8") => Variant::MegaChip,
+            _ => Variant::Chip8,
+        }
+    };
+
+    with_core(|core| {
+        if let Some(environment) = core.environment {
+            let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+            if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+                return false;
+            }
+        }
+        core.read_options();
+        core.rom = rom;
+        core.variant = variant;
+        core.start()
+    })
+    .unwrap_or(false)
+}
+
+/// # Safety
+/// Subsystems aren't supported, this never reads its arguments.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game_special(_type: c_uint, _info: *const RetroGameInfo, _num: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.cpu = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| core.start());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core(|core| core.run_frame());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(|core| match (&mut core.cpu, id) {
+        (Some(cpu), RETRO_MEMORY_SYSTEM_RAM) => cpu.memory.as_mut_ptr() as *mut c_void,
+        _ => ptr::null_mut(),
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(|core| match (&core.cpu, id) {
+        (Some(cpu), RETRO_MEMORY_SYSTEM_RAM) => cpu.memory.len(),
+        _ => 0,
+    })
+    .unwrap_or(0)
+}
+
+// Save states aren't supported (yet), a size of 0 tells the frontend so
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    0
+}
+
+/// # Safety
+/// Never touches `data`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(_data: *mut c_void, _size: usize) -> bool {
+    false
+}
+
+/// # Safety
+/// Never touches `data`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(_data: *const c_void, _size: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+/// # Safety
+/// Cheats aren't supported, never reads `code`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+impl Core {
+    // (Re)start the loaded game on a fresh machine
+    fn start(&mut self) -> bool {
+        let mut cpu = Cpu::with_variant(self.variant);
+        cpu.display.set_phosphor_decay(self.phosphor_decay);
+        if cpu.load_rom(&self.rom).is_err() {
+            self.cpu = None;
+            return false;
+        }
+        self.cpu = Some(cpu);
+        true
+    }
+
+    // Pick up the core options, the user can change them from the menu at any time
+    fn read_options(&mut self) {
+        if let Some(palette) = self.option(PALETTE_KEY) {
+            self.palette = Palette::parse(&palette).unwrap_or_default();
+        }
+        if let Some(phosphor) = self.option(PHOSPHOR_KEY) {
+            self.phosphor_decay = phosphor.parse().unwrap_or(0);
+            if let Some(cpu) = &mut self.cpu {
+                cpu.display.set_phosphor_decay(self.phosphor_decay);
+            }
+        }
+    }
+
+    fn option(&self, key: &CStr) -> Option<String> {
+        let environment = self.environment?;
+        let mut variable = RetroVariable { key: key.as_ptr(), value: ptr::null() };
+        // SAFETY: GET_VARIABLE fills in value with a string that lives until the next call
+        let found = unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void) };
+        if !found || variable.value.is_null() {
+            return None;
+        }
+        // SAFETY: checked for null, and libretro strings are nul terminated
+        Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+    }
+
+    fn run_frame(&mut self) {
+        if let Some(environment) = self.environment {
+            let mut updated = false;
+            // SAFETY: GET_VARIABLE_UPDATE writes a bool
+            unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) };
+            if updated {
+                self.read_options();
+            }
+        }
+        let Some(cpu) = self.cpu.as_mut() else { return };
+
+        if let (Some(poll), Some(state)) = (self.input_poll, self.input_state) {
+            // SAFETY: the frontend gave us these callbacks for exactly this
+            unsafe {
+                poll();
+                let keymap = Keymap::default();
+                let mut keys = [false; 16];
+                for c in "1234qwerasdfzxcv".chars() {
+                    // RETROK codes for letters and digits are just their ASCII values
+                    if state(0, RETRO_DEVICE_KEYBOARD, 0, c as c_uint) != 0 {
+                        if let Some(key) = keymap.key_for(c) {
+                            keys[key as usize] = true;
+                        }
+                    }
+                }
+                for (button, key) in JOYPAD_MAP {
+                    if state(0, RETRO_DEVICE_JOYPAD, 0, button) != 0 {
+                        keys[key as usize] = true;
+                    }
+                }
+                for (key, pressed) in keys.into_iter().enumerate() {
+                    cpu.set_key(key as u8, pressed);
+                }
+            }
+        }
+
+        // the frontend can write to memory through retro_get_memory_data (cheats do)
+        cpu.flush_decoded();
+        cpu.run_frame();
+
+        let display = &cpu.display;
+        let (width, height) = (display.width(), display.height());
+        self.video.clear();
+        self.video.extend(display.rgb_pixels(&self.palette).map(|[r, g, b]| u32::from_be_bytes([0, r, g, b])));
+        if let Some(video_refresh) = self.video_refresh {
+            // SAFETY: the buffer is width * height XRGB8888 pixels, which is what we said we'd send
+            unsafe {
+                video_refresh(self.video.as_ptr() as *const c_void, width as c_uint, height as c_uint, width * 4);
+            }
+        }
+
+        self.audio.fill(&cpu.sound(), &mut self.samples);
+        for (frame, sample) in self.stereo.chunks_mut(2).zip(&self.samples) {
+            frame.fill((sample * i16::MAX as f32) as i16);
+        }
+        if let Some(audio_sample_batch) = self.audio_sample_batch {
+            // SAFETY: stereo holds SAMPLES_PER_FRAME interleaved left/right frames
+            unsafe {
+                audio_sample_batch(self.stereo.as_ptr(), SAMPLES_PER_FRAME);
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// Differential testing.
+// Runs two cores side by side one instruction at a time and stops at the first one after which
+// they disagree about PC, I, the registers, memory or the screen. The two cores can be this one
+// with two different configurations (the easiest way to see what a quirk actually changes for a
+// ROM), or this one against another emulator wrapped in the Core trait as a reference.
+
+use core::fmt;
+
+use crate::cpu::Cpu;
+
+/// What lockstep running needs from a core. Implemented for Cpu, implement it for another
+/// emulator to check this one against it.
+pub trait Core {
+    /// Run one instruction.
+    fn step(&mut self);
+    /// The 60Hz tick for the delay and sound timers.
+    fn tick_timers(&mut self);
+    fn is_halted(&self) -> bool;
+    fn pc(&self) -> usize;
+    fn index_register(&self) -> u32;
+    fn registers(&self) -> [u8; 16];
+    fn memory(&self) -> &[u8];
+    /// Width and height of the screen in pixels.
+    fn screen_size(&self) -> (usize, usize);
+    /// The pixel at (x, y), 0 for off. Cores with more than one plane give the colour number.
+    fn pixel(&self, x: usize, y: usize) -> u8;
+    /// A number that goes up whenever the screen changes, so the screens only get compared
+    /// pixel by pixel when one of them might have. None if the core doesn't keep track.
+    fn screen_generation(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Core for Cpu {
+    fn step(&mut self) {
+        Cpu::step(self)
+    }
+
+    fn tick_timers(&mut self) {
+        Cpu::tick_timers(self)
+    }
+
+    fn is_halted(&self) -> bool {
+        Cpu::is_halted(self)
+    }
+
+    fn pc(&self) -> usize {
+        self.position_in_memory
+    }
+
+    fn index_register(&self) -> u32 {
+        self.index_register
+    }
+
+    fn registers(&self) -> [u8; 16] {
+        self.registers
+    }
+
+    fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn screen_size(&self) -> (usize, usize) {
+        (self.display.width(), self.display.height())
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.display.pixel_color(x, y)
+    }
+
+    fn screen_generation(&self) -> Option<u64> {
+        Some(self.display.changes())
+    }
+}
+
+/// The first thing two cores disagreed on, with the first core's value then the second's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    Halted(bool, bool),
+    Pc(usize, usize),
+    IndexRegister(u32, u32),
+    Register(usize, u8, u8),
+    Memory(usize, u8, u8),
+    ScreenSize((usize, usize), (usize, usize)),
+    Pixel(usize, usize, u8, u8),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Halted(a, b) => write!(f, "halted: {} vs {}", a, b),
+            Divergence::Pc(a, b) => write!(f, "PC: {:03X} vs {:03X}", a, b),
+            Divergence::IndexRegister(a, b) => write!(f, "I: {:03X} vs {:03X}", a, b),
+            Divergence::Register(n, a, b) => write!(f, "V{:X}: {:02X} vs {:02X}", n, a, b),
+            Divergence::Memory(addr, a, b) => write!(f, "memory at {:03X}: {:02X} vs {:02X}", addr, a, b),
+            Divergence::ScreenSize(a, b) => write!(f, "screen size: {}x{} vs {}x{}", a.0, a.1, b.0, b.1),
+            Divergence::Pixel(x, y, a, b) => write!(f, "pixel ({}, {}): {} vs {}", x, y, a, b),
+        }
+    }
+}
+
+/// Where two cores went their separate ways.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// How many instructions had run, 0 if they disagreed before starting
+    pub cycle: u64,
+    /// The first core's PC before the instruction that made them disagree
+    pub pc: usize,
+    pub divergence: Divergence,
+}
+
+/// Run both cores for up to `cycles` instructions, ticking their timers every
+/// `instructions_per_frame`, comparing them after every instruction. Returns how many ran if
+/// they never disagreed (fewer than `cycles` if both halted), or the first disagreement.
+pub fn run_lockstep(a: &mut dyn Core, b: &mut dyn Core, cycles: u64, instructions_per_frame: u32) -> Result<u64, Mismatch> {
+    let per_frame = instructions_per_frame.max(1) as u64;
+    let mismatch = |cycle, pc, divergence| Mismatch { cycle, pc, divergence };
+
+    if let Some(divergence) = compare(a, b) {
+        return Err(mismatch(0, a.pc(), divergence));
+    }
+    let mut generations = (a.screen_generation(), b.screen_generation());
+    for cycle in 1..=cycles {
+        if a.is_halted() && b.is_halted() {
+            return Ok(cycle - 1);
+        }
+
+        let pc = a.pc();
+        a.step();
+        b.step();
+        if cycle % per_frame == 0 {
+            a.tick_timers();
+            b.tick_timers();
+        }
+
+        let now = (a.screen_generation(), b.screen_generation());
+        let screens_unchanged = now.0.is_some() && now.1.is_some() && now == generations;
+        generations = now;
+        if let Some(divergence) = compare_state(a, b, !screens_unchanged) {
+            return Err(mismatch(cycle, pc, divergence));
+        }
+    }
+    Ok(cycles)
+}
+
+/// The first difference between two cores, checking the cheap things first.
+pub fn compare(a: &dyn Core, b: &dyn Core) -> Option<Divergence> {
+    compare_state(a, b, true)
+}
+
+fn compare_state(a: &dyn Core, b: &dyn Core, screens: bool) -> Option<Divergence> {
+    if a.is_halted() != b.is_halted() {
+        return Some(Divergence::Halted(a.is_halted(), b.is_halted()));
+    }
+    if a.pc() != b.pc() {
+        return Some(Divergence::Pc(a.pc(), b.pc()));
+    }
+    if a.index_register() != b.index_register() {
+        return Some(Divergence::IndexRegister(a.index_register(), b.index_register()));
+    }
+    let (registers_a, registers_b) = (a.registers(), b.registers());
+    if let Some(n) = (0..16).find(|&n| registers_a[n] != registers_b[n]) {
+        return Some(Divergence::Register(n, registers_a[n], registers_b[n]));
+    }
+
+    // XO-CHIP has more memory than the others, only what they both have is compared
+    let len = a.memory().len().min(b.memory().len());
+    let (memory_a, memory_b) = (&a.memory()[..len], &b.memory()[..len]);
+    if memory_a != memory_b {
+        let addr = (0..len).find(|&addr| memory_a[addr] != memory_b[addr])?;
+        return Some(Divergence::Memory(addr, memory_a[addr], memory_b[addr]));
+    }
+
+    if !screens {
+        return None;
+    }
+    let size = a.screen_size();
+    if size != b.screen_size() {
+        return Some(Divergence::ScreenSize(size, b.screen_size()));
+    }
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let (pixel_a, pixel_b) = (a.pixel(x, y), b.pixel(x, y));
+            if pixel_a != pixel_b {
+                return Some(Divergence::Pixel(x, y, pixel_a, pixel_b));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use crate::quirks::Quirks;
+
+    fn machine(quirks: Quirks, program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(quirks);
+        cpu.load_rom(program).unwrap();
+        cpu
+    }
+
+    // 8XY6 shifts VY on the VIP and VX in place on the SUPER-CHIP
+    const SHIFT: [u8; 8] = [0x60, 0x81, 0x61, 0x02, 0x80, 0x16, 0x00, 0x00];
+
+    #[test]
+    fn the_same_core_agrees() {
+        let mut a = machine(Quirks::cosmac_vip(), &SHIFT);
+        let mut b = machine(Quirks::cosmac_vip(), &SHIFT);
+        // the three instructions and the halt
+        assert_eq!(run_lockstep(&mut a, &mut b, 100, 10), Ok(4));
+        assert_eq!(run_lockstep(&mut a, &mut b, 100, 10), Ok(0));
+    }
+
+    #[test]
+    fn stops_at_the_first_difference() {
+        let mut a = machine(Quirks::cosmac_vip(), &SHIFT);
+        let mut b = machine(Quirks::superchip(), &SHIFT);
+        let mismatch = run_lockstep(&mut a, &mut b, 100, 10).unwrap_err();
+        // VF differs as well, but V0 comes first
+        assert_eq!(mismatch, Mismatch { cycle: 3, pc: 0x204, divergence: Divergence::Register(0, 0x01, 0x40) });
+        assert_eq!(mismatch.divergence.to_string(), "V0: 01 vs 40");
+    }
+
+    #[test]
+    fn cheap_things_first() {
+        let a = machine(Quirks::cosmac_vip(), &SHIFT);
+        let mut b = machine(Quirks::cosmac_vip(), &SHIFT);
+        b.registers[3] = 1;
+        b.memory[0x300] = 1;
+        assert_eq!(compare(&a, &b), Some(Divergence::Register(3, 0, 1)));
+        b.position_in_memory = 0x202;
+        assert_eq!(compare(&a, &b), Some(Divergence::Pc(0x200, 0x202)));
+        b.registers[3] = 0;
+        b.position_in_memory = 0x200;
+        assert_eq!(compare(&a, &b), Some(Divergence::Memory(0x300, 0, 1)));
+        b.memory[0x300] = 0;
+        b.display.draw_sprite(0, 0, &[0x80], false);
+        assert_eq!(compare(&a, &b), Some(Divergence::Pixel(0, 0, 0, 1)));
+    }
+
+    #[test]
+    fn timers_tick_every_frame() {
+        // LD V0, 5; LD DT, V0; then spin reading DT into V1
+        let program = [0x60, 0x05, 0xF0, 0x15, 0xF1, 0x07, 0x12, 0x04];
+        let mut a = machine(Quirks::cosmac_vip(), &program);
+        let mut b = machine(Quirks::cosmac_vip(), &program);
+        assert_eq!(run_lockstep(&mut a, &mut b, 20, 4), Ok(20));
+        // set at the 2nd, ticked at the 4th, 8th, ... 20th, last read at the 19th
+        assert_eq!((a.delay_timer, b.delay_timer), (0, 0));
+        assert_eq!(a.registers[1], 1);
+    }
+}
@@ -1,194 +1,1744 @@
-// Ref: rust in action
-
-// CHIP-8 Emulator.
-
-// Decoding CHIP-8 opcodes.
-// they're u16 values made up of 4 nibbles (half a byte / 4 bits)
-// rust has no 4 bit type so we just wrap them all together in a 16bit type and then awkwardly
-// filter out the bits we dont care about to access each individual nibble
-
-// CHIP-8 breaks opcodes down into 2 bytes, high byte, low byte, then nibbles, high nibble, low nibble
-// (layout of the bytes is called endianness, cpu manufacturers decide this)
-// 0xAB12
-// high byte = AB
-  // high nibble A
-  // low nibble B
-// low byte = 12
-  // high nibble 1
-  // low nibble 2
-
-// Note that control flow in a CPU is done by comparing values in a register
-// then modifying position_in_memory, depending on the outcome. There are no while
-// or for loops in the CPU, thats the job of the programming languages compiler.
-use core::panic;
-
-// All CHIP-8 opcodes are U16 values, defined by who makes the architecture
-struct CPU {
-    // Moved now to 16 registers. Means that a single hex num (0 to F) can address these,
-    // let's all opcodes be compactly represented as u16 values.
-    registers: [u8; 16],
-    // Usually called 'program counter' but this naming makes it obvious
-    position_in_memory: usize, // diverges from original spec, but rust lets us use this for indexing
-    // 0x1000 is hex for 4096 (4kb), the amount of bytes of RAM a CHIP-8 had.
-    // The chip-8 usize equiv basically, only 2^12 (12 bits = 4096)
-    // In original spec, the first 512 bytes (0x100) are reserved for the system, others are for programs
-    memory: [u8; 0x1000],
-
-    // ~ The stack ~ specialised memory for CALL and RETURN opcodes
-    stack: [u16; 16], // stacks maximum height is 16m after 16 nested function calls we say its a stack overflow
-    stack_pointer: usize // giving the stack_pointer usize makes it easier to index values cause rust
-}
-
-impl CPU {
-    fn read_opcode(&self) -> u16 {
-        // combine 2 u8 into a single u16
-        let p = self.position_in_memory;
-        let op_byte1 = self.memory[p] as u16;
-        let op_byte2 = self.memory[p + 1] as u16;
-
-        // to create a u16 opcode, combine two values from memory with logical OR
-        // they need to be cast as u16 to start with; otherwise,
-        // the left shift sets all of the bits to 0
-        // left shift to ignore the right most 8 bits, we're adding op_byte2 to get those.
-        op_byte1 << 8 | op_byte2
-    }
-
-    /// Main CPU loop
-    /// 1. Read u16 opcode from values in memory (2 u8 values, the high byte and low byte)
-    /// 2. Decodes instructions
-    /// 3. Matches decoded instructions to known opcodes
-    /// 4. dispatches execution of the operation to a specific function
-    fn run(&mut self) {
-        loop {
-            let opcode = self.read_opcode();
-
-            // we've read and loaded the instruction from memory; point to next instruction
-            // Increment in twos because when we create the opcodes
-            // we combine 2 values from memory (whatever values we want to add together for example)
-            self.position_in_memory += 2;
-
-            // Extract nibbles from bytes.
-            // filter with & bit AND operator.
-            // then shift to move the bits to the lowest significant place
-            // hex is convenient cause each hex represents 4 bits
-            // cast cause otherwise it leaves them as u16 from opcode and we want nibbles.
-            // Variable definitions can be found in page 161 table 5.2
-            let c = ((opcode & 0xF000) >> 12) as u8;
-            let x = ((opcode & 0x0F00) >> 8) as u8;
-            let y = ((opcode & 0x00F0) >> 4) as u8;
-            let d = ((opcode & 0x000F) >> 0) as u8;
-
-            // You can select multiple nibbles by increasing the width of the filter.
-            // we dont need to bit shift them cause they're already in lowest significant place
-
-            // To support functions
-            let nnn = opcode & 0x0FFF;
-            let kk = opcode & 0x00FF;
-
-            // 
-
-            match (c, x, y, d) {
-                (0, 0, 0, 0) => { return; }, // terminate execution when opcode 0x0000 is encountered
-                (0, 0, 0xE, 0xE) => self.ret(),
-                (0x2, _, _, _) => self.call(nnn),
-                (0x8, _, _, 0x4) => self.add_xy(x, y),
-                _ => todo!("opcode {:04x}", opcode) // add more functionality
-            }
-        }
-    }
-
-    // ADD_XY: Add y to x register
-    fn add_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-
-        // overflowing add returns a tuple, the value and boolean letting us know if overflow was detected
-        let (val, overflow) = arg1.overflowing_add(arg2);
-        self.registers[x as usize] = val;
-
-        // within CHIP-8, the last register is a 'carry flag'. When set it indiciates
-        // that an operation has overflowed the u8 register size.
-        if overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
+// The chip8 command line runner.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use chip_8_emulator::analysis::{self, RegionKind};
+use chip_8_emulator::audio::{Tone, Waveform, BEEP_FREQUENCY};
+use chip_8_emulator::batch::{self, BatchSettings};
+use chip_8_emulator::bench;
+use chip_8_emulator::builtin_roms::{self, BuiltinRom};
+use chip_8_emulator::clock::{Clock, CHIP48_CLOCK_SPEED, DEFAULT_CLOCK_SPEED, TIMER_HZ};
+use chip_8_emulator::config::Config;
+use chip_8_emulator::disasm::disassemble_at;
+use chip_8_emulator::coverage::{heat_color, Coverage};
+use chip_8_emulator::flow_graph::{BasicBlock, FlowGraph};
+use chip_8_emulator::frontend::{AudioSink, DisplaySink, InputSource, Silent};
+#[cfg(feature = "gamepad")]
+use chip_8_emulator::gamepad::{GamepadMap, Gamepads};
+use chip_8_emulator::gdb::GdbServer;
+use chip_8_emulator::json_trace::JsonTrace;
+use chip_8_emulator::keymap::{Hotkey, Keymap};
+use chip_8_emulator::lockstep::run_lockstep;
+use chip_8_emulator::metadata::{self, RomMetadata};
+use chip_8_emulator::netplay::{self, Netplay};
+use chip_8_emulator::overlay::overlay_lines;
+use chip_8_emulator::palette::{self, Palette};
+use chip_8_emulator::patch::{self, Patch};
+use chip_8_emulator::recording::GifRecorder;
+#[cfg(feature = "plugins")]
+use chip_8_emulator::plugin::{self, LoadedPlugin};
+use chip_8_emulator::profiler::Profiler;
+use chip_8_emulator::rom_db::{KnownRom, RomDatabase};
+use chip_8_emulator::rom_format::{self, RomFormat};
+use chip_8_emulator::rpl_flags::RplFlagStore;
+use chip_8_emulator::save_state::{self, SaveState};
+use chip_8_emulator::screenshot::capture_path;
+#[cfg(feature = "scripting")]
+use chip_8_emulator::scripting::{Script, ScriptError};
+use chip_8_emulator::sha1;
+use chip_8_emulator::stats::StatsMeter;
+use chip_8_emulator::symbols::Symbols;
+use chip_8_emulator::terminal::{Renderer, TerminalFrontend};
+use chip_8_emulator::test_roms::{self, Outcome};
+use chip_8_emulator::variant::{is_two_page_hires, platform_hints};
+use chip_8_emulator::video::VideoRecorder;
+use chip_8_emulator::vip_timing::Timing;
+use chip_8_emulator::watch::RomWatcher;
+use chip_8_emulator::wav::WavRecorder;
+use chip_8_emulator::{Cpu, CpuBuilder, CpuError, HaltReason, Instruction, LoopDetection, MemoryProtection, Quirks, UnknownOpcodePolicy, Variant};
+
+// Headless exit statuses, so scripts can tell how a ROM finished
+const EXIT_INFINITE_LOOP: u8 = 2;
+const EXIT_FAULT: u8 = 3;
+const EXIT_WATCHDOG: u8 = 4;
+
+// screenshots and recordings are 512x256 for a lores screen unless asked otherwise
+const CAPTURE_SCALE: u32 = 8;
+
+#[derive(Parser)]
+#[command(name = "chip8", version, about = "A CHIP-8 emulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Read settings from this file instead of ~/.config/chip8/config.toml (see src/config.rs)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Read ROM files as this instead of going by their extension (see src/rom_format.rs)
+    #[arg(long, global = true, value_enum)]
+    rom_format: Option<RomFormatArg>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play a ROM in the terminal. F2 switches between block, half block and braille
+    /// characters, F3 shows the registers, timers and speed over the screen, F4 mutes the
+    /// sound, F12 saves a screenshot in the current directory, F10 starts and stops recording
+    /// a GIF (and a WAV of the sound) there.
+    /// Headless runs exit with status 2 if the ROM got stuck in an infinite loop,
+    /// 3 if a hardened run hit a broken instruction and 4 if the watchdog stopped it
+    Run(Box<RunArgs>),
+    /// Recompile a ROM into a Rust program that plays it
+    Transpile(TranspileArgs),
+    /// Draw a ROM's control flow graph: follow its jumps, calls and skips from 0x200 and write
+    /// the basic blocks out for Graphviz (dot -Tsvg game.dot -o game.svg)
+    Cfg(CfgArgs),
+    /// Look for unreachable code, data mixed in with the code and self-modifying code, without
+    /// running the ROM
+    Analyze(AnalyzeArgs),
+    /// Draw a ROM as a heatmap of how often each part ran, from counts saved by run --coverage,
+    /// and list the code that never ran
+    Coverage(CoverageArgs),
+    /// Run a directory of test ROMs and check the screens they finish on (see suite.txt in
+    /// src/test_roms.rs). Exits with status 1 if any failed
+    TestRoms(TestRomsArgs),
+    /// Run every ROM in a directory headless, several at once, and report how each one
+    /// finished and the screen it finished on (see src/batch.rs). Exits with status 1 if any
+    /// crashed or wouldn't load
+    Batch(BatchArgs),
+    /// Show what's known about ROMs: size, SHA-1, which machine's opcodes they use, and the
+    /// title, author and settings from the ROM database or a CHIP-8 Archive programs.json next
+    /// to them (or one directory up)
+    #[command(alias = "rom-info")]
+    Info(InfoArgs),
+    /// Run a ROM on two differently configured cores in lockstep and report where they diverge.
+    /// Exits with status 1 if they did
+    Diff(DiffArgs),
+    /// Compare two save states (from run --save-state) and list the registers, memory and
+    /// pixels that differ. Exits with status 1 if anything does
+    Statediff(StatediffArgs),
+    /// Run a ROM as fast as it'll go for a few seconds and report the instructions and frames
+    /// per second, to see how fast the emulator is on this machine (see src/bench.rs). Without a
+    /// ROM it runs a built in loop of drawing, arithmetic, calls and memory access
+    Bench(BenchArgs),
+    /// Step through a ROM in the terminal debugger
+    #[cfg(feature = "tui")]
+    Debug(Box<DebugArgs>),
+    /// Step through a ROM in the graphical debugger
+    #[cfg(feature = "egui")]
+    DebugGui(Box<DebugArgs>),
+}
+
+#[derive(clap::Args)]
+#[command(group(clap::ArgGroup::new("budget").multiple(true).args(["max_cycles", "watchdog"])))]
+struct RunArgs {
+    /// The ROM file to load
+    #[arg(required_unless_present = "builtin")]
+    rom: Option<PathBuf>,
+    /// Run one of the ROMs that come with the emulator instead of a file: ibm-logo,
+    /// opcode-test or catch
+    #[arg(long, value_name = "NAME", conflicts_with = "rom", value_parser = parse_builtin)]
+    builtin: Option<&'static BuiltinRom>,
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Start the ROM again whenever its file changes, for testing a ROM while you write it. The
+    /// settings, keys and gdb's breakpoints stay as they were (see src/watch.rs)
+    #[arg(long, conflicts_with_all = ["builtin", "headless", "netplay"])]
+    watch: bool,
+    /// How many times faster fast-forward (Tab) runs, uncapped if not given
+    #[arg(long)]
+    turbo_factor: Option<u32>,
+    /// Run without any frontend and print the final machine state. Needs --max-cycles or
+    /// --watchdog, so it can't run forever
+    #[arg(long, requires = "budget")]
+    headless: bool,
+    /// Give up after this many instructions (headless only)
+    #[arg(long)]
+    max_cycles: Option<u64>,
+    /// Stop with an error once this many instructions have run, for batch jobs over ROMs that
+    /// might never finish. Unlike --max-cycles it counts as a failure
+    #[arg(long, value_name = "CYCLES")]
+    watchdog: Option<u64>,
+    /// Treat this many instructions in a row without any change to registers, memory or
+    /// the display as an infinite loop (headless only, jumps to self are always caught)
+    #[arg(long)]
+    loop_window: Option<u64>,
+    /// Stop with an error on broken instructions (bad memory accesses, stack overflows, unknown
+    /// opcodes) instead of crashing, for ROMs you don't trust
+    #[arg(long)]
+    hardened: bool,
+    /// Let a hardened run write below 0x200, for the few ROMs that keep data there
+    #[arg(long, requires = "hardened")]
+    allow_low_memory: bool,
+    /// Also stop a hardened run when it reads or runs memory nothing has written
+    #[arg(long, requires = "hardened", conflicts_with = "allow_low_memory")]
+    strict_memory: bool,
+    /// What opcodes the machine doesn't have do: stop with an error (the default), or skip
+    /// them and carry on, for ROMs that stray into junk
+    #[arg(long, value_enum, default_value_t = UnknownOpcodesArg::Error)]
+    unknown_opcodes: UnknownOpcodesArg,
+    /// Compile straight-line code to native code with the experimental JIT (headless only)
+    #[cfg(feature = "jit")]
+    #[arg(long, requires = "headless")]
+    jit: bool,
+    /// How to draw the screen, auto (the default) picks kitty graphics in terminals that support
+    /// them and block characters everywhere else. Sixel has to be asked for
+    #[arg(long, value_enum)]
+    renderer: Option<RendererArg>,
+    /// Keyboard keys for the keypad: qwerty (the default), azerty, dvorak, colemak, or 16 keys
+    /// in the order the keypad reads (qwerty is 1234qwerasdfzxcv). split gives two players a
+    /// keypad each, as does any two of those as "left|right" (see src/keymap.rs). Overrides
+    /// config.toml
+    #[arg(long, value_parser = parse_keymap)]
+    keymap: Option<Keymap>,
+    #[command(flatten)]
+    colors: ColorArgs,
+    /// How many pixels across each CHIP-8 pixel is in screenshots (F12), GIF recordings (F10)
+    /// and --record-video
+    #[arg(long, default_value_t = CAPTURE_SCALE)]
+    capture_scale: u32,
+    /// Write every frame to this file as uncompressed video for ffmpeg: Y4M if the name ends in
+    /// .y4m, raw RGB if not (see src/video.rs). It can be a named pipe with ffmpeg on the end.
+    /// The sound goes next to it as a WAV file with the same name, as does a GIF's
+    #[arg(long, value_name = "FILE", conflicts_with = "headless")]
+    record_video: Option<PathBuf>,
+    /// Write every instruction to this file as a line of JSON (pc, opcode, mnemonic,
+    /// registers, I and SP, see src/json_trace.rs), for scripts to go through afterwards
+    #[arg(long, value_name = "FILE")]
+    trace_json: Option<PathBuf>,
+    /// Count how many times each address runs and add the counts to this file when the run
+    /// ends (see src/coverage.rs), for chip8 coverage to draw
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+    /// Save the machine's state to this file when the run ends, for chip8 statediff to compare
+    #[arg(long, value_name = "FILE")]
+    save_state: Option<PathBuf>,
+    /// Time every instruction and print where the time went when the run ends: the hottest
+    /// blocks disassembled, the busiest loops and the time per kind of instruction (see
+    /// src/profiler.rs). Names come from the ROM's .sym file if there is one
+    #[arg(long)]
+    profile: bool,
+    /// How many blocks and loops the profile lists
+    #[arg(long, default_value_t = 10, requires = "profile")]
+    profile_top: usize,
+    /// Keep a line along the bottom of the terminal showing the instructions and frames per
+    /// second it's really running at (see src/stats.rs)
+    #[arg(long, conflicts_with = "headless")]
+    show_stats: bool,
+    /// How loud the sound is, from 0 to 100 [default: 100]. Recordings are always at 100
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    volume: Option<u8>,
+    /// The buzzer's pitch in Hz [default: 440]
+    #[arg(long, value_name = "HZ")]
+    beep_frequency: Option<f32>,
+    /// What the buzzer sounds like: square (the default), triangle, sine, or a WAV file to play
+    /// over and over while it's on (see src/audio.rs)
+    #[arg(long, value_parser = parse_waveform)]
+    waveform: Option<Waveform>,
+    /// Wait for gdb to attach on this address (e.g. 127.0.0.1:1234) before starting
+    #[arg(long, value_name = "ADDR", conflicts_with = "headless")]
+    gdb: Option<String>,
+    /// Experimental: play a two-player ROM with someone running the same ROM at this address
+    /// (host:port), swapping key presses over UDP (see src/netplay.rs)
+    #[arg(long, value_name = "PEER", conflicts_with_all = ["headless", "gdb"])]
+    netplay: Option<String>,
+    /// The address to listen on for the other player
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:7070", requires = "netplay")]
+    netplay_bind: String,
+    /// Frames to hold back key presses by, so the other player's arrive in time. More is
+    /// smoother over a slow connection, but the keys feel less responsive
+    #[arg(long, value_name = "FRAMES", default_value_t = netplay::DEFAULT_INPUT_DELAY, requires = "netplay")]
+    input_delay: u32,
+    /// Run this rhai script alongside the ROM. It can watch frames, draws, memory writes and
+    /// key waits, press keys and change memory (see src/scripting.rs)
+    #[cfg(feature = "scripting")]
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+    /// Load this plugin as well as the ones in the plugins directory next to config.toml (see
+    /// src/plugin.rs), can be given more than once
+    #[cfg(feature = "plugins")]
+    #[arg(long, value_name = "FILE")]
+    plugin: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct TranspileArgs {
+    /// The ROM file to recompile
+    rom: PathBuf,
+    /// Where to write the Rust source, standard output if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Which machine the ROM is for
+    #[arg(long, value_enum, default_value_t = VariantArg::Chip8)]
+    variant: VariantArg,
+}
+
+#[derive(clap::Args)]
+struct CfgArgs {
+    /// The ROM file to follow
+    rom: PathBuf,
+    /// Where to write the graph, standard output if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Which machine the ROM is for [default: the oldest one that has every opcode it uses]
+    #[arg(long, value_enum)]
+    variant: Option<VariantArg>,
+    /// Names for addresses, for the blocks and instructions (see src/symbols.rs). Defaults to
+    /// the ROM's name with .sym, if there is one
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct AnalyzeArgs {
+    /// The ROM file to look at
+    rom: PathBuf,
+    /// Which machine the ROM is for [default: the oldest one that has every opcode it uses]
+    #[arg(long, value_enum)]
+    variant: Option<VariantArg>,
+    /// Names for addresses in the report (see src/symbols.rs). Defaults to the ROM's name with
+    /// .sym, if there is one
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CoverageArgs {
+    /// The ROM file the counts are for
+    rom: PathBuf,
+    /// Coverage files from run --coverage, added together if there's more than one
+    #[arg(required = true)]
+    counts: Vec<PathBuf>,
+    /// Which machine the ROM is for [default: the oldest one that has every opcode it uses]
+    #[arg(long, value_enum)]
+    variant: Option<VariantArg>,
+    /// Names for addresses in the list of code that never ran (see src/symbols.rs). Defaults
+    /// to the ROM's name with .sym, if there is one
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct InfoArgs {
+    /// The ROM files to look at
+    #[arg(required = true)]
+    roms: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct TestRomsArgs {
+    /// The directory holding the ROMs and their suite.txt
+    dir: PathBuf,
+    /// Save the screens the ROMs finish on as the expected ones, instead of checking them
+    #[arg(long)]
+    bless: bool,
+}
+
+#[derive(clap::Args)]
+struct BatchArgs {
+    /// The directory holding the ROMs
+    dir: PathBuf,
+    /// How many instructions to run each ROM for
+    #[arg(long, default_value_t = 1_000_000)]
+    cycles: u64,
+    /// How many ROMs to run at once [default: one per core]
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Which machine to run them on [default: the oldest one that has every opcode each uses]
+    #[arg(long, value_enum)]
+    variant: Option<VariantArg>,
+    /// Count a ROM as failed if it's still running after this many instructions
+    #[arg(long, value_name = "CYCLES")]
+    watchdog: Option<u64>,
+    /// Where to write the report, standard output if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    /// The ROM file to run
+    rom: PathBuf,
+    /// Instructions per second, which sets how often the timers tick
+    #[arg(long, default_value_t = DEFAULT_CLOCK_SPEED)]
+    ips: u32,
+    /// The first core's machine
+    #[arg(long, value_enum, default_value_t = VariantArg::Chip8)]
+    variant: VariantArg,
+    /// The second core's machine, the same as the first if not given
+    #[arg(long, value_enum)]
+    other_variant: Option<VariantArg>,
+    /// Flip this quirk on the second core, can be given more than once
+    #[arg(long, value_enum)]
+    flip_quirk: Vec<QuirkArg>,
+    /// Give up after this many instructions
+    #[arg(long, default_value_t = 10_000_000)]
+    max_cycles: u64,
+}
+
+#[derive(clap::Args)]
+struct StatediffArgs {
+    /// The first save state
+    a: PathBuf,
+    /// The second save state
+    b: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct BenchArgs {
+    /// The ROM file to run, the built in workload if not given
+    rom: Option<PathBuf>,
+    /// How long to run for
+    #[arg(long, default_value_t = 5.0)]
+    seconds: f64,
+    /// Compile straight-line code to native code with the experimental JIT
+    #[cfg(feature = "jit")]
+    #[arg(long)]
+    jit: bool,
+    #[command(flatten)]
+    machine: MachineArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum QuirkArg {
+    ShiftVxInPlace,
+    LoadStoreIncrementsIndex,
+    LoadStoreIndexShort,
+    JumpOffsetUsesVx,
+    DisplayWait,
+    WrapSprites,
+    IndexOverflowSetsVf,
+    WaitForKeyRelease,
+}
+
+impl QuirkArg {
+    fn flip(self, quirks: &mut Quirks) {
+        let quirk = match self {
+            QuirkArg::ShiftVxInPlace => &mut quirks.shift_vx_in_place,
+            QuirkArg::LoadStoreIncrementsIndex => &mut quirks.load_store_increments_index,
+            QuirkArg::LoadStoreIndexShort => &mut quirks.load_store_index_short,
+            QuirkArg::JumpOffsetUsesVx => &mut quirks.jump_offset_uses_vx,
+            QuirkArg::DisplayWait => &mut quirks.display_wait,
+            QuirkArg::WrapSprites => &mut quirks.wrap_sprites,
+            QuirkArg::IndexOverflowSetsVf => &mut quirks.index_overflow_sets_vf,
+            QuirkArg::WaitForKeyRelease => &mut quirks.wait_for_key_release,
+        };
+        *quirk = !*quirk;
+    }
+}
+
+#[cfg(any(feature = "tui", feature = "egui"))]
+#[derive(clap::Args)]
+struct DebugArgs {
+    /// The ROM file to load
+    rom: PathBuf,
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Keyboard keys for the keypad: qwerty (the default), azerty, dvorak, colemak, or 16 keys
+    /// in the order the keypad reads (qwerty is 1234qwerasdfzxcv). split gives two players a
+    /// keypad each, as does any two of those as "left|right" (see src/keymap.rs). Overrides
+    /// config.toml
+    #[arg(long, value_parser = parse_keymap)]
+    keymap: Option<Keymap>,
+    #[command(flatten)]
+    colors: ColorArgs,
+    /// Names for addresses, to show in the disassembly and set breakpoints by (see
+    /// src/symbols.rs for the format). Defaults to the ROM's name with .sym, if there is one
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct MachineArgs {
+    /// Instructions executed per second [default: 700]
+    #[arg(long)]
+    ips: Option<u32>,
+    /// Charge each instruction what it cost on a COSMAC VIP instead of running --ips of them a
+    /// second, for games timed by the real thing (see src/vip_timing.rs)
+    #[arg(long)]
+    vip_timing: bool,
+    /// Which machine to emulate [default: chip8, or chip8hires for ROMs that start with 1260]
+    #[arg(long, value_enum)]
+    variant: Option<VariantArg>,
+    /// Which interpreter's quirks to follow, instead of the ones the machine's ROMs usually expect.
+    /// chip48 runs at the HP-48's speed too, unless something else sets one
+    #[arg(long, value_enum)]
+    quirks: Option<QuirksArg>,
+    /// Bytes of memory, instead of the machine's own (4096, or 65536 for XO-CHIP). Decimal or 0x hex
+    #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+    memory_size: Option<usize>,
+    /// How many CALLs can be nested before a stack overflow [default: 16]
+    #[arg(long, value_name = "CALLS")]
+    stack_depth: Option<usize>,
+    /// Rows on the ETI-660's screen, 48 or 64 [default: 48]
+    #[arg(long, value_name = "ROWS")]
+    screen_height: Option<usize>,
+    /// Change memory after loading the ROM: a patch file, or a code like 02A412 (see
+    /// src/patch.rs). Can be given more than once
+    #[arg(long, value_name = "FILE|CODE")]
+    patch: Vec<String>,
+    // what --patch says to change
+    #[arg(skip)]
+    patches: Vec<Patch>,
+    // the quirks the ROM database or the ROM's metadata asks for, which don't have to match a preset
+    #[arg(skip)]
+    rom_quirks: Option<Quirks>,
+}
+
+impl MachineArgs {
+    // Flags win, then the ROM database, then the ROM's metadata, then config.toml
+    fn apply(&mut self, known: Option<&KnownRom>, rom: Option<&RomMetadata>, config: &Config) -> Result<(), Box<dyn Error>> {
+        if let Some(known) = known {
+            self.ips = self.ips.or(known.clock_speed);
+            self.variant = self.variant.or(Some(known.variant.into()));
+        }
+        if let Some(rom) = rom {
+            self.ips = self.ips.or(rom.clock_speed());
+            self.variant = self.variant.or(rom.variant().map(VariantArg::from));
+        }
+        self.ips = self.ips.or(config.ips);
+        self.screen_height = self.screen_height.or(config.screen_height);
+        self.variant = self.variant.or(setting(&config.variant, "variant", |text| VariantArg::from_str(text, true))?);
+
+        // the ROM's quirks are changes to the machine's usual ones, so the machine goes first
+        let variant: Variant = self.variant.unwrap_or(VariantArg::Chip8).into();
+        self.rom_quirks = known.map(|known| known.quirks).or_else(|| rom.and_then(|rom| rom.quirks(variant.default_quirks())));
+        if self.rom_quirks.is_none() {
+            self.quirks = self.quirks.or(setting(&config.quirks, "quirks", |text| QuirksArg::from_str(text, true))?);
         }
+        self.ips = self.ips.or(self.quirks.and_then(QuirksArg::clock_speed));
+
+        for patch in &self.patch {
+            match Patch::from_code(patch) {
+                Some(code) => self.patches.push(code),
+                None => self.patches.extend(patch::load(Path::new(patch))?),
+            }
+        }
+        Ok(())
     }
 
-    // CALL: opcode 0x2nnn sets position_in_memory to nnn, the address of the function
-    // Each CALL opcode adds an address to the stack by incrementing the stack pointer
-    // and writing nnn to that position in the stack.
+    // The machine the flags and settings describe, with `rom` loaded
+    fn cpu(&self, rom: &[u8]) -> Result<Cpu, CpuError> {
+        // nothing said which machine, but two-page hi-res ROMs all start the same way
+        let detected = if is_two_page_hires(rom) { VariantArg::Chip8hires } else { VariantArg::Chip8 };
+        let mut builder = CpuBuilder::new(self.variant.unwrap_or(detected).into())
+            .clock_speed(self.ips.unwrap_or(DEFAULT_CLOCK_SPEED))
+            .rom(rom);
+        if self.vip_timing {
+            builder = builder.timing(Timing::CosmacVip);
+        }
+        if let Some(quirks) = self.quirks {
+            builder = builder.quirks(quirks.into());
+        } else if let Some(quirks) = self.rom_quirks {
+            builder = builder.quirks(quirks);
+        }
+        if let Some(size) = self.memory_size {
+            builder = builder.memory_size(size);
+        }
+        if let Some(depth) = self.stack_depth {
+            builder = builder.stack_depth(depth);
+        }
+        if let Some(rows) = self.screen_height {
+            builder = builder.lores_height(rows);
+        }
+        let mut cpu = builder.build()?;
+        self.patch(&mut cpu)?;
+        Ok(cpu)
+    }
+
+    // --patch, on a CPU with the ROM just loaded
+    fn patch(&self, cpu: &mut Cpu) -> Result<(), CpuError> {
+        for patch in &self.patches {
+            if !patch.apply(cpu)? {
+                eprintln!("skipping patch {}, the ROM has 0x{:02X} there", patch, cpu.memory[patch.addr]);
+            }
+        }
+        Ok(())
+    }
+}
 
-    fn call(&mut self, addr: u16) {
-        let sp = self.stack_pointer;   
-        let stack = &mut self.stack;
+#[derive(clap::Args)]
+struct ColorArgs {
+    /// The screen's colours: monochrome (the default), green, amber, paper, or hex colours with
+    /// the background first (000000,FFFFFF, or four of them for XO-CHIP)
+    #[arg(long, value_parser = parse_palette)]
+    palette: Option<Palette>,
+    /// Background colour (RRGGBB), instead of the palette's
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    background: Option<[u8; 3]>,
+    /// Foreground colour (RRGGBB), instead of the palette's
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    foreground: Option<[u8; 3]>,
+    /// Fade pixels out over this many frames instead of turning them straight off, which hides
+    /// a lot of flicker. The block character renderers can't show it
+    #[arg(long, value_name = "FRAMES")]
+    phosphor: Option<u8>,
+}
+
+impl ColorArgs {
+    fn apply(&mut self, rom: Option<&RomMetadata>, config: &Config) -> Result<(), Box<dyn Error>> {
+        self.palette = self.palette.or(rom.and_then(RomMetadata::palette));
+        self.palette = self.palette.or(setting(&config.palette, "palette", parse_palette)?);
+        self.background = self.background.or(setting(&config.background, "background", parse_color)?);
+        self.foreground = self.foreground.or(setting(&config.foreground, "foreground", parse_color)?);
+        self.phosphor = self.phosphor.or(config.phosphor);
+        Ok(())
+    }
 
-        if sp > stack.len() {
-            panic!("Stack overflow");
+    fn palette(&self) -> Palette {
+        let mut palette = self.palette.unwrap_or_default();
+        if let Some(rgb) = self.background {
+            palette.set_background(rgb);
         }
+        if let Some(rgb) = self.foreground {
+            palette.set_foreground(rgb);
+        }
+        palette
+    }
+}
+
+// A setting from config.toml, parsed the same way as the flag it stands in for
+fn setting<T>(value: &Option<String>, name: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Option<T>, Box<dyn Error>> {
+    match value {
+        Some(text) => Ok(Some(parse(text).map_err(|e| format!("config.toml: {}: {}", name, e))?)),
+        None => Ok(None),
+    }
+}
 
-        // add current position in memory to stack
-        // memory address is two bytes higher than calling location as it is incremented within the body of run()
-        stack[sp] = self.position_in_memory as u16;
-        self.stack_pointer += 1;
+// What the CHIP-8 Archive says about a ROM, from a programs.json near it or the one config.toml
+// points at. A broken programs.json shouldn't stop the game, so that's just a warning
+fn rom_metadata(rom: &Path, config: &Config) -> Option<RomMetadata> {
+    let found = match RomMetadata::find(rom) {
+        Ok(None) => match &config.metadata {
+            Some(programs) => RomMetadata::lookup(programs, rom),
+            None => Ok(None),
+        },
+        found => found,
+    };
+    found.unwrap_or_else(|e| {
+        eprintln!("ignoring ROM metadata: {}", e);
+        None
+    })
+}
 
-        // modify position in memory to affect jumping to that address
-        self.position_in_memory = addr as usize;
+// What the ROM database (built in, plus the user's roms.txt) knows about a ROM. Like the
+// metadata, a broken roms.txt is only a warning
+fn known_rom(rom: &[u8]) -> Option<KnownRom> {
+    match RomDatabase::load_default() {
+        Ok(database) => database.lookup(rom).cloned(),
+        Err(e) => {
+            eprintln!("ignoring the ROM database: {}", e);
+            RomDatabase::built_in().lookup(rom).cloned()
+        }
     }
+}
 
-    // RETURN: opcode 0x00EE sets position_in_memory to the memory address of the previous CALL opcode
-    // Each RETURN opcode removes the top address by decrementing the stack pointer.
-    fn ret(&mut self) {
-        if self.stack_pointer == 0 {
-            panic!("Stack underflow");
+fn parse_builtin(name: &str) -> Result<&'static BuiltinRom, String> {
+    builtin_roms::find(name).ok_or_else(|| {
+        let names: Vec<&str> = builtin_roms::BUILTIN_ROMS.iter().map(|rom| rom.name).collect();
+        format!("there's no built in ROM called that, there's {}", names.join(", "))
+    })
+}
+
+// --symbols, or game.sym next to game.ch8
+fn symbols(flag: Option<&Path>, rom: &Path) -> io::Result<Symbols> {
+    match flag {
+        Some(path) => Symbols::load(path),
+        None => {
+            let path = rom.with_extension("sym");
+            if path.exists() {
+                Symbols::load(&path)
+            } else {
+                Ok(Symbols::new())
+            }
         }
+    }
+}
 
-        self.stack_pointer -= 1;
-        // jump to position in memory where an earlier call was made
-        let call_addr = self.stack[self.stack_pointer];
-        self.position_in_memory = call_addr as usize;
+fn parse_keymap(text: &str) -> Result<Keymap, String> {
+    Keymap::parse(text).ok_or_else(|| "expected a layout name, 16 different keys, or two of those as left|right with no keys in common".to_string())
+}
+
+// --keymap, or whatever config.toml has for this ROM
+fn keymap(flag: Option<Keymap>, config: &Config, rom: &Path) -> io::Result<Keymap> {
+    match flag {
+        Some(keymap) => Ok(keymap),
+        None => config.keymap(rom_name(rom)),
     }
 }
 
-fn main() {
-    // Values are held in the registers
-    // Instructions on what to do with them are decoded from memory
-    let mut cpu = CPU {
-        // repeat expressions [x; N], which produces an array with N copies of x
-        registers: [0; 16],
-        memory: [0; 4096],
-        position_in_memory: 0,
-        stack: [0; 16],
-        stack_pointer: 0
+// What config.toml's [rom.<name>] sections go by
+fn rom_name(rom: &Path) -> Option<&str> {
+    rom.file_stem().and_then(|name| name.to_str())
+}
+
+fn parse_size(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
     };
+    parsed.map_err(|_| "expected a number of bytes, like 4096 or 0x1000".to_string())
+}
 
-    // Use our CPU to calculate: 5 + (10 * 2) + (10 * 2) = 45
+fn parse_palette(text: &str) -> Result<Palette, String> {
+    Palette::parse(text).ok_or_else(|| "expected a preset name or 2 or 4 hex colours separated by commas".to_string())
+}
 
-    // load some data into our registers for processing
-    cpu.registers[0] = 5;
-    cpu.registers[1] = 10;
+fn parse_waveform(text: &str) -> Result<Waveform, String> {
+    match Waveform::from_name(text) {
+        Some(waveform) => Ok(waveform),
+        None => Waveform::load(Path::new(text)).map_err(|e| format!("expected square, triangle, sine or a WAV file ({})", e)),
+    }
+}
 
-    let mem = &mut cpu.memory;
+fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    palette::parse_color(text).ok_or_else(|| "expected a hex colour like 33FF66".to_string())
+}
 
-    // set opcode to 0x2100: CALL the function at 0x100
-    mem[0x000] = 0x21; mem[0x001] = 0x00;
-    // set opcode to 0x2100: CALL the function at 0x100
-    mem[0x002] = 0x21; mem[0x003] = 0x00;
-    // sets opcode to 0x0000: HALT (not really needed as cpu.memory is initialized with null bytes)
-    mem[0x004] = 0x00; mem[0x005] = 0x00;
+#[derive(Clone, Copy, ValueEnum)]
+enum RomFormatArg {
+    Binary,
+    IntelHex,
+    HexText,
+}
 
+impl From<RomFormatArg> for RomFormat {
+    fn from(arg: RomFormatArg) -> Self {
+        match arg {
+            RomFormatArg::Binary => RomFormat::Binary,
+            RomFormatArg::IntelHex => RomFormat::IntelHex,
+            RomFormatArg::HexText => RomFormat::HexText,
+        }
+    }
+}
 
-    // sets opcode to 0x8014: ADD register 1s value to register 0
-    mem[0x100] = 0x80; mem[0x101] = 0x14;
-    // sets opcode to 0x8014: ADD register 1s value to register 0
-    mem[0x102] = 0x80; mem[0x103] = 0x14;
-    // sets opcode to 0x00EE: RETURN
-    mem[0x104] = 0x00; mem[0x105] = 0xEE;
+#[derive(Clone, Copy, ValueEnum)]
+enum VariantArg {
+    Chip8,
+    Schip,
+    Xochip,
+    Chip8x,
+    Megachip,
+    Eti660,
+    Chip8hires,
+}
 
-    // execute main cpu loop
-    cpu.run();
+impl From<Variant> for VariantArg {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Chip8 => VariantArg::Chip8,
+            Variant::SuperChip => VariantArg::Schip,
+            Variant::XoChip => VariantArg::Xochip,
+            Variant::Chip8X => VariantArg::Chip8x,
+            Variant::MegaChip => VariantArg::Megachip,
+            Variant::Eti660 => VariantArg::Eti660,
+            Variant::Chip8Hires => VariantArg::Chip8hires,
+        }
+    }
+}
+
+impl From<VariantArg> for Variant {
+    fn from(arg: VariantArg) -> Self {
+        match arg {
+            VariantArg::Chip8 => Variant::Chip8,
+            VariantArg::Schip => Variant::SuperChip,
+            VariantArg::Xochip => Variant::XoChip,
+            VariantArg::Chip8x => Variant::Chip8X,
+            VariantArg::Megachip => Variant::MegaChip,
+            VariantArg::Eti660 => Variant::Eti660,
+            VariantArg::Chip8hires => Variant::Chip8Hires,
+        }
+    }
+}
 
-    assert_eq!(cpu.registers[0], 45);
+#[derive(Clone, Copy, ValueEnum)]
+enum QuirksArg {
+    Vip,
+    Chip48,
+    Schip,
+    Xochip,
+}
+
+impl QuirksArg {
+    // The speed that goes with the quirks, when nothing else says how fast to run
+    fn clock_speed(self) -> Option<u32> {
+        match self {
+            QuirksArg::Chip48 => Some(CHIP48_CLOCK_SPEED),
+            _ => None,
+        }
+    }
+}
+
+impl From<QuirksArg> for Quirks {
+    fn from(arg: QuirksArg) -> Self {
+        match arg {
+            QuirksArg::Vip => Quirks::cosmac_vip(),
+            QuirksArg::Chip48 => Quirks::chip48(),
+            QuirksArg::Schip => Quirks::superchip(),
+            QuirksArg::Xochip => Quirks::xo_chip(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UnknownOpcodesArg {
+    Error,
+    Skip,
+}
+
+impl From<UnknownOpcodesArg> for UnknownOpcodePolicy {
+    fn from(arg: UnknownOpcodesArg) -> Self {
+        match arg {
+            UnknownOpcodesArg::Error => UnknownOpcodePolicy::Error,
+            UnknownOpcodesArg::Skip => UnknownOpcodePolicy::Skip,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RendererArg {
+    Auto,
+    Blocks,
+    HalfBlocks,
+    Braille,
+    Sixel,
+    Kitty,
+}
+
+impl From<RendererArg> for Renderer {
+    fn from(arg: RendererArg) -> Self {
+        match arg {
+            RendererArg::Auto => Renderer::detect(),
+            RendererArg::Blocks => Renderer::Blocks,
+            RendererArg::HalfBlocks => Renderer::HalfBlocks,
+            RendererArg::Braille => Renderer::Braille,
+            RendererArg::Sixel => Renderer::Sixel,
+            RendererArg::Kitty => Renderer::Kitty,
+        }
+    }
+}
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    let format = cli.rom_format.map(RomFormat::from);
+    match cli.command {
+        Command::Run(args) => run(*args, format, &config),
+        Command::Transpile(args) => transpile(args, format),
+        Command::Cfg(args) => cfg(args, format),
+        Command::Analyze(args) => analyze(args, format),
+        Command::Coverage(args) => coverage(args, format),
+        Command::Info(args) => info(args, format, &config),
+        Command::TestRoms(args) => test_roms(args),
+        Command::Batch(args) => batch(args),
+        Command::Diff(args) => diff(args, format),
+        Command::Statediff(args) => statediff(args),
+        Command::Bench(args) => bench(args, format, &config),
+        #[cfg(feature = "tui")]
+        Command::Debug(args) => debug(*args, format, &config),
+        #[cfg(feature = "egui")]
+        Command::DebugGui(args) => debug_gui(*args, format, &config),
+    }
+}
 
+// The ROM at `path`, in the format --rom-format asked for or going by its extension
+fn read_rom(path: &Path, format: Option<RomFormat>) -> io::Result<Vec<u8>> {
+    rom_format::read_as(path, format.unwrap_or_else(|| RomFormat::from_path(path)))
 }
 
+fn transpile(args: TranspileArgs, format: Option<RomFormat>) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let name = args.rom.file_name().unwrap_or_default().to_string_lossy();
+
+    let source = chip_8_emulator::transpile::transpile(&rom, args.variant.into(), &name)?;
+    match &args.output {
+        Some(path) => fs::write(path, source)?,
+        None => print!("{}", source),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn cfg(args: CfgArgs, format: Option<RomFormat>) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let variant = match args.variant {
+        Some(variant) => variant.into(),
+        None => platform_hints(&rom).variant(),
+    };
+    let mut cpu = Cpu::with_variant(variant);
+    cpu.load_binary(&rom)?;
+    let symbols = symbols(args.symbols.as_deref(), &args.rom)?;
+    let name = args.rom.file_stem().unwrap_or_default().to_string_lossy();
+
+    let dot = FlowGraph::build(&cpu.memory, variant).to_dot(&name, &symbols, &cpu.quirks());
+    match &args.output {
+        Some(path) => fs::write(path, dot)?,
+        None => print!("{}", dot),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn analyze(args: AnalyzeArgs, format: Option<RomFormat>) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let variant = match args.variant {
+        Some(variant) => variant.into(),
+        None => platform_hints(&rom).variant(),
+    };
+    let mut cpu = Cpu::with_variant(variant);
+    cpu.load_binary(&rom)?;
+    let symbols = symbols(args.symbols.as_deref(), &args.rom)?;
+    let analysis = analysis::analyze(&cpu.memory, rom.len(), variant);
+    let name = |addr: usize| match symbols.name(addr) {
+        Some(name) => format!("0x{:03X} ({})", addr, name),
+        None => format!("0x{:03X}", addr),
+    };
+    let span = |range: &std::ops::Range<usize>| format!("0x{:03X}-0x{:03X}  {} bytes", range.start, range.end - 1, range.len());
+
+    println!("regions:");
+    for region in &analysis.regions {
+        let kind = match region.kind {
+            RegionKind::Code => "code",
+            RegionKind::Data if analysis.embedded_data().any(|embedded| embedded == region) => "data, between code",
+            RegionKind::Data => "data",
+            RegionKind::Unreachable => "unreachable",
+        };
+        println!("  {}  {}", span(&region.range), kind);
+    }
+    if !analysis.runs_into_data.is_empty() {
+        println!("code runs into data:");
+        for &(addr, opcode) in &analysis.runs_into_data {
+            println!("  {}  {:04X} isn't an instruction", name(addr), opcode);
+        }
+    }
+    if !analysis.code_writes.is_empty() {
+        println!("self-modifying code:");
+        for write in &analysis.code_writes {
+            let instruction = Instruction::decode_at(&cpu.memory, write.pc, variant);
+            println!("  {}  {} writes over code at {}", name(write.pc), symbols.format(&instruction, &cpu.quirks()), span(&write.target));
+        }
+    }
+    if !analysis.unknown_writes.is_empty() {
+        println!("memory writes with I not known, these could write anywhere:");
+        for &addr in &analysis.unknown_writes {
+            println!("  {}  {}", name(addr), symbols.format(&Instruction::decode_at(&cpu.memory, addr, variant), &cpu.quirks()));
+        }
+    }
+    if !analysis.computed_jumps.is_empty() {
+        println!("computed jumps, code only they reach shows up as unreachable:");
+        for &addr in &analysis.computed_jumps {
+            println!("  {}  {}", name(addr), symbols.format(&Instruction::decode_at(&cpu.memory, addr, variant), &cpu.quirks()));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn coverage(args: CoverageArgs, format: Option<RomFormat>) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let variant = match args.variant {
+        Some(variant) => variant.into(),
+        None => platform_hints(&rom).variant(),
+    };
+    let mut cpu = Cpu::with_variant(variant);
+    cpu.load_binary(&rom)?;
+    let symbols = symbols(args.symbols.as_deref(), &args.rom)?;
+    let mut coverage = Coverage::new();
+    for path in &args.counts {
+        coverage.merge(&Coverage::load(path)?);
+    }
+    let graph = FlowGraph::build(&cpu.memory, variant);
+
+    // which instruction each byte belongs to, going by the flow graph and anything else that ran
+    let rom_start = variant.program_start();
+    let rom_end = rom_start + rom.len();
+    let mut owner = vec![None; rom_end];
+    let instructions = graph.blocks.values().flat_map(|block| block.instructions.iter().copied());
+    let ran = coverage.executed().map(|(addr, _)| (addr, Instruction::decode_at(&cpu.memory, addr, variant)));
+    for (addr, instruction) in instructions.chain(ran) {
+        let end = (addr + instruction.size()).min(rom_end);
+        if addr < end {
+            owner[addr..end].fill(Some(addr));
+        }
+    }
+
+    let reachable: Vec<usize> = graph.blocks.values().flat_map(|block| block.instructions.iter().map(|&(addr, _)| addr)).collect();
+    let covered = reachable.iter().filter(|&&addr| coverage.count(addr) > 0).count();
+    println!(
+        "{} of {} reachable instructions ran ({:.0}%), the hottest {} times",
+        covered,
+        reachable.len(),
+        100.0 * covered as f32 / reachable.len().max(1) as f32,
+        coverage.max()
+    );
+    // 16 bytes a row, coloured by how hot the instruction they're part of is
+    for row in (rom_start..rom_end).step_by(16) {
+        print!("{:03X} ", row);
+        for (addr, &start) in (row..).zip(&owner[row..(row + 16).min(rom_end)]) {
+            match start {
+                Some(start) => {
+                    let [r, g, b] = heat_color(coverage.heat(start));
+                    print!("\x1b[48;2;{};{};{}m\x1b[97m{:02X}\x1b[0m", r, g, b, cpu.memory[addr]);
+                }
+                // not code, dimmed
+                None => print!("\x1b[2m{:02X}\x1b[0m", cpu.memory[addr]),
+            }
+        }
+        println!();
+    }
+    println!("grey: code that never ran, blue to yellow: cold to hot, dim: not code");
+
+    let cold: Vec<&BasicBlock> = graph.blocks.values().filter(|block| coverage.count(block.start) == 0).collect();
+    if !cold.is_empty() {
+        println!("never ran:");
+        for block in cold {
+            let (_, first) = block.instructions[0];
+            let name = symbols.name(block.start).map(|name| format!(" ({})", name)).unwrap_or_default();
+            let count = block.instructions.len();
+            let plural = if count == 1 { "" } else { "s" };
+            println!("  0x{:03X}{}  {}  ({} instruction{})", block.start, name, symbols.format(&first, &cpu.quirks()), count, plural);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn info(args: InfoArgs, format: Option<RomFormat>, config: &Config) -> Result<ExitCode, Box<dyn Error>> {
+    // unlike playing it, a broken roms.txt or programs.json is worth stopping for here
+    let database = RomDatabase::load_default()?;
+    for (n, rom) in args.roms.iter().enumerate() {
+        if n > 0 {
+            println!();
+        }
+        rom_info(rom, &database, format, config)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn rom_info(path: &Path, database: &RomDatabase, format: Option<RomFormat>, config: &Config) -> Result<(), Box<dyn Error>> {
+    println!("{}", path.display());
+    let bytes = read_rom(path, format)?;
+    let hints = platform_hints(&bytes);
+    let variant = hints.variant();
+    // XO-CHIP's 64K aside, everything has 4K at most
+    let room = Variant::Chip8.memory_size().saturating_sub(variant.program_start());
+    let fits = match room.checked_sub(bytes.len()) {
+        Some(spare) => format!("fits in 4K with {} to spare", spare),
+        None => format!("too big for 4K by {}, only XO-CHIP has room", bytes.len() - room),
+    };
+    println!("  {:<12}{} bytes, {}", "size", bytes.len(), fits);
+    println!("  {:<12}{}", "sha1", sha1::to_hex(&sha1::sha1(&bytes)));
+
+    let entry_point = variant.entry_point();
+    let entry = Instruction::decode_at(&bytes, entry_point - variant.program_start(), variant);
+    println!("  {:<12}{:#05X}  {}", "entry point", entry_point, entry);
+    if hints.two_page_hires {
+        println!("  {:<12}two-page hi-res, 64x64", "screen");
+    }
+    let found: Vec<String> = [("SUPER-CHIP", &hints.superchip), ("XO-CHIP", &hints.xo_chip)]
+        .into_iter()
+        .filter_map(|(name, found)| {
+            let &(addr, instruction) = found.first()?;
+            Some(format!("{} {} (the first is {} at {:#05X})", found.len(), name, instruction, addr))
+        })
+        .collect();
+    let opcodes = if found.is_empty() { "CHIP-8 only".to_string() } else { found.join(", ") };
+    println!("  {:<12}{}", "opcodes", opcodes);
+
+    match database.lookup(&bytes) {
+        Some(known) => {
+            println!("  {:<12}{}", "known ROM", known.title.as_deref().unwrap_or("(untitled)"));
+            let variant: VariantArg = known.variant.into();
+            let variant = variant.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+            let speed = known.clock_speed.map(|ips| format!(", {} instructions a second", ips)).unwrap_or_default();
+            println!("  {:<12}{}{}, {}", "settings", variant, speed, quirk_switches(&known.quirks));
+        }
+        None => println!("  {:<12}not in the ROM database", "known ROM"),
+    }
+    let found = match RomMetadata::find(path)? {
+        Some(metadata) => Some(metadata),
+        None => match &config.metadata {
+            Some(programs) => RomMetadata::lookup(programs, path)?,
+            None => None,
+        },
+    };
+    let Some(rom) = found else {
+        let mut searched = metadata::search_paths(path);
+        searched.extend(config.metadata.clone());
+        let searched: Vec<String> = searched.iter().map(|path| path.display().to_string()).collect();
+        println!("no metadata, looked in {}", searched.join(", "));
+        return Ok(());
+    };
+
+    let field = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            println!("  {:<12}{}", name, value);
+        }
+    };
+    field("title", rom.title.clone());
+    field("authors", (!rom.authors.is_empty()).then(|| rom.authors.join(", ")));
+    field("released", rom.release.clone());
+    field("event", rom.event.clone());
+    field("platform", rom.platform.clone());
+    field("speed", rom.clock_speed().map(|ips| format!("{} instructions a second", ips)));
+
+    let options = &rom.options;
+    let quirks: Vec<String> = [
+        ("shift", options.shift_quirks),
+        ("load-store", options.load_store_quirks),
+        ("jump", options.jump_quirks),
+        ("vblank", options.v_blank_quirks),
+        ("clip", options.clip_quirks),
+    ]
+    .into_iter()
+    .filter_map(|(name, on)| on.map(|on| format!("{} {}", name, if on { "on" } else { "off" })))
+    .collect();
+    field("quirks", (!quirks.is_empty()).then(|| quirks.join(", ")));
+    field(
+        "palette",
+        rom.palette().map(|palette| {
+            let colors: Vec<String> = palette.0.iter().map(|[r, g, b]| format!("{:02X}{:02X}{:02X}", r, g, b)).collect();
+            colors.join(",")
+        }),
+    );
+    if let Some(description) = &rom.description {
+        println!();
+        println!("{}", description.trim());
+    }
+    Ok(())
+}
+
+// Quirks the way the ROM database and the CHIP-8 Archive write them, Octo's switches
+fn quirk_switches(quirks: &Quirks) -> String {
+    let switches = [
+        ("shift", quirks.shift_vx_in_place),
+        ("load-store", !quirks.load_store_increments_index),
+        ("jump", quirks.jump_offset_uses_vx),
+        ("vblank", quirks.display_wait),
+        ("clip", !quirks.wrap_sprites),
+    ];
+    let switches: Vec<String> = switches.iter().map(|(name, on)| format!("{} {}", name, if *on { "on" } else { "off" })).collect();
+    switches.join(", ")
+}
+
+fn test_roms(args: TestRomsArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let suite = test_roms::load_suite(&args.dir)?;
+
+    if args.bless {
+        for test in &suite {
+            test.bless()?;
+            println!("saved {}", test.screen_path().display());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for test in &suite {
+        match test.check()? {
+            Outcome::Pass => {
+                println!("PASS  {}", test.name());
+                passed += 1;
+            }
+            Outcome::Fail { pixels, actual } => {
+                // keep what it did show, to compare with the .screen file
+                let actual_path = test.screen_path().with_extension("actual");
+                fs::write(&actual_path, actual)?;
+                let what = match pixels {
+                    Some(pixels) => format!("{} pixels differ", pixels),
+                    None => "the screen hash differs".to_string(),
+                };
+                println!("FAIL  {}: {}, see {}", test.name(), what, actual_path.display());
+                failed += 1;
+            }
+            Outcome::Fault(error) => {
+                println!("FAIL  {}: {}", test.name(), error);
+                failed += 1;
+            }
+            Outcome::NoExpectedScreen => {
+                println!("SKIP  {}: no expected screen, --bless saves one", test.name());
+                skipped += 1;
+            }
+        }
+    }
+
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+    Ok(if failed > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn batch(args: BatchArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let roms = batch::find_roms(&args.dir)?;
+    let settings = BatchSettings {
+        cycles: args.cycles,
+        variant: args.variant.map(Variant::from),
+        watchdog: args.watchdog,
+    };
+    let jobs = args.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let results = batch::run_all(&roms, &settings, jobs);
+
+    let mut report = String::new();
+    report.push_str(batch::REPORT_HEADER);
+    report.push('\n');
+    for result in &results {
+        report.push_str(&format!("{}\n", result));
+    }
+    match &args.output {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+
+    // a count of each outcome, on stderr so it stays out of a report on stdout
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for result in &results {
+        match counts.iter_mut().find(|(name, _)| *name == result.outcome.name()) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((result.outcome.name(), 1)),
+        }
+    }
+    let summary: Vec<String> = counts.iter().map(|(name, count)| format!("{} {}", count, name)).collect();
+    eprintln!("{} ROMs: {}", results.len(), summary.join(", "));
+    Ok(if results.iter().any(|result| result.outcome.is_failure()) { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn diff(args: DiffArgs, format: Option<RomFormat>) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+
+    let mut cores = [args.variant, args.other_variant.unwrap_or(args.variant)].map(|variant| {
+        let mut cpu = Cpu::with_variant(variant.into());
+        // a broken instruction is just another way for the two to differ
+        cpu.hardened = true;
+        cpu
+    });
+    for quirk in &args.flip_quirk {
+        quirk.flip(&mut cores[1].quirks);
+    }
+    for cpu in &mut cores {
+        cpu.load_binary(&rom)?;
+    }
+
+    let [a, b] = &mut cores;
+    match run_lockstep(a, b, args.max_cycles, args.ips / TIMER_HZ) {
+        Ok(ran) if a.is_halted() => {
+            println!("no divergence, both halted after {} instructions", ran);
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(ran) => {
+            println!("no divergence in {} instructions", ran);
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(mismatch) => {
+            let instruction = disassemble_at(&a.memory, mismatch.pc, a.variant, &a.quirks());
+            println!(
+                "diverged after {} instructions, at {:03X} {}: {}",
+                mismatch.cycle, mismatch.pc, instruction.text, mismatch.divergence
+            );
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn statediff(args: StatediffArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let differences = save_state::diff(&SaveState::load(&args.a)?, &SaveState::load(&args.b)?);
+    print!("{}", differences);
+    Ok(if differences.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn bench(mut args: BenchArgs, format: Option<RomFormat>, config: &Config) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = match &args.rom {
+        Some(path) => {
+            let rom = read_rom(path, format)?;
+            let known = known_rom(&rom);
+            let metadata = rom_metadata(path, config);
+            args.machine.apply(known.as_ref(), metadata.as_ref(), config)?;
+            rom
+        }
+        None => {
+            args.machine.apply(None, None, config)?;
+            bench::WORKLOAD.to_vec()
+        }
+    };
+    let duration = Duration::try_from_secs_f64(args.seconds).map_err(|_| "--seconds should be a positive number")?;
+    let mut cpu = args.machine.cpu(&rom)?;
+    let restart = |cpu: &mut Cpu| {
+        cpu.restart_with_rom(&rom)?;
+        args.machine.patch(cpu)
+    };
+
+    eprintln!("running {} for {:.1}s at {} IPS...", args.rom.as_ref().map_or("the built in workload".into(), |path| path.display().to_string()), args.seconds, cpu.clock_speed);
+    #[cfg(feature = "jit")]
+    let result = if args.jit {
+        let mut jit = chip_8_emulator::jit::Jit::new()?;
+        bench::run(&mut cpu, duration, Some(&mut jit), restart)?
+    } else {
+        bench::run(&mut cpu, duration, None, restart)?
+    };
+    #[cfg(not(feature = "jit"))]
+    let result = bench::run(&mut cpu, duration, None, restart)?;
+    println!("{}", result);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "egui")]
+fn debug_gui(mut args: DebugArgs, format: Option<RomFormat>, config: &Config) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let known = known_rom(&rom);
+    let metadata = rom_metadata(&args.rom, config);
+    args.machine.apply(known.as_ref(), metadata.as_ref(), config)?;
+    args.colors.apply(metadata.as_ref(), config)?;
+
+    let mut cpu = args.machine.cpu(&rom)?;
+
+    cpu.display.set_phosphor_decay(args.colors.phosphor.unwrap_or(0));
+    let mut debugger = chip_8_emulator::gui_debugger::GuiDebugger::new(cpu, rom, keymap(args.keymap, config, &args.rom)?);
+    debugger.set_palette(args.colors.palette());
+    debugger.set_symbols(symbols(args.symbols.as_deref(), &args.rom)?);
+    debugger.run()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "tui")]
+fn debug(mut args: DebugArgs, format: Option<RomFormat>, config: &Config) -> Result<ExitCode, Box<dyn Error>> {
+    let rom = read_rom(&args.rom, format)?;
+    let known = known_rom(&rom);
+    let metadata = rom_metadata(&args.rom, config);
+    args.machine.apply(known.as_ref(), metadata.as_ref(), config)?;
+    args.colors.apply(metadata.as_ref(), config)?;
+
+    let mut cpu = args.machine.cpu(&rom)?;
+
+    // before the debugger takes the terminal over, so a broken symbol file's error can be seen
+    let symbols = symbols(args.symbols.as_deref(), &args.rom)?;
+    let mut debugger = chip_8_emulator::tui::Debugger::open(keymap(args.keymap, config, &args.rom)?)?;
+    debugger.set_palette(args.colors.palette());
+    debugger.set_symbols(symbols);
+    debugger.run(&mut cpu)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run(mut args: RunArgs, format: Option<RomFormat>, config: &Config) -> Result<ExitCode, Box<dyn Error>> {
+    // before the CPU, so they're unloaded after everything that could be holding their code
+    #[cfg(feature = "plugins")]
+    let mut plugins = load_plugins(&args.plugin)?;
+
+    // a built in ROM goes by its name, for config.toml's [rom.NAME] and screenshot names
+    let (rom_path, rom) = match (args.builtin, &args.rom) {
+        (Some(builtin), _) => (PathBuf::from(builtin.name), builtin.bytes.to_vec()),
+        (None, Some(path)) => (path.clone(), read_rom(path, format)?),
+        (None, None) => unreachable!("clap wants a ROM or --builtin"),
+    };
+    let known = known_rom(&rom);
+    let metadata = if args.builtin.is_none() { rom_metadata(&rom_path, config) } else { None };
+    args.machine.apply(known.as_ref(), metadata.as_ref(), config)?;
+    args.colors.apply(metadata.as_ref(), config)?;
+    args.renderer = args.renderer.or(setting(&config.renderer, "renderer", |text| RendererArg::from_str(text, true))?);
+
+    let mut cpu = args.machine.cpu(&rom)?;
+    cpu.hardened = args.hardened;
+    cpu.memory_protection = match (args.hardened, args.allow_low_memory, args.strict_memory) {
+        (true, _, true) => MemoryProtection::strict(),
+        (true, false, _) => MemoryProtection::reserved(),
+        _ => MemoryProtection::off(),
+    };
+    cpu.watchdog = args.watchdog;
+    cpu.set_unknown_opcode_policy(args.unknown_opcodes.into());
+
+    let trace = match &args.trace_json {
+        Some(path) => {
+            let trace = Arc::new(Mutex::new(JsonTrace::new(BufWriter::new(File::create(path)?))));
+            cpu.add_observer(Box::new(trace.clone()));
+            Some(trace)
+        }
+        None => None,
+    };
+    let coverage = match &args.coverage {
+        Some(_) => {
+            let coverage = Arc::new(Mutex::new(Coverage::new()));
+            cpu.add_observer(Box::new(coverage.clone()));
+            Some(coverage)
+        }
+        None => None,
+    };
+    let profiler = if args.profile {
+        let profiler = Arc::new(Mutex::new(Profiler::new()));
+        cpu.add_observer(Box::new(profiler.clone()));
+        Some(profiler)
+    } else {
+        None
+    };
+    #[cfg(feature = "scripting")]
+    let script = match &args.script {
+        Some(path) => {
+            let script = Arc::new(Mutex::new(Script::load(path, &cpu)?));
+            cpu.add_observer(Box::new(script.clone()));
+            Some(script)
+        }
+        None => None,
+    };
+    #[cfg(feature = "plugins")]
+    for plugin in &mut plugins {
+        if let Some(observer) = plugin.observer() {
+            cpu.add_observer(observer);
+        }
+    }
+
+    if args.headless {
+        let meter = StatsMeter::new(&cpu);
+        cpu.loop_detection = LoopDetection {
+            jump_to_self: true,
+            idle_window: args.loop_window,
+        };
+        let max_cycles = args.max_cycles.unwrap_or(u64::MAX);
+        // a script runs it itself, leaving nothing for below
+        #[cfg(feature = "scripting")]
+        let max_cycles = match &script {
+            Some(script) => {
+                run_scripted(&mut cpu, script, max_cycles)?;
+                0
+            }
+            None => max_cycles,
+        };
+        #[cfg(feature = "jit")]
+        if args.jit {
+            let mut jit = chip_8_emulator::jit::Jit::new()?;
+            jit.run_for(&mut cpu, max_cycles);
+        } else {
+            cpu.run_until_halt(max_cycles);
+        }
+        #[cfg(not(feature = "jit"))]
+        cpu.run_until_halt(max_cycles);
+        if let Some(trace) = &trace {
+            trace.lock().unwrap().finish()?;
+        }
+        if let (Some(path), Some(coverage)) = (&args.coverage, &coverage) {
+            save_coverage(path, &coverage.lock().unwrap())?;
+        }
+        if let Some(path) = &args.save_state {
+            SaveState::capture(&cpu).save(path)?;
+        }
+        print_state(&cpu);
+        // on stderr, the state's the same every run and the speed isn't
+        eprintln!("stats: {}", meter.overall(&cpu));
+        if let Some(profiler) = &profiler {
+            println!();
+            print!("{}", profiler.lock().unwrap().report(&cpu, &symbols(None, &rom_path)?, args.profile_top));
+        }
+        #[cfg(feature = "plugins")]
+        for plugin in &mut plugins {
+            plugin.finish(&cpu);
+        }
+        return Ok(match cpu.halt_reason() {
+            Some(HaltReason::InfiniteLoop) => ExitCode::from(EXIT_INFINITE_LOOP),
+            Some(HaltReason::Fault) if matches!(cpu.fault(), Some(CpuError::Watchdog { .. })) => ExitCode::from(EXIT_WATCHDOG),
+            Some(HaltReason::Fault) => ExitCode::from(EXIT_FAULT),
+            _ => ExitCode::SUCCESS,
+        });
+    }
+
+    let mut flag_store = RplFlagStore::for_rom(&rom);
+    if let Some(store) = &flag_store {
+        cpu.rpl_flags = store.load()?;
+    }
+
+    let tone = Tone {
+        frequency: args.beep_frequency.or(config.beep_frequency).unwrap_or(BEEP_FREQUENCY),
+        waveform: match args.waveform.take() {
+            Some(waveform) => waveform,
+            None => setting(&config.waveform, "waveform", parse_waveform)?.unwrap_or(Waveform::Square),
+        },
+    };
+    if !(tone.frequency > 0.0 && tone.frequency.is_finite()) {
+        return Err("the beep's frequency should be above 0".into());
+    }
+    #[cfg(feature = "audio")]
+    let mut audio: Box<dyn AudioSink> = match chip_8_emulator::audio_output::AudioOutput::open(tone.clone()) {
+        Ok(output) => Box::new(output),
+        Err(e) => {
+            eprintln!("no sound: {}", e);
+            Box::new(Silent)
+        }
+    };
+    #[cfg(not(feature = "audio"))]
+    let mut audio: Box<dyn AudioSink> = Box::new(Silent);
+    match args.volume.or(config.volume) {
+        Some(volume) if volume <= 100 => audio.set_volume(volume as f32 / 100.0),
+        Some(_) => return Err("config.toml: volume: should be 0 to 100".into()),
+        None => {}
+    }
+    let mut muted = false;
+
+    let mut gdb = match &args.gdb {
+        Some(addr) => {
+            let mut server = GdbServer::bind(addr.as_str())?;
+            eprintln!("waiting for gdb on {}", server.local_addr()?);
+            server.wait_for_client()?;
+            Some(server)
+        }
+        None => None,
+    };
+    let mut netplay = match &args.netplay {
+        Some(peer) => {
+            let netplay = Netplay::connect(args.netplay_bind.as_str(), peer.as_str(), args.input_delay, &cpu)?;
+            eprintln!("netplay: listening on {}, playing with {}", netplay.local_addr()?, peer);
+            Some(netplay)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = {
+        let mut map = GamepadMap::default();
+        for (button, key) in config.gamepad_buttons(rom_name(&rom_path))? {
+            if !map.bind_named(&button, key) {
+                return Err(format!("config.toml: gamepad: there's no button called {:?}", button).into());
+            }
+        }
+        match Gamepads::open(map) {
+            Ok(gamepads) => Some(gamepads),
+            Err(e) => {
+                eprintln!("no gamepads: {}", e);
+                None
+            }
+        }
+    };
+
+    let mut terminal = TerminalFrontend::open(keymap(args.keymap, config, &rom_path)?)?;
+    terminal.set_renderer(args.renderer.unwrap_or(RendererArg::Auto).into());
+    let palette = args.colors.palette();
+    terminal.set_palette(palette);
+    cpu.display.set_phosphor_decay(args.colors.phosphor.unwrap_or(0));
+    let mut clock = Clock::new();
+    clock.set_turbo_factor(args.turbo_factor);
+    let rom_name = rom_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    // the screenshots and GIFs saved, to list once the terminal's back to normal
+    let mut captures = Vec::new();
+    let mut recording: Option<(GifRecorder, WavRecorder<_>, PathBuf)> = None;
+    let mut video = match &args.record_video {
+        Some(path) => Some((VideoRecorder::create(path, &cpu.display, args.capture_scale, &palette)?, WavRecorder::create(path.with_extension("wav"), tone.clone())?)),
+        None => None,
+    };
+    let mut meter = StatsMeter::new(&cpu);
+    #[cfg(feature = "plugins")]
+    let mut plugin_displays: Vec<_> = plugins.iter_mut().filter_map(|plugin| plugin.display()).collect();
+    #[cfg(feature = "plugins")]
+    let mut plugin_inputs: Vec<_> = plugins.iter_mut().filter_map(|plugin| plugin.input()).collect();
+    let mut watcher = args.watch.then(|| RomWatcher::new(&rom_path, format.unwrap_or_else(|| RomFormat::from_path(&rom_path))));
+    let mut show_overlay = false;
+
+    'frames: while !cpu.is_halted() || watcher.is_some() {
+        if let Some(rom) = watcher.as_mut().and_then(RomWatcher::poll) {
+            // a ROM that doesn't load yet is left for the next save to fix
+            let status = match cpu.restart_with_rom(&rom).and_then(|()| args.machine.patch(&mut cpu)) {
+                Ok(()) => {
+                    flag_store = RplFlagStore::for_rom(&rom);
+                    if let Some(store) = &flag_store {
+                        cpu.rpl_flags = store.load()?;
+                    }
+                    meter = StatsMeter::new(&cpu);
+                    format!("reloaded {}", rom_path.display())
+                }
+                Err(e) => format!("{}: {}", rom_path.display(), e),
+            };
+            terminal.present(&cpu.display)?;
+            terminal.draw_status(&status)?;
+        }
+
+        let hotkeys = terminal.poll(&mut cpu)?;
+        #[cfg(feature = "gamepad")]
+        let hotkeys = match &mut gamepads {
+            Some(gamepads) => [hotkeys, gamepads.poll(&mut cpu)?].concat(),
+            None => hotkeys,
+        };
+        #[cfg(feature = "plugins")]
+        let hotkeys = {
+            let mut hotkeys = hotkeys;
+            for input in &mut plugin_inputs {
+                hotkeys.extend(input.poll(&mut cpu)?);
+            }
+            hotkeys
+        };
+        for hotkey in hotkeys {
+            match hotkey {
+                Hotkey::Quit => break 'frames,
+                Hotkey::ToggleTurbo => clock.toggle_turbo(),
+                Hotkey::TogglePause if cpu.is_paused() => cpu.resume(),
+                Hotkey::TogglePause => cpu.pause(),
+                // a frame the other side doesn't run would put the two out of step
+                Hotkey::AdvanceFrame if cpu.is_paused() && netplay.is_none() => cpu.advance_frame(),
+                Hotkey::AdvanceFrame => {}
+                Hotkey::NextTextRenderer => terminal.set_renderer(terminal.renderer().next_text()),
+                Hotkey::ToggleOverlay => show_overlay = !show_overlay,
+                Hotkey::ToggleMute => {
+                    muted = !muted;
+                    audio.set_muted(muted);
+                }
+                Hotkey::ToggleRecording => match recording.take() {
+                    Some((recorder, sound, path)) => {
+                        recorder.finish()?;
+                        sound.finish()?;
+                        captures.push(path.with_extension("wav"));
+                        captures.push(path);
+                    }
+                    None => {
+                        let path = capture_path(&rom_name, "gif");
+                        let recorder = GifRecorder::create(&path, &cpu.display, args.capture_scale, &palette)?;
+                        recording = Some((recorder, WavRecorder::create(path.with_extension("wav"), tone.clone())?, path));
+                    }
+                },
+                Hotkey::Screenshot => {
+                    let path = capture_path(&rom_name, "png");
+                    cpu.display.save_png(&path, args.capture_scale, &palette)?;
+                    captures.push(path);
+                }
+            }
+        }
+
+        match (&mut gdb, &mut netplay) {
+            (Some(gdb), _) => gdb.run_frame(&mut cpu)?,
+            // waiting on the other side just means no frame this time round
+            (None, Some(netplay)) if !cpu.is_paused() => {
+                netplay.run_frame(&mut cpu)?;
+            }
+            _ => cpu.run_frame(),
+        }
+        #[cfg(feature = "scripting")]
+        if let (Some(script), false) = (&script, cpu.is_paused()) {
+            script.lock().unwrap().end_frame(&mut cpu)?;
+        }
+        InputSource::end_frame(&mut terminal, &mut cpu);
+        #[cfg(feature = "plugins")]
+        for input in &mut plugin_inputs {
+            input.end_frame(&mut cpu);
+        }
+        if let Some((recorder, sound, _)) = &mut recording {
+            recorder.capture(&cpu.display)?;
+            sound.capture(&cpu.sound())?;
+        }
+        if let Some((video, sound)) = &mut video {
+            video.capture(&cpu.display)?;
+            sound.capture(&cpu.sound())?;
+        }
+        if clock.should_present() {
+            terminal.present(&cpu.display)?;
+            #[cfg(feature = "plugins")]
+            for display in &mut plugin_displays {
+                display.present(&cpu.display)?;
+            }
+            meter.frame_presented();
+            let stats = meter.current(&cpu);
+            terminal.draw_overlay(show_overlay.then(|| overlay_lines(&cpu, stats.ips)).as_deref())?;
+            if args.show_stats {
+                terminal.draw_status(&stats.to_string())?;
+            }
+        }
+        audio.update(cpu.sound());
+
+        if cpu.rpl_flags_dirty {
+            if let Some(store) = &flag_store {
+                store.save(&cpu.rpl_flags)?;
+            }
+            cpu.rpl_flags_dirty = false;
+        }
+
+        clock.wait_for_next_frame();
+    }
+
+    if let Some((recorder, sound, path)) = recording {
+        recorder.finish()?;
+        sound.finish()?;
+        captures.push(path.with_extension("wav"));
+        captures.push(path);
+    }
+    let video = match video {
+        Some((video, sound)) => {
+            let summary = (video.size(), video.frames());
+            video.finish()?;
+            sound.finish()?;
+            Some(summary)
+        }
+        None => None,
+    };
+    if let Some(trace) = &trace {
+        trace.lock().unwrap().finish()?;
+    }
+    if let (Some(path), Some(coverage)) = (&args.coverage, &coverage) {
+        save_coverage(path, &coverage.lock().unwrap())?;
+    }
+    if let Some(path) = &args.save_state {
+        SaveState::capture(&cpu).save(path)?;
+    }
+    // the terminal has to be back to normal before anything gets printed
+    drop(terminal);
+    for path in captures {
+        println!("saved {}", path.display());
+    }
+    if let (Some(path), Some(((width, height), frames))) = (&args.record_video, video) {
+        // raw RGB doesn't say, and ffmpeg needs to be told
+        println!("saved {} frames of {}x{} video to {}", frames, width, height, path.display());
+        println!("saved {}", path.with_extension("wav").display());
+    }
+    if let Some(profiler) = &profiler {
+        print!("{}", profiler.lock().unwrap().report(&cpu, &symbols(None, &rom_path)?, args.profile_top));
+    }
+    #[cfg(feature = "plugins")]
+    for plugin in &mut plugins {
+        plugin.finish(&cpu);
+    }
+    if let Some(error) = cpu.fault() {
+        eprint!("{}", cpu.dump());
+        return Err(error.clone().into());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// The plugins in the plugins directory, then the ones asked for with --plugin. One in the
+// directory that won't load gets skipped, one asked for is an error
+#[cfg(feature = "plugins")]
+fn load_plugins(asked_for: &[PathBuf]) -> Result<Vec<LoadedPlugin>, Box<dyn Error>> {
+    let mut plugins = Vec::new();
+    if let Some(dir) = plugin::plugin_dir() {
+        for path in plugin::discover(&dir)? {
+            match LoadedPlugin::load(&path) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(e) => eprintln!("skipping plugin {}", e),
+            }
+        }
+    }
+    for path in asked_for {
+        plugins.push(LoadedPlugin::load(path)?);
+    }
+    Ok(plugins)
+}
+
+// A headless run with a script, an instruction at a time so the script's end of frame comes
+// exactly where the timers tick, like it would running interactively
+#[cfg(feature = "scripting")]
+fn run_scripted(cpu: &mut Cpu, script: &Mutex<Script>, max_cycles: u64) -> Result<(), ScriptError> {
+    let mut ran = 0;
+    while ran < max_cycles && !cpu.is_halted() {
+        let ticks = cpu.timer_ticks();
+        ran += cpu.run_for(1);
+        if cpu.timer_ticks() != ticks {
+            script.lock().unwrap().end_frame(cpu)?;
+        }
+    }
+    Ok(())
+}
+
+// Add a run's counts to what's already in the file, so it builds up over play sessions
+fn save_coverage(path: &Path, coverage: &Coverage) -> io::Result<()> {
+    let mut total = if path.exists() { Coverage::load(path)? } else { Coverage::new() };
+    total.merge(coverage);
+    total.save(path)
+}
+
+// What a headless run leaves behind: the CPU's state, then the screen as # and .
+fn print_state(cpu: &Cpu) {
+    let outcome = match (cpu.halt_reason(), cpu.fault()) {
+        (Some(HaltReason::Fault), Some(error)) => format!("faulted: {}", error),
+        (Some(HaltReason::Fault), None) => "faulted".to_string(),
+        (Some(HaltReason::Exit), _) => "halted".to_string(),
+        (Some(HaltReason::InfiniteLoop), _) => "stopped in an infinite loop".to_string(),
+        (None, _) => "cycle budget exhausted".to_string(),
+    };
+    println!("{}", outcome);
+    print!("{}", cpu.dump());
+    println!("screen hash {:016X}", cpu.display.hash());
+    println!("state digest {:016X}", cpu.state_digest());
+    println!();
+    print!("{}", cpu.display.to_text());
+}
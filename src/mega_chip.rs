@@ -0,0 +1,269 @@
+// The Mega-Chip screen.
+// MEGA-CHIP8 (Revival Studios, 2007) is SUPER-CHIP with a proper screen bolted on: 256x192 and
+// a byte per pixel, each byte a colour from a 256 colour palette the ROM loads itself. In
+// Mega-Chip mode sprites aren't XOR'd any more, they're blitted: every byte of the sprite is a
+// pixel's colour, 0 is see-through, and the size comes from 03NN/04NN rather than the opcode.
+// A sprite only collides with pixels of one colour, whichever 09NN picked. Everything's drawn
+// to a buffer that isn't shown until the next 00E0, which shows it and starts a blank one, so
+// games never flicker. 0010 goes back to the SUPER-CHIP screen (and its opcodes all still work).
+//
+//   0010 / 0011    Mega-Chip mode off / on
+//   00BN           scroll up N rows
+//   01NN NNNN      I = NNNNNN, 24 bits so it reaches all of memory
+//   02NN           load NN colours from I into the palette starting at colour 1, 4 bytes each (ARGB)
+//   03NN / 04NN    sprite width / height, 0 meaning 256
+//   05NN           how opaque the whole screen is
+//   060N           play the digitised sound at I, over and over unless N is 1
+//   0700           stop the sound
+//   080N           blend mode: 0 normal, 1 25%, 2 50%, 3 75%, 4 add, 5 multiply
+//   09NN           the colour sprites collide with
+//   DXYN           blit the sprite at I (N doesn't count)
+//
+// Text is the exception: with I pointing at the font (anywhere below 0x200) DXYN draws the
+// usual 1 bit sprite, in colour 255. Colour 0 is black and everything else starts white, so
+// text shows up before a ROM loads its palette.
+//
+// The screen only holds colour numbers, so the blend mode is kept (save states, debuggers) but
+// sprites are all drawn as if it was normal. Digitised sound is 2 bytes of sample rate, 3 of
+// length and one spare, then a byte per sample (unsigned, 128 is silence). Cpu::digitised_sound
+// tracks what's playing and the audio engine plays it (see src/audio.rs).
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
+/// Colours in the palette.
+pub const PALETTE_SIZE: usize = 256;
+/// The colour text is drawn in.
+pub const TEXT_COLOR: u8 = 255;
+/// Bytes before the samples in a digitised sound.
+pub const SOUND_HEADER_LEN: usize = 6;
+
+/// A sound 060N started, see the top of this file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigitisedSound {
+    /// Where the samples start, just past the header
+    pub start: usize,
+    /// How many samples there are, a byte each
+    pub len: usize,
+    /// Samples a second
+    pub sample_rate: u16,
+    pub looping: bool,
+}
+
+impl DigitisedSound {
+    /// Read the header at the front of `header` (the sound's address onwards).
+    pub fn parse(addr: usize, header: &[u8; SOUND_HEADER_LEN], looping: bool) -> Self {
+        DigitisedSound {
+            start: addr + SOUND_HEADER_LEN,
+            len: (header[2] as usize) << 16 | (header[3] as usize) << 8 | header[4] as usize,
+            sample_rate: u16::from_be_bytes([header[0], header[1]]),
+            looping,
+        }
+    }
+}
+
+/// The Mega-Chip screen and its settings, see the top of this file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MegaScreen {
+    enabled: bool,
+    // ARGB
+    palette: [[u8; 4]; PALETTE_SIZE],
+    sprite_width: usize,
+    sprite_height: usize,
+    alpha: u8,
+    blend_mode: u8,
+    collision_color: u8,
+    // what's showing, and what's being drawn for the next 00E0 to show. A colour a byte, row by row
+    front: Vec<u8>,
+    back: Vec<u8>,
+}
+
+impl MegaScreen {
+    /// Switched off, with the palette black then white.
+    pub fn new() -> Self {
+        let mut palette = [[0xFF; 4]; PALETTE_SIZE];
+        palette[0] = [0; 4];
+        MegaScreen {
+            enabled: false,
+            palette,
+            sprite_width: 8,
+            sprite_height: 8,
+            alpha: 0xFF,
+            blend_mode: 0,
+            collision_color: 0,
+            front: vec![0; MEGA_WIDTH * MEGA_HEIGHT],
+            back: vec![0; MEGA_WIDTH * MEGA_HEIGHT],
+        }
+    }
+
+    /// Whether it's in Mega-Chip mode (0011) rather than showing the SUPER-CHIP screen.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 0010 and 0011. Either way both buffers start blank.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.front.fill(0);
+        self.back.fill(0);
+    }
+
+    /// A palette colour as ARGB.
+    pub fn palette_color(&self, color: u8) -> [u8; 4] {
+        self.palette[color as usize]
+    }
+
+    pub fn set_palette_color(&mut self, color: u8, argb: [u8; 4]) {
+        self.palette[color as usize] = argb;
+    }
+
+    /// 02NN, `colors` being the 4 bytes for each colour from colour 1 on.
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (n, argb) in colors.chunks_exact(4).enumerate().take(PALETTE_SIZE - 1) {
+            self.palette[n + 1] = [argb[0], argb[1], argb[2], argb[3]];
+        }
+    }
+
+    /// Width and height of the sprites DXYN draws, each 1 to 256.
+    pub fn sprite_size(&self) -> (usize, usize) {
+        (self.sprite_width, self.sprite_height)
+    }
+
+    /// 03NN, 0 meaning 256.
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = if width == 0 { 256 } else { width as usize };
+    }
+
+    /// 04NN, 0 meaning 256.
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = if height == 0 { 256 } else { height as usize };
+    }
+
+    /// How opaque the whole screen is, 255 for completely.
+    pub fn alpha(&self) -> u8 {
+        self.alpha
+    }
+
+    /// 05NN
+    pub fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha;
+    }
+
+    pub fn blend_mode(&self) -> u8 {
+        self.blend_mode
+    }
+
+    /// 080N
+    pub fn set_blend_mode(&mut self, mode: u8) {
+        self.blend_mode = mode;
+    }
+
+    pub fn collision_color(&self) -> u8 {
+        self.collision_color
+    }
+
+    /// 09NN
+    pub fn set_collision_color(&mut self, color: u8) {
+        self.collision_color = color;
+    }
+
+    /// The colour of a pixel on the screen that's showing.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.front[y * MEGA_WIDTH + x]
+    }
+
+    /// A row of what's showing.
+    pub fn row(&self, y: usize) -> &[u8] {
+        &self.front[y * MEGA_WIDTH..(y + 1) * MEGA_WIDTH]
+    }
+
+    /// Both buffers, what's showing then what's being drawn, for save states.
+    pub fn buffers(&self) -> (&[u8], &[u8]) {
+        (&self.front, &self.back)
+    }
+
+    /// Put saved buffers back, each MEGA_WIDTH x MEGA_HEIGHT. Short ones leave the rest as it was.
+    pub fn set_buffers(&mut self, front: &[u8], back: &[u8]) {
+        for (to, from) in [(&mut self.front, front), (&mut self.back, back)] {
+            let len = to.len().min(from.len());
+            to[..len].copy_from_slice(&from[..len]);
+        }
+    }
+
+    /// 00E0: show what's been drawn and start drawing on a blank screen.
+    pub fn present(&mut self) {
+        core::mem::swap(&mut self.front, &mut self.back);
+        self.back.fill(0);
+    }
+
+    /// Blit a sprite_size() sprite, a colour a byte, with its top left corner at (x, y).
+    /// Returns true if it drew over any pixel of the collision colour.
+    pub fn blit(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let width = self.sprite_width;
+        self.put(x, y, width, sprite.len() / width, wrap, |col, row| sprite[row * width + col])
+    }
+
+    /// Draw an ordinary 1 bit sprite, `bytes_per_row` bytes a row, in TEXT_COLOR.
+    pub fn blit_bits(&mut self, x: usize, y: usize, sprite: &[u8], bytes_per_row: usize, wrap: bool) -> bool {
+        let lit = |col: usize, row: usize| sprite[row * bytes_per_row + col / 8] & (0x80 >> (col % 8)) != 0;
+        self.put(x, y, bytes_per_row * 8, sprite.len() / bytes_per_row, wrap, |col, row| if lit(col, row) { TEXT_COLOR } else { 0 })
+    }
+
+    // Copy every non-zero colour `color(col, row)` gives onto the back buffer
+    fn put(&mut self, x: usize, y: usize, width: usize, height: usize, wrap: bool, color: impl Fn(usize, usize) -> u8) -> bool {
+        let (x, y) = (x % MEGA_WIDTH, y % MEGA_HEIGHT);
+        let mut collision = false;
+        for row in 0..height {
+            let py = y + row;
+            if py >= MEGA_HEIGHT && !wrap {
+                break;
+            }
+            for col in 0..width {
+                let px = x + col;
+                if px >= MEGA_WIDTH && !wrap {
+                    break;
+                }
+                let color = color(col, row);
+                if color == 0 {
+                    continue;
+                }
+                let pixel = &mut self.back[(py % MEGA_HEIGHT) * MEGA_WIDTH + px % MEGA_WIDTH];
+                collision |= *pixel == self.collision_color;
+                *pixel = color;
+            }
+        }
+        collision
+    }
+
+    /// Move what's being drawn by (dx, dy), whatever gets uncovered is blank.
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let before = self.back.clone();
+        let (width, height) = (MEGA_WIDTH as isize, MEGA_HEIGHT as isize);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                self.back[(y * width + x) as usize] = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    before[(sy * width + sx) as usize]
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
+    /// RGB for a pixel that's showing: its palette colour, faded to black by its own alpha and
+    /// the screen's.
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let [alpha, r, g, b] = self.palette[self.pixel(x, y) as usize];
+        let opacity = alpha as u32 * self.alpha as u32;
+        [r, g, b].map(|channel| (channel as u32 * opacity / (255 * 255)) as u8)
+    }
+}
+
+impl Default for MegaScreen {
+    fn default() -> Self {
+        MegaScreen::new()
+    }
+}
@@ -0,0 +1,128 @@
+// Memory protection.
+// Everything below 0x200 was the interpreter's own memory on the original machines (on the
+// VIP it was the interpreter itself), so a well-behaved ROM never writes there, and never
+// reads memory that nothing put anything in. A ROM that does is usually following a bad
+// pointer, and catching that at the write is a lot easier than working out later why the
+// font's gone wrong. Both checks are off by default, and `chip8 run --hardened` turns on the
+// first one (opt out with --allow-low-memory, a few ROMs do keep data down there).
+//
+// "Written" for the strict read check means the font, the ROM and anything the program has
+// stored since. Running code counts as reading it. Changes made straight to Cpu::memory from
+// outside (a debugger poking bytes) don't count, call Cpu::mark_initialized() for those.
+
+/// Which memory accesses are errors. A broken access raises a CpuError like any other broken
+/// instruction, a panic unless the CPU is hardened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryProtection {
+    /// Writes below 0x200 (CpuError::ProtectedWrite)
+    pub reserved: bool,
+    /// Reads of memory nothing has written, including running it (CpuError::UninitializedRead)
+    pub uninitialized_reads: bool,
+}
+
+impl MemoryProtection {
+    /// No checks, anything goes. The default.
+    pub const fn off() -> Self {
+        MemoryProtection {
+            reserved: false,
+            uninitialized_reads: false,
+        }
+    }
+
+    /// Just keep the program out of the interpreter's memory.
+    pub const fn reserved() -> Self {
+        MemoryProtection {
+            reserved: true,
+            uninitialized_reads: false,
+        }
+    }
+
+    /// Every check.
+    pub const fn strict() -> Self {
+        MemoryProtection {
+            reserved: true,
+            uninitialized_reads: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::error::CpuError;
+    use crate::quirks::Quirks;
+
+    // A hardened CPU with `protection` and `program` loaded
+    fn machine(protection: MemoryProtection, program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.hardened = true;
+        cpu.memory_protection = protection;
+        cpu.load_rom(program).unwrap();
+        cpu.run_for(10);
+        cpu
+    }
+
+    // LD I, 0x100; LD [I], V0
+    const LOW_WRITE: [u8; 4] = [0xA1, 0x00, 0xF0, 0x55];
+    // LD I, 0x400; LD V0, [I]
+    const UNWRITTEN_READ: [u8; 4] = [0xA4, 0x00, 0xF0, 0x65];
+
+    #[test]
+    fn off_by_default() {
+        assert_eq!(MemoryProtection::default(), MemoryProtection::off());
+        assert_eq!(Cpu::new(Quirks::cosmac_vip()).memory_protection, MemoryProtection::off());
+        let cpu = machine(MemoryProtection::off(), &LOW_WRITE);
+        assert_eq!(cpu.fault(), None);
+        let cpu = machine(MemoryProtection::reserved(), &UNWRITTEN_READ);
+        assert_eq!(cpu.fault(), None);
+    }
+
+    #[test]
+    fn writes_below_0x200() {
+        let mut program = LOW_WRITE.to_vec();
+        program[2] = 0xF2;
+        let cpu = machine(MemoryProtection::reserved(), &program);
+        assert_eq!(cpu.fault(), Some(&CpuError::ProtectedWrite { pc: 0x202, addr: 0x100 }));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.memory[0x100..0x103], [0; 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "write to 100")]
+    fn panics_unless_hardened() {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.memory_protection = MemoryProtection::reserved();
+        cpu.load_rom(&LOW_WRITE).unwrap();
+        cpu.run_for(2);
+    }
+
+    #[test]
+    fn reads_of_unwritten_memory() {
+        let cpu = machine(MemoryProtection::strict(), &UNWRITTEN_READ);
+        assert_eq!(cpu.fault(), Some(&CpuError::UninitializedRead { pc: 0x202, addr: 0x400 }));
+
+        // LD [I], V0 first, then read it back (the VIP moves I along, so it's set again)
+        let cpu = machine(MemoryProtection::strict(), &[0xA4, 0x00, 0xF0, 0x55, 0xA4, 0x00, 0xF0, 0x65, 0x00, 0x00]);
+        assert_eq!(cpu.fault(), None);
+        // the font counts as written
+        let cpu = machine(MemoryProtection::strict(), &[0xF0, 0x29, 0xF0, 0x65, 0x00, 0x00]);
+        assert_eq!(cpu.fault(), None);
+    }
+
+    #[test]
+    fn running_unwritten_memory() {
+        let cpu = machine(MemoryProtection::strict(), &[0x14, 0x00]);
+        assert_eq!(cpu.fault(), Some(&CpuError::UninitializedRead { pc: 0x400, addr: 0x400 }));
+
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.hardened = true;
+        cpu.memory_protection = MemoryProtection::strict();
+        cpu.load_rom(&[0x14, 0x00]).unwrap();
+        // poked in from outside, and marked
+        cpu.memory[0x400..0x402].copy_from_slice(&[0x00, 0x00]);
+        cpu.mark_initialized(0x400, 2);
+        cpu.run_for(10);
+        assert_eq!(cpu.fault(), None);
+    }
+}
@@ -0,0 +1,158 @@
+// ROM metadata.
+// The CHIP-8 Archive (github.com/JohnEarnest/chip8Archive) keeps everything it knows about its
+// ROMs in one programs.json, keyed by the ROM's file name without the extension:
+//   "octojam1title": {
+//     "title": "Octojam 1 Title", "authors": ["John Earnest"], "release": "2014-10-01",
+//     "desc": "...", "platform": "xochip",
+//     "options": { "tickrate": 100, "shiftQuirks": false, "backgroundColor": "#996600", ... }
+//   }
+// The options are Octo's settings for the ROM: instructions per frame, its quirk switches
+// and the colours to show it in. Anything else in there (images, fonts, touch modes) is
+// ignored, and so are the quirks we don't emulate (vfOrderQuirks, logicQuirks).
+//
+// The ROMs sit in a roms/ directory next to programs.json, so that's where it gets looked for:
+// next to the ROM and one directory up.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::clock::TIMER_HZ;
+use crate::palette::{parse_color, Palette};
+use crate::quirks::Quirks;
+use crate::variant::Variant;
+
+const FILE_NAME: &str = "programs.json";
+
+/// What the archive says about one ROM.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RomMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    /// When it came out, as YYYY-MM-DD
+    pub release: Option<String>,
+    /// Usually the game jam it was made for
+    pub event: Option<String>,
+    #[serde(rename = "desc")]
+    pub description: Option<String>,
+    /// "chip8", "schip" or "xochip"
+    pub platform: Option<String>,
+    pub options: ArchiveOptions,
+}
+
+/// The Octo settings a ROM was made with. Quirks are Octo's switches, all false is Octo's
+/// own behaviour.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ArchiveOptions {
+    /// Instructions per 60Hz frame
+    pub tickrate: Option<u32>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    /// 8XY6/8XYE shift VX in place
+    pub shift_quirks: Option<bool>,
+    /// FX55/FX65 leave I alone
+    pub load_store_quirks: Option<bool>,
+    /// BNNN is BXNN
+    pub jump_quirks: Option<bool>,
+    /// Draws wait for the vertical blank
+    pub v_blank_quirks: Option<bool>,
+    /// Sprites are clipped at the edges instead of wrapping
+    pub clip_quirks: Option<bool>,
+}
+
+impl RomMetadata {
+    /// Look for the ROM in a programs.json next to it or one directory up. None if there's no
+    /// programs.json or it doesn't have the ROM.
+    pub fn find(rom: &Path) -> io::Result<Option<Self>> {
+        match search_paths(rom).into_iter().find(|path| path.exists()) {
+            Some(path) => RomMetadata::lookup(&path, rom),
+            None => Ok(None),
+        }
+    }
+
+    /// Look for the ROM in a particular programs.json.
+    pub fn lookup(programs: &Path, rom: &Path) -> io::Result<Option<Self>> {
+        let Some(name) = rom.file_stem().and_then(|name| name.to_str()) else { return Ok(None) };
+        Ok(load_programs(programs)?.remove(name))
+    }
+
+    /// The machine it's for, if the platform is one we emulate.
+    pub fn variant(&self) -> Option<Variant> {
+        match self.platform.as_deref()?.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" => Some(Variant::Chip8),
+            "schip" | "superchip" | "super-chip" | "schip1.1" => Some(Variant::SuperChip),
+            "xochip" | "xo-chip" => Some(Variant::XoChip),
+            "chip8x" | "chip-8x" => Some(Variant::Chip8X),
+            "megachip" | "mega-chip" | "megachip8" => Some(Variant::MegaChip),
+            "eti660" | "eti-660" => Some(Variant::Eti660),
+            "chip8hires" | "chip-8-hires" | "hires" => Some(Variant::Chip8Hires),
+            _ => None,
+        }
+    }
+
+    /// Instructions per second, from the tickrate.
+    pub fn clock_speed(&self) -> Option<u32> {
+        self.options.tickrate.map(|tickrate| tickrate.saturating_mul(TIMER_HZ))
+    }
+
+    /// `base` with whichever quirks the archive sets, None if it doesn't set any.
+    pub fn quirks(&self, base: Quirks) -> Option<Quirks> {
+        let options = &self.options;
+        let set = [options.shift_quirks, options.load_store_quirks, options.jump_quirks, options.v_blank_quirks, options.clip_quirks];
+        if set.iter().all(Option::is_none) {
+            return None;
+        }
+        let mut quirks = base;
+        if let Some(on) = options.shift_quirks {
+            quirks.shift_vx_in_place = on;
+        }
+        if let Some(on) = options.load_store_quirks {
+            quirks.load_store_increments_index = !on;
+        }
+        if let Some(on) = options.jump_quirks {
+            quirks.jump_offset_uses_vx = on;
+        }
+        if let Some(on) = options.v_blank_quirks {
+            quirks.display_wait = on;
+        }
+        if let Some(on) = options.clip_quirks {
+            quirks.wrap_sprites = !on;
+        }
+        Some(quirks)
+    }
+
+    /// The colours, None unless at least the background and fill colours are there. Octo's
+    /// fill2 and blend are XO-CHIP's colours 2 and 3, missing ones are blended like a two
+    /// colour palette.
+    pub fn palette(&self) -> Option<Palette> {
+        let options = &self.options;
+        let color = |color: &Option<String>| color.as_deref().and_then(parse_color);
+        let mut palette = Palette::two_color(color(&options.background_color)?, color(&options.fill_color)?);
+        if let Some(rgb) = color(&options.fill_color2) {
+            palette.0[2] = rgb;
+        }
+        if let Some(rgb) = color(&options.blend_color) {
+            palette.0[3] = rgb;
+        }
+        Some(palette)
+    }
+}
+
+/// Every ROM in a programs.json, by name.
+pub fn load_programs(path: &Path) -> io::Result<BTreeMap<String, RomMetadata>> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+/// Where find() would look for a ROM's programs.json, whether or not it's there.
+pub fn search_paths(rom: &Path) -> Vec<PathBuf> {
+    let dir = rom.parent().unwrap_or(Path::new(""));
+    [Some(dir), dir.parent()].into_iter().flatten().map(|dir| dir.join(FILE_NAME)).collect()
+}
@@ -0,0 +1,302 @@
+// Machine monitor.
+// The one letter commands the old 8-bit monitors had, for poking at a stopped machine by hand.
+// Numbers are hex whether or not they start with 0x (decimal after a #), and addresses can
+// be symbols. m and d carry on from where the last one left off when they're not given an
+// address, so pressing them again pages through memory.
+//
+//   m [addr] [len]        dump memory, 16 bytes a line with the ASCII alongside
+//   d [addr] [count]      disassemble
+//   r                     show the registers
+//   r <reg> <value>       change V0-VF, I, PC, DT or ST
+//   e <addr> <bytes...>   write bytes into memory
+//   f <addr> <len> <byte> fill memory with a byte
+//   g [addr]              carry on running, from addr if it's given
+//
+// It only knows the machine, so running is left to whoever's asking: execute() says to go and
+// the debugger goes however it goes.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::cpu::{Cpu, PROGRAM_START};
+use crate::disasm::disassemble_at;
+use crate::symbols::Symbols;
+
+/// What the monitor's commands are, for a help command to list.
+pub const MONITOR_HELP: &[&str] = &[
+    "m [addr] [len]    dump memory",
+    "d [addr] [count]  disassemble",
+    "r [<reg> <value>] show the registers, or change one",
+    "e <addr> <bytes>  write bytes into memory",
+    "f <addr> <len> <byte>  fill memory",
+    "g [addr]          run, from addr if given",
+];
+
+// How much m and d show when they're not told
+const DUMP_LEN: usize = 0x40;
+const DISASSEMBLY_LINES: usize = 16;
+
+/// What a command did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reply {
+    /// What to show, a line each
+    pub lines: Vec<String>,
+    /// g: the machine should carry on running
+    pub go: bool,
+}
+
+/// The monitor, remembering where m and d got up to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    next_dump: usize,
+    next_disassembly: usize,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor { next_dump: PROGRAM_START, next_disassembly: PROGRAM_START }
+    }
+
+    /// Run `command` on `cpu`, with addresses looked up in `symbols` as well. None if it isn't
+    /// one of the monitor's commands at all, so it can go to something else.
+    pub fn execute(&mut self, cpu: &mut Cpu, symbols: &Symbols, command: &str) -> Option<Reply> {
+        let mut words = command.split_whitespace();
+        let name = words.next()?;
+        let args: Vec<&str> = words.collect();
+        let addr = |text: &str| symbols.addr(text).or_else(|| parse_number(text));
+        let mut lines = Vec::new();
+        let mut go = false;
+
+        match (name, args.as_slice()) {
+            ("m", &[]) | ("m", &[_]) | ("m", &[_, _]) => {
+                let start = match args.first() {
+                    Some(text) => addr(text),
+                    None => Some(self.next_dump),
+                };
+                let len = args.get(1).map_or(Some(DUMP_LEN), |text| parse_number(text));
+                let (Some(start), Some(len)) = (start, len) else {
+                    lines.push("usage: m [addr] [len]".to_string());
+                    return Some(Reply { lines, go });
+                };
+                let end = start.saturating_add(len).min(cpu.memory.len());
+                for line in (start..end).step_by(16) {
+                    lines.push(dump_line(&cpu.memory[line..(line + 16).min(end)], line));
+                }
+                self.next_dump = end;
+            }
+            ("d", &[]) | ("d", &[_]) | ("d", &[_, _]) => {
+                let start = match args.first() {
+                    Some(text) => addr(text),
+                    None => Some(self.next_disassembly),
+                };
+                let count = args.get(1).map_or(Some(DISASSEMBLY_LINES), |text| parse_number(text));
+                let (Some(mut at), Some(count)) = (start, count) else {
+                    lines.push("usage: d [addr] [count]".to_string());
+                    return Some(Reply { lines, go });
+                };
+                for _ in 0..count {
+                    if at >= cpu.memory.len() {
+                        break;
+                    }
+                    let line = disassemble_at(&cpu.memory, at, cpu.variant, &cpu.quirks()).with_symbols(symbols);
+                    let label = symbols.name(at).map_or(String::new(), |name| format!("{}: ", name));
+                    lines.push(format!("{:03X}  {:04X}  {}{}", at, line.opcode, label, line.text));
+                    at += line.len;
+                }
+                self.next_disassembly = at;
+            }
+            ("r", &[]) => {
+                for line in cpu.dump().lines() {
+                    lines.push(line.to_string());
+                }
+            }
+            ("r", &[register, value]) => match parse_number(value) {
+                Some(value) if set_register(cpu, register, value) => {}
+                Some(_) => lines.push(format!("no register called {}", register)),
+                None => lines.push(format!("not a number: {}", value)),
+            },
+            ("e", &[start, ref bytes @ ..]) if !bytes.is_empty() => {
+                let bytes: Option<Vec<u8>> = bytes.iter().map(|text| parse_number(text).filter(|&byte| byte <= 0xFF).map(|byte| byte as u8)).collect();
+                match (addr(start), bytes) {
+                    (Some(start), Some(bytes)) if start.saturating_add(bytes.len()) <= cpu.memory.len() => {
+                        poke(cpu, start, &bytes);
+                        lines.push(format!("wrote {} bytes at {:03X}", bytes.len(), start));
+                    }
+                    (Some(_), Some(_)) => lines.push("that goes past the end of memory".to_string()),
+                    _ => lines.push("usage: e <addr> <bytes...>, bytes 00 to FF".to_string()),
+                }
+            }
+            ("f", &[start, len, byte]) => match (addr(start), parse_number(len), parse_number(byte).filter(|&byte| byte <= 0xFF)) {
+                (Some(start), Some(len), Some(byte)) if start.saturating_add(len) <= cpu.memory.len() => {
+                    poke(cpu, start, &vec![byte as u8; len]);
+                    lines.push(format!("filled {} bytes at {:03X} with {:02X}", len, start, byte));
+                }
+                (Some(_), Some(_), Some(_)) => lines.push("that goes past the end of memory".to_string()),
+                _ => lines.push("usage: f <addr> <len> <byte>".to_string()),
+            },
+            ("g", &[]) => go = true,
+            ("g", &[start]) => match addr(start) {
+                Some(start) if start < cpu.memory.len() => {
+                    cpu.position_in_memory = start;
+                    go = true;
+                }
+                _ => lines.push(format!("not an address: {}", start)),
+            },
+            ("m" | "d" | "r" | "e" | "f" | "g", _) => {
+                let usage = MONITOR_HELP.iter().find(|help| help.starts_with(name)).unwrap_or(&"");
+                lines.push(format!("usage: {}", usage));
+            }
+            _ => return None,
+        }
+        Some(Reply { lines, go })
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+/// Hex, with or without 0x, or decimal after a #.
+pub fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix('#') {
+        Some(decimal) => decimal.parse().ok(),
+        None => usize::from_str_radix(text.trim_start_matches("0x"), 16).ok(),
+    }
+}
+
+/// Set V0-VF, I, PC, DT or ST by name. False if there's no such register.
+pub fn set_register(cpu: &mut Cpu, register: &str, value: usize) -> bool {
+    let register = register.to_ascii_lowercase();
+    match register.as_str() {
+        "i" => cpu.index_register = value as u32,
+        "pc" => cpu.position_in_memory = value,
+        "dt" => cpu.delay_timer = value as u8,
+        "st" => cpu.sound_timer = value as u8,
+        _ => {
+            let n = register.strip_prefix('v').and_then(|n| u8::from_str_radix(n, 16).ok());
+            match n {
+                Some(n) if n < 16 => cpu.registers[n as usize] = value as u8,
+                _ => return false,
+            }
+        }
+    }
+    true
+}
+
+// Write straight into memory, the way a person poking bytes in would
+fn poke(cpu: &mut Cpu, start: usize, bytes: &[u8]) {
+    cpu.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    cpu.mark_initialized(start, bytes.len());
+    cpu.flush_decoded();
+}
+
+// 200  60 05 12 02 ...  `..
+fn dump_line(bytes: &[u8], addr: usize) -> String {
+    let mut line = format!("{:03X} ", addr);
+    for n in 0..16 {
+        match bytes.get(n) {
+            Some(byte) => line.push_str(&format!(" {:02X}", byte)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str("  ");
+    line.extend(bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    fn machine() -> Cpu {
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(b"\x60\x05\x22\x06Hi\x00\xEE").unwrap();
+        cpu
+    }
+
+    fn lines(monitor: &mut Monitor, cpu: &mut Cpu, command: &str) -> Vec<String> {
+        monitor.execute(cpu, &Symbols::new(), command).unwrap().lines
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("200"), Some(0x200));
+        assert_eq!(parse_number("0x2a"), Some(0x2A));
+        assert_eq!(parse_number("#16"), Some(16));
+        assert_eq!(parse_number("#1f"), None);
+        assert_eq!(parse_number("zz"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn dump_pages_on() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        assert_eq!(lines(&mut monitor, &mut cpu, "m 200 8"), ["200  60 05 22 06 48 69 00 EE                          `.\".Hi.."]);
+        let next = lines(&mut monitor, &mut cpu, "m");
+        assert_eq!(next.len(), DUMP_LEN / 16);
+        assert!(next[0].starts_with("208  00"));
+        // past the end stops at the end
+        assert_eq!(lines(&mut monitor, &mut cpu, "m FFF 100").len(), 1);
+        assert!(lines(&mut monitor, &mut cpu, "m 0 FFFFFFFFFFFFFFFF").len() > 1);
+    }
+
+    #[test]
+    fn disassembly_uses_symbols() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        let symbols = Symbols::parse("206 greet").unwrap();
+        let reply = monitor.execute(&mut cpu, &symbols, "d 200 2").unwrap();
+        assert_eq!(reply.lines, ["200  6005  LD V0, 0x05", "202  2206  CALL greet"]);
+        let reply = monitor.execute(&mut cpu, &symbols, "d greet 1").unwrap();
+        assert_eq!(reply.lines, ["206  00EE  greet: RET"]);
+    }
+
+    #[test]
+    fn registers() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        assert!(lines(&mut monitor, &mut cpu, "r vA 0x7f").is_empty());
+        assert!(lines(&mut monitor, &mut cpu, "r I #300").is_empty());
+        assert_eq!((cpu.registers[0xA], cpu.index_register), (0x7F, 300));
+        assert_eq!(lines(&mut monitor, &mut cpu, "r vg 1"), ["no register called vg"]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "r v0 xyz"), ["not a number: xyz"]);
+        assert!(lines(&mut monitor, &mut cpu, "r")[0].starts_with("PC 0200"));
+    }
+
+    #[test]
+    fn writing_memory() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        assert_eq!(lines(&mut monitor, &mut cpu, "e 300 1 2 ff"), ["wrote 3 bytes at 300"]);
+        assert_eq!(cpu.memory[0x300..0x303], [0x01, 0x02, 0xFF]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "f 300 2 aa"), ["filled 2 bytes at 300 with AA"]);
+        assert_eq!(cpu.memory[0x300..0x303], [0xAA, 0xAA, 0xFF]);
+
+        assert_eq!(lines(&mut monitor, &mut cpu, "e 300 100"), ["usage: e <addr> <bytes...>, bytes 00 to FF"]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "e FFF 1 2"), ["that goes past the end of memory"]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "f 0 FFFFFFFFFFFFFFFF 0"), ["that goes past the end of memory"]);
+        assert_eq!(cpu.memory[0x300..0x303], [0xAA, 0xAA, 0xFF]);
+    }
+
+    #[test]
+    fn go() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        assert!(monitor.execute(&mut cpu, &Symbols::new(), "g").unwrap().go);
+        assert!(monitor.execute(&mut cpu, &Symbols::new(), "g 204").unwrap().go);
+        assert_eq!(cpu.position_in_memory, 0x204);
+        let reply = monitor.execute(&mut cpu, &Symbols::new(), "g 10000").unwrap();
+        assert_eq!(reply, Reply { lines: vec!["not an address: 10000".to_string()], go: false });
+    }
+
+    #[test]
+    fn wrong_arguments_and_other_commands() {
+        let (mut monitor, mut cpu) = (Monitor::new(), machine());
+        assert_eq!(lines(&mut monitor, &mut cpu, "r v0"), ["usage: r [<reg> <value>] show the registers, or change one"]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "m 200 10 20"), ["usage: m [addr] [len]    dump memory"]);
+        assert_eq!(lines(&mut monitor, &mut cpu, "d nowhere"), ["usage: d [addr] [count]"]);
+        assert_eq!(monitor.execute(&mut cpu, &Symbols::new(), "step"), None);
+        assert_eq!(monitor.execute(&mut cpu, &Symbols::new(), "  "), None);
+    }
+}
@@ -0,0 +1,348 @@
+// Netplay, experimental.
+// Two copies of the emulator run the same ROM and swap keypad inputs over UDP every frame, so
+// two people can play Pong or Tank from different machines. Each side's keypad is the two
+// players' keys put together, so each player just presses their own keys.
+//
+// Waiting for the other side's input before every frame would make the game as slow as the
+// round trip, so it doesn't: when the other side's input for a frame hasn't turned up yet it
+// guesses (the same keys as last time, which is nearly always right) and carries on. If the
+// real input turns out different, it rolls back to a save state from before that frame and
+// runs the frames since again with the right keys. Rollbacks are invisible unless the guess
+// changed something on screen, and input delay makes them rarer still: local keys are held
+// back a couple of frames, giving the other side's input time to arrive before it's needed.
+//
+// It only goes back ROLLBACK_FRAMES. If the other side falls further behind than that (or
+// stops), this side waits for it. Both sides need the same ROM and the same settings, which
+// gets checked, as far as it can be, before anything runs.
+//
+// Every packet carries the sender's keypad for its last few frames, so a lost packet doesn't
+// matter as long as the next one gets through:
+//
+//   "C8NP", session (8 bytes, the same on both sides), first frame (4), count (1), then count
+//   keypads (2 bytes each, bit N for key N), all little endian
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::cpu::Cpu;
+use crate::save_state::SaveState;
+
+/// How many frames back a late input can still be fixed up.
+pub const ROLLBACK_FRAMES: u32 = 8;
+/// Frames of input delay, unless Netplay::connect() is given something else.
+pub const DEFAULT_INPUT_DELAY: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"C8NP";
+// How many frames of input each packet repeats
+const REDUNDANCY: u32 = 8;
+const HEADER_LEN: usize = 4 + 8 + 4 + 1;
+
+/// A netplay session with one other player, see the top of this file.
+pub struct Netplay {
+    socket: UdpSocket,
+    session: u64,
+    delay: u32,
+    // the next frame to run
+    frame: u32,
+    // this side's keypad for each frame, including the ones input delay is holding back
+    local: BTreeMap<u32, u16>,
+    // the other side's keypad, for the frames it's sent
+    remote: BTreeMap<u32, u16>,
+    // the first frame the other side's keypad hasn't arrived for
+    confirmed: u32,
+    // what the other side's keypad was guessed to be, for frames run before it arrived
+    guessed: BTreeMap<u32, u16>,
+    // the state at the start of each frame that might still need running again
+    snapshots: VecDeque<(u32, SaveState)>,
+    rollbacks: u64,
+}
+
+impl Netplay {
+    /// Listen on `bind` and play with whoever's at `peer`. `cpu` has to have the ROM loaded
+    /// and not have run yet, the other side's gets checked against it.
+    pub fn connect(bind: impl ToSocketAddrs, peer: impl ToSocketAddrs, delay: u32, cpu: &Cpu) -> io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+        let peer: SocketAddr = peer.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address for the other player"))?;
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+        Ok(Netplay {
+            socket,
+            // covers the ROM, the starting state and the speed, not the quirks
+            session: cpu.state_digest() ^ cpu.clock_speed as u64,
+            delay,
+            frame: 0,
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            confirmed: 0,
+            guessed: BTreeMap::new(),
+            snapshots: VecDeque::new(),
+            rollbacks: 0,
+        })
+    }
+
+    /// The address it's listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// How many frames have run (not counting ones run again).
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// How many times a guess was wrong and frames had to be run again.
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    /// Run a frame with this side's keypad as it is now (Cpu::keypad, which it's left as).
+    /// Returns false if it had to wait for the other side instead, call it again next frame.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> io::Result<bool> {
+        let keypad = cpu.keypad;
+        self.local.entry(self.frame + self.delay).or_insert(to_bits(&keypad));
+        self.send()?;
+        if let Some(from) = self.receive()? {
+            self.rollbacks += 1;
+            let index = self.snapshots.iter().position(|(frame, _)| *frame == from).expect("snapshots go back as far as rollbacks can");
+            self.snapshots[index].1.restore(cpu).map_err(|e| io::Error::other(e.to_string()))?;
+            self.snapshots.truncate(index);
+            // it's the observers' second time through these frames, they've seen them already
+            let observers = mem::take(&mut cpu.observers);
+            for frame in from..self.frame {
+                self.simulate(cpu, frame);
+            }
+            cpu.observers = observers;
+        }
+        if self.frame >= self.confirmed + ROLLBACK_FRAMES {
+            cpu.keypad = keypad;
+            return Ok(false);
+        }
+
+        self.simulate(cpu, self.frame);
+        self.frame += 1;
+
+        // nothing before the first frame still waiting on the other side can change now (or
+        // the next frame to run, when this side's the one behind). The keypad the other side
+        // had just before that stays, it's the guess for the frames after it
+        let settled = self.confirmed.min(self.frame);
+        while self.snapshots.front().is_some_and(|(frame, _)| *frame < settled) {
+            self.snapshots.pop_front();
+        }
+        // this side's keypads are still needed for running frames again, and for sending
+        let oldest = settled.min((self.frame + self.delay).saturating_sub(REDUNDANCY));
+        self.local.retain(|&frame, _| frame >= oldest);
+        self.remote.retain(|&frame, _| frame + 1 >= settled);
+        self.guessed.retain(|&frame, _| frame >= settled);
+
+        cpu.keypad = keypad;
+        Ok(true)
+    }
+
+    // Run `frame` from the state the CPU's in, saving that first in case it has to run again
+    fn simulate(&mut self, cpu: &mut Cpu, frame: u32) {
+        self.snapshots.push_back((frame, SaveState::capture(cpu)));
+        let remote = match self.remote.get(&frame) {
+            Some(&keys) => keys,
+            None => {
+                let guess = self.remote.range(..frame).next_back().map_or(0, |(_, &keys)| keys);
+                self.guessed.insert(frame, guess);
+                guess
+            }
+        };
+        let local = self.local.get(&frame).copied().unwrap_or(0);
+        cpu.keypad = from_bits(local | remote);
+        cpu.advance_frame();
+    }
+
+    // This side's keypad for the last few frames it knows
+    fn send(&self) -> io::Result<()> {
+        let last = self.frame + self.delay;
+        let first = (last + 1).saturating_sub(REDUNDANCY);
+        let mut packet = Vec::with_capacity(HEADER_LEN + 2 * REDUNDANCY as usize);
+        packet.extend_from_slice(MAGIC);
+        packet.extend_from_slice(&self.session.to_le_bytes());
+        packet.extend_from_slice(&first.to_le_bytes());
+        packet.push((last - first + 1) as u8);
+        for frame in first..=last {
+            packet.extend_from_slice(&self.local.get(&frame).copied().unwrap_or(0).to_le_bytes());
+        }
+        match self.socket.send(&packet) {
+            Ok(_) => Ok(()),
+            // nobody listening yet, or a full buffer: the next packet has all of this in it
+            Err(e) if matches!(e.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::WouldBlock) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Everything the other side's sent, returning the first frame that ran with a wrong guess
+    fn receive(&mut self) -> io::Result<Option<u32>> {
+        let mut rollback: Option<u32> = None;
+        let mut packet = [0; 512];
+        loop {
+            let len = match self.socket.recv(&mut packet) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(rollback),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(e) => return Err(e),
+            };
+            let packet = &packet[..len];
+            if len < HEADER_LEN || &packet[..4] != MAGIC {
+                continue;
+            }
+            if u64::from_le_bytes(packet[4..12].try_into().unwrap()) != self.session {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the other side is running a different ROM, or at a different speed"));
+            }
+            let first = u32::from_le_bytes(packet[12..16].try_into().unwrap());
+            let count = packet[16] as usize;
+            let keypads = packet[HEADER_LEN..].chunks_exact(2).take(count).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+            for (frame, keys) in (first..).zip(keypads) {
+                // already had it
+                if frame < self.confirmed || self.remote.contains_key(&frame) {
+                    continue;
+                }
+                self.remote.insert(frame, keys);
+                if self.guessed.get(&frame).is_some_and(|&guess| guess != keys) {
+                    rollback = Some(rollback.map_or(frame, |from| from.min(frame)));
+                }
+            }
+            while self.remote.contains_key(&self.confirmed) {
+                self.confirmed += 1;
+            }
+        }
+    }
+}
+
+fn to_bits(keypad: &[bool; 16]) -> u16 {
+    keypad.iter().enumerate().fold(0, |bits, (key, &down)| bits | (down as u16) << key)
+}
+
+fn from_bits(bits: u16) -> [bool; 16] {
+    core::array::from_fn(|key| bits & 1 << key != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // Counts frames' worth of instructions with 1 held in V2 and with C held in V3
+    const COUNT_KEYS: [u8; 14] = [
+        0x60, 0x01, // 200 LD V0, 1
+        0x61, 0x0C, // 202 LD V1, C
+        0xE0, 0xA1, // 204 SKNP V0
+        0x72, 0x01, // 206 ADD V2, 1
+        0xE1, 0xA1, // 208 SKNP V1
+        0x73, 0x01, // 20A ADD V3, 1
+        0x12, 0x04, // 20C JP 0x204
+    ];
+
+    fn machine(rom: &[u8]) -> Cpu {
+        let mut cpu = Cpu::with_variant(Variant::Chip8);
+        cpu.load_rom(rom).unwrap();
+        cpu
+    }
+
+    // Two sides talking to each other on localhost
+    fn pair(a: &Cpu, b: &Cpu) -> (Netplay, Netplay) {
+        let free_port = || UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (addr_a, addr_b) = (free_port(), free_port());
+        let a = Netplay::connect(addr_a, addr_b, DEFAULT_INPUT_DELAY, a).unwrap();
+        let b = Netplay::connect(addr_b, addr_a, DEFAULT_INPUT_DELAY, b).unwrap();
+        (a, b)
+    }
+
+    // One side's player, holding `key` down for the frames in `held`
+    struct Side<'a> {
+        netplay: &'a mut Netplay,
+        cpu: &'a mut Cpu,
+        key: usize,
+        held: core::ops::Range<u32>,
+    }
+
+    impl Side<'_> {
+        fn turn(&mut self, frames: u32) {
+            if self.netplay.frame() < frames {
+                self.cpu.keypad = [false; 16];
+                self.cpu.keypad[self.key] = self.held.contains(&self.netplay.frame());
+                self.netplay.run_frame(self.cpu).unwrap();
+            }
+        }
+    }
+
+    // Take turns until both sides have run `frames`
+    fn play(mut a: Side, mut b: Side, frames: u32) {
+        for _ in 0..frames * 10 {
+            a.turn(frames);
+            b.turn(frames);
+            if a.netplay.frame() == frames && b.netplay.frame() == frames {
+                return;
+            }
+        }
+        panic!("stuck at frames {} and {}", a.netplay.frame(), b.netplay.frame());
+    }
+
+    #[test]
+    fn keypads_round_trip() {
+        let keypad = core::array::from_fn(|key| key % 3 == 0);
+        assert_eq!(to_bits(&keypad), 0b1001_0010_0100_1001);
+        assert_eq!(from_bits(to_bits(&keypad)), keypad);
+    }
+
+    #[test]
+    fn both_sides_run_the_same_frames() {
+        let (mut cpu_a, mut cpu_b) = (machine(&COUNT_KEYS), machine(&COUNT_KEYS));
+        let (mut a, mut b) = pair(&cpu_a, &cpu_b);
+        play(
+            Side { netplay: &mut a, cpu: &mut cpu_a, key: 0x1, held: 5..15 },
+            Side { netplay: &mut b, cpu: &mut cpu_b, key: 0xC, held: 10..20 },
+            40,
+        );
+
+        assert_eq!(cpu_a.state_digest(), cpu_b.state_digest());
+        // both keys did something, on both sides
+        assert!(cpu_a.registers[2] > 0 && cpu_a.registers[3] > 0);
+        // each side's keypad is left how it was
+        assert!(!cpu_a.keypad[0xC] && !cpu_b.keypad[0x1]);
+    }
+
+    #[test]
+    fn late_input_rolls_back() {
+        let (mut cpu_a, mut cpu_b) = (machine(&COUNT_KEYS), machine(&COUNT_KEYS));
+        let (mut a, mut b) = pair(&cpu_a, &cpu_b);
+        // player 1 gets ahead, guessing player 2 isn't pressing anything
+        for _ in 0..5 {
+            assert!(a.run_frame(&mut cpu_a).unwrap());
+        }
+        play(
+            Side { netplay: &mut a, cpu: &mut cpu_a, key: 0x1, held: 0..0 },
+            Side { netplay: &mut b, cpu: &mut cpu_b, key: 0xC, held: 0..30 },
+            20,
+        );
+
+        assert!(a.rollbacks() > 0);
+        assert_eq!(b.rollbacks(), 0);
+        assert_eq!(cpu_a.state_digest(), cpu_b.state_digest());
+    }
+
+    #[test]
+    fn waits_when_the_other_side_is_too_far_behind() {
+        let (mut cpu_a, cpu_b) = (machine(&COUNT_KEYS), machine(&COUNT_KEYS));
+        let (mut a, _b) = pair(&cpu_a, &cpu_b);
+        for _ in 0..ROLLBACK_FRAMES {
+            assert!(a.run_frame(&mut cpu_a).unwrap());
+        }
+        assert!(!a.run_frame(&mut cpu_a).unwrap());
+        assert_eq!(a.frame(), ROLLBACK_FRAMES);
+    }
+
+    #[test]
+    fn different_roms_are_caught() {
+        let (mut cpu_a, mut cpu_b) = (machine(&COUNT_KEYS), machine(&[0x12, 0x00]));
+        let (mut a, mut b) = pair(&cpu_a, &cpu_b);
+        a.run_frame(&mut cpu_a).unwrap();
+        let error = (0..100).find_map(|_| b.run_frame(&mut cpu_b).err()).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,27 @@
+// Debug overlay.
+// A few lines of the machine's state for a frontend to draw over the top left corner of the
+// game, for a quick look at what a ROM's up to without opening the debugger: F3 in chip8 run
+// turns it on and off. It's kept narrow enough to fit across the screen even with the braille
+// renderer (32 characters for a lo-res screen):
+//
+//   PC 0206  I 0210  SP 1
+//   DT 00  ST 00  700 IPS
+//   V0-7 05 00 00 00 00 00 00 00
+//   V8-F 00 00 00 00 00 00 00 01
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::cpu::Cpu;
+
+/// The overlay's lines, `ips` being how fast it's really running (see stats.rs).
+pub fn overlay_lines(cpu: &Cpu, ips: f64) -> Vec<String> {
+    let registers = |range: core::ops::Range<usize>| cpu.registers[range].iter().map(|v| format!(" {:02X}", v)).collect::<String>();
+    Vec::from([
+        format!("PC {:04X}  I {:04X}  SP {}", cpu.position_in_memory, cpu.index_register, cpu.stack_pointer),
+        format!("DT {:02X}  ST {:02X}  {:.0} IPS", cpu.delay_timer, cpu.sound_timer, ips),
+        format!("V0-7{}", registers(0..8)),
+        format!("V8-F{}", registers(8..16)),
+    ])
+}
@@ -0,0 +1,101 @@
+// Colours.
+// The display only knows colour indexes, 0 (off) and 1 (on), plus 2 and 3 with XO-CHIP's second
+// plane. A palette says what RGB each one is when the screen gets turned into an image, and every
+// frontend takes one so a ROM looks the same in all of them.
+//
+// Palettes can be written down as a preset name or as hex colours, background first:
+//   amber
+//   000000,FFFFFF
+//   000000,FFFFFF,AAAAAA,555555
+// Two colours leave the XO-CHIP ones blended between them.
+
+/// RGB for each of the four colour indexes. Everything except XO-CHIP only uses the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    /// White on black, with greys for XO-CHIP's other two colours.
+    pub const MONOCHROME: Palette =
+        Palette([[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]]);
+    /// Green phosphor, like a VT100 or an Apple II monitor.
+    pub const GREEN: Palette =
+        Palette([[0x0A, 0x1A, 0x0A], [0x33, 0xFF, 0x66], [0x22, 0xAA, 0x44], [0x11, 0x55, 0x22]]);
+    /// Amber phosphor.
+    pub const AMBER: Palette =
+        Palette([[0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00], [0xAA, 0x75, 0x00], [0x55, 0x3A, 0x00]]);
+    /// Dark ink on off-white, for screenshots that are going on a page.
+    pub const PAPER: Palette =
+        Palette([[0xF4, 0xF1, 0xE8], [0x22, 0x22, 0x22], [0x77, 0x77, 0x77], [0xBB, 0xBB, 0xBB]]);
+
+    /// The presets by name.
+    pub const PRESETS: [(&'static str, Palette); 4] =
+        [("monochrome", Palette::MONOCHROME), ("green", Palette::GREEN), ("amber", Palette::AMBER), ("paper", Palette::PAPER)];
+
+    /// Two colours, with XO-CHIP's two extra ones a third and two thirds of the way between them.
+    pub fn two_color(background: [u8; 3], foreground: [u8; 3]) -> Self {
+        let blend = |thirds: u16| {
+            core::array::from_fn(|n| ((background[n] as u16 * (3 - thirds) + foreground[n] as u16 * thirds) / 3) as u8)
+        };
+        Palette([background, foreground, blend(2), blend(1)])
+    }
+
+    /// A preset name or a list of hex colours (see the top of this file).
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if let Some(&(_, palette)) = Self::PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(text)) {
+            return Some(palette);
+        }
+
+        let mut colors = [[0; 3]; 4];
+        let mut count = 0;
+        for color in text.split(',') {
+            *colors.get_mut(count)? = parse_color(color)?;
+            count += 1;
+        }
+        match count {
+            2 => Some(Palette::two_color(colors[0], colors[1])),
+            4 => Some(Palette(colors)),
+            _ => None,
+        }
+    }
+
+    /// The RGB for a colour index, only the bottom 2 bits count.
+    pub fn rgb(&self, color: u8) -> [u8; 3] {
+        self.0[color as usize & 3]
+    }
+
+    /// Colour 0, what's behind everything.
+    pub fn background(&self) -> [u8; 3] {
+        self.0[0]
+    }
+
+    /// Colour 1, what everything but XO-CHIP draws in.
+    pub fn foreground(&self) -> [u8; 3] {
+        self.0[1]
+    }
+
+    pub fn set_background(&mut self, rgb: [u8; 3]) {
+        self.0[0] = rgb;
+    }
+
+    pub fn set_foreground(&mut self, rgb: [u8; 3]) {
+        self.0[1] = rgb;
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::MONOCHROME
+    }
+}
+
+/// One colour as six hex digits, with or without a #.
+pub fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let text = text.trim();
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |n: usize| u8::from_str_radix(&hex[n * 2..n * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
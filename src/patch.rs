@@ -0,0 +1,193 @@
+// Patches.
+// Changes to memory made right after a ROM's loaded, for fixing ROMs with known bugs or for
+// cheats (infinite lives and the like) without editing the ROM itself. A patch file has either
+// of these per line, mixed as you like:
+//
+//     2A4: 12 34 56      ADDRESS: BYTES, all hex with or without 0x, the bytes going from ADDRESS on
+//     02A412             a code: 4 hex digits of address then the byte
+//     02A41260           ...then the byte that has to be there already
+//
+// Comments start with ; or #. Addresses are memory addresses, so a ROM's first byte is at 0x200.
+// The codes are Game Genie's idea: short enough to pass around, and the optional compare byte
+// means a code written for one ROM does nothing to a different one (or a different version of
+// the same one), instead of corrupting it. `chip8 run --patch` takes a file or a single code.
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::cpu::Cpu;
+use crate::error::CpuError;
+
+/// One change to memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    /// What has to be at `addr` already for the patch to go on, from a code's compare byte
+    pub compare: Option<u8>,
+}
+
+/// A patch file line that didn't parse. `line` counts from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl core::error::Error for PatchError {}
+
+impl Patch {
+    /// Read a code, 6 or 8 hex digits (see the top of this file).
+    pub fn from_code(code: &str) -> Option<Self> {
+        if !matches!(code.len(), 6 | 8) || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digits = |range: core::ops::Range<usize>| usize::from_str_radix(&code[range], 16).ok();
+        Some(Patch {
+            addr: digits(0..4)?,
+            bytes: vec![digits(4..6)? as u8],
+            compare: if code.len() == 8 { Some(digits(6..8)? as u8) } else { None },
+        })
+    }
+
+    /// Write the bytes into `cpu`'s memory. Returns false, changing nothing, if the compare byte
+    /// doesn't match what's there.
+    pub fn apply(&self, cpu: &mut Cpu) -> Result<bool, CpuError> {
+        // an address can be anything up to usize::MAX, so adding the length can overflow
+        let end = match self.addr.checked_add(self.bytes.len()) {
+            Some(end) if end <= cpu.memory.len() => end,
+            _ => return Err(CpuError::InvalidConfig { reason: "a patch goes past the end of memory" }),
+        };
+        if self.compare.is_some_and(|compare| cpu.memory[self.addr] != compare) {
+            return Ok(false);
+        }
+        cpu.memory[self.addr..end].copy_from_slice(&self.bytes);
+        cpu.mark_initialized(self.addr, self.bytes.len());
+        cpu.flush_decoded();
+        Ok(true)
+    }
+}
+
+// The way a patch file would write it: a code when it can be one, ADDRESS: BYTES if not
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.bytes.as_slice(), self.compare) {
+            (&[byte], compare) if self.addr <= 0xFFFF => {
+                write!(f, "{:04X}{:02X}", self.addr, byte)?;
+                match compare {
+                    Some(compare) => write!(f, "{:02X}", compare),
+                    None => Ok(()),
+                }
+            }
+            (bytes, _) => {
+                let bytes: Vec<_> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                write!(f, "{:X}: {}", self.addr, bytes.join(" "))
+            }
+        }
+    }
+}
+
+/// Read a patch file, in the forms at the top of this file.
+pub fn parse(text: &str) -> Result<Vec<Patch>, PatchError> {
+    let mut patches = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split([';', '#']).next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |reason| PatchError { line: n + 1, reason };
+        let patch = match line.split_once(':') {
+            Some((addr, bytes)) => {
+                let addr = hex(addr.trim()).ok_or_else(|| error("the address should be hex"))?;
+                let bytes = bytes
+                    .split_whitespace()
+                    .map(|byte| hex(byte).filter(|&byte| byte <= 0xFF).map(|byte| byte as u8))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| error("the bytes should be hex, 00 to FF, with spaces between"))?;
+                if bytes.is_empty() {
+                    return Err(error("missing the bytes after the address"));
+                }
+                Patch { addr, bytes, compare: None }
+            }
+            None => Patch::from_code(line).ok_or_else(|| error("expected ADDRESS: BYTES or a 6 or 8 digit code"))?,
+        };
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+/// Read a patch file from disk.
+#[cfg(feature = "std")]
+pub fn load(path: &std::path::Path) -> std::io::Result<Vec<Patch>> {
+    let text = std::fs::read_to_string(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    parse(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+// Hex with or without 0x
+fn hex(text: &str) -> Option<usize> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_both_forms() {
+        let patches = parse("; infinite lives\n2A4: 12 0x34 56   # a jump\n\n02A412\n02A41260\n").unwrap();
+        assert_eq!(
+            patches,
+            [
+                Patch { addr: 0x2A4, bytes: vec![0x12, 0x34, 0x56], compare: None },
+                Patch { addr: 0x2A4, bytes: vec![0x12], compare: None },
+                Patch { addr: 0x2A4, bytes: vec![0x12], compare: Some(0x60) },
+            ]
+        );
+        // and back again
+        let lines: Vec<_> = patches.iter().map(|patch| patch.to_string()).collect();
+        assert_eq!(lines, ["2A4: 12 34 56", "02A412", "02A41260"]);
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(error("02A412\nzz: 00").line, 2);
+        assert_eq!(error("zz: 00").reason, "the address should be hex");
+        assert_eq!(error("2A4: 100").reason, "the bytes should be hex, 00 to FF, with spaces between");
+        assert_eq!(error("2A4:").reason, "missing the bytes after the address");
+        assert_eq!(error("02A4").reason, "expected ADDRESS: BYTES or a 6 or 8 digit code");
+        assert_eq!(error("02A4120").reason, "expected ADDRESS: BYTES or a 6 or 8 digit code");
+        assert_eq!(Patch::from_code("02A41G"), None);
+    }
+
+    #[test]
+    fn compare_byte_has_to_match() {
+        let mut cpu = Cpu::new(Default::default());
+        cpu.memory[0x2A4] = 0x60;
+        assert_eq!(Patch::from_code("02A41261").unwrap().apply(&mut cpu), Ok(false));
+        assert_eq!(cpu.memory[0x2A4], 0x60);
+        assert_eq!(Patch::from_code("02A41260").unwrap().apply(&mut cpu), Ok(true));
+        assert_eq!(cpu.memory[0x2A4], 0x12);
+    }
+
+    #[test]
+    fn past_the_end_of_memory() {
+        let mut cpu = Cpu::new(Default::default());
+        let end = cpu.memory.len();
+        assert!(Patch { addr: end - 2, bytes: vec![1, 2], compare: None }.apply(&mut cpu).is_ok());
+        assert!(Patch { addr: end - 1, bytes: vec![1, 2], compare: None }.apply(&mut cpu).is_err());
+        // an address so big the end wraps round
+        let patch = Patch { addr: usize::MAX, bytes: vec![0], compare: None };
+        assert!(matches!(patch.apply(&mut cpu), Err(CpuError::InvalidConfig { .. })));
+    }
+}
@@ -0,0 +1,158 @@
+// Plugins.
+// Renderers, input devices and analysis passes can ship as shared libraries of their own
+// instead of patches to this crate. A plugin is a cdylib crate depending on this one, with a
+// type implementing Plugin and one line to export it:
+//
+//   struct LedMatrix { .. }
+//   impl Plugin for LedMatrix {
+//       fn name(&self) -> &str { "led-matrix" }
+//       fn display(&mut self) -> Option<Box<dyn DisplaySink>> { .. }
+//   }
+//   chip_8_emulator::declare_plugin!(LedMatrix::new());
+//
+// `chip8 run` loads every library in the plugins directory next to config.toml (see config.rs)
+// when it starts, plus any given with --plugin. Trait objects only work across the boundary if
+// both sides agree on Rust's layout, which isn't stable, so a plugin has to be built with the
+// same compiler and the same version of this crate, with the same features, as the chip8
+// loading it. API_VERSION catches a plugin built against an incompatible version, nothing can
+// catch a different compiler.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libloading::{Library, Symbol};
+
+use crate::cpu::Cpu;
+use crate::frontend::{DisplaySink, InputSource};
+use crate::hooks::Observer;
+
+/// Goes up whenever Plugin (or anything it hands over) changes.
+pub const API_VERSION: u32 = 1;
+
+/// What a plugin adds to a run. Everything's optional, a plugin implements whichever parts it
+/// provides. Each part is asked for once, when the run starts.
+pub trait Plugin {
+    /// What to call it in messages.
+    fn name(&self) -> &str;
+
+    /// Something to watch the CPU, for analysis passes.
+    fn observer(&mut self) -> Option<Box<dyn Observer>> {
+        None
+    }
+
+    /// Somewhere else to show the screen, alongside the terminal.
+    fn display(&mut self) -> Option<Box<dyn DisplaySink>> {
+        None
+    }
+
+    /// Another source of key presses, alongside the keyboard.
+    fn input(&mut self) -> Option<Box<dyn InputSource>> {
+        None
+    }
+
+    /// The run's over, for reports and cleaning up.
+    fn finish(&mut self, _cpu: &Cpu) {}
+}
+
+/// Export a Plugin from a cdylib: `declare_plugin!(MyPlugin::new())`. The expression is
+/// evaluated every time the plugin is loaded.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:expr) => {
+        #[no_mangle]
+        pub extern "C" fn chip8_plugin_api_version() -> u32 {
+            $crate::plugin::API_VERSION
+        }
+
+        // Boxed twice, a Box<dyn Plugin> is two pointers and a C function can only return one
+        #[no_mangle]
+        pub extern "C" fn chip8_plugin_create() -> *mut ::std::boxed::Box<dyn $crate::plugin::Plugin> {
+            let plugin: ::std::boxed::Box<dyn $crate::plugin::Plugin> = ::std::boxed::Box::new($plugin);
+            ::std::boxed::Box::into_raw(::std::boxed::Box::new(plugin))
+        }
+    };
+}
+
+/// Why a plugin didn't load.
+#[derive(Debug)]
+pub struct PluginError {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// A plugin and the library its code lives in.
+pub struct LoadedPlugin {
+    // declared first so it's dropped before the library's unloaded
+    plugin: Box<dyn Plugin>,
+    _library: Library,
+}
+
+impl LoadedPlugin {
+    /// Load the plugin in a shared library.
+    pub fn load(path: &Path) -> Result<Self, PluginError> {
+        let error = |reason: String| PluginError { path: path.to_path_buf(), reason };
+        // Safety: loading a library runs its initialisers, anything in the plugins directory
+        // is trusted the same as the chip8 binary itself
+        let library = unsafe { Library::new(path) }.map_err(|e| error(e.to_string()))?;
+        // Safety: the symbols have the types declare_plugin!() gives them
+        let plugin = unsafe {
+            let version: Symbol<extern "C" fn() -> u32> = library
+                .get(b"chip8_plugin_api_version\0")
+                .map_err(|_| error("not a chip8 plugin (no declare_plugin!)".to_string()))?;
+            if version() != API_VERSION {
+                return Err(error(format!("built for plugin API {}, this is {}", version(), API_VERSION)));
+            }
+            let create: Symbol<extern "C" fn() -> *mut Box<dyn Plugin>> =
+                library.get(b"chip8_plugin_create\0").map_err(|e| error(e.to_string()))?;
+            *Box::from_raw(create())
+        };
+        Ok(LoadedPlugin { plugin, _library: library })
+    }
+}
+
+impl std::ops::Deref for LoadedPlugin {
+    type Target = dyn Plugin;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.plugin
+    }
+}
+
+impl std::ops::DerefMut for LoadedPlugin {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.plugin
+    }
+}
+
+/// Every shared library (.so, .dylib or .dll) in `dir`, in name order. A directory that
+/// isn't there just has no plugins in it.
+pub fn discover(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// The plugins directory, next to config.toml.
+pub fn plugin_dir() -> Option<PathBuf> {
+    Some(crate::config::config_dir()?.join("plugins"))
+}
@@ -0,0 +1,229 @@
+// Profiler.
+// `chip8 run --profile` counts every instruction and times it, then when the run ends prints
+// where the time went, block by block (basic blocks from flow_graph.rs), with the hottest
+// blocks disassembled, and the loops that went round the most.
+//
+// That's useful from both sides: for a ROM it points at the loop worth tightening, and for the
+// interpreter the time per kind of instruction shows which ones are slow to emulate. The timing
+// is wall clock time around each instruction, so the profiler's own overhead is in every number.
+// Compare them with each other rather than reading them as how fast the emulator really runs.
+//
+// A loop is any backwards jump or skip (anything but RET that leaves PC at or before where it
+// was), from the jump back to where it lands. Its instruction count only includes what's inside
+// that range, not the subroutines it calls.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::cpu::Cpu;
+use crate::disasm::disassemble_at;
+use crate::flow_graph::FlowGraph;
+use crate::hooks::Observer;
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+
+/// Instruction counts and times per address. Add it as an observer (hooks.rs), then report().
+#[derive(Debug, Default)]
+pub struct Profiler {
+    // indexed by address, grown as higher addresses run
+    counts: Vec<u64>,
+    nanos: Vec<u64>,
+    // (the jump back, where it went) to how many times
+    back_edges: BTreeMap<(usize, usize), u64>,
+    // when the instruction that's running started
+    started: Option<Instant>,
+    // the first and last instruction, for the total time
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+/// A basic block's totals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockProfile {
+    pub start: usize,
+    /// Instructions run in the block, counting each time round
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+/// A loop's totals, see the top of this file for what counts as one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    /// Where the loop starts (where the jump back goes to)
+    pub start: usize,
+    /// The jump back
+    pub end: usize,
+    /// How many times it went round
+    pub iterations: u64,
+    /// Instructions run between start and end
+    pub instructions: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// How many instructions have run.
+    pub fn total_instructions(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Wall clock time from the first instruction to the last.
+    pub fn elapsed(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Every block that ran, hottest (most instructions) first. The blocks come from the flow
+    /// graph of `cpu`'s memory, anything that ran outside of it (reached by BNNN, say) gets a
+    /// block per address.
+    pub fn blocks(&self, cpu: &Cpu) -> Vec<BlockProfile> {
+        self.blocks_in(&FlowGraph::build(&cpu.memory, cpu.variant))
+    }
+
+    fn blocks_in(&self, graph: &FlowGraph) -> Vec<BlockProfile> {
+        let mut blocks: BTreeMap<usize, BlockProfile> = BTreeMap::new();
+        let mut owner = BTreeMap::new();
+        for block in graph.blocks.values() {
+            for &(addr, _) in &block.instructions {
+                owner.insert(addr, block.start);
+            }
+        }
+        for (addr, &count) in self.counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let start = owner.get(&addr).copied().unwrap_or(addr);
+            let block = blocks.entry(start).or_insert(BlockProfile { start, instructions: 0, time: Duration::ZERO });
+            block.instructions += count;
+            block.time += Duration::from_nanos(self.nanos[addr]);
+        }
+        let mut blocks: Vec<BlockProfile> = blocks.into_values().collect();
+        blocks.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        blocks
+    }
+
+    /// Every loop that went round, most instructions first.
+    pub fn loops(&self) -> Vec<LoopProfile> {
+        let mut loops: Vec<LoopProfile> = self
+            .back_edges
+            .iter()
+            .map(|(&(end, start), &iterations)| {
+                let inside = self.counts.get(start..=end).unwrap_or(&[]);
+                LoopProfile { start, end, iterations, instructions: inside.iter().sum() }
+            })
+            .collect();
+        loops.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    /// Total time per kind of instruction (CLS, Draw, LoadIndex, ...), slowest on average first,
+    /// as (name, count, total time).
+    pub fn instruction_kinds(&self, cpu: &Cpu) -> Vec<(String, u64, Duration)> {
+        let mut kinds: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for (addr, &count) in self.counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let instruction = Instruction::decode_at(&cpu.memory, addr, cpu.variant);
+            // the variant's name without its operands
+            let name = format!("{:?}", instruction).split('(').next().unwrap_or("").to_string();
+            let kind = kinds.entry(name).or_default();
+            kind.0 += count;
+            kind.1 += self.nanos[addr];
+        }
+        let mut kinds: Vec<(String, u64, Duration)> =
+            kinds.into_iter().map(|(name, (count, nanos))| (name, count, Duration::from_nanos(nanos))).collect();
+        kinds.sort_by_key(|&(_, count, time)| std::cmp::Reverse(time.as_nanos() / count as u128));
+        kinds
+    }
+
+    /// The report `chip8 run --profile` prints: the `top` hottest blocks with their disassembly,
+    /// the hottest loops and the time per kind of instruction.
+    pub fn report(&self, cpu: &Cpu, symbols: &Symbols, top: usize) -> String {
+        let mut report = String::new();
+        let total = self.total_instructions();
+        let elapsed = self.elapsed();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        let name = |addr: usize| match symbols.name(addr) {
+            Some(name) => format!("0x{:03X} ({})", addr, name),
+            None => format!("0x{:03X}", addr),
+        };
+        let _ = writeln!(
+            report,
+            "{} instructions in {:.2?} ({:.1} million a second, profiling included)",
+            total,
+            elapsed,
+            total as f64 / elapsed.as_secs_f64().max(f64::EPSILON) / 1e6
+        );
+
+        let graph = FlowGraph::build(&cpu.memory, cpu.variant);
+        let _ = writeln!(report, "\nhottest blocks:");
+        for block in self.blocks_in(&graph).iter().take(top) {
+            let _ = writeln!(
+                report,
+                "  {:<20} {:>12} instructions {:5.1}%  {:>10.2?}",
+                name(block.start),
+                block.instructions,
+                percent(block.instructions),
+                block.time
+            );
+            // blocks that aren't in the graph are one instruction long
+            let addrs: Vec<usize> = match graph.blocks.get(&block.start) {
+                Some(graph_block) => graph_block.instructions.iter().map(|&(addr, _)| addr).collect(),
+                None => vec![block.start],
+            };
+            for addr in addrs {
+                let instruction = disassemble_at(&cpu.memory, addr, cpu.variant, &cpu.quirks()).with_symbols(symbols);
+                let count = self.counts.get(addr).copied().unwrap_or(0);
+                let _ = writeln!(report, "      {:03X}  {:04X}  {:<20} {:>10}", addr, instruction.opcode, instruction.text, count);
+            }
+        }
+
+        let loops = self.loops();
+        if !loops.is_empty() {
+            let _ = writeln!(report, "\nhottest loops:");
+            for hot in loops.iter().take(top) {
+                let _ = writeln!(
+                    report,
+                    "  {} to 0x{:03X}  {:>10} times round {:>12} instructions {:5.1}%",
+                    name(hot.start),
+                    hot.end,
+                    hot.iterations,
+                    hot.instructions,
+                    percent(hot.instructions)
+                );
+            }
+        }
+
+        let _ = writeln!(report, "\ntime per instruction (average, times run):");
+        for (kind, count, time) in self.instruction_kinds(cpu) {
+            let _ = writeln!(report, "  {:<24} {:>8} ns {:>12}", kind, time.as_nanos() / count as u128, count);
+        }
+        report
+    }
+}
+
+impl Observer for Profiler {
+    fn before_instruction(&mut self, _cpu: &Cpu, _pc: usize) {
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.started = Some(now);
+    }
+
+    fn after_instruction(&mut self, cpu: &Cpu, pc: usize) {
+        let now = Instant::now();
+        self.last = Some(now);
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+            self.nanos.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+        if let Some(started) = self.started.take() {
+            self.nanos[pc] += (now - started).as_nanos() as u64;
+        }
+
+        let next = cpu.position_in_memory;
+        if next <= pc && Instruction::decode_at(&cpu.memory, pc, cpu.variant) != Instruction::Return {
+            *self.back_edges.entry((pc, next)).or_default() += 1;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// Python bindings.
+// Exposes the core as a `chip8` Python module so it can be driven from scripts and notebooks:
+//
+//   import chip8
+//   cpu = chip8.Cpu("chip8")
+//   cpu.load_rom(open("ibm.ch8", "rb").read())
+//   cpu.run_for(1000)
+//   screen = cpu.framebuffer()   # bytes, cpu.width * cpu.height, row by row
+//
+// Build with maturin (see pyproject.toml), or by hand:
+//   cargo rustc --lib --release --no-default-features --features python --crate-type cdylib
+// and copy the library to chip8.so / chip8.pyd.
+
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::cpu::Cpu;
+use crate::variant::Variant;
+
+/// A CHIP-8 machine.
+#[pyclass(name = "Cpu")]
+pub struct PyCpu {
+    cpu: Cpu,
+}
+
+#[pymethods]
+impl PyCpu {
+    /// variant is "chip8", "schip", "xochip", "chip8x", "megachip", "eti660" or "chip8hires"
+    #[new]
+    #[pyo3(signature = (variant = "chip8"))]
+    fn new(variant: &str) -> PyResult<Self> {
+        let variant = match variant {
+            "chip8" => Variant::Chip8,
+            "schip" => Variant::SuperChip,
+            "xochip" => Variant::XoChip,
+            "chip8x" => Variant::Chip8X,
+            "megachip" => Variant::MegaChip,
+            "eti660" => Variant::Eti660,
+            "chip8hires" => Variant::Chip8Hires,
+            other => return Err(PyValueError::new_err(format!("unknown variant {:?}", other))),
+        };
+        Ok(PyCpu { cpu: Cpu::with_variant(variant) })
+    }
+
+    /// Copy a ROM into memory at 0x200 and point the program counter at it.
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        self.cpu.load_rom(rom).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Execute a single instruction.
+    fn step(&mut self) {
+        self.cpu.step();
+    }
+
+    /// Run one 60Hz frame: the frame's share of instructions then a timer tick.
+    fn run_frame(&mut self) {
+        self.cpu.run_frame();
+    }
+
+    /// Run up to `cycles` instructions without waiting, returns how many ran.
+    fn run_for(&mut self, cycles: u64) -> u64 {
+        self.cpu.run_for(cycles)
+    }
+
+    fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    /// Press or release keypad key 0x0 to 0xF.
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        self.cpu.set_key(key, pressed);
+    }
+
+    /// The screen as bytes, one per pixel row by row. 0 is unlit, XO-CHIP uses 0 to 3.
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let pixels: Vec<u8> = self.cpu.display.rows().flatten().copied().collect();
+        PyBytes::new(py, &pixels)
+    }
+
+    /// `length` bytes of memory starting at `address`.
+    fn read_memory<'py>(&self, py: Python<'py>, address: usize, length: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = self
+            .cpu
+            .memory
+            .get(address..address + length)
+            .ok_or_else(|| PyIndexError::new_err("memory range out of bounds"))?;
+        Ok(PyBytes::new(py, bytes))
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.cpu.display.width()
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.cpu.display.height()
+    }
+
+    /// V0 to VF, as bytes
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.cpu.registers.to_vec()
+    }
+
+    #[getter]
+    fn pc(&self) -> usize {
+        self.cpu.position_in_memory
+    }
+
+    #[getter]
+    fn i(&self) -> u32 {
+        self.cpu.index_register
+    }
+
+    #[getter]
+    fn sp(&self) -> usize {
+        self.cpu.stack_pointer
+    }
+
+    #[getter]
+    fn delay_timer(&self) -> u8 {
+        self.cpu.delay_timer
+    }
+
+    #[getter]
+    fn sound_timer(&self) -> u8 {
+        self.cpu.sound_timer
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    #[getter]
+    fn clock_speed(&self) -> u32 {
+        self.cpu.clock_speed
+    }
+
+    #[setter]
+    fn set_clock_speed(&mut self, instructions_per_second: u32) {
+        self.cpu.clock_speed = instructions_per_second;
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "chip8")]
+fn chip8_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyCpu>()?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+// Quirks.
+// CHIP-8 was never one machine, it was an interpreter written for the COSMAC VIP in 1977
+// and then re-written (slightly differently) for the HP-48 calculators (CHIP-48, SUPER-CHIP)
+// and later PCs. A handful of opcodes ended up behaving differently depending on which
+// interpreter a ROM was written against, so we keep those differences in one place here
+// and let the opcode handlers consult them instead of hard-coding one interpretation.
+//
+// CHIP-48 and SUPER-CHIP get told apart here even though they ran on the same calculators:
+// CHIP-48 still moved I along after FX55/FX65 (one short of where the VIP left it, see
+// load_store_index_short below), SUPER-CHIP 1.1 stopped moving it at all.
+
+/// Behavioural differences between CHIP-8 interpreters.
+/// `Quirks::default()` is the original COSMAC VIP behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place and ignore VY (CHIP-48/SCHIP).
+    /// When false VY is shifted and the result stored in VX (COSMAC VIP).
+    pub shift_vx_in_place: bool,
+    /// FX55/FX65 leave I pointing past the last register stored/loaded (COSMAC VIP).
+    /// When false I is left unchanged (SCHIP).
+    pub load_store_increments_index: bool,
+    /// When FX55/FX65 move I, it goes on by X instead of X + 1, ending up on the last register
+    /// rather than past it (CHIP-48). Does nothing without load_store_increments_index.
+    pub load_store_index_short: bool,
+    /// BNNN is read as BXNN and jumps to XNN + VX (CHIP-48/SCHIP).
+    /// When false it jumps to NNN + V0 (COSMAC VIP).
+    pub jump_offset_uses_vx: bool,
+    /// DXYN waits for the vertical blank, so at most one sprite is drawn per 60Hz frame (COSMAC VIP).
+    /// When false draws happen immediately.
+    pub display_wait: bool,
+    /// Sprites drawn past the edge of the screen wrap around to the opposite side.
+    /// When false they're clipped, which is what nearly every interpreter does.
+    pub wrap_sprites: bool,
+    /// FX1E sets VF to 1 when I + VX goes past 0xFFF and to 0 when it doesn't (the Amiga
+    /// interpreter, which Spacefight 2091! relies on). When false VF is left alone.
+    pub index_overflow_sets_vf: bool,
+    /// FX0A doesn't finish until the key's been let go, and the tone plays while it's held
+    /// (COSMAC VIP). When false it finishes as soon as a key is down.
+    pub wait_for_key_release: bool,
+}
+
+impl Quirks {
+    /// The original interpreter, most pre-1990 ROMs expect this.
+    pub const fn cosmac_vip() -> Self {
+        Quirks {
+            shift_vx_in_place: false,
+            load_store_increments_index: true,
+            load_store_index_short: false,
+            jump_offset_uses_vx: false,
+            display_wait: true,
+            wrap_sprites: false,
+            index_overflow_sets_vf: false,
+            wait_for_key_release: true,
+        }
+    }
+
+    /// CHIP-48, the first HP-48 interpreter, which ROMs written on the calculators expect.
+    /// Pair it with clock::CHIP48_CLOCK_SPEED for its timing.
+    pub const fn chip48() -> Self {
+        Quirks {
+            shift_vx_in_place: true,
+            load_store_increments_index: true,
+            load_store_index_short: true,
+            jump_offset_uses_vx: true,
+            display_wait: false,
+            wrap_sprites: false,
+            index_overflow_sets_vf: false,
+            wait_for_key_release: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1 (and most "modern" emulators that copied it).
+    pub const fn superchip() -> Self {
+        Quirks {
+            shift_vx_in_place: true,
+            load_store_increments_index: false,
+            load_store_index_short: false,
+            jump_offset_uses_vx: true,
+            display_wait: false,
+            wrap_sprites: false,
+            index_overflow_sets_vf: false,
+            wait_for_key_release: false,
+        }
+    }
+
+    /// XO-CHIP, as defined by Octo. Mostly the VIP behaviour, but sprites wrap and draws don't wait.
+    pub const fn xo_chip() -> Self {
+        Quirks {
+            shift_vx_in_place: false,
+            load_store_increments_index: true,
+            load_store_index_short: false,
+            jump_offset_uses_vx: false,
+            display_wait: false,
+            wrap_sprites: true,
+            index_overflow_sets_vf: false,
+            wait_for_key_release: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::cosmac_vip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_disagree_where_the_interpreters_did() {
+        assert_eq!(Quirks::default(), Quirks::cosmac_vip());
+        let (vip, chip48, schip, xo) = (Quirks::cosmac_vip(), Quirks::chip48(), Quirks::superchip(), Quirks::xo_chip());
+        assert!(!vip.shift_vx_in_place && chip48.shift_vx_in_place && schip.shift_vx_in_place && !xo.shift_vx_in_place);
+        assert!(vip.load_store_increments_index && chip48.load_store_index_short && !schip.load_store_increments_index);
+        assert!(!vip.jump_offset_uses_vx && chip48.jump_offset_uses_vx && schip.jump_offset_uses_vx);
+        assert!(vip.display_wait && !chip48.display_wait && !schip.display_wait && !xo.display_wait);
+        assert!(xo.wrap_sprites && !vip.wrap_sprites);
+        assert!(vip.wait_for_key_release && !schip.wait_for_key_release);
+        // only ever turned on by hand or the ROM database
+        assert!([vip, chip48, schip, xo].iter().all(|quirks| !quirks.index_overflow_sets_vf));
+    }
+}
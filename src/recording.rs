@@ -0,0 +1,90 @@
+// GIF recording.
+// Captures the screen once per 60Hz frame and writes it out as an animated GIF. The display's
+// colour indexes go straight into the GIF's 4 colour palette, so there's no quantising, and a
+// frame that's the same as the last one just makes the last one stay up longer.
+//
+// GIF delays are in hundredths of a second, which 60Hz doesn't divide into. Each frame's delay
+// is worked out from when it ends rather than added up, so the rounding never drifts.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::display::Display;
+use crate::palette::Palette;
+
+// frames captured per second, the timer rate
+const FRAME_RATE: u64 = 60;
+
+/// Records the screen to an animated GIF, one capture() per frame.
+pub struct GifRecorder {
+    encoder: Encoder<BufWriter<File>>,
+    // the size of the GIF, fixed by the screen when recording started
+    width: u16,
+    height: u16,
+    // the last frame captured, held back until it's known how long it stays up
+    pending: Option<Vec<u8>>,
+    // frames captured so far, and the time written out so far in hundredths of a second
+    frames: u64,
+    written: u64,
+}
+
+impl GifRecorder {
+    /// Start recording to `path`, with each pixel of the current screen `scale` x `scale`
+    /// pixels in the GIF. A screen that changes resolution partway is stretched to fit.
+    pub fn create(path: impl AsRef<Path>, display: &Display, scale: u32, palette: &Palette) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let size = |pixels: usize| {
+            u16::try_from(pixels * scale).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too big for a GIF"))
+        };
+        let (width, height) = (size(display.width())?, size(display.height())?);
+
+        let colors: Vec<u8> = (0..4).flat_map(|color| palette.rgb(color)).collect();
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, width, height, &colors).map_err(io::Error::other)?;
+        encoder.set_repeat(Repeat::Infinite).map_err(io::Error::other)?;
+        Ok(GifRecorder { encoder, width, height, pending: None, frames: 0, written: 0 })
+    }
+
+    /// Add the screen as it is now, call once per frame.
+    pub fn capture(&mut self, display: &Display) -> io::Result<()> {
+        let image = self.scale(display);
+        if self.pending.as_ref() != Some(&image) {
+            self.write_pending()?;
+            self.pending = Some(image);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Write out the last frame and close the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_pending()?;
+        let mut file = self.encoder.into_inner().map_err(io::Error::other)?;
+        file.flush()
+    }
+
+    // Colour indexes for the whole GIF, each one from the pixel it lands on in the screen
+    fn scale(&self, display: &Display) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut image = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let screen_y = y * display.height() / height;
+            image.extend((0..width).map(|x| display.pixel_color(x * display.width() / width, screen_y)));
+        }
+        image
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let Some(image) = self.pending.take() else {
+            return Ok(());
+        };
+        let end = (self.frames * 100 + FRAME_RATE / 2) / FRAME_RATE;
+        let mut frame = Frame::from_indexed_pixels(self.width, self.height, image, None);
+        frame.delay = (end - self.written).min(u16::MAX as u64) as u16;
+        self.written = end;
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
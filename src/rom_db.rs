@@ -0,0 +1,208 @@
+// ROM database.
+// Plenty of ROMs only work with the right quirks or speed, and there's nothing in a ROM that
+// says which. So known ROMs are recognised by their SHA-1 and get the settings they need without
+// anyone having to pass flags. The list is a text file, one ROM per line:
+//
+//     # sha1                                     variant  settings           # title
+//     0123456789abcdef0123456789abcdef01234567   schip    ips=1000 jump=off  # Some Game
+//
+// The variant comes first (chip8, schip, xochip, chip8x, megachip, eti660 or chip8hires) and
+// sets the quirks to that machine's usual ones. After it, any of:
+//   ips=N                              instructions per second
+//   quirks=vip|chip48|schip|xochip     a preset instead of the variant's quirks
+//   shift= load-store= jump= vblank= clip=   on or off, one quirk on top of those
+// The quirks are Octo's switches, the same as the CHIP-8 Archive's (see src/metadata.rs): on
+// means 8XY6 shifts VX in place, FX55/FX65 leave I alone, BNNN is BXNN, draws wait for the
+// vertical blank and sprites are clipped.
+//   index-overflow=on|off              whether FX1E sets VF when I goes past 0xFFF
+//   key-release=on|off                 whether FX0A waits for the key to be let go
+// The comment at the end of the line is the ROM's title, for `chip8 info`.
+//
+// One list is built in (src/rom_db.txt). A roms.txt in the config directory is read on top of
+// it, and its lines win, so that's the place for corrections and ROMs the built in one hasn't
+// got. `chip8 info` prints a ROM's SHA-1 to put there.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+use crate::quirks::Quirks;
+use crate::sha1::{from_hex, sha1};
+use crate::variant::Variant;
+
+const BUILT_IN: &str = include_str!("rom_db.txt");
+
+/// The file in the config directory with the user's own entries.
+pub const USER_FILE: &str = "roms.txt";
+
+/// The settings a known ROM needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownRom {
+    pub title: Option<String>,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    /// Instructions per second, None for the usual speed
+    pub clock_speed: Option<u32>,
+}
+
+/// ROM settings by SHA-1.
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    roms: BTreeMap<[u8; 20], KnownRom>,
+}
+
+impl RomDatabase {
+    /// The built in list.
+    pub fn built_in() -> Self {
+        RomDatabase::parse(BUILT_IN, "rom_db.txt").expect("the built in ROM database should parse")
+    }
+
+    /// The built in list with the user's roms.txt on top, if there is one.
+    pub fn load_default() -> io::Result<Self> {
+        let mut database = RomDatabase::built_in();
+        if let Some(path) = user_path().filter(|path| path.exists()) {
+            database.extend(RomDatabase::load(&path)?);
+        }
+        Ok(database)
+    }
+
+    /// Read a list from a file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        RomDatabase::parse(&text, &path.display().to_string())
+    }
+
+    /// Read a list in the format at the top of this file. `source` names it in errors.
+    pub fn parse(text: &str, source: &str) -> io::Result<Self> {
+        let mut roms = BTreeMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let (line, title) = line.split_once('#').unwrap_or((line, ""));
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = |what: &str| {
+                let message = format!("{} line {}: {}", source, n + 1, what);
+                io::Error::new(io::ErrorKind::InvalidData, message)
+            };
+
+            let mut fields = line.split_whitespace();
+            let hash = fields.next().and_then(from_hex).ok_or_else(|| bad_line("expected a SHA-1, 40 hex digits"))?;
+            let variant = fields.next().ok_or_else(|| bad_line("missing variant"))?;
+            let variant = Variant::from_name(variant).ok_or_else(|| bad_line("variant should be chip8, schip, xochip, chip8x, megachip, eti660 or chip8hires"))?;
+            let title = Some(title.trim()).filter(|title| !title.is_empty()).map(String::from);
+            let mut rom = KnownRom { title, variant, quirks: variant.default_quirks(), clock_speed: None };
+            for setting in fields {
+                apply_setting(&mut rom, setting).map_err(|what| bad_line(&format!("{}: {}", setting, what)))?;
+            }
+            roms.insert(hash, rom);
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    /// Add another list's ROMs, replacing any already here.
+    pub fn extend(&mut self, other: RomDatabase) {
+        self.roms.extend(other.roms);
+    }
+
+    /// What's known about a ROM, by its contents.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&KnownRom> {
+        self.roms.get(&sha1(rom))
+    }
+
+    /// How many ROMs are in the list.
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+/// Where the user's roms.txt goes, whether or not it's there.
+pub fn user_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(USER_FILE))
+}
+
+fn apply_setting(rom: &mut KnownRom, setting: &str) -> Result<(), &'static str> {
+    let (name, value) = setting.split_once('=').ok_or("settings look like NAME=VALUE")?;
+    let on = || match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("should be on or off"),
+    };
+    match name {
+        "ips" => rom.clock_speed = Some(value.parse().map_err(|_| "isn't a number")?),
+        "quirks" => {
+            rom.quirks = match value {
+                "vip" => Quirks::cosmac_vip(),
+                "chip48" => Quirks::chip48(),
+                "schip" => Quirks::superchip(),
+                "xochip" => Quirks::xo_chip(),
+                _ => return Err("should be vip, chip48, schip or xochip"),
+            }
+        }
+        "shift" => rom.quirks.shift_vx_in_place = on()?,
+        "load-store" => rom.quirks.load_store_increments_index = !on()?,
+        "jump" => rom.quirks.jump_offset_uses_vx = on()?,
+        "vblank" => rom.quirks.display_wait = on()?,
+        "clip" => rom.quirks.wrap_sprites = !on()?,
+        "index-overflow" => rom.quirks.index_overflow_sets_vf = on()?,
+        "key-release" => rom.quirks.wait_for_key_release = on()?,
+        _ => return Err("unknown setting"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha1::to_hex;
+
+    // not a real ROM, just something with a hash
+    const ROM: &[u8] = &[0x00, 0xE0, 0x12, 0x02];
+
+    #[test]
+    fn known_roms_get_their_own_quirks() {
+        let line = format!("{}   schip    ips=30 jump=off index-overflow=on  # A Test\n", to_hex(&sha1(ROM)));
+        let mut database = RomDatabase::built_in();
+        database.extend(RomDatabase::parse(&line, "roms.txt").unwrap());
+        let known = database.lookup(ROM).unwrap();
+        assert_eq!((known.title.as_deref(), known.variant, known.clock_speed), (Some("A Test"), Variant::SuperChip, Some(30)));
+        // the variant's quirks with the line's on top, not the defaults
+        assert_ne!(known.quirks, Quirks::default());
+        assert_eq!(known.quirks, Quirks { jump_offset_uses_vx: false, index_overflow_sets_vf: true, ..Quirks::superchip() });
+    }
+
+    #[test]
+    fn later_lists_win() {
+        let hash = to_hex(&sha1(ROM));
+        let mut database = RomDatabase::parse(&format!("{} chip8 ips=600\n", hash), "a").unwrap();
+        database.extend(RomDatabase::parse(&format!("{} chip8 quirks=chip48\n", hash), "b").unwrap());
+        let known = database.lookup(ROM).unwrap();
+        assert_eq!((known.quirks, known.clock_speed), (Quirks::chip48(), None));
+    }
+
+    #[test]
+    fn built_in_roms_are_known() {
+        let database = RomDatabase::built_in();
+        let known = database.lookup(include_bytes!("../roms/ibm-logo.ch8")).unwrap();
+        assert_eq!((known.title.as_deref(), known.variant), (Some("IBM Logo"), Variant::Chip8));
+        assert!(database.lookup(ROM).is_none());
+    }
+
+    #[test]
+    fn bad_lines() {
+        let hash = to_hex(&sha1(ROM));
+        let error = |line: String| RomDatabase::parse(&format!("# a comment\n{}", line), "roms.txt").unwrap_err().to_string();
+        assert_eq!(error("abc chip8".into()), "roms.txt line 2: expected a SHA-1, 40 hex digits");
+        assert!(error(hash.clone()).ends_with("missing variant"));
+        assert!(error(format!("{} chip9", hash)).contains("variant should be"));
+        assert_eq!(error(format!("{} chip8 jump=yes", hash)), "roms.txt line 2: jump=yes: should be on or off");
+        assert_eq!(error(format!("{} chip8 speed=9", hash)), "roms.txt line 2: speed=9: unknown setting");
+        assert_eq!(error(format!("{} chip8 ips", hash)), "roms.txt line 2: ips: settings look like NAME=VALUE");
+    }
+}
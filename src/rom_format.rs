@@ -0,0 +1,290 @@
+// ROM file formats.
+// Most ROMs are plain binaries, but plenty of old listings (magazine type-ins, forum posts,
+// EPROM dumps) went round as text instead, and those get turned into bytes here:
+//
+//   Intel HEX, the EPROM programmer format: lines like ":0A020000...CC" with a byte count, an
+//   address, a record type, the data and a checksum. If every address is 0x200 or above
+//   they're taken as where the bytes go in memory, otherwise as offsets into the ROM. Gaps
+//   are filled with zeroes.
+//
+//   Hex text, the bytes written out in hex, how listings usually print them:
+//       0200: 00 E0 A2 2A   ; comments after ; # or //
+//       0204: 600C6108
+//   Spacing doesn't matter and bytes can be run together or written 0x00. Anything ending in
+//   a colon is an address column and skipped, the bytes just follow on from each other.
+//
+// Nothing's ever guessed from the contents, a binary that happened to look like hex would
+// load as garbage. From a file, read() goes by the extension: .hex, .ihx and .ihex are Intel
+// HEX, .txt is hex text and anything else is a binary. `chip8 --rom-format` and
+// Cpu::load_rom_as() say which it is outright.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// What a ROM file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    Binary,
+    IntelHex,
+    HexText,
+}
+
+/// Why a text ROM didn't parse. `line` counts from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomFormatError {
+    pub format: RomFormat,
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for RomFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            RomFormat::Binary => "binary",
+            RomFormat::IntelHex => "Intel HEX",
+            RomFormat::HexText => "hex text",
+        };
+        write!(f, "{} line {}: {}", format, self.line, self.reason)
+    }
+}
+
+impl core::error::Error for RomFormatError {}
+
+impl RomFormat {
+    /// The format a ROM file extension means, None for ones that aren't ROM extensions.
+    pub fn from_extension(extension: &str) -> Option<RomFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ch8" | "c8" | "sc8" | "xo8" | "c8x" | "mc8" | "bin" | "rom" => Some(RomFormat::Binary),
+            "hex" | "ihx" | "ihex" => Some(RomFormat::IntelHex),
+            "txt" => Some(RomFormat::HexText),
+            _ => None,
+        }
+    }
+
+    /// The format of the file at `path` going by its extension, a binary if that says nothing.
+    #[cfg(feature = "std")]
+    pub fn from_path(path: &std::path::Path) -> RomFormat {
+        path.extension().and_then(|extension| extension.to_str()).and_then(RomFormat::from_extension).unwrap_or(RomFormat::Binary)
+    }
+}
+
+/// The ROM in `file`, taking it as `format`.
+pub fn decode(file: &[u8], format: RomFormat) -> Result<Cow<'_, [u8]>, RomFormatError> {
+    match format {
+        RomFormat::Binary => Ok(Cow::Borrowed(file)),
+        RomFormat::IntelHex => parse_intel_hex(file).map(Cow::Owned),
+        RomFormat::HexText => parse_hex_text(file).map(Cow::Owned),
+    }
+}
+
+/// Read a ROM file, in the format its extension says (see RomFormat::from_path()).
+#[cfg(feature = "std")]
+pub fn read(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    read_as(path, RomFormat::from_path(path))
+}
+
+/// Read a ROM file in `format`, whatever the extension says.
+#[cfg(feature = "std")]
+pub fn read_as(path: &std::path::Path, format: RomFormat) -> std::io::Result<Vec<u8>> {
+    let file = std::fs::read(path)?;
+    let invalid = |e: RomFormatError| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+    Ok(decode(&file, format).map_err(invalid)?.into_owned())
+}
+
+fn parse_intel_hex(file: &[u8]) -> Result<Vec<u8>, RomFormatError> {
+    let text = core::str::from_utf8(file).map_err(|_| intel_hex_error(1, "not text"))?;
+    // (address, data) for each data record
+    let mut records = Vec::new();
+    // the top 16 bits of the address, from type 02 and 04 records
+    let mut base = 0;
+    let mut ended = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |reason| intel_hex_error(n + 1, reason);
+        if ended {
+            return Err(error("records after the end of file record"));
+        }
+        let hex = line.strip_prefix(':').ok_or_else(|| error("records start with a colon"))?;
+        let bytes = hex_bytes(hex).ok_or_else(|| error("expected pairs of hex digits"))?;
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+            return Err(error("the byte count doesn't match the record's length"));
+        }
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error("bad checksum"));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => records.push((base + address, data.to_vec())),
+            0x01 => ended = true,
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+            // start addresses mean nothing here, CHIP-8 always starts at 0x200
+            0x03 | 0x05 => {}
+            _ => return Err(error("unknown record type")),
+        }
+    }
+    if records.is_empty() {
+        return Err(intel_hex_error(1, "no data records"));
+    }
+
+    let start = records.iter().map(|(address, _)| *address).min().unwrap_or(0);
+    let start = if start >= crate::cpu::PROGRAM_START { crate::cpu::PROGRAM_START } else { 0 };
+    let end = records.iter().map(|(address, data)| address + data.len()).max().unwrap_or(0);
+    // XO-CHIP's 64K is the most memory there is, don't allocate gigabytes for a typo
+    if end - start > 0x10000 {
+        return Err(intel_hex_error(1, "addresses go past 64K"));
+    }
+    let mut rom = alloc::vec![0; end - start];
+    for (address, data) in records {
+        rom[address - start..address - start + data.len()].copy_from_slice(&data);
+    }
+    Ok(rom)
+}
+
+fn intel_hex_error(line: usize, reason: &'static str) -> RomFormatError {
+    RomFormatError { format: RomFormat::IntelHex, line, reason }
+}
+
+fn parse_hex_text(file: &[u8]) -> Result<Vec<u8>, RomFormatError> {
+    let error = |line, reason| RomFormatError { format: RomFormat::HexText, line, reason };
+    let text = core::str::from_utf8(file).map_err(|_| error(1, "not text"))?;
+    let mut rom = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = [";", "#", "//"].iter().fold(line, |line, comment| line.split(comment).next().unwrap_or(""));
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() || word.ends_with(':') {
+                continue;
+            }
+            let word = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")).unwrap_or(word);
+            rom.extend(hex_bytes(word).ok_or_else(|| error(n + 1, "expected pairs of hex digits"))?);
+        }
+    }
+    if rom.is_empty() {
+        return Err(error(1, "no bytes"));
+    }
+    Ok(rom)
+}
+
+// "00E0A2" to [0x00, 0xE0, 0xA2], None unless it's all pairs of hex digits
+fn hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|n| u8::from_str_radix(&hex[n..n + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::String;
+
+    use crate::cpu::Cpu;
+    use crate::error::CpuError;
+
+    // An Intel HEX record with its byte count and checksum worked out
+    fn record(address: u16, kind: u8, data: &[u8]) -> String {
+        let mut bytes = alloc::vec![data.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        bytes.push(sum.wrapping_neg());
+        let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!(":{}\n", hex)
+    }
+
+    fn error(result: Result<Vec<u8>, RomFormatError>) -> (usize, &'static str) {
+        let e = result.unwrap_err();
+        (e.line, e.reason)
+    }
+
+    #[test]
+    fn intel_hex_addresses_are_memory_or_offsets() {
+        // at 0x200 on they're memory addresses, and the gap between is zeroes
+        let file = [record(0x200, 0, &[0x00, 0xE0]), record(0x204, 0, &[0x12, 0x04]), record(0, 1, &[])].concat();
+        assert_eq!(parse_intel_hex(file.as_bytes()).unwrap(), [0x00, 0xE0, 0, 0, 0x12, 0x04]);
+        // and below it they're offsets into the ROM
+        let file = [record(2, 0, &[0x12, 0x00]), record(0, 0, &[0x00, 0xE0])].concat();
+        assert_eq!(parse_intel_hex(file.as_bytes()).unwrap(), [0x00, 0xE0, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn intel_hex_record_types() {
+        // a segment address (type 02) of 0x20 puts address 0 at 0x200, start addresses (03, 05)
+        // are ignored and a linear address (04) of 0 changes nothing
+        let file = [
+            record(0, 2, &[0x00, 0x20]),
+            record(0, 3, &[0, 0, 0x02, 0]),
+            record(0, 4, &[0, 0]),
+            record(0, 0, &[0xA2, 0x2A]),
+            record(0, 5, &[0, 0, 0x02, 0]),
+        ]
+        .concat();
+        assert_eq!(parse_intel_hex(file.as_bytes()).unwrap(), [0xA2, 0x2A]);
+
+        let file = [record(0x200, 0, &[0x00, 0xE0]), record(0, 6, &[])].concat();
+        assert_eq!(error(parse_intel_hex(file.as_bytes())), (2, "unknown record type"));
+        let file = [record(0, 1, &[]), record(0x200, 0, &[0x00, 0xE0])].concat();
+        assert_eq!(error(parse_intel_hex(file.as_bytes())), (2, "records after the end of file record"));
+        // a linear address past 64K
+        let file = [record(0, 4, &[0, 1]), record(0x200, 0, &[0x00, 0xE0])].concat();
+        assert_eq!(error(parse_intel_hex(file.as_bytes())), (1, "addresses go past 64K"));
+    }
+
+    #[test]
+    fn intel_hex_checksums_and_lengths() {
+        let good = record(0x200, 0, &[0x00, 0xE0]);
+        let bad_checksum = good.replace("1C\n", "1D\n");
+        assert_ne!(good, bad_checksum);
+        assert_eq!(error(parse_intel_hex([good, bad_checksum].concat().as_bytes())), (2, "bad checksum"));
+        // the count says 3 bytes but there are 2
+        assert_eq!(error(parse_intel_hex(b":0302000000E01B")), (1, "the byte count doesn't match the record's length"));
+        assert_eq!(error(parse_intel_hex(b":0202000000E01")), (1, "expected pairs of hex digits"));
+        assert_eq!(error(parse_intel_hex(b"0202000000E01C")), (1, "records start with a colon"));
+        assert_eq!(error(parse_intel_hex(record(0, 1, &[]).as_bytes())), (1, "no data records"));
+    }
+
+    #[test]
+    fn hex_text_skips_comments_and_address_columns() {
+        let file = "; IBM logo, from a listing\n\
+                    0200: 00 E0 A2 2A   ; CLS, LD I\n\
+                    0204: 600C6108      # two at once\n\
+                    0208: 0x12,0x08     // and a jump to itself\n";
+        assert_eq!(parse_hex_text(file.as_bytes()).unwrap(), [0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0x12, 0x08]);
+    }
+
+    #[test]
+    fn hex_text_wants_whole_bytes() {
+        assert_eq!(error(parse_hex_text(b"00 E0\nA2 2A 6\n")), (2, "expected pairs of hex digits"));
+        assert_eq!(error(parse_hex_text(b"00E 0\n")), (1, "expected pairs of hex digits"));
+        assert_eq!(error(parse_hex_text(b"; nothing but comments\n")), (1, "no bytes"));
+        assert_eq!(error(parse_hex_text(b"00 GG\n")), (1, "expected pairs of hex digits"));
+    }
+
+    #[test]
+    fn formats_by_extension() {
+        assert_eq!(RomFormat::from_extension("ch8"), Some(RomFormat::Binary));
+        assert_eq!(RomFormat::from_extension("HEX"), Some(RomFormat::IntelHex));
+        assert_eq!(RomFormat::from_extension("txt"), Some(RomFormat::HexText));
+        assert_eq!(RomFormat::from_extension("png"), None);
+    }
+
+    #[test]
+    fn load_rom_never_guesses() {
+        // a ROM that happens to be all hex digits is still a binary
+        let rom = b"00E0";
+        let mut cpu = Cpu::new(Default::default());
+        cpu.load_rom(rom).unwrap();
+        assert_eq!(&cpu.memory[0x200..0x204], rom);
+
+        cpu.load_rom_as(rom, RomFormat::HexText).unwrap();
+        assert_eq!(&cpu.memory[0x200..0x202], &[0x00, 0xE0]);
+        assert!(matches!(cpu.load_rom_as(b"00E", RomFormat::HexText), Err(CpuError::InvalidRomFile(_))));
+    }
+}
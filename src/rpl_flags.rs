@@ -0,0 +1,62 @@
+// RPL user flags.
+// On the HP-48 SUPER-CHIP could stash registers in the calculator's user flags with FX75
+// and read them back with FX85. They survived turning the calculator off, so games used them
+// for high scores. We get the same effect by keeping them in a small file per ROM.
+
+// The CPU only needs the flag count, storing them on disk needs std.
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+#[cfg(feature = "std")]
+use crate::config::config_dir;
+#[cfg(feature = "std")]
+use crate::fnv::fnv1a;
+
+/// Number of flag registers. SUPER-CHIP had 8, XO-CHIP has 16.
+pub const RPL_FLAG_COUNT: usize = 16;
+
+#[cfg(feature = "std")]
+/// The saved flags for one ROM, identified by a hash of its contents so renaming
+/// the file doesn't lose the high scores.
+pub struct RplFlagStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl RplFlagStore {
+    /// The store for `rom` under the config directory, None if there's no home directory.
+    pub fn for_rom(rom: &[u8]) -> Option<Self> {
+        let dir = config_dir()?.join("flags");
+        Some(RplFlagStore::at(dir.join(format!("{:016x}.flags", fnv1a(rom.iter().copied())))))
+    }
+
+    /// A store at an explicit path.
+    pub fn at(path: PathBuf) -> Self {
+        RplFlagStore { path }
+    }
+
+    /// Read the saved flags, all zeroes if nothing has been saved for this ROM yet.
+    pub fn load(&self) -> io::Result<[u8; RPL_FLAG_COUNT]> {
+        let mut flags = [0; RPL_FLAG_COUNT];
+        match fs::read(&self.path) {
+            Ok(bytes) => {
+                let n = bytes.len().min(RPL_FLAG_COUNT);
+                flags[..n].copy_from_slice(&bytes[..n]);
+                Ok(flags)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(flags),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, flags: &[u8; RPL_FLAG_COUNT]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
@@ -0,0 +1,730 @@
+// Save states.
+// A snapshot of everything a running program can see or change: registers, timers, the stack,
+// memory, the screen, XO-CHIP's audio and the RNG, so restoring one carries on exactly where
+// it left off. Settings (quirks, clock speed, hardening) aren't in it, they belong to whoever
+// runs the CPU. `chip8 run --save-state FILE` writes one when the run ends.
+//
+// diff() compares two of them, which is the quickest way into "why did these two runs end up
+// different": run both to the same point, save, then `chip8 statediff a.state b.state`.
+//
+// The file is binary, numbers little endian:
+//
+//   "C8ST", format version (2), variant (0 chip8, 1 schip, 2 xochip, 3 chip8x, 4 megachip,
+//   5 eti660, 6 chip8hires)
+//   V0-VF, PC (4 bytes), I (2), DT, ST, SP (4), stack depth (4), the stack then the function
+//   each entry called (2 bytes each, depth of each), RNG state (8), whether there's an audio
+//   pattern then the pattern (16), audio pitch, the RPL flags (16), hires, selected planes,
+//   the pixels (128x64, a colour index each, row by row, the planes even in Mega-Chip mode),
+//   memory size (4), memory, then why it halted (0 it hasn't, 1 exit, 2 infinite loop,
+//   3 fault), whether it's waiting for the vertical blank, the key FX0A is waiting to be let
+//   go of (0xFF for none), and where it is in the current frame (4 bytes, 4 bytes). CHIP-8X's have the colour board on the end: the background, then
+//   each zone's colour (8 across, 32 down). Mega-Chip's have I's top 8 bits on the end, then
+//   whether it's in Mega-Chip mode, the palette (256 colours, ARGB), sprite width and height
+//   (0 for 256), screen alpha, blend mode, collision colour, the digitised sound (whether
+//   there is one, then where its samples start (4), how many (4), the sample rate (2) and
+//   whether it loops), and last what's showing and what's being drawn (256x192 each).
+//   ETI-660's have how many rows its lo-res screen has on the end
+//
+// A fault's CpuError isn't kept, a restored faulted CPU is just halted with HaltReason::Fault.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use crate::audio::PATTERN_LEN;
+use crate::color_board::{ColorBoard, ZONE_WIDTH};
+use crate::cpu::Cpu;
+use crate::display::{HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH};
+use crate::error::CpuError;
+use crate::halt::HaltReason;
+use crate::mega_chip::{DigitisedSound, MegaScreen, MEGA_HEIGHT, MEGA_WIDTH, PALETTE_SIZE};
+use crate::rpl_flags::RPL_FLAG_COUNT;
+use crate::variant::Variant;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 2;
+
+/// A snapshot of a CPU, see the top of this file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub variant: Variant,
+    pub registers: [u8; 16],
+    pub pc: usize,
+    pub index_register: u32,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack_pointer: usize,
+    /// The whole stack, used or not, so its length is the stack depth
+    pub stack: Vec<u16>,
+    pub call_targets: Vec<u16>,
+    pub rng_state: u64,
+    pub audio_pattern: Option<[u8; PATTERN_LEN]>,
+    pub audio_pitch: u8,
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+    pub hires: bool,
+    /// Rows in lo-res, 32 except on an ETI-660
+    pub lores_height: usize,
+    pub selected_planes: u8,
+    /// Every pixel's colour index, HIRES_WIDTH x HIRES_HEIGHT whatever the resolution
+    pub pixels: Vec<u8>,
+    pub memory: Vec<u8>,
+    pub halt_reason: Option<HaltReason>,
+    pub waiting_for_vblank: bool,
+    /// The key FX0A is waiting on being let go, with the wait_for_key_release quirk
+    pub held_key: Option<u8>,
+    /// Where the CPU is in the current 60Hz frame, so run_frame() carries on the same
+    pub frame_remainder: u32,
+    pub frame_cycles_left: u32,
+    /// CHIP-8X's colours
+    pub color_board: Option<ColorBoard>,
+    /// Mega-Chip's screen and what it's playing
+    pub mega_screen: Option<MegaScreen>,
+    pub digitised_sound: Option<DigitisedSound>,
+}
+
+/// A save state file that didn't load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveStateError {
+    pub reason: &'static str,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a usable save state: {}", self.reason)
+    }
+}
+
+impl core::error::Error for SaveStateError {}
+
+impl SaveState {
+    /// Snapshot `cpu`.
+    pub fn capture(cpu: &Cpu) -> Self {
+        let mut pixels = Vec::with_capacity(HIRES_WIDTH * HIRES_HEIGHT);
+        for y in 0..HIRES_HEIGHT {
+            pixels.extend((0..HIRES_WIDTH).map(|x| cpu.display.plane_pixel_color(x, y)));
+        }
+        SaveState {
+            variant: cpu.variant,
+            registers: cpu.registers,
+            pc: cpu.position_in_memory,
+            index_register: cpu.index_register,
+            delay_timer: cpu.delay_timer,
+            sound_timer: cpu.sound_timer,
+            stack_pointer: cpu.stack_pointer,
+            stack: cpu.stack.clone(),
+            call_targets: cpu.call_targets.clone(),
+            rng_state: cpu.rng_state,
+            audio_pattern: cpu.audio_pattern,
+            audio_pitch: cpu.audio_pitch,
+            rpl_flags: cpu.rpl_flags,
+            hires: cpu.display.is_hires(),
+            lores_height: cpu.display.lores_height(),
+            selected_planes: cpu.display.selected_planes(),
+            pixels,
+            memory: cpu.memory.clone(),
+            halt_reason: cpu.halt_reason,
+            waiting_for_vblank: cpu.waiting_for_vblank,
+            held_key: cpu.held_key,
+            frame_remainder: cpu.frame_remainder,
+            frame_cycles_left: cpu.frame_cycles_left,
+            color_board: cpu.display.color_board().cloned(),
+            mega_screen: cpu.display.mega_screen().cloned(),
+            digitised_sound: cpu.digitised_sound,
+        }
+    }
+
+    /// Put `cpu` back the way it was when this was captured. Its settings stay as they are,
+    /// but the variant, memory size and stack depth come from the snapshot.
+    pub fn restore(&self, cpu: &mut Cpu) -> Result<(), CpuError> {
+        cpu.set_variant(self.variant);
+        cpu.set_memory_size(self.memory.len())?;
+        cpu.set_stack_depth(self.stack.len())?;
+        cpu.memory.copy_from_slice(&self.memory);
+        // nothing says which bytes had been written, so say they all had
+        cpu.mark_initialized(0, self.memory.len());
+        cpu.flush_decoded();
+
+        cpu.registers = self.registers;
+        cpu.position_in_memory = self.pc;
+        cpu.index_register = self.index_register;
+        cpu.delay_timer = self.delay_timer;
+        cpu.sound_timer = self.sound_timer;
+        cpu.stack_pointer = self.stack_pointer.min(self.stack.len());
+        cpu.stack.copy_from_slice(&self.stack);
+        for (target, &saved) in cpu.call_targets.iter_mut().zip(&self.call_targets) {
+            *target = saved;
+        }
+        cpu.rng_state = self.rng_state;
+        cpu.audio_pattern = self.audio_pattern;
+        cpu.audio_pitch = self.audio_pitch;
+        cpu.digitised_sound = self.digitised_sound;
+        cpu.copy_digitised_samples();
+        cpu.rpl_flags = self.rpl_flags;
+        cpu.halt_reason = self.halt_reason;
+        cpu.fault = None;
+        cpu.waiting_for_vblank = self.waiting_for_vblank;
+        cpu.held_key = self.held_key;
+        cpu.frame_remainder = self.frame_remainder;
+        cpu.frame_cycles_left = self.frame_cycles_left;
+
+        cpu.display.set_lores_height(self.lores_height);
+        cpu.display.set_hires(self.hires);
+        cpu.display.select_planes(self.selected_planes);
+        for (n, &color) in self.pixels.iter().take(HIRES_WIDTH * HIRES_HEIGHT).enumerate() {
+            cpu.display.set_pixel_color(n % HIRES_WIDTH, n / HIRES_WIDTH, color);
+        }
+        if let Some(board) = &self.color_board {
+            cpu.display.set_color_board(Some(board.clone()));
+        }
+        if let Some(screen) = &self.mega_screen {
+            cpu.display.set_mega_screen(Some(screen.clone()));
+        }
+        Ok(())
+    }
+
+    /// The file format at the top of this file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 + self.stack.len() * 4 + self.pixels.len() + self.memory.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(match self.variant {
+            Variant::Chip8 => 0,
+            Variant::SuperChip => 1,
+            Variant::XoChip => 2,
+            Variant::Chip8X => 3,
+            Variant::MegaChip => 4,
+            Variant::Eti660 => 5,
+            Variant::Chip8Hires => 6,
+        });
+        bytes.extend_from_slice(&self.registers);
+        bytes.extend_from_slice(&(self.pc as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.index_register as u16).to_le_bytes());
+        bytes.extend_from_slice(&[self.delay_timer, self.sound_timer]);
+        bytes.extend_from_slice(&(self.stack_pointer as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.stack.len() as u32).to_le_bytes());
+        for entry in self.stack.iter().chain(&self.call_targets) {
+            bytes.extend_from_slice(&entry.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.rng_state.to_le_bytes());
+        bytes.push(self.audio_pattern.is_some() as u8);
+        bytes.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        bytes.push(self.audio_pitch);
+        bytes.extend_from_slice(&self.rpl_flags);
+        bytes.extend_from_slice(&[self.hires as u8, self.selected_planes]);
+        bytes.extend_from_slice(&self.pixels);
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+        bytes.push(match self.halt_reason {
+            None => 0,
+            Some(HaltReason::Exit) => 1,
+            Some(HaltReason::InfiniteLoop) => 2,
+            Some(HaltReason::Fault) => 3,
+        });
+        bytes.push(self.waiting_for_vblank as u8);
+        bytes.push(self.held_key.unwrap_or(0xFF));
+        bytes.extend_from_slice(&self.frame_remainder.to_le_bytes());
+        bytes.extend_from_slice(&self.frame_cycles_left.to_le_bytes());
+        if let Some(board) = &self.color_board {
+            bytes.push(board.background());
+            for y in 0..HEIGHT {
+                bytes.extend((0..WIDTH).step_by(ZONE_WIDTH).map(|x| board.foreground(x, y)));
+            }
+        }
+        if let Some(screen) = &self.mega_screen {
+            bytes.push((self.index_register >> 16) as u8);
+            bytes.push(screen.is_enabled() as u8);
+            for color in 0..PALETTE_SIZE {
+                bytes.extend_from_slice(&screen.palette_color(color as u8));
+            }
+            let (width, height) = screen.sprite_size();
+            bytes.extend_from_slice(&[width as u8, height as u8, screen.alpha(), screen.blend_mode(), screen.collision_color()]);
+            let sound = self.digitised_sound;
+            bytes.push(sound.is_some() as u8);
+            bytes.extend_from_slice(&sound.map_or(0, |sound| sound.start as u32).to_le_bytes());
+            bytes.extend_from_slice(&sound.map_or(0, |sound| sound.len as u32).to_le_bytes());
+            bytes.extend_from_slice(&sound.map_or(0, |sound| sound.sample_rate).to_le_bytes());
+            bytes.push(sound.is_some_and(|sound| sound.looping) as u8);
+            let (front, back) = screen.buffers();
+            bytes.extend_from_slice(front);
+            bytes.extend_from_slice(back);
+        }
+        if self.variant == Variant::Eti660 {
+            bytes.push(self.lores_height as u8);
+        }
+        bytes
+    }
+
+    /// Read a snapshot written by to_bytes().
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SaveStateError { reason: "it doesn't start with C8ST" });
+        }
+        if reader.u8()? != VERSION {
+            return Err(SaveStateError { reason: "it's from a different version of the format" });
+        }
+        let variant = match reader.u8()? {
+            0 => Variant::Chip8,
+            1 => Variant::SuperChip,
+            2 => Variant::XoChip,
+            3 => Variant::Chip8X,
+            4 => Variant::MegaChip,
+            5 => Variant::Eti660,
+            6 => Variant::Chip8Hires,
+            _ => return Err(SaveStateError { reason: "unknown variant" }),
+        };
+        let registers = reader.array()?;
+        let pc = reader.u32()? as usize;
+        let mut index_register = reader.u16()? as u32;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let stack_pointer = reader.u32()? as usize;
+        let depth = reader.u32()? as usize;
+        let mut stack = Vec::with_capacity(depth.min(bytes.len()));
+        for _ in 0..depth {
+            stack.push(reader.u16()?);
+        }
+        let mut call_targets = Vec::with_capacity(stack.len());
+        for _ in 0..depth {
+            call_targets.push(reader.u16()?);
+        }
+        let rng_state = u64::from_le_bytes(reader.array()?);
+        let has_pattern = reader.u8()? != 0;
+        let pattern = reader.array()?;
+        let audio_pitch = reader.u8()?;
+        let rpl_flags = reader.array()?;
+        let hires = reader.u8()? != 0;
+        let selected_planes = reader.u8()?;
+        let pixels = reader.take(HIRES_WIDTH * HIRES_HEIGHT)?.to_vec();
+        let memory_size = reader.u32()? as usize;
+        let memory = reader.take(memory_size)?.to_vec();
+        let halt_reason = match reader.u8()? {
+            0 => None,
+            1 => Some(HaltReason::Exit),
+            2 => Some(HaltReason::InfiniteLoop),
+            3 => Some(HaltReason::Fault),
+            _ => return Err(SaveStateError { reason: "unknown halt reason" }),
+        };
+        let waiting_for_vblank = reader.u8()? != 0;
+        let held_key = match reader.u8()? {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            _ => return Err(SaveStateError { reason: "FX0A's waiting on a key that doesn't exist" }),
+        };
+        let frame_remainder = reader.u32()?;
+        let frame_cycles_left = reader.u32()?;
+        let color_board = match variant {
+            Variant::Chip8X => {
+                let mut board = ColorBoard::new();
+                board.set_background(reader.u8()?);
+                for y in 0..HEIGHT {
+                    for (x, &color) in reader.take(WIDTH / ZONE_WIDTH)?.iter().enumerate() {
+                        board.set_zone(x, y, color);
+                    }
+                }
+                Some(board)
+            }
+            _ => None,
+        };
+        let (mega_screen, digitised_sound) = match variant {
+            Variant::MegaChip => {
+                index_register |= (reader.u8()? as u32) << 16;
+                let mut screen = MegaScreen::new();
+                screen.set_enabled(reader.u8()? != 0);
+                for color in 0..PALETTE_SIZE {
+                    screen.set_palette_color(color as u8, reader.array()?);
+                }
+                screen.set_sprite_width(reader.u8()?);
+                screen.set_sprite_height(reader.u8()?);
+                screen.set_alpha(reader.u8()?);
+                screen.set_blend_mode(reader.u8()?);
+                screen.set_collision_color(reader.u8()?);
+                let playing = reader.u8()? != 0;
+                let sound = DigitisedSound {
+                    start: reader.u32()? as usize,
+                    len: reader.u32()? as usize,
+                    sample_rate: reader.u16()?,
+                    looping: reader.u8()? != 0,
+                };
+                let front = reader.take(MEGA_WIDTH * MEGA_HEIGHT)?;
+                let back = reader.take(MEGA_WIDTH * MEGA_HEIGHT)?;
+                screen.set_buffers(front, back);
+                (Some(screen), playing.then_some(sound))
+            }
+            _ => (None, None),
+        };
+        let lores_height = match variant {
+            Variant::Eti660 => reader.u8()? as usize,
+            _ => variant.lores_height(),
+        };
+        if !reader.bytes.is_empty() {
+            return Err(SaveStateError { reason: "there's more after the end of it" });
+        }
+        Ok(SaveState {
+            variant,
+            registers,
+            pc,
+            index_register,
+            delay_timer,
+            sound_timer,
+            stack_pointer,
+            stack,
+            call_targets,
+            rng_state,
+            audio_pattern: has_pattern.then_some(pattern),
+            audio_pitch,
+            rpl_flags,
+            hires,
+            lores_height,
+            selected_planes,
+            pixels,
+            memory,
+            halt_reason,
+            waiting_for_vblank,
+            held_key,
+            frame_remainder,
+            frame_cycles_left,
+            color_board,
+            mega_screen,
+            digitised_sound,
+        })
+    }
+
+    /// Read a save state from disk.
+    #[cfg(feature = "std")]
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        SaveState::from_bytes(&bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Write the save state to disk.
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// The colour index of the pixel at (x, y).
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * HIRES_WIDTH + x]
+    }
+
+    // What the screen shows, the same size as Display::width() and height()
+    fn screen_size(&self) -> (usize, usize) {
+        if self.hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (WIDTH, self.lores_height) }
+    }
+}
+
+// Pulls the fields off the front of a save state
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < len {
+            return Err(SaveStateError { reason: "it ends too soon" });
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+/// A register (or timer, or anything else with a single value) that's different.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueDiff {
+    pub name: String,
+    pub a: u64,
+    pub b: u64,
+}
+
+/// A run of bytes that differ, with what's there in each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDiff {
+    pub range: Range<usize>,
+    pub a: Vec<u8>,
+    pub b: Vec<u8>,
+}
+
+/// A pixel that's a different colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelDiff {
+    pub x: usize,
+    pub y: usize,
+    pub a: u8,
+    pub b: u8,
+}
+
+/// Everything that's different between two save states, from diff().
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub values: Vec<ValueDiff>,
+    pub memory: Vec<MemoryDiff>,
+    pub pixels: Vec<PixelDiff>,
+}
+
+impl StateDiff {
+    /// Nothing's different.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.memory.is_empty() && self.pixels.is_empty()
+    }
+}
+
+/// What's different between `a` and `b`. Memory only past the end of the smaller one, and the
+/// stack above both stack pointers, don't count (their sizes show up as values).
+pub fn diff(a: &SaveState, b: &SaveState) -> StateDiff {
+    let mut diff = StateDiff::default();
+    let mut value = |name: String, a: u64, b: u64| {
+        if a != b {
+            diff.values.push(ValueDiff { name, a, b });
+        }
+    };
+    let variant = |state: &SaveState| state.variant as u64;
+    value("variant".into(), variant(a), variant(b));
+    value("PC".into(), a.pc as u64, b.pc as u64);
+    value("I".into(), a.index_register as u64, b.index_register as u64);
+    for n in 0..16 {
+        value(format!("V{:X}", n), a.registers[n] as u64, b.registers[n] as u64);
+    }
+    value("DT".into(), a.delay_timer as u64, b.delay_timer as u64);
+    value("ST".into(), a.sound_timer as u64, b.sound_timer as u64);
+    value("SP".into(), a.stack_pointer as u64, b.stack_pointer as u64);
+    value("stack depth".into(), a.stack.len() as u64, b.stack.len() as u64);
+    let live = a.stack_pointer.min(b.stack_pointer).min(a.stack.len()).min(b.stack.len());
+    for n in 0..live {
+        value(format!("stack[{}]", n), a.stack[n] as u64, b.stack[n] as u64);
+    }
+    value("RNG state".into(), a.rng_state, b.rng_state);
+    value("audio pitch".into(), a.audio_pitch as u64, b.audio_pitch as u64);
+    // the pattern's 16 bytes as two numbers, no pattern at all as 0
+    let pattern = |state: &SaveState, half: usize| {
+        let pattern = state.audio_pattern.unwrap_or_default();
+        u64::from_be_bytes(pattern[half * 8..half * 8 + 8].try_into().unwrap())
+    };
+    value("audio pattern bytes 0-7".into(), pattern(a, 0), pattern(b, 0));
+    value("audio pattern bytes 8-15".into(), pattern(a, 1), pattern(b, 1));
+    for n in 0..RPL_FLAG_COUNT {
+        value(format!("RPL flag {}", n), a.rpl_flags[n] as u64, b.rpl_flags[n] as u64);
+    }
+    value("hires".into(), a.hires as u64, b.hires as u64);
+    value("lo-res height".into(), a.lores_height as u64, b.lores_height as u64);
+    value("selected planes".into(), a.selected_planes as u64, b.selected_planes as u64);
+    if let (Some(board_a), Some(board_b)) = (&a.color_board, &b.color_board) {
+        value("background colour".into(), board_a.background() as u64, board_b.background() as u64);
+        // a row's 8 zone colours as one number, a digit each
+        let zones = |board: &ColorBoard, y: usize| (0..WIDTH).step_by(ZONE_WIDTH).fold(0, |row, x| row << 4 | board.foreground(x, y) as u64);
+        for y in 0..HEIGHT {
+            value(format!("colour zones row {}", y), zones(board_a, y), zones(board_b, y));
+        }
+    }
+    if let (Some(screen_a), Some(screen_b)) = (&a.mega_screen, &b.mega_screen) {
+        value("Mega-Chip mode".into(), screen_a.is_enabled() as u64, screen_b.is_enabled() as u64);
+        for color in 0..PALETTE_SIZE {
+            let argb = |screen: &MegaScreen| u32::from_be_bytes(screen.palette_color(color as u8)) as u64;
+            value(format!("palette colour {}", color), argb(screen_a), argb(screen_b));
+        }
+        value("sprite width".into(), screen_a.sprite_size().0 as u64, screen_b.sprite_size().0 as u64);
+        value("sprite height".into(), screen_a.sprite_size().1 as u64, screen_b.sprite_size().1 as u64);
+        value("screen alpha".into(), screen_a.alpha() as u64, screen_b.alpha() as u64);
+        value("blend mode".into(), screen_a.blend_mode() as u64, screen_b.blend_mode() as u64);
+        value("collision colour".into(), screen_a.collision_color() as u64, screen_b.collision_color() as u64);
+        // where the sound's samples start, 0 for no sound
+        let sound = |state: &SaveState| state.digitised_sound.map_or(0, |sound| sound.start as u64);
+        value("digitised sound".into(), sound(a), sound(b));
+    }
+    value("memory size".into(), a.memory.len() as u64, b.memory.len() as u64);
+    // halt reasons as numbers, the same ones as in the file
+    let halted = |state: &SaveState| state.halt_reason.map_or(0, |reason| reason as u64 + 1);
+    value("halted".into(), halted(a), halted(b));
+    value("waiting for vblank".into(), a.waiting_for_vblank as u64, b.waiting_for_vblank as u64);
+    value("key FX0A's waiting on".into(), a.held_key.map_or(0xFF, u64::from), b.held_key.map_or(0xFF, u64::from));
+
+    let len = a.memory.len().min(b.memory.len());
+    let mut addr = 0;
+    while addr < len {
+        if a.memory[addr] == b.memory[addr] {
+            addr += 1;
+            continue;
+        }
+        let start = addr;
+        while addr < len && a.memory[addr] != b.memory[addr] {
+            addr += 1;
+        }
+        diff.memory.push(MemoryDiff { range: start..addr, a: a.memory[start..addr].to_vec(), b: b.memory[start..addr].to_vec() });
+    }
+
+    // two Mega-Chip screens compare what they're showing
+    let showing = |screen: &&MegaScreen| screen.is_enabled();
+    if let (Some(screen_a), Some(screen_b)) = (a.mega_screen.as_ref().filter(showing), b.mega_screen.as_ref().filter(showing)) {
+        for y in 0..MEGA_HEIGHT {
+            for x in 0..MEGA_WIDTH {
+                let (pixel_a, pixel_b) = (screen_a.pixel(x, y), screen_b.pixel(x, y));
+                if pixel_a != pixel_b {
+                    diff.pixels.push(PixelDiff { x, y, a: pixel_a, b: pixel_b });
+                }
+            }
+        }
+        return diff;
+    }
+
+    // only the part of the screen that's showing, unless the resolutions differ
+    let (width, height) = if a.screen_size() == b.screen_size() { a.screen_size() } else { (HIRES_WIDTH, HIRES_HEIGHT) };
+    for y in 0..height {
+        for x in 0..width {
+            let (pixel_a, pixel_b) = (a.pixel(x, y), b.pixel(x, y));
+            if pixel_a != pixel_b {
+                diff.pixels.push(PixelDiff { x, y, a: pixel_a, b: pixel_b });
+            }
+        }
+    }
+    diff
+}
+
+// How many differing bytes of each memory range, and pixels, the report shows
+const SHOWN_BYTES: usize = 16;
+const SHOWN_PIXELS: usize = 20;
+
+// The report `chip8 statediff` prints, the first state's side on the left
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        if !self.values.is_empty() {
+            writeln!(f, "registers and the rest:")?;
+            for value in &self.values {
+                writeln!(f, "  {:<26} {:>6X} vs {:X}", value.name, value.a, value.b)?;
+            }
+        }
+        if !self.memory.is_empty() {
+            let bytes: usize = self.memory.iter().map(|range| range.range.len()).sum();
+            writeln!(f, "memory: {} bytes differ in {} ranges", bytes, self.memory.len())?;
+            let hex = |bytes: &[u8]| {
+                let mut text = String::new();
+                for byte in bytes.iter().take(SHOWN_BYTES) {
+                    let _ = write!(text, "{:02X} ", byte);
+                }
+                if bytes.len() > SHOWN_BYTES {
+                    text.push_str("...");
+                }
+                text
+            };
+            for range in &self.memory {
+                writeln!(f, "  {:03X}-{:03X}  {}", range.range.start, range.range.end - 1, hex(&range.a))?;
+                writeln!(f, "  {:>7}  {}", "vs", hex(&range.b))?;
+            }
+        }
+        if !self.pixels.is_empty() {
+            writeln!(f, "screen: {} pixels differ", self.pixels.len())?;
+            for pixel in self.pixels.iter().take(SHOWN_PIXELS) {
+                writeln!(f, "  ({}, {})  {} vs {}", pixel.x, pixel.y, pixel.a, pixel.b)?;
+            }
+            if self.pixels.len() > SHOWN_PIXELS {
+                writeln!(f, "  and {} more", self.pixels.len() - SHOWN_PIXELS)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    use crate::mega_chip::DigitisedSound;
+    use crate::quirks::Quirks;
+
+    const VARIANTS: [Variant; 7] = [
+        Variant::Chip8,
+        Variant::SuperChip,
+        Variant::XoChip,
+        Variant::Chip8X,
+        Variant::MegaChip,
+        Variant::Eti660,
+        Variant::Chip8Hires,
+    ];
+
+    // A machine partway through something, with a bit of everything a save state holds
+    fn busy(variant: Variant) -> Cpu {
+        let entry = variant.entry_point();
+        let call = 0x2000 | (entry as u16 + 10);
+        let mut rom = vec![0; entry - variant.program_start()];
+        rom.extend_from_slice(&[
+            0x6A, 0x42, // VA = 0x42
+            0x6B, 0x09, // VB = 9
+            0xFB, 0x15, // DT = VB
+            0xFB, 0x18, // ST = VB
+        ]);
+        rom.extend_from_slice(&call.to_be_bytes());
+        rom.extend_from_slice(&[
+            0xA0, 0x00, // I = the font's 0
+            0xD0, 0x05, // draw it, which waits for the display on the VIP
+            0xA3, 0x21, // I = 0x321
+        ]);
+        let mut cpu = Cpu::with_variant(variant);
+        cpu.load_binary(&rom).unwrap();
+        for _ in 0..8 {
+            cpu.step();
+        }
+        cpu.audio_pattern = Some([0xA5; PATTERN_LEN]);
+        cpu.audio_pitch = 80;
+        if variant == Variant::MegaChip {
+            cpu.digitised_sound = Some(DigitisedSound { start: 0x400, len: 16, sample_rate: 8000, looping: true });
+        }
+        cpu
+    }
+
+    #[test]
+    fn round_trip_every_variant() {
+        for variant in VARIANTS {
+            let cpu = busy(variant);
+            let state = SaveState::capture(&cpu);
+            assert_eq!(state.registers[0xA], 0x42, "{:?}", variant);
+            assert_eq!(state.stack_pointer, 1, "{:?}", variant);
+
+            let loaded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+            assert_eq!(loaded, state, "{:?}", variant);
+
+            let mut restored = Cpu::new(Quirks::default());
+            loaded.restore(&mut restored).unwrap();
+            assert_eq!(restored.variant, variant);
+            assert_eq!(SaveState::capture(&restored), state, "{:?}", variant);
+            assert_eq!(restored.state_digest(), cpu.state_digest(), "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn restored_digitised_sound_plays() {
+        let mut restored = Cpu::new(Quirks::default());
+        SaveState::capture(&busy(Variant::MegaChip)).restore(&mut restored).unwrap();
+        assert_eq!(restored.sound().digitised.map(|sound| sound.samples.len()), Some(16));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(SaveState::from_bytes(b"not a save state").is_err());
+        let bytes = SaveState::capture(&busy(Variant::Chip8)).to_bytes();
+        assert!(SaveState::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+// Screenshots.
+// Saves the screen as a PNG, scaled up with every CHIP-8 pixel a solid square so it stays sharp.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::display::Display;
+use crate::palette::Palette;
+
+impl Display {
+    /// Save the screen as a PNG at `path`, each pixel `scale` x `scale` pixels in the image.
+    pub fn save_png(&self, path: impl AsRef<Path>, scale: u32, palette: &Palette) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?), scale, palette)
+    }
+
+    /// The same as save_png(), to anything that can be written to.
+    pub fn write_png(&self, out: impl Write, scale: u32, palette: &Palette) -> io::Result<()> {
+        let scale = scale.max(1) as usize;
+        let (width, height) = (self.width() * scale, self.height() * scale);
+
+        let mut image = Vec::with_capacity(width * height * 3);
+        let pixels: Vec<[u8; 3]> = self.rgb_pixels(palette).collect();
+        for row in pixels.chunks(self.width()) {
+            let line: Vec<u8> = row.iter().flat_map(|rgb| rgb.repeat(scale)).collect();
+            for _ in 0..scale {
+                image.extend_from_slice(&line);
+            }
+        }
+
+        let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&image).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
+
+/// A file name in the current directory for a new screenshot or recording,
+/// `<name>-<unix time>.<extension>`, with a number added if there's already one from the same second.
+pub fn capture_path(name: &str, extension: &str) -> PathBuf {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut path = PathBuf::from(format!("{}-{}.{}", name, seconds, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = PathBuf::from(format!("{}-{}-{}.{}", name, seconds, n, extension));
+        n += 1;
+    }
+    path
+}
@@ -0,0 +1,298 @@
+// Scripting, with rhai (https://rhai.rs).
+// A script hooks into a running ROM without rebuilding the emulator: pressing keys for you,
+// checking something the ROM should never do hasn't happened, logging events of your own, or
+// a trainer that keeps your lives topped up. It defines whichever of these it wants, they
+// get called as things happen:
+//
+//   fn init()                      once, before the ROM starts
+//   fn on_frame()                  at the end of every 60Hz frame
+//   fn on_instruction(pc)          before every instruction (slow, only define it if you need it)
+//   fn on_draw(x, y, collision)    after a sprite's drawn
+//   fn on_write(addr, value)       after the program writes memory
+//   fn on_key_wait(x)              every time FX0A runs with no key down
+//
+// and can call these to look at the machine, as it is right then:
+//
+//   pc()  i()  sp()  dt()  st()  v(x)  peek(addr)  key(k)  frame()  instructions()
+//
+// Changes wait for the end of the frame (hooks can only look, see hooks.rs), then happen in
+// the order they were asked for:
+//
+//   press(k)  release(k)  poke(addr, value)  set_v(x, value)
+//
+// Functions can't see variables from outside them in rhai, so anything that has to last
+// between calls goes in `this`, a map that's there for the whole run. print() and debug()
+// go to stderr, and `throw` stops the run with an error, which is how to assert things:
+//
+//   fn init() { this.collisions = 0; }
+//   fn on_draw(x, y, collision) {
+//       if collision { this.collisions += 1; print(`hit ${this.collisions} at ${x},${y}`); }
+//   }
+//   fn on_frame() {
+//       if peek(0x3F0) > 9 { throw "more than 9 lives"; }
+//       poke(0x3F0, 3);
+//   }
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+
+use crate::cpu::Cpu;
+use crate::hooks::Observer;
+
+/// Why a script didn't load, or stopped.
+#[derive(Debug)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// A change the script asked for, made at the end of the frame
+enum Change {
+    Key(u8, bool),
+    Poke(usize, u8),
+    Register(usize, u8),
+}
+
+// What the script's functions share: a copy of the machine for them to read, taken just
+// before each hook's called, and the changes they've asked for
+#[derive(Default)]
+struct Shared {
+    registers: [u8; 16],
+    pc: usize,
+    i: u32,
+    sp: usize,
+    dt: u8,
+    st: u8,
+    keypad: [bool; 16],
+    memory: Vec<u8>,
+    frame: u64,
+    instructions: u64,
+    changes: Vec<Change>,
+}
+
+impl Shared {
+    fn look_at(&mut self, cpu: &Cpu) {
+        self.registers = cpu.registers;
+        self.pc = cpu.position_in_memory;
+        self.i = cpu.index_register;
+        self.sp = cpu.stack_pointer;
+        self.dt = cpu.delay_timer;
+        self.st = cpu.sound_timer;
+        self.keypad = cpu.keypad;
+        self.memory.clone_from(&cpu.memory);
+        self.frame = cpu.timer_ticks();
+        self.instructions = cpu.instructions_retired();
+    }
+}
+
+// Which hooks the script defines, so the rest cost nothing
+#[derive(Default)]
+struct Hooks {
+    on_frame: bool,
+    on_instruction: bool,
+    on_draw: bool,
+    on_write: bool,
+    on_key_wait: bool,
+}
+
+/// A loaded script, see the top of this file. Add it to the CPU as an observer (shared, in an
+/// Arc<Mutex>) and call end_frame() after every frame.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    shared: Arc<Mutex<Shared>>,
+    hooks: Hooks,
+    // the first thing that went wrong in a hook, end_frame() hands it on
+    error: Option<ScriptError>,
+}
+
+impl Script {
+    /// Compile a script and run its init(), if it has one. `cpu` is the machine it'll be
+    /// watching, for init() to look at.
+    pub fn new(source: &str, cpu: &Cpu) -> Result<Self, ScriptError> {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let mut engine = Engine::new();
+        engine.on_print(|text| eprintln!("script: {}", text));
+        engine.on_debug(|text, _, position| eprintln!("script: {} ({})", text, position));
+        register_functions(&mut engine, &shared);
+
+        let ast = engine.compile(source).map_err(|e| ScriptError(e.to_string()))?;
+        let defines = |name: &str, params: usize| ast.iter_functions().any(|f| f.name == name && f.params.len() == params);
+        let hooks = Hooks {
+            on_frame: defines("on_frame", 0),
+            on_instruction: defines("on_instruction", 1),
+            on_draw: defines("on_draw", 3),
+            on_write: defines("on_write", 2),
+            on_key_wait: defines("on_key_wait", 1),
+        };
+        let has_init = defines("init", 0);
+
+        let mut script = Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from(Map::new()),
+            shared,
+            hooks,
+            error: None,
+        };
+        // anything at the top level runs once, like a main()
+        script.engine.run_ast_with_scope(&mut script.scope, &script.ast).map_err(|e| ScriptError(e.to_string()))?;
+        if has_init {
+            script.call(cpu, "init", ());
+        }
+        match script.error.take() {
+            Some(error) => Err(error),
+            None => Ok(script),
+        }
+    }
+
+    /// Read and compile a script file, see new().
+    pub fn load(path: &std::path::Path, cpu: &Cpu) -> Result<Self, ScriptError> {
+        let source = std::fs::read_to_string(path).map_err(|e| ScriptError(format!("{}: {}", path.display(), e)))?;
+        Script::new(&source, cpu).map_err(|ScriptError(e)| ScriptError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Call on_frame() and make the changes the script's asked for since the last frame.
+    /// An error means the script threw or broke, and the run should stop.
+    pub fn end_frame(&mut self, cpu: &mut Cpu) -> Result<(), ScriptError> {
+        if self.hooks.on_frame {
+            self.call(cpu, "on_frame", ());
+        }
+        let changes = std::mem::take(&mut self.shared.lock().unwrap().changes);
+        for change in changes {
+            match change {
+                Change::Key(key, pressed) => cpu.set_key(key, pressed),
+                Change::Register(x, value) => cpu.registers[x] = value,
+                Change::Poke(addr, value) => {
+                    if let Some(byte) = cpu.memory.get_mut(addr) {
+                        *byte = value;
+                        cpu.mark_initialized(addr, 1);
+                    }
+                }
+            }
+        }
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    // Run one of the script's functions, keeping the error if it's the first
+    fn call(&mut self, cpu: &Cpu, name: &str, args: impl FuncArgs) {
+        if self.error.is_some() {
+            return;
+        }
+        self.shared.lock().unwrap().look_at(cpu);
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.this);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args);
+        if let Err(e) = result {
+            self.fail(format!("{}: {}", name, e));
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.error.get_or_insert(ScriptError(message));
+    }
+}
+
+impl Observer for Script {
+    fn before_instruction(&mut self, cpu: &Cpu, pc: usize) {
+        if self.hooks.on_instruction {
+            self.call(cpu, "on_instruction", (pc as i64,));
+        }
+    }
+
+    fn memory_write(&mut self, cpu: &Cpu, addr: usize, value: u8) {
+        if self.hooks.on_write {
+            self.call(cpu, "on_write", (addr as i64, value as i64));
+        }
+    }
+
+    fn draw(&mut self, cpu: &Cpu, x: usize, y: usize, collision: bool) {
+        if self.hooks.on_draw {
+            self.call(cpu, "on_draw", (x as i64, y as i64, collision));
+        }
+    }
+
+    fn key_wait(&mut self, cpu: &Cpu, register: u8) {
+        if self.hooks.on_key_wait {
+            self.call(cpu, "on_key_wait", (register as i64,));
+        }
+    }
+}
+
+// A register, key or byte number from the script, if it's in 0..limit
+fn index(what: &str, n: i64, limit: usize) -> Result<usize, Box<EvalAltResult>> {
+    usize::try_from(n).ok().filter(|&n| n < limit).ok_or_else(|| format!("there's no {} {}", what, n).into())
+}
+
+fn byte(value: i64) -> Result<u8, Box<EvalAltResult>> {
+    u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", value).into())
+}
+
+// The functions from the top of this file
+fn register_functions(engine: &mut Engine, shared: &Arc<Mutex<Shared>>) {
+    let read = |engine: &mut Engine, name: &str, get: fn(&Shared) -> i64| {
+        let shared = shared.clone();
+        engine.register_fn(name, move || get(&shared.lock().unwrap()));
+    };
+    read(engine, "pc", |machine| machine.pc as i64);
+    read(engine, "i", |machine| machine.i as i64);
+    read(engine, "sp", |machine| machine.sp as i64);
+    read(engine, "dt", |machine| machine.dt as i64);
+    read(engine, "st", |machine| machine.st as i64);
+    read(engine, "frame", |machine| machine.frame as i64);
+    read(engine, "instructions", |machine| machine.instructions as i64);
+
+    let machine = shared.clone();
+    engine.register_fn("v", move |x: i64| -> Result<i64, Box<EvalAltResult>> {
+        Ok(machine.lock().unwrap().registers[index("register", x, 16)?] as i64)
+    });
+    let machine = shared.clone();
+    engine.register_fn("peek", move |addr: i64| -> Result<i64, Box<EvalAltResult>> {
+        let machine = machine.lock().unwrap();
+        Ok(machine.memory[index("address", addr, machine.memory.len())?] as i64)
+    });
+    let machine = shared.clone();
+    engine.register_fn("key", move |k: i64| -> Result<bool, Box<EvalAltResult>> {
+        Ok(machine.lock().unwrap().keypad[index("key", k, 16)?])
+    });
+
+    let machine = shared.clone();
+    engine.register_fn("press", move |k: i64| -> Result<(), Box<EvalAltResult>> {
+        let key = index("key", k, 16)? as u8;
+        machine.lock().unwrap().changes.push(Change::Key(key, true));
+        Ok(())
+    });
+    let machine = shared.clone();
+    engine.register_fn("release", move |k: i64| -> Result<(), Box<EvalAltResult>> {
+        let key = index("key", k, 16)? as u8;
+        machine.lock().unwrap().changes.push(Change::Key(key, false));
+        Ok(())
+    });
+    let machine = shared.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+        let value = byte(value)?;
+        let mut machine = machine.lock().unwrap();
+        let addr = index("address", addr, machine.memory.len())?;
+        machine.changes.push(Change::Poke(addr, value));
+        Ok(())
+    });
+    let machine = shared.clone();
+    engine.register_fn("set_v", move |x: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+        let x = index("register", x, 16)?;
+        let value = byte(value)?;
+        machine.lock().unwrap().changes.push(Change::Register(x, value));
+        Ok(())
+    });
+}
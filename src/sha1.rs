@@ -0,0 +1,79 @@
+// SHA-1.
+// ROM lists (the ROM database, the CHIP-8 community's own databases) identify ROMs by their
+// SHA-1. It's long broken for anything security related, but for telling files apart it's
+// fine, and it's short enough to do here rather than pull in a crate for it.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+/// The SHA-1 of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+    // the data, a 1 bit, zeroes up to 8 bytes short of a 64 byte block, then the length in bits
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    let padded_len = (data.len() + 9).div_ceil(64) * 64;
+    let byte = |n: usize| match n {
+        n if n < data.len() => data[n],
+        n if n == data.len() => 0x80,
+        n if n >= padded_len - 8 => (bit_len >> ((padded_len - 1 - n) * 8)) as u8,
+        _ => 0,
+    };
+
+    for block in (0..padded_len).step_by(64) {
+        let mut w = [0u32; 80];
+        for (n, word) in w.iter_mut().take(16).enumerate() {
+            let at = block + n * 4;
+            *word = u32::from_be_bytes([byte(at), byte(at + 1), byte(at + 2), byte(at + 3)]);
+        }
+        for n in 16..80 {
+            w[n] = (w[n - 3] ^ w[n - 8] ^ w[n - 14] ^ w[n - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (n, &word) in w.iter().enumerate() {
+            let (f, k) = match n {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (total, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *total = total.wrapping_add(value);
+        }
+    }
+
+    let mut hash = [0; 20];
+    for (chunk, word) in hash.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+/// A hash as lowercase hex, the way ROM lists write it.
+pub fn to_hex(hash: &[u8; 20]) -> String {
+    let mut hex = String::with_capacity(40);
+    for byte in hash {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// The other way, None unless it's exactly 40 hex digits.
+pub fn from_hex(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 || !text.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (n, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[n * 2..n * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
@@ -0,0 +1,80 @@
+// Sixel graphics.
+// Sixel is DEC's old way of putting bitmaps in a terminal, still supported by xterm (with
+// -ti vt340), foot, mlterm, WezTerm and a few others. The image goes out in bands 6 pixels high.
+// Each band is painted once per colour, one character per column whose 6 bits say which of
+// the column's pixels get that colour, with `$` going back to the start of the band and `-`
+// moving down to the next one. Runs of the same character are written as !<count><char>, which
+// makes the blown up CHIP-8 pixels cheap.
+
+use std::fmt::Write;
+
+use crate::display::Display;
+use crate::palette::Palette;
+
+// The image is this many pixels across whatever the resolution, so hires doesn't get twice as big
+const IMAGE_WIDTH: usize = 512;
+
+/// The screen as a sixel image, ready to write to the terminal at the cursor.
+pub fn encode(display: &Display, palette: &Palette) -> String {
+    let scale = (IMAGE_WIDTH / display.width()).max(1);
+    let height = display.height() * scale;
+
+    // DCS, 1:1 pixels, then the size so the terminal can clear the area first
+    let mut out = String::from("\x1bP0;0;0q");
+    let _ = write!(out, "\"1;1;{};{}", display.width() * scale, height);
+    for color in 0..4 {
+        // colours are in percent
+        let [r, g, b] = palette.rgb(color).map(|c| c as u32 * 100 / 255);
+        let _ = write!(out, "#{};2;{};{};{}", color, r, g, b);
+    }
+
+    let rows: Vec<&[u8]> = display.rows().collect();
+    for band in (0..height).step_by(6) {
+        // which CHIP-8 row each of the band's 6 pixel rows comes from
+        let band_rows: Vec<&[u8]> = (band..(band + 6).min(height)).map(|y| rows[y / scale]).collect();
+
+        let mut first = true;
+        for color in 0..4u8 {
+            let sixels: Vec<u8> = (0..display.width())
+                .map(|x| {
+                    let bits = band_rows
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, row)| row[x] == color)
+                        .fold(0, |bits, (n, _)| bits | 1 << n);
+                    b'?' + bits
+                })
+                .collect();
+            if sixels.iter().all(|&sixel| sixel == b'?') {
+                continue;
+            }
+
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            let _ = write!(out, "#{}", color);
+            write_runs(&mut out, &sixels, scale);
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+// Each sixel repeated `scale` times, with repeats run length encoded
+fn write_runs(out: &mut String, sixels: &[u8], scale: usize) {
+    let mut x = 0;
+    while x < sixels.len() {
+        let sixel = sixels[x];
+        let same = sixels[x..].iter().take_while(|&&s| s == sixel).count();
+        let run = same * scale;
+        if run > 3 {
+            let _ = write!(out, "!{}{}", run, sixel as char);
+        } else {
+            out.extend(std::iter::repeat_n(sixel as char, run));
+        }
+        x += same;
+    }
+}
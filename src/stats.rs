@@ -0,0 +1,118 @@
+// Runtime stats.
+// How fast a run is really going, as opposed to how fast it's meant to go: the CPU counts the
+// instructions it retires and the timer ticks, the frontend counts the frames it presents,
+// and a StatsMeter turns those into rates. `chip8 run --show-stats` keeps a line of them at
+// the bottom of the terminal, the graphical debugger shows them in its Timing panel, and
+// headless runs print them when they finish.
+//
+// IPS should sit at the clock speed and FPS at 60. Lower means the host can't keep up, and
+// fast-forward pushes both up. The rates are over the last half second, so they settle
+// quickly after a change without flickering every frame.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+use crate::cpu::Cpu;
+
+// How long the current rates are measured over
+#[cfg(feature = "std")]
+const WINDOW: Duration = Duration::from_millis(500);
+
+/// How a run is going.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Instructions run
+    pub instructions: u64,
+    /// Frames shown by the frontend (fewer than emulated in fast-forward)
+    pub frames: u64,
+    /// 60Hz timer ticks, one per emulated frame
+    pub timer_ticks: u64,
+    /// Instructions per second
+    pub ips: f64,
+    /// Frames shown per second
+    pub fps: f64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} IPS  {:.1} FPS  {} instructions  {} frames  {} timer ticks",
+            self.ips, self.fps, self.instructions, self.frames, self.timer_ticks
+        )
+    }
+}
+
+/// Keeps the counts and works out the rates. Call frame_presented() whenever the frontend
+/// shows a frame and current() to read them.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct StatsMeter {
+    started: Instant,
+    started_instructions: u64,
+    frames: u64,
+    // where the current window started, and the counts then
+    window_start: Instant,
+    window_instructions: u64,
+    window_frames: u64,
+    ips: f64,
+    fps: f64,
+}
+
+#[cfg(feature = "std")]
+impl StatsMeter {
+    /// Start measuring from now, counting from wherever `cpu` has got to.
+    pub fn new(cpu: &Cpu) -> Self {
+        let now = Instant::now();
+        StatsMeter {
+            started: now,
+            started_instructions: cpu.instructions_retired(),
+            frames: 0,
+            window_start: now,
+            window_instructions: cpu.instructions_retired(),
+            window_frames: 0,
+            ips: 0.0,
+            fps: 0.0,
+        }
+    }
+
+    /// The frontend showed a frame.
+    pub fn frame_presented(&mut self) {
+        self.frames += 1;
+    }
+
+    /// The stats with the rates over the last half second. Call it often (every frame), the
+    /// rates only move on when it's called.
+    pub fn current(&mut self, cpu: &Cpu) -> Stats {
+        let now = Instant::now();
+        let elapsed = now - self.window_start;
+        if elapsed >= WINDOW {
+            let seconds = elapsed.as_secs_f64();
+            self.ips = (cpu.instructions_retired() - self.window_instructions) as f64 / seconds;
+            self.fps = (self.frames - self.window_frames) as f64 / seconds;
+            self.window_start = now;
+            self.window_instructions = cpu.instructions_retired();
+            self.window_frames = self.frames;
+        }
+        self.stats(cpu, self.ips, self.fps)
+    }
+
+    /// The stats with the rates averaged over the whole run, for the end of it.
+    pub fn overall(&self, cpu: &Cpu) -> Stats {
+        let seconds = self.started.elapsed().as_secs_f64().max(f64::EPSILON);
+        let ips = (cpu.instructions_retired() - self.started_instructions) as f64 / seconds;
+        self.stats(cpu, ips, self.frames as f64 / seconds)
+    }
+
+    fn stats(&self, cpu: &Cpu, ips: f64, fps: f64) -> Stats {
+        Stats {
+            instructions: cpu.instructions_retired(),
+            frames: self.frames,
+            timer_ticks: cpu.timer_ticks(),
+            ips,
+            fps,
+        }
+    }
+}
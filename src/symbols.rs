@@ -0,0 +1,209 @@
+// Symbols.
+// Names for addresses in a ROM, so the debuggers can show CALL draw_sprite instead of
+// CALL 0x2A4 and take breakpoints by name. Assemblers know every label's address, and a
+// symbol file is just that list written out. Either of these per line, mixed as you like:
+//
+//     draw_sprite = 0x2A4      ; NAME = VALUE, the value in hex with 0x or decimal (Octo's :const)
+//     2A4 draw_sprite          # ADDRESS NAME, the address in hex with or without 0x
+//
+// Comments start with ; or #. A name can only have one address, the last line wins. If two
+// names share an address, the first one is what gets shown.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+
+/// Names for addresses, looked up either way round.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    by_name: BTreeMap<String, usize>,
+    // the first name given for each address
+    by_addr: BTreeMap<usize, String>,
+}
+
+/// A symbol file line that didn't parse. `line` counts from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl core::error::Error for SymbolError {}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    /// Read a symbol file, in either of the forms at the top of this file.
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Symbols::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason| SymbolError { line: n + 1, reason };
+            let (name, addr) = match line.split_once('=') {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let addr = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+                        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+                        None => value.parse().ok(),
+                    };
+                    (name.trim(), addr.ok_or_else(|| error("the value should be hex with 0x or decimal"))?)
+                }
+                None => {
+                    let mut fields = line.split_whitespace();
+                    let addr = fields.next().unwrap_or("");
+                    let addr = addr.strip_prefix("0x").unwrap_or(addr);
+                    let addr = usize::from_str_radix(addr, 16).map_err(|_| error("expected NAME = VALUE or a hex address and a name"))?;
+                    let name = fields.next().ok_or_else(|| error("missing the name after the address"))?;
+                    if fields.next().is_some() {
+                        return Err(error("names can't have spaces in"));
+                    }
+                    (name, addr)
+                }
+            };
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(error("names can't be empty or have spaces in"));
+            }
+            symbols.insert(name, addr);
+        }
+        Ok(symbols)
+    }
+
+    /// Read a symbol file from disk.
+    #[cfg(feature = "std")]
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Symbols::parse(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Name `addr`, replacing wherever the name pointed before.
+    pub fn insert(&mut self, name: &str, addr: usize) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr) {
+            if self.by_addr.get(&old).is_some_and(|other| other == name) {
+                self.by_addr.remove(&old);
+                // another name for the old address can take over
+                if let Some((other, _)) = self.by_name.iter().find(|&(other, &addr)| addr == old && other != name) {
+                    self.by_addr.insert(old, other.clone());
+                }
+            }
+        }
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    /// The name for an address.
+    pub fn name(&self, addr: usize) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    /// The address a name is for.
+    pub fn addr(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// An instruction's mnemonic with the address it uses swapped for its name, when it has one.
+    /// `quirks` decides how BNNN reads, see Instruction::disassemble().
+    pub fn format(&self, instruction: &Instruction, quirks: &Quirks) -> String {
+        use Instruction::*;
+        let jump_register;
+        let (mnemonic, addr) = match *instruction {
+            Jump(nnn) => ("JP", nnn),
+            Call(nnn) => ("CALL", nnn),
+            LoadIndex(nnn) => ("LD I,", nnn),
+            JumpWithOffset(x, nnn) if quirks.jump_offset_uses_vx => {
+                jump_register = format!("JP V{:X},", x);
+                (jump_register.as_str(), nnn)
+            }
+            JumpWithOffset(_, nnn) => ("JP V0,", nnn),
+            LoadLongIndex(nnnn) => ("LD I, long", nnnn),
+            _ => return instruction.disassemble(quirks),
+        };
+        match self.name(addr as usize) {
+            Some(name) => format!("{} {}", mnemonic, name),
+            None => instruction.disassemble(quirks),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    const FILE: &str = "\
+; from the assembler
+draw_sprite = 0x2A4
+lives = 3          ; decimal
+0x2B0 main_loop    # with 0x
+2c2 game_over
+";
+
+    #[test]
+    fn both_forms() {
+        let symbols = Symbols::parse(FILE).unwrap();
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.addr("draw_sprite"), Some(0x2A4));
+        assert_eq!(symbols.addr("lives"), Some(3));
+        assert_eq!(symbols.addr("main_loop"), Some(0x2B0));
+        assert_eq!(symbols.name(0x2C2), Some("game_over"));
+        assert_eq!(symbols.name(0x2C4), None);
+        assert!(Symbols::parse("\n  ; nothing here\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_lines() {
+        let error = |text| Symbols::parse(text).unwrap_err();
+        assert_eq!(error("a = 0x2A4\nb = 2A4").line, 2);
+        assert_eq!(error("b = 2A4").reason, "the value should be hex with 0x or decimal");
+        assert_eq!(error("main 200").reason, "expected NAME = VALUE or a hex address and a name");
+        assert_eq!(error("200").reason, "missing the name after the address");
+        assert_eq!(error("200 main loop").reason, "names can't have spaces in");
+        assert_eq!(error(" = 0x200").reason, "names can't be empty or have spaces in");
+        assert_eq!(error("main loop = 0x200").to_string(), "line 1: names can't be empty or have spaces in");
+    }
+
+    #[test]
+    fn the_last_address_and_the_first_name_win() {
+        let symbols = Symbols::parse("200 start\n202 start\n202 again\n").unwrap();
+        assert_eq!(symbols.addr("start"), Some(0x202));
+        assert_eq!(symbols.name(0x200), None);
+        assert_eq!(symbols.name(0x202), Some("start"));
+
+        // moving a name lets the other name for its old address show
+        let mut symbols = Symbols::parse("200 start\n200 entry\n").unwrap();
+        symbols.insert("start", 0x300);
+        assert_eq!(symbols.name(0x200), Some("entry"));
+        assert_eq!(symbols.name(0x300), Some("start"));
+    }
+
+    #[test]
+    fn named_operands() {
+        let symbols = Symbols::parse("2A4 draw_sprite\n300 table\n").unwrap();
+        let vip = Quirks::cosmac_vip();
+        assert_eq!(symbols.format(&Instruction::Call(0x2A4), &vip), "CALL draw_sprite");
+        assert_eq!(symbols.format(&Instruction::LoadIndex(0x300), &vip), "LD I, table");
+        assert_eq!(symbols.format(&Instruction::Jump(0x2A6), &vip), "JP 0x2A6");
+        assert_eq!(symbols.format(&Instruction::LoadByte(0, 0x12), &vip), "LD V0, 0x12");
+    }
+}
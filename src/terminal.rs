@@ -0,0 +1,381 @@
+// Terminal frontend.
+// Draws the screen with block characters (or half blocks, braille, sixel or kitty graphics, see
+// Renderer) and reads the keypad from the keyboard using crossterm.
+// Most terminals only report key presses, never releases, so unless the terminal supports the
+// kitty keyboard protocol we treat a press as holding the key down for a few frames
+// (auto-repeat keeps refreshing it while the key really is held).
+
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+
+use crate::cpu::Cpu;
+use crate::display::Display;
+use crate::frontend::{DisplaySink, FrontendError, InputSource};
+use crate::keymap::{Hotkey, Keymap, Player};
+use crate::kitty;
+use crate::palette::Palette;
+use crate::sixel;
+use crate::variant::Variant;
+
+// Picks the character for a cell of pixels, given a bit per pixel that's on
+type Glyph = fn(u8) -> char;
+
+// How long a press counts as held when we can't see the release, in 60Hz frames
+const HOLD_FRAMES: u8 = 8;
+
+/// How the screen gets drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    /// One block character per pixel, works everywhere
+    #[default]
+    Blocks,
+    /// Half block characters, 1x2 pixels a character
+    HalfBlocks,
+    /// Braille characters, 2x4 pixels a character, a SCHIP screen fits in 64x16
+    Braille,
+    /// The real pixels as a sixel image, for terminals that support it
+    Sixel,
+    /// The real pixels through the kitty graphics protocol (kitty, WezTerm, Ghostty)
+    Kitty,
+}
+
+impl Renderer {
+    /// The best the terminal looks like it can do: kitty graphics if it says it supports
+    /// them, otherwise block characters.
+    pub fn detect() -> Self {
+        if kitty::is_supported() {
+            Renderer::Kitty
+        } else {
+            Renderer::Blocks
+        }
+    }
+
+    /// The next of the renderers that only use text (Blocks, HalfBlocks, Braille), for
+    /// switching between them while running. Graphics renderers go back to Blocks.
+    pub fn next_text(self) -> Self {
+        match self {
+            Renderer::Blocks => Renderer::HalfBlocks,
+            Renderer::HalfBlocks => Renderer::Braille,
+            _ => Renderer::Blocks,
+        }
+    }
+}
+
+pub struct TerminalFrontend {
+    stdout: Stdout,
+    keymap: Keymap,
+    renderer: Renderer,
+    palette: Palette,
+    // the screen's changes() and size when it was last drawn, None when the next draw has to
+    // start from scratch
+    drawn: Option<(u64, (usize, usize))>,
+    // true when the terminal tells us about key releases
+    reports_releases: bool,
+    // whether draw_overlay() has text over the screen
+    overlaid: bool,
+    held: HeldKeys,
+}
+
+// For terminals that only report presses: frames left before each key is let go, each player's
+#[derive(Default)]
+pub(crate) struct HeldKeys {
+    frames: [[u8; 16]; 2],
+}
+
+impl HeldKeys {
+    // Press a key and hold it for a while, pressing it again (auto-repeat) keeps it held
+    pub(crate) fn press(&mut self, cpu: &mut Cpu, player: Player, key: u8) {
+        set_player_key(cpu, player, key, true);
+        self.frames[player as usize][key as usize] = HOLD_FRAMES;
+    }
+
+    // Once per frame, lets go of keys whose hold time has run out
+    pub(crate) fn end_frame(&mut self, cpu: &mut Cpu) {
+        for (player, frames) in [Player::One, Player::Two].into_iter().zip(&mut self.frames) {
+            for (key, frames) in frames.iter_mut().enumerate() {
+                if *frames > 0 {
+                    *frames -= 1;
+                    if *frames == 0 {
+                        set_player_key(cpu, player, key as u8, false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Player 2 has CHIP-8X's second keypad to themselves, every other machine has one keypad for
+// both players to share
+pub(crate) fn set_player_key(cpu: &mut Cpu, player: Player, key: u8, pressed: bool) {
+    match player {
+        Player::Two if cpu.variant == Variant::Chip8X => cpu.set_key2(key, pressed),
+        _ => cpu.set_key(key, pressed),
+    }
+}
+
+impl TerminalFrontend {
+    /// Switch the terminal into raw mode on the alternate screen. Dropping it switches back.
+    pub fn open(keymap: Keymap) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(TerminalFrontend {
+            stdout,
+            keymap,
+            renderer: Renderer::default(),
+            palette: Palette::default(),
+            drawn: None,
+            reports_releases,
+            overlaid: false,
+            held: HeldKeys::default(),
+        })
+    }
+
+    /// Feed any pending key events to the keypad and return the hotkeys that were pressed.
+    /// Ctrl-C counts as Quit too, raw mode stops it being a signal.
+    pub fn poll_input(&mut self, cpu: &mut Cpu) -> io::Result<Vec<Hotkey>> {
+        let mut hotkeys = Vec::new();
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+                continue;
+            };
+
+            let hotkey = match code {
+                KeyCode::Esc => Some(Hotkey::Quit),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => Some(Hotkey::Quit),
+                KeyCode::Tab => Some(Hotkey::ToggleTurbo),
+                KeyCode::Char('p') => Some(Hotkey::TogglePause),
+                KeyCode::Char('n') => Some(Hotkey::AdvanceFrame),
+                KeyCode::F(2) => Some(Hotkey::NextTextRenderer),
+                KeyCode::F(3) => Some(Hotkey::ToggleOverlay),
+                KeyCode::F(4) => Some(Hotkey::ToggleMute),
+                KeyCode::F(10) => Some(Hotkey::ToggleRecording),
+                KeyCode::F(12) => Some(Hotkey::Screenshot),
+                _ => None,
+            };
+            if let Some(hotkey) = hotkey {
+                if kind != KeyEventKind::Release {
+                    hotkeys.push(hotkey);
+                }
+                continue;
+            }
+
+            let KeyCode::Char(c) = code else { continue };
+            let Some((player, key)) = self.keymap.player_key_for(c) else { continue };
+
+            match kind {
+                KeyEventKind::Release => set_player_key(cpu, player, key, false),
+                _ if self.reports_releases => set_player_key(cpu, player, key, true),
+                _ => self.held.press(cpu, player, key),
+            }
+        }
+        Ok(hotkeys)
+    }
+
+    /// Call once per 60Hz frame, lets go of keys whose hold time has run out.
+    pub fn end_frame(&mut self, cpu: &mut Cpu) {
+        if !self.reports_releases {
+            self.held.end_frame(cpu);
+        }
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    /// Switch how the screen is drawn, from the next draw() on.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if self.renderer == Renderer::Kitty {
+            let _ = self.stdout.write_all(kitty::CLEAR.as_bytes());
+        }
+        self.renderer = renderer;
+        self.drawn = None;
+        let _ = queue!(self.stdout, terminal::Clear(terminal::ClearType::All));
+    }
+
+    /// Change the colours. The text renderers only have two, the background and foreground.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.drawn = None;
+    }
+
+    /// Bring the terminal up to date with the screen. The text renderers only rewrite the lines
+    /// that changed since last time (Display::rows_changed_since()), which matters over a slow
+    /// SSH connection. The graphics ones send a whole new image, but only if anything changed.
+    pub fn draw(&mut self, display: &Display) -> io::Result<()> {
+        let size = (display.width(), display.height());
+        let since = match self.drawn {
+            Some((changes, drawn_size)) if drawn_size == size => Some(changes),
+            _ => None,
+        };
+        self.drawn = Some((display.changes(), size));
+
+        let glyphs: Option<(usize, usize, Glyph)> = match self.renderer {
+            Renderer::Blocks => Some((1, 1, block)),
+            Renderer::HalfBlocks => Some((1, 2, half_block)),
+            Renderer::Braille => Some((2, 4, braille)),
+            Renderer::Sixel | Renderer::Kitty => None,
+        };
+        let Some((cell_width, cell_height, glyph)) = glyphs else {
+            // phosphor decay keeps changing the picture after the screen stops changing
+            if since == Some(display.changes()) && display.phosphor_decay() == 0 {
+                return Ok(());
+            }
+            let image = match self.renderer {
+                Renderer::Sixel => sixel::encode(display, &self.palette),
+                _ => kitty::encode(display, &self.palette)?,
+            };
+            queue!(self.stdout, cursor::MoveTo(0, 0))?;
+            self.stdout.write_all(image.as_bytes())?;
+            return self.stdout.flush();
+        };
+
+        let mut lines: Vec<usize> = match since {
+            Some(since) => display.rows_changed_since(since).map(|y| y / cell_height).collect(),
+            None => (0..size.1.div_ceil(cell_height)).collect(),
+        };
+        lines.dedup();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let rgb = |[r, g, b]: [u8; 3]| Color::Rgb { r, g, b };
+        queue!(
+            self.stdout,
+            SetForegroundColor(rgb(self.palette.foreground())),
+            SetBackgroundColor(rgb(self.palette.background())),
+        )?;
+        if since.is_none() {
+            // the screen might have been bigger last time
+            queue!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
+        }
+        for line in lines {
+            queue!(self.stdout, cursor::MoveTo(0, line as u16))?;
+            let text = Self::line(display, line * cell_height, cell_width, cell_height, glyph);
+            self.stdout.write_all(text.as_bytes())?;
+        }
+        self.stdout.flush()
+    }
+
+    /// Write `lines` over the top left corner of the screen (the debug overlay), in the screen's
+    /// colours the other way round so they stand out. None takes them off again, the next
+    /// draw() puts back what was underneath.
+    pub fn draw_overlay(&mut self, lines: Option<&[String]>) -> io::Result<()> {
+        let Some(lines) = lines else {
+            if self.overlaid {
+                self.overlaid = false;
+                self.drawn = None;
+            }
+            return Ok(());
+        };
+        let rgb = |[r, g, b]: [u8; 3]| Color::Rgb { r, g, b };
+        queue!(
+            self.stdout,
+            SetForegroundColor(rgb(self.palette.background())),
+            SetBackgroundColor(rgb(self.palette.foreground())),
+        )?;
+        for (row, line) in lines.iter().enumerate() {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            self.stdout.write_all(line.as_bytes())?;
+        }
+        self.overlaid = true;
+        self.stdout.flush()
+    }
+
+    /// Write a line of text (stats, say) along the bottom of the terminal, under the screen.
+    pub fn draw_status(&mut self, text: &str) -> io::Result<()> {
+        let (_, rows) = terminal::size()?;
+        queue!(
+            self.stdout,
+            ResetColor,
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        )?;
+        self.stdout.write_all(text.as_bytes())?;
+        self.stdout.flush()
+    }
+
+    // One line of characters, each covering `width` x `height` pixels. `glyph` gets a bit per
+    // pixel that's on (across then down)
+    fn line(display: &Display, top: usize, width: usize, height: usize, glyph: Glyph) -> String {
+        let mut line = String::with_capacity(display.width() * 3);
+        for left in (0..display.width()).step_by(width) {
+            let mut bits = 0;
+            for n in 0..width * height {
+                let (x, y) = (left + n % width, top + n / width);
+                if x < display.width() && y < display.height() && display.pixel(x, y) {
+                    bits |= 1 << n;
+                }
+            }
+            line.push(glyph(bits));
+        }
+        line
+    }
+}
+
+fn block(bits: u8) -> char {
+    if bits != 0 { '█' } else { ' ' }
+}
+
+// Top pixel in bit 0, bottom in bit 1
+fn half_block(bits: u8) -> char {
+    [' ', '▀', '▄', '█'][bits as usize]
+}
+
+// Pixels across then down in the bits, braille numbers its dots down the left column first
+// (1 2 3 then 7 for the bottom row), so they need shuffling into place
+fn braille(bits: u8) -> char {
+    if bits == 0 {
+        // a blank braille pattern shows as a box in some fonts
+        return ' ';
+    }
+    const DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+    let dots = (0..8).filter(|n| bits & 1 << n != 0).fold(0, |dots, n| dots | DOTS[n]);
+    char::from_u32(0x2800 + dots).unwrap_or(' ')
+}
+
+impl DisplaySink for TerminalFrontend {
+    fn present(&mut self, display: &Display) -> Result<(), FrontendError> {
+        Ok(self.draw(display)?)
+    }
+
+    fn overlay(&mut self, lines: Option<&[String]>) -> Result<(), FrontendError> {
+        Ok(self.draw_overlay(lines)?)
+    }
+}
+
+impl InputSource for TerminalFrontend {
+    fn poll(&mut self, cpu: &mut Cpu) -> Result<Vec<Hotkey>, FrontendError> {
+        Ok(self.poll_input(cpu)?)
+    }
+
+    fn end_frame(&mut self, cpu: &mut Cpu) {
+        TerminalFrontend::end_frame(self, cpu)
+    }
+}
+
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        if self.renderer == Renderer::Kitty {
+            let _ = self.stdout.write_all(kitty::CLEAR.as_bytes());
+        }
+        if self.reports_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
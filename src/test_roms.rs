@@ -0,0 +1,159 @@
+// Test ROM suites.
+// Runs test ROMs (Timendus' chip8-test-suite, BC_test and the like) for a fixed number of
+// instructions and checks the screen they finish on against what a correct interpreter shows.
+// A suite is a directory holding the ROMs and a suite.txt listing them, one per line:
+//
+//     # rom              variant  cycles   pokes
+//     1-chip8-logo.ch8   chip8    1000
+//     5-quirks.ch8       schip    2000000  1FF=02
+//
+// Pokes are bytes written to memory after the ROM is loaded (ADDR=BYTE, both hex). Timendus'
+// ROMs read 0x1FF to pick which platform to test, so that's how to skip their menus.
+// The expected screen for foo.ch8 is foo.ch8.screen, in Display::to_text() form (the same as
+// `chip8 run --headless` prints). --bless writes them from whatever the ROMs show now.
+// A .screen file can hold just a Display::hash() in hex instead, which is all a suite needs
+// to pass or fail but doesn't show what went wrong.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Cpu;
+use crate::error::CpuError;
+use crate::halt::LoopDetection;
+use crate::rom_format;
+use crate::variant::Variant;
+
+/// The file listing a suite's ROMs.
+pub const SUITE_FILE: &str = "suite.txt";
+
+/// One ROM in a suite and how to run it.
+pub struct TestRom {
+    pub rom: PathBuf,
+    pub variant: Variant,
+    /// How many instructions to run before looking at the screen
+    pub cycles: u64,
+    /// Bytes to write into memory after loading, (address, value)
+    pub pokes: Vec<(usize, u8)>,
+}
+
+/// How a test ROM went.
+pub enum Outcome {
+    Pass,
+    /// The screen wasn't what was expected, `pixels` is how many differ (None when only the
+    /// hash was known)
+    Fail { pixels: Option<usize>, actual: String },
+    /// There's no expected screen to compare against, run with --bless to make one
+    NoExpectedScreen,
+    /// The ROM did something broken (the CPU runs hardened)
+    Fault(CpuError),
+}
+
+/// Read the suite.txt in `dir`.
+pub fn load_suite(dir: &Path) -> io::Result<Vec<TestRom>> {
+    let listing = fs::read_to_string(dir.join(SUITE_FILE))?;
+    let mut roms = Vec::new();
+    for (n, line) in listing.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad_line = |what: &str| {
+            let message = format!("{} line {}: {}", SUITE_FILE, n + 1, what);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        };
+
+        let mut fields = line.split_whitespace();
+        let rom = fields.next().ok_or_else(|| bad_line("missing ROM"))?;
+        let variant = fields.next().ok_or_else(|| bad_line("missing variant"))?;
+        let variant = Variant::from_name(variant).ok_or_else(|| bad_line("variant should be chip8, schip, xochip, chip8x, megachip, eti660 or chip8hires"))?;
+        let cycles = fields.next().ok_or_else(|| bad_line("missing cycle count"))?;
+        let cycles = cycles.parse().map_err(|_| bad_line("cycle count isn't a number"))?;
+        let pokes = fields
+            .map(|poke| parse_poke(poke).ok_or_else(|| bad_line("pokes look like ADDR=BYTE, in hex")))
+            .collect::<io::Result<_>>()?;
+
+        roms.push(TestRom { rom: dir.join(rom), variant, cycles, pokes });
+    }
+    Ok(roms)
+}
+
+fn parse_poke(poke: &str) -> Option<(usize, u8)> {
+    let (addr, value) = poke.split_once('=')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, u8::from_str_radix(value, 16).ok()?))
+}
+
+impl TestRom {
+    /// The ROM's file name, for reports.
+    pub fn name(&self) -> String {
+        self.rom.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    /// Where the screen it should finish on is kept.
+    pub fn screen_path(&self) -> PathBuf {
+        let mut path = self.rom.clone().into_os_string();
+        path.push(".screen");
+        PathBuf::from(path)
+    }
+
+    /// Load and run the ROM, returning the CPU as it finished.
+    pub fn run(&self) -> io::Result<Cpu> {
+        let rom = rom_format::read(&self.rom)?;
+        let mut cpu = Cpu::with_variant(self.variant);
+        cpu.hardened = true;
+        // test ROMs sit in a jump to self once they've drawn their results
+        cpu.loop_detection = LoopDetection { jump_to_self: true, idle_window: None };
+        cpu.load_binary(&rom).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for &(addr, value) in &self.pokes {
+            if let Some(byte) = cpu.memory.get_mut(addr) {
+                *byte = value;
+            }
+        }
+        cpu.flush_decoded();
+
+        cpu.run_for(self.cycles);
+        Ok(cpu)
+    }
+
+    /// Run the ROM and compare its screen with the expected one.
+    pub fn check(&self) -> io::Result<Outcome> {
+        let cpu = self.run()?;
+        if let Some(error) = cpu.fault() {
+            return Ok(Outcome::Fault(error.clone()));
+        }
+        let expected = match fs::read_to_string(self.screen_path()) {
+            Ok(expected) => expected,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Outcome::NoExpectedScreen),
+            Err(e) => return Err(e),
+        };
+
+        let actual = cpu.display.to_text();
+        if let Ok(hash) = u64::from_str_radix(expected.trim(), 16) {
+            let pass = hash == cpu.display.hash();
+            return Ok(if pass { Outcome::Pass } else { Outcome::Fail { pixels: None, actual } });
+        }
+        let pixels = differing_pixels(&expected, &actual);
+        Ok(if pixels == 0 { Outcome::Pass } else { Outcome::Fail { pixels: Some(pixels), actual } })
+    }
+
+    /// Run the ROM and save its screen as the expected one.
+    pub fn bless(&self) -> io::Result<()> {
+        let cpu = self.run()?;
+        fs::write(self.screen_path(), cpu.display.to_text())
+    }
+}
+
+// Pixels that differ between two screens in text form. A different size counts every pixel.
+fn differing_pixels(expected: &str, actual: &str) -> usize {
+    let expected: Vec<&str> = expected.lines().map(str::trim_end).collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let same_size = expected.len() == actual.len() && expected.iter().zip(&actual).all(|(a, b)| a.len() == b.len());
+    if !same_size {
+        return actual.iter().map(|row| row.len()).sum::<usize>().max(1);
+    }
+    expected
+        .iter()
+        .zip(&actual)
+        .map(|(a, b)| a.chars().zip(b.chars()).filter(|(a, b)| a != b).count())
+        .sum()
+}
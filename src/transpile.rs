@@ -0,0 +1,290 @@
+// Static recompiler.
+// `chip8 transpile` turns a ROM into a Rust source file. It follows the ROM's control flow from
+// the entry point (jumps, calls, both sides of every skip) to find the code, then writes each run of
+// register arithmetic out as Rust, ending in the jump or skip that finishes it if there is one.
+// The generated file runs those through Cpu::run_frame_with() as a BlockRunner, and everything
+// else (drawing, input, timers, memory, calls) still goes through the interpreter, so the
+// result is a native binary that behaves exactly like `chip8 run`.
+//
+// Anything the walk can't see, like the target of a BNNN or code the ROM writes for itself,
+// is left to the interpreter too. Writing over a compiled block turns that block off.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::cpu::Cpu;
+use crate::error::CpuError;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use crate::variant::Variant;
+
+// One compiled run of instructions
+struct Block {
+    start: usize,
+    end: usize,
+    len: usize,
+    body: Vec<String>,
+}
+
+/// Recompile `rom` into the source of a standalone program that plays it in the terminal.
+/// `name` is only used in the comments at the top.
+pub fn transpile(rom: &[u8], variant: Variant, name: &str) -> Result<String, CpuError> {
+    let mut cpu = Cpu::with_variant(variant);
+    cpu.load_rom(rom)?;
+
+    let blocks: Vec<Block> = find_block_starts(&cpu.memory, variant)
+        .into_iter()
+        .filter_map(|start| compile_block(&cpu.memory, start, variant, cpu.quirks))
+        .collect();
+
+    let mut out = String::new();
+    write_source(&mut out, rom, variant, name, &blocks).expect("writing to a String can't fail");
+    Ok(out)
+}
+
+// Walk everything reachable from the entry point, returning where the blocks should start:
+// the entry point, anything jumped or called to, both sides of a skip, and whatever follows an
+// instruction the recompiler leaves to the interpreter
+fn find_block_starts(memory: &[u8], variant: Variant) -> BTreeSet<usize> {
+    use Instruction::*;
+
+    let mut starts = BTreeSet::new();
+    let mut seen = vec![false; memory.len()];
+    let mut to_visit = vec![variant.entry_point()];
+    starts.insert(variant.entry_point());
+
+    while let Some(addr) = to_visit.pop() {
+        if addr + 1 >= memory.len() || seen[addr] {
+            continue;
+        }
+        seen[addr] = true;
+
+        let instruction = Instruction::decode_at(memory, addr, variant);
+        let next = addr + instruction.size();
+        let mut branch_to = |target: usize, to_visit: &mut Vec<usize>| {
+            starts.insert(target);
+            to_visit.push(target);
+        };
+        match instruction {
+            // nowhere to go, or nowhere we can know about before it runs
+            Halt | Exit | Return | JumpWithOffset(..) | Unknown(_) => {}
+            Jump(nnn) => branch_to(nnn as usize, &mut to_visit),
+            Call(nnn) => {
+                branch_to(nnn as usize, &mut to_visit);
+                branch_to(next, &mut to_visit);
+            }
+            SkipIfEqual(..)
+            | SkipIfNotEqual(..)
+            | SkipIfRegistersEqual(..)
+            | SkipIfRegistersDiffer(..)
+            | SkipIfKey(_)
+            | SkipIfNotKey(_) => {
+                let skipped = Instruction::decode_at(memory, next, variant).size();
+                branch_to(next, &mut to_visit);
+                branch_to(next + skipped, &mut to_visit);
+            }
+            _ if instruction.only_touches_registers() => to_visit.push(next),
+            _ => branch_to(next, &mut to_visit),
+        }
+    }
+
+    starts.retain(|&addr| addr + 1 < memory.len());
+    starts
+}
+
+// The Rust for the run of instructions at `start`, or None if there's nothing there to compile
+fn compile_block(memory: &[u8], start: usize, variant: Variant, quirks: Quirks) -> Option<Block> {
+    use Instruction::*;
+
+    let mut body = Vec::new();
+    let mut addr = start;
+    let mut len = 0;
+    let mut next_pc = None;
+    while addr + 1 < memory.len() {
+        let instruction = Instruction::decode_at(memory, addr, variant);
+        let next = addr + 2;
+        let skip = |condition: String| {
+            let skipped = Instruction::decode_at(memory, next, variant).size();
+            format!("if {} {{ 0x{:03X} }} else {{ 0x{:03X} }}", condition, next + skipped, next)
+        };
+
+        let end_of_block = match instruction {
+            // jumps to self stay with the interpreter, which is what spots them as infinite loops
+            Jump(nnn) if nnn as usize != addr => Some(format!("0x{:03X}", nnn)),
+            SkipIfEqual(x, kk) => Some(skip(format!("v[0x{:X}] == 0x{:02X}", x, kk))),
+            SkipIfNotEqual(x, kk) => Some(skip(format!("v[0x{:X}] != 0x{:02X}", x, kk))),
+            SkipIfRegistersEqual(x, y) => Some(skip(format!("v[0x{:X}] == v[0x{:X}]", x, y))),
+            SkipIfRegistersDiffer(x, y) => Some(skip(format!("v[0x{:X}] != v[0x{:X}]", x, y))),
+            _ if instruction.only_touches_registers() => None,
+            _ => break,
+        };
+
+        len += 1;
+        addr = next;
+        match end_of_block {
+            Some(target) => {
+                body.push(format!("// {}", instruction));
+                next_pc = Some(target);
+                break;
+            }
+            None => body.push(format!("{} // {}", register_op(instruction, variant, quirks), instruction)),
+        }
+    }
+
+    if len == 0 {
+        return None;
+    }
+    body.push(format!("cpu.position_in_memory = {};", next_pc.unwrap_or_else(|| format!("0x{:03X}", addr))));
+    Some(Block { start, end: addr, len, body })
+}
+
+// One of the instructions only_touches_registers() lets through, as Rust
+fn register_op(instruction: Instruction, variant: Variant, quirks: Quirks) -> String {
+    use Instruction::*;
+
+    // 8XY6/8XYE read VX or VY depending on the machine, which is known now
+    let shift_source = |x: u8, y: u8| if quirks.shift_vx_in_place { x } else { y };
+    match instruction {
+        LoadByte(x, kk) => format!("v[0x{:X}] = 0x{:02X};", x, kk),
+        AddByte(x, kk) => format!("v[0x{:X}] = v[0x{:X}].wrapping_add(0x{:02X});", x, x, kk),
+        Move(x, y) => format!("v[0x{:X}] = v[0x{:X}];", x, y),
+        Or(x, y) => format!("v[0x{:X}] |= v[0x{:X}];", x, y),
+        And(x, y) => format!("v[0x{:X}] &= v[0x{:X}];", x, y),
+        Xor(x, y) => format!("v[0x{:X}] ^= v[0x{:X}];", x, y),
+        Add(x, y) => format!(
+            "{{ let (sum, carry) = v[0x{:X}].overflowing_add(v[0x{:X}]); v[0x{:X}] = sum; v[0xF] = carry as u8; }}",
+            x, y, x
+        ),
+        Sub(x, y) => format!(
+            "{{ let (difference, borrow) = v[0x{:X}].overflowing_sub(v[0x{:X}]); v[0x{:X}] = difference; v[0xF] = !borrow as u8; }}",
+            x, y, x
+        ),
+        SubReversed(x, y) => format!(
+            "{{ let (difference, borrow) = v[0x{:X}].overflowing_sub(v[0x{:X}]); v[0x{:X}] = difference; v[0xF] = !borrow as u8; }}",
+            y, x, x
+        ),
+        ShiftRight(x, y) => format!(
+            "{{ let value = v[0x{:X}]; v[0x{:X}] = value >> 1; v[0xF] = value & 0x1; }}",
+            shift_source(x, y),
+            x
+        ),
+        ShiftLeft(x, y) => format!(
+            "{{ let value = v[0x{:X}]; v[0x{:X}] = value << 1; v[0xF] = value >> 7; }}",
+            shift_source(x, y),
+            x
+        ),
+        LoadIndex(nnn) => format!("cpu.index_register = 0x{:03X};", nnn),
+        AddIndex(x) if quirks.index_overflow_sets_vf => format!(
+            "{{ let sum = cpu.index_register + v[0x{:X}] as u32; v[0xF] = (sum > 0xFFF) as u8; cpu.index_register = sum & 0x{:X}; }}",
+            x,
+            variant.index_mask()
+        ),
+        AddIndex(x) => format!("cpu.index_register = cpu.index_register.wrapping_add(v[0x{:X}] as u32) & 0x{:X};", x, variant.index_mask()),
+        _ => unreachable!("{} doesn't only touch registers", instruction),
+    }
+}
+
+fn write_source(out: &mut String, rom: &[u8], variant: Variant, name: &str, blocks: &[Block]) -> core::fmt::Result {
+    writeln!(out, "// {}, recompiled to Rust by `chip8 transpile`.", name)?;
+    writeln!(out, "//")?;
+    writeln!(out, "// The register arithmetic is native code below, everything else still runs on the")?;
+    writeln!(out, "// chip_8_emulator interpreter. Build it against chip_8_emulator with the `terminal`")?;
+    writeln!(out, "// feature, e.g. save it in the crate's examples/ directory and `cargo run --example`.")?;
+    writeln!(out)?;
+    writeln!(out, "use std::error::Error;")?;
+    writeln!(out)?;
+    writeln!(out, "use chip_8_emulator::clock::Clock;")?;
+    writeln!(out, "use chip_8_emulator::cpu::{{BlockRunner, Cpu}};")?;
+    writeln!(out, "use chip_8_emulator::keymap::{{Hotkey, Keymap}};")?;
+    writeln!(out, "use chip_8_emulator::terminal::TerminalFrontend;")?;
+    writeln!(out, "use chip_8_emulator::Variant;")?;
+    writeln!(out)?;
+
+    writeln!(out, "const ROM: &[u8] = &[")?;
+    for line in rom.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02X},", b)).collect();
+        writeln!(out, "    {}", bytes.join(" "))?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "// Where each compiled block starts and ends, to spot the ROM writing over one")?;
+    writeln!(out, "const BLOCKS: [(usize, usize); {}] = [", blocks.len())?;
+    for block in blocks {
+        writeln!(out, "    (0x{:03X}, 0x{:03X}),", block.start, block.end)?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "struct Recompiled {{")?;
+    writeln!(out, "    // blocks the ROM has written over, the interpreter runs those from then on")?;
+    writeln!(out, "    stale: [bool; {}],", blocks.len())?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    let max = if blocks.is_empty() { "_max" } else { "max" };
+    writeln!(out, "impl BlockRunner for Recompiled {{")?;
+    writeln!(out, "    fn run_block(&mut self, cpu: &mut Cpu, {}: u32) -> u32 {{", max)?;
+    writeln!(out, "        if let Some((start, end)) = cpu.take_code_writes() {{")?;
+    writeln!(out, "            for (n, &(block_start, block_end)) in BLOCKS.iter().enumerate() {{")?;
+    writeln!(out, "                if start < block_end && block_start < end {{")?;
+    writeln!(out, "                    self.stale[n] = true;")?;
+    writeln!(out, "                }}")?;
+    writeln!(out, "            }}")?;
+    writeln!(out, "        }}")?;
+    writeln!(out)?;
+    writeln!(out, "        match cpu.position_in_memory {{")?;
+    for (n, block) in blocks.iter().enumerate() {
+        writeln!(out, "            0x{:03X} if max >= {} && !self.stale[{}] => {{", block.start, block.len, n)?;
+        if block.body.iter().any(|line| line.contains("v[")) {
+            writeln!(out, "                let v = &mut cpu.registers;")?;
+        }
+        for line in &block.body {
+            writeln!(out, "                {}", line)?;
+        }
+        writeln!(out, "                {}", block.len)?;
+        writeln!(out, "            }}")?;
+    }
+    writeln!(out, "            _ => 0,")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "fn main() -> Result<(), Box<dyn Error>> {{")?;
+    writeln!(out, "    let mut cpu = Cpu::with_variant(Variant::{:?});", variant)?;
+    writeln!(out, "    cpu.load_rom(ROM)?;")?;
+    writeln!(out, "    // loading counts as a write to all of memory, which isn't the ROM modifying itself")?;
+    writeln!(out, "    cpu.take_code_writes();")?;
+    writeln!(out, "    let mut recompiled = Recompiled {{ stale: [false; {}] }};", blocks.len())?;
+    writeln!(out)?;
+    writeln!(out, "    let mut terminal = TerminalFrontend::open(Keymap::default())?;")?;
+    writeln!(out, "    let mut clock = Clock::new();")?;
+    writeln!(out, "    'frames: while !cpu.is_halted() {{")?;
+    writeln!(out, "        for hotkey in terminal.poll_input(&mut cpu)? {{")?;
+    writeln!(out, "            match hotkey {{")?;
+    writeln!(out, "                Hotkey::Quit => break 'frames,")?;
+    writeln!(out, "                Hotkey::ToggleTurbo => clock.toggle_turbo(),")?;
+    writeln!(out, "                Hotkey::TogglePause if cpu.is_paused() => cpu.resume(),")?;
+    writeln!(out, "                Hotkey::TogglePause => cpu.pause(),")?;
+    writeln!(out, "                Hotkey::AdvanceFrame if cpu.is_paused() => cpu.advance_frame(),")?;
+    writeln!(out, "                Hotkey::AdvanceFrame => {{}}")?;
+    writeln!(out, "                Hotkey::NextTextRenderer => terminal.set_renderer(terminal.renderer().next_text()),")?;
+    writeln!(out, "                Hotkey::ToggleOverlay | Hotkey::ToggleMute | Hotkey::ToggleRecording | Hotkey::Screenshot => {{}}")?;
+    writeln!(out, "            }}")?;
+    writeln!(out, "        }}")?;
+    writeln!(out)?;
+    writeln!(out, "        cpu.run_frame_with(&mut recompiled);")?;
+    writeln!(out, "        terminal.end_frame(&mut cpu);")?;
+    writeln!(out, "        if clock.should_present() {{")?;
+    writeln!(out, "            terminal.draw(&cpu.display)?;")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "        clock.wait_for_next_frame();")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "    Ok(())")?;
+    writeln!(out, "}}")
+}
@@ -0,0 +1,490 @@
+// Terminal debugger.
+// A full screen ratatui interface that's both a player and a debugger: the screen, registers,
+// stack and disassembly around PC in panes, and a command line at the bottom.
+// The keypad works as usual while the program runs. Press : to type a command, F5 to
+// continue/stop, F10 to step one instruction, Esc to quit. The command line takes the machine
+// monitor's one letter commands too (m 200 to dump memory, d to disassemble, see monitor.rs).
+// The disassembly's addresses are coloured by how often they've run (see coverage.rs), grey
+// for never.
+
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::breakpoints::{Breakpoints, OpcodePattern, Stop};
+use crate::call_stack::CallFrame;
+use crate::clock::Clock;
+use crate::coverage::{heat_color, Coverage};
+use crate::cpu::Cpu;
+use crate::disasm::disassemble_around;
+use crate::display::Display;
+use crate::history::History;
+use crate::keymap::Keymap;
+use crate::monitor::{parse_number, set_register, Monitor, MONITOR_HELP};
+use crate::palette::Palette;
+use crate::symbols::Symbols;
+use crate::terminal::HeldKeys;
+
+// How many messages the log pane keeps
+const LOG_LINES: usize = 100;
+
+const HELP: &[&str] = &[
+    "step [n]          run n instructions (default 1)",
+    "step back [n]     go back n instructions (default 1)",
+    "next              step, running a CALL until it returns",
+    "finish            run until this subroutine returns",
+    "frame             run one frame",
+    "continue          run until a breakpoint",
+    "stop              stop running",
+    "break <addr>      set a breakpoint, by address or symbol",
+    "delete <addr>     remove a breakpoint (delete opcode <op> for a pattern)",
+    "break collision   stop after draws that collide (on/off)",
+    "break opcode <op> stop before matching opcodes, X for any nibble (FX0A)",
+    "set <reg> <value> change V0-VF, I, PC, DT or ST",
+    "dump              print the registers and stack",
+    "coverage <file>   save how often each address ran",
+    "coverage clear    start counting again",
+    "quit",
+];
+
+pub struct Debugger {
+    terminal: DefaultTerminal,
+    keymap: Keymap,
+    palette: Palette,
+    held: HeldKeys,
+    breakpoints: Breakpoints,
+    symbols: Symbols,
+    // shared with the observer counting in the CPU
+    coverage: Arc<Mutex<Coverage>>,
+    // snapshots for stepping back, recording as an observer too
+    history: Arc<Mutex<History>>,
+    monitor: Monitor,
+    running: bool,
+    // Some while a command is being typed
+    command: Option<String>,
+    log: Vec<String>,
+    quit: bool,
+}
+
+impl Debugger {
+    /// Take over the terminal. Dropping it gives the terminal back.
+    pub fn open(keymap: Keymap) -> io::Result<Self> {
+        Ok(Debugger {
+            terminal: ratatui::try_init()?,
+            keymap,
+            palette: Palette::default(),
+            held: HeldKeys::default(),
+            breakpoints: Breakpoints::new(),
+            symbols: Symbols::new(),
+            coverage: Arc::new(Mutex::new(Coverage::new())),
+            history: Arc::new(Mutex::new(History::new())),
+            monitor: Monitor::new(),
+            running: false,
+            command: None,
+            log: vec!["stopped, F5 to run, : for commands (try help)".to_string()],
+            quit: false,
+        })
+    }
+
+    /// Change the screen's colours, it only shows two (the background and foreground).
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Names for addresses, for the disassembly and breakpoints.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Run the debugger until the user quits. The program starts out stopped.
+    pub fn run(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        let mut clock = Clock::new();
+        let counting = cpu.add_observer(Box::new(self.coverage.clone()));
+        let recording = cpu.add_observer(Box::new(self.history.clone()));
+        while !self.quit {
+            self.handle_input(cpu)?;
+            if self.running {
+                self.run_frame(cpu);
+            }
+            self.held.end_frame(cpu);
+            if clock.should_present() {
+                let coverage = self.coverage.lock().unwrap();
+                self.terminal.draw(|frame| draw(frame, cpu, &self.palette, &self.breakpoints, &self.symbols, &coverage, self.running, &self.command, &self.log))?;
+            }
+            clock.wait_for_next_frame();
+        }
+        cpu.remove_observer(counting);
+        cpu.remove_observer(recording);
+        Ok(())
+    }
+
+    fn run_frame(&mut self, cpu: &mut Cpu) {
+        let stop = self.breakpoints.run_frame(cpu);
+        self.report(stop, cpu);
+    }
+
+    fn report(&mut self, stop: Option<Stop>, cpu: &Cpu) {
+        match stop {
+            Some(Stop::Breakpoint(addr)) => {
+                self.running = false;
+                self.message(format!("breakpoint at {}", self.describe(addr)));
+            }
+            Some(Stop::Collision(addr)) => {
+                self.running = false;
+                self.message(format!("collision drawing at {}", self.describe(addr)));
+            }
+            Some(Stop::Opcode(addr, opcode)) => {
+                self.running = false;
+                self.message(format!("{:04X} at {}", opcode, self.describe(addr)));
+            }
+            Some(Stop::Returned(addr)) => {
+                self.running = false;
+                self.message(format!("returned to {}", self.describe(addr)));
+            }
+            Some(Stop::Halted) => {
+                self.running = false;
+                self.message(format!("halted: {:?}", cpu.halt_reason().unwrap()));
+            }
+            None => {}
+        }
+    }
+
+    fn handle_input(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+                continue;
+            };
+            if kind == KeyEventKind::Release {
+                continue;
+            }
+
+            // typing a command
+            if let Some(command) = &mut self.command {
+                match code {
+                    KeyCode::Enter => {
+                        let command = self.command.take().unwrap_or_default();
+                        self.execute(&command, cpu);
+                    }
+                    KeyCode::Esc => self.command = None,
+                    KeyCode::Backspace => {
+                        command.pop();
+                    }
+                    KeyCode::Char(c) => command.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+
+            match code {
+                KeyCode::Esc => self.quit = true,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+                KeyCode::Char(':') => self.command = Some(String::new()),
+                KeyCode::F(5) if self.running => self.stop(),
+                KeyCode::F(5) => self.resume(cpu),
+                KeyCode::F(10) => self.step(cpu, 1),
+                KeyCode::Char(c) => {
+                    if let Some((player, key)) = self.keymap.player_key_for(c) {
+                        self.held.press(cpu, player, key);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, command: &str, cpu: &mut Cpu) {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else { return };
+        let arg = words.next();
+        self.message(format!(": {}", command));
+
+        match (name, arg) {
+            ("step" | "s", None) => self.step(cpu, 1),
+            ("step" | "s", Some("back")) => match words.next().map_or(Ok(1), str::parse) {
+                Ok(n) => self.step_back(cpu, n),
+                Err(_) => self.message("usage: step back [n]".to_string()),
+            },
+            ("step" | "s", Some(n)) => match n.parse() {
+                Ok(n) => self.step(cpu, n),
+                Err(_) => self.message(format!("not a number: {}", n)),
+            },
+            ("next" | "n", _) => {
+                if self.breakpoints.step_over(cpu) {
+                    self.resume(cpu);
+                } else {
+                    self.step(cpu, 1);
+                }
+            }
+            ("finish", _) => {
+                if self.breakpoints.step_out(cpu) {
+                    self.resume(cpu);
+                } else {
+                    self.message("not in a subroutine".to_string());
+                }
+            }
+            ("frame", _) | ("f", None) => {
+                self.running = false;
+                self.breakpoints.cancel_step();
+                cpu.advance_frame();
+            }
+            ("continue" | "c", _) => self.resume(cpu),
+            ("stop", _) => self.stop(),
+            ("break" | "b", Some("collision")) => {
+                let on = !self.breakpoints.break_on_collision();
+                self.breakpoints.set_break_on_collision(on);
+                self.message(format!("break on collision {}", if on { "on" } else { "off" }));
+            }
+            ("break" | "b", Some("opcode")) => match words.next().and_then(OpcodePattern::parse) {
+                Some(pattern) => {
+                    self.breakpoints.insert_pattern(pattern);
+                    self.message(format!("stopping before {} opcodes", pattern));
+                }
+                None => self.message("usage: break opcode <op>, like FX0A or DXX5".to_string()),
+            },
+            ("delete" | "del", Some("opcode")) => match words.next().and_then(OpcodePattern::parse) {
+                Some(pattern) if self.breakpoints.remove_pattern(pattern) => self.message(format!("no longer stopping on {}", pattern)),
+                Some(pattern) => self.message(format!("not stopping on {} anyway", pattern)),
+                None => self.message("usage: delete opcode <op>".to_string()),
+            },
+            ("break" | "b", Some(addr)) => match self.parse_address(addr) {
+                Some(addr) => {
+                    self.breakpoints.insert(addr);
+                    self.message(format!("breakpoint set at {}", self.describe(addr)));
+                }
+                None => self.message(format!("not an address: {}", addr)),
+            },
+            ("delete" | "del", Some(addr)) => match self.parse_address(addr) {
+                Some(addr) if self.breakpoints.remove(addr) => self.message(format!("breakpoint at {} removed", self.describe(addr))),
+                _ => self.message(format!("no breakpoint at {}", addr)),
+            },
+            ("set", Some(register)) => {
+                let value = words.next().and_then(parse_number);
+                match value.map(|value| set_register(cpu, register, value)) {
+                    Some(true) => {}
+                    Some(false) => self.message(format!("no register called {}", register)),
+                    None => self.message("usage: set <reg> <value>".to_string()),
+                }
+            }
+            ("dump", _) => {
+                for line in cpu.dump().lines() {
+                    self.message(line.to_string());
+                }
+            }
+            ("coverage", Some("clear")) => {
+                self.coverage.lock().unwrap().clear();
+                self.message("coverage cleared".to_string());
+            }
+            ("coverage", Some(path)) => {
+                let saved = self.coverage.lock().unwrap().save(Path::new(path));
+                match saved {
+                    Ok(()) => self.message(format!("saved coverage to {}", path)),
+                    Err(e) => self.message(format!("couldn't save coverage: {}", e)),
+                }
+            }
+            ("quit" | "q", _) => self.quit = true,
+            ("help" | "h", _) => {
+                for line in HELP.iter().chain(MONITOR_HELP) {
+                    self.message(line.to_string());
+                }
+            }
+            _ => match self.monitor.execute(cpu, &self.symbols, command) {
+                Some(reply) => {
+                    for line in reply.lines {
+                        self.message(line);
+                    }
+                    if reply.go {
+                        self.resume(cpu);
+                    }
+                }
+                None => self.message(format!("unknown command {}, try help", command)),
+            },
+        }
+    }
+
+    fn step(&mut self, cpu: &mut Cpu, count: u64) {
+        self.running = false;
+        self.breakpoints.cancel_step();
+        cpu.run_for(count);
+    }
+
+    fn step_back(&mut self, cpu: &mut Cpu, count: u64) {
+        self.running = false;
+        let mut history = self.history.lock().unwrap();
+        if !history.step_back(cpu, count) {
+            let reach = history.reach(cpu);
+            drop(history);
+            self.message(format!("can't go back {}, the history only goes back {}", count, reach));
+        }
+    }
+
+    fn resume(&mut self, cpu: &mut Cpu) {
+        self.running = true;
+        let stop = self.breakpoints.resume(cpu);
+        self.report(stop, cpu);
+    }
+
+    fn stop(&mut self) {
+        self.running = false;
+        self.breakpoints.cancel_step();
+        self.message("stopped".to_string());
+    }
+
+    // A symbol's name, or a number like any other
+    fn parse_address(&self, text: &str) -> Option<usize> {
+        self.symbols.addr(text).or_else(|| parse_number(text))
+    }
+
+    // The address, with its name if it has one
+    fn describe(&self, addr: usize) -> String {
+        match self.symbols.name(addr) {
+            Some(name) => format!("0x{:03X} ({})", addr, name),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    fn message(&mut self, message: String) {
+        self.log.push(message);
+        if self.log.len() > LOG_LINES {
+            self.log.remove(0);
+        }
+    }
+}
+
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw(frame: &mut Frame, cpu: &Cpu, palette: &Palette, breakpoints: &Breakpoints, symbols: &Symbols, coverage: &Coverage, running: bool, command: &Option<String>, log: &[String]) {
+    let [main, command_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(frame.area());
+    let [left, right] = Layout::horizontal([Constraint::Min(0), Constraint::Length(32)]).areas(main);
+    let screen_height = (cpu.display.height() / 2) as u16 + 2;
+    let [screen_area, log_area] = Layout::vertical([Constraint::Length(screen_height), Constraint::Min(0)]).areas(left);
+    let [registers_area, stack_area, disassembly_area] =
+        Layout::vertical([Constraint::Length(9), Constraint::Length(6), Constraint::Min(0)]).areas(right);
+
+    frame.render_widget(screen(&cpu.display, palette), screen_area);
+    frame.render_widget(registers(cpu, running), registers_area);
+    frame.render_widget(stack(cpu), stack_area);
+    frame.render_widget(disassembly(cpu, breakpoints, symbols, coverage, disassembly_area), disassembly_area);
+
+    let visible = log_area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = log[log.len().saturating_sub(visible)..].iter().map(|l| Line::raw(l.as_str())).collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Log")), log_area);
+
+    let prompt = match command {
+        Some(command) => format!(":{}", command),
+        None => "F5 run/stop  F10 step  : command  Esc quit".to_string(),
+    };
+    frame.render_widget(Paragraph::new(prompt).block(Block::bordered().title("Command")), command_area);
+}
+
+// Two pixels per character cell using half blocks, so the screen keeps its shape
+fn screen(display: &Display, palette: &Palette) -> Paragraph<'static> {
+    let rows: Vec<&[u8]> = display.rows().collect();
+    let lines: Vec<Line> = rows
+        .chunks(2)
+        .map(|pair| {
+            let top = pair[0];
+            let bottom = pair.get(1).copied().unwrap_or(top);
+            let text: String = top
+                .iter()
+                .zip(bottom)
+                .map(|(&t, &b)| match (t != 0, b != 0) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect();
+            Line::raw(text)
+        })
+        .collect();
+    let rgb = |[r, g, b]: [u8; 3]| Color::Rgb(r, g, b);
+    let style = Style::new().fg(rgb(palette.foreground())).bg(rgb(palette.background()));
+    Paragraph::new(lines).style(style).block(Block::bordered().title("Screen"))
+}
+
+fn registers(cpu: &Cpu, running: bool) -> Paragraph<'static> {
+    let mut lines: Vec<Line> = cpu
+        .registers
+        .chunks(4)
+        .enumerate()
+        .map(|(row, values)| {
+            let text: Vec<String> = values.iter().enumerate().map(|(n, v)| format!("V{:X} {:02X}", row * 4 + n, v)).collect();
+            Line::raw(text.join("  "))
+        })
+        .collect();
+    lines.push(Line::raw(format!("I  {:04X}  PC {:04X}", cpu.index_register, cpu.position_in_memory)));
+    lines.push(Line::raw(format!("DT {:02X}    ST {:02X}", cpu.delay_timer, cpu.sound_timer)));
+    let state = match cpu.halt_reason() {
+        Some(reason) => format!("halted ({:?})", reason),
+        None if running => "running".to_string(),
+        None => "stopped".to_string(),
+    };
+    lines.push(Line::styled(state, Style::new().add_modifier(Modifier::BOLD)));
+    Paragraph::new(lines).block(Block::bordered().title("Registers"))
+}
+
+// Calls that haven't returned, most recent first
+fn stack(cpu: &Cpu) -> Paragraph<'static> {
+    let frames: Vec<CallFrame> = cpu.call_stack().collect();
+    let lines: Vec<Line> = frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(depth, frame)| Line::raw(format!("{:2}: {}", depth, frame)))
+        .collect();
+    Paragraph::new(lines).block(Block::bordered().title(format!("Stack ({})", cpu.stack_pointer)))
+}
+
+// Centred on PC, so it scrolls along as the program runs. Addresses with a symbol get a label
+// line of their own above them, and are coloured by how often they've run
+fn disassembly(cpu: &Cpu, breakpoints: &Breakpoints, symbols: &Symbols, coverage: &Coverage, area: Rect) -> Paragraph<'static> {
+    let rows = area.height.saturating_sub(2) as usize;
+    let pc = cpu.position_in_memory;
+    let mut lines = Vec::new();
+    // which line PC is on
+    let mut pc_line = 0;
+    for instruction in disassemble_around(&cpu.memory, pc, cpu.variant, &cpu.quirks(), rows) {
+        let instruction = instruction.with_symbols(symbols);
+        let addr = instruction.addr;
+        if let Some(name) = symbols.name(addr) {
+            lines.push(Line::from(Span::styled(format!("{}:", name), Style::new().fg(Color::Cyan))));
+        }
+        if addr == pc {
+            pc_line = lines.len();
+        }
+        let marker = if breakpoints.contains(addr) { "●" } else { " " };
+        let arrow = if addr == pc { ">" } else { " " };
+        let text = format!(" {:04X}  {}", instruction.opcode, instruction.text);
+        let style = if addr == pc {
+            Style::new().fg(Color::Black).bg(Color::Yellow)
+        } else if breakpoints.contains(addr) {
+            Style::new().fg(Color::LightRed)
+        } else {
+            Style::new()
+        };
+        let [r, g, b] = heat_color(coverage.heat(addr));
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}{} ", marker, arrow), style),
+            Span::styled(format!("{:03X}", addr), Style::new().fg(Color::White).bg(Color::Rgb(r, g, b))),
+            Span::styled(text, style),
+        ]));
+    }
+    // labels make it too long, trim it back down keeping PC in the middle
+    let first = pc_line.saturating_sub(rows / 2).min(lines.len().saturating_sub(rows));
+    let lines: Vec<Line> = lines.into_iter().skip(first).take(rows).collect();
+    Paragraph::new(lines).block(Block::bordered().title("Disassembly"))
+}
@@ -0,0 +1,256 @@
+// Machine variants.
+// Quirks cover opcodes that different interpreters disagree on, variants cover
+// which opcodes exist at all. SUPER-CHIP added a hi-res mode, scrolling and a
+// big font on top of everything base CHIP-8 had, XO-CHIP then built on SUPER-CHIP
+// with 64K of memory, a second display plane and a handful of conveniences.
+// CHIP-8X went another way entirely: RCA's own update for a VIP with their colour board and a
+// second keypad, which moved programs up to 0x300 to make room for a bigger interpreter.
+// Mega-Chip is SUPER-CHIP for a PC, with a 256 colour 256x192 screen and 24 bit addresses.
+// The ETI-660 was an Australian kit computer that ran plain CHIP-8 with programs at 0x600 and a
+// taller screen, 64x48 or 64x64 depending on how it was built.
+// Two-page hi-res CHIP-8 was a patch to the VIP's own interpreter that doubled the screen to
+// 64x64 (two pages of display memory). Those ROMs start with 1260, a jump into the patch, and
+// the program proper starts at 0x2C0, which is where this starts them.
+
+use alloc::vec::Vec;
+
+use crate::cpu::PROGRAM_START;
+use crate::display::{HEIGHT, HIRES_HEIGHT};
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+
+/// Where two-page hi-res programs start, past the interpreter patch.
+pub const TWO_PAGE_HIRES_START: usize = 0x2C0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// The original instruction set, 64x32 display.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1, adds the 128x64 hi-res mode, scrolling, 16x16 sprites and the big font.
+    SuperChip,
+    /// XO-CHIP, SUPER-CHIP plus 64K memory, two display planes (4 colours), F000 NNNN,
+    /// ranged register save/load (5XY2/5XY3) and scrolling up.
+    XoChip,
+    /// CHIP-8X, base CHIP-8 plus the VP-590 colour board (02A0, BXY0, BXYN instead of BNNN), a
+    /// second keypad (EXF2/EXF5), nibble addition (5XY1) and an I/O port (FXF8/FXFB).
+    /// Programs start at 0x300.
+    Chip8X,
+    /// MEGA-CHIP8, SUPER-CHIP plus a 256x192 screen with a byte of colour per pixel (see
+    /// mega_chip.rs), 16MB of memory and the 24 bit I to reach it.
+    MegaChip,
+    /// ETI-660, base CHIP-8 with programs at 0x600 and a 64x48 screen (64x64 on some, see
+    /// Display::set_lores_height()).
+    Eti660,
+    /// The VIP's two-page hi-res CHIP-8, base CHIP-8 on a 64x64 screen with 0230 to clear it.
+    /// Programs load at 0x200 like always but start running at 0x2C0.
+    Chip8Hires,
+}
+
+impl Variant {
+    /// The quirks ROMs written for this machine usually expect.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Variant::Chip8 | Variant::Chip8X | Variant::Eti660 | Variant::Chip8Hires => Quirks::cosmac_vip(),
+            Variant::SuperChip | Variant::MegaChip => Quirks::superchip(),
+            Variant::XoChip => Quirks::xo_chip(),
+        }
+    }
+
+    /// Bytes of RAM the machine has.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Variant::Chip8 | Variant::SuperChip | Variant::Chip8X | Variant::Eti660 | Variant::Chip8Hires => 0x1000,
+            Variant::XoChip => 0x10000,
+            Variant::MegaChip => 0x100_0000,
+        }
+    }
+
+    /// What I wraps round at: 16 bits, except Mega-Chip's 24.
+    pub fn index_mask(self) -> u32 {
+        match self {
+            Variant::MegaChip => 0xFF_FFFF,
+            _ => 0xFFFF,
+        }
+    }
+
+    /// Where programs get loaded and start running.
+    pub fn program_start(self) -> usize {
+        match self {
+            Variant::Chip8X => 0x300,
+            Variant::Eti660 => 0x600,
+            _ => PROGRAM_START,
+        }
+    }
+
+    /// Where programs start running, past the patch on two-page hi-res and program_start()
+    /// everywhere else.
+    pub fn entry_point(self) -> usize {
+        match self {
+            Variant::Chip8Hires => TWO_PAGE_HIRES_START,
+            _ => self.program_start(),
+        }
+    }
+
+    /// Rows on the lo-res screen it comes with.
+    pub fn lores_height(self) -> usize {
+        match self {
+            Variant::Eti660 => 48,
+            Variant::Chip8Hires => HIRES_HEIGHT,
+            _ => HEIGHT,
+        }
+    }
+
+    /// The machine called `name` in files and on the command line: chip8, schip, xochip,
+    /// chip8x, megachip, eti660 or chip8hires.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Variant::Chip8),
+            "schip" => Some(Variant::SuperChip),
+            "xochip" => Some(Variant::XoChip),
+            "chip8x" => Some(Variant::Chip8X),
+            "megachip" => Some(Variant::MegaChip),
+            "eti660" => Some(Variant::Eti660),
+            "chip8hires" => Some(Variant::Chip8Hires),
+            _ => None,
+        }
+    }
+
+    /// What from_name() calls it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Chip8 => "chip8",
+            Variant::SuperChip => "schip",
+            Variant::XoChip => "xochip",
+            Variant::Chip8X => "chip8x",
+            Variant::MegaChip => "megachip",
+            Variant::Eti660 => "eti660",
+            Variant::Chip8Hires => "chip8hires",
+        }
+    }
+
+    /// Whether the SUPER-CHIP opcodes (hi-res, scrolling, big sprites and font) are available.
+    pub fn has_superchip_opcodes(self) -> bool {
+        matches!(self, Variant::SuperChip | Variant::XoChip | Variant::MegaChip)
+    }
+}
+
+/// Opcodes in a ROM that only the later machines have, from platform_hints().
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlatformHints {
+    /// Where (loaded at 0x200) and what, for the ones SUPER-CHIP added
+    pub superchip: Vec<(usize, Instruction)>,
+    /// The same for XO-CHIP's
+    pub xo_chip: Vec<(usize, Instruction)>,
+    /// It starts with the jump into the two-page hi-res patch
+    pub two_page_hires: bool,
+}
+
+impl PlatformHints {
+    /// The smallest machine that has every opcode found.
+    pub fn variant(&self) -> Variant {
+        if !self.xo_chip.is_empty() {
+            Variant::XoChip
+        } else if !self.superchip.is_empty() {
+            Variant::SuperChip
+        } else if self.two_page_hires {
+            Variant::Chip8Hires
+        } else {
+            Variant::Chip8
+        }
+    }
+}
+
+/// Look through a ROM's code for SUPER-CHIP and XO-CHIP opcodes. Sprites and other data can
+/// look like opcodes (F0 00 is a common sprite row and XO-CHIP's F000), so this follows the
+/// code from 0x200 through jumps, calls and skips and only looks at what it reaches. Code only
+/// reached through BNNN's computed jumps gets missed. Two-page hi-res ROMs are followed from
+/// 0x2C0, the patch before that is 1802 code.
+pub fn platform_hints(rom: &[u8]) -> PlatformHints {
+    let mut hints = PlatformHints { two_page_hires: is_two_page_hires(rom), ..PlatformHints::default() };
+    let mut seen = alloc::vec![false; rom.len()];
+    let start = if hints.two_page_hires { TWO_PAGE_HIRES_START - PROGRAM_START } else { 0 };
+    let mut to_visit = alloc::vec![start];
+    while let Some(offset) = to_visit.pop() {
+        if offset >= rom.len() || seen[offset] {
+            continue;
+        }
+        seen[offset] = true;
+
+        // decoded as each machine in turn, whichever first knows it is the one that added it
+        let decode = |variant| Instruction::decode_at(rom, offset, variant);
+        let addr = PROGRAM_START + offset;
+        let instruction = decode(Variant::XoChip);
+        if matches!(decode(Variant::Chip8), Instruction::Unknown(_)) {
+            match (decode(Variant::SuperChip), instruction) {
+                (_, Instruction::Unknown(_)) => {}
+                (Instruction::Unknown(_), instruction) => hints.xo_chip.push((addr, instruction)),
+                (instruction, _) => hints.superchip.push((addr, instruction)),
+            }
+        }
+
+        let next = offset + instruction.size();
+        // targets before the ROM (the font, mostly) can't be followed
+        let target = |nnn: u16| (nnn as usize).checked_sub(PROGRAM_START);
+        use Instruction::*;
+        match instruction {
+            Jump(nnn) => to_visit.extend(target(nnn)),
+            Call(nnn) => to_visit.extend(target(nnn).into_iter().chain([next])),
+            SkipIfEqual(..) | SkipIfNotEqual(..) | SkipIfRegistersEqual(..) | SkipIfRegistersDiffer(..) | SkipIfKey(_) | SkipIfNotKey(_) => {
+                let skipped = next + Instruction::decode_at(rom, next, Variant::XoChip).size();
+                to_visit.extend([next, skipped]);
+            }
+            Return | Exit | Halt | JumpWithOffset(..) | Unknown(_) => {}
+            _ => to_visit.push(next),
+        }
+    }
+    hints.superchip.sort_by_key(|&(addr, _)| addr);
+    hints.xo_chip.sort_by_key(|&(addr, _)| addr);
+    hints
+}
+
+/// Whether a ROM is for two-page hi-res CHIP-8, going by the 1260 those all start with.
+pub fn is_two_page_hires(rom: &[u8]) -> bool {
+    rom.starts_with(&[0x12, 0x60])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn program_start_and_entry_point() {
+        let expected = [
+            (Variant::Chip8, 0x200, 0x200),
+            (Variant::SuperChip, 0x200, 0x200),
+            (Variant::XoChip, 0x200, 0x200),
+            (Variant::Chip8X, 0x300, 0x300),
+            (Variant::MegaChip, 0x200, 0x200),
+            (Variant::Eti660, 0x600, 0x600),
+            (Variant::Chip8Hires, 0x200, 0x2C0),
+        ];
+        for (variant, start, entry) in expected {
+            assert_eq!((variant.program_start(), variant.entry_point()), (start, entry), "{:?}", variant);
+
+            let mut cpu = Cpu::with_variant(variant);
+            cpu.load_binary(&[0x12, 0x60]).unwrap();
+            assert_eq!(&cpu.memory[start..start + 2], &[0x12, 0x60], "{:?}", variant);
+            assert_eq!(cpu.position_in_memory, entry, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn names() {
+        for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip, Variant::Chip8X, Variant::MegaChip, Variant::Eti660, Variant::Chip8Hires] {
+            assert_eq!(Variant::from_name(variant.name()), Some(variant));
+        }
+        assert_eq!(Variant::from_name("chip-8"), None);
+    }
+
+    #[test]
+    fn two_page_hires_roms() {
+        assert!(is_two_page_hires(&[0x12, 0x60, 0x00, 0xE0]));
+        assert!(!is_two_page_hires(&[0x12, 0x02]));
+    }
+}
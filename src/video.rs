@@ -0,0 +1,186 @@
+// Video recording.
+// Writes every frame, uncompressed, for ffmpeg to turn into a proper video. GIF recordings (F10)
+// can't show the phosphor fade and round each frame's time to a hundredth of a second, this
+// keeps everything. Either of two formats, picked by the file name:
+//
+//   game.y4m    YUV4MPEG2, which carries its own size and frame rate, so
+//               ffmpeg -i game.y4m -c:v libx264 -crf 12 game.mp4
+//   anything    raw RGB, 3 bytes a pixel and nothing else, so ffmpeg has to be told what it is:
+//   else        ffmpeg -f rawvideo -pix_fmt rgb24 -s 512x256 -r 60 -i game.rgb game.mp4
+//
+// The frames are exactly 60 a second whatever the host managed, one per emulated frame, and
+// the file can be a named pipe (mkfifo) with ffmpeg reading the other end to skip the huge
+// file. The Y4M frames are 4:4:4 so the pixels' edges stay sharp, with the colours converted
+// the way ffmpeg expects (BT.601, 16-235).
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::display::Display;
+use crate::palette::Palette;
+
+/// The frames per second the video runs at, the timer rate.
+pub const FRAME_RATE: u32 = 60;
+
+/// How the frames are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// YUV4MPEG2
+    Y4m,
+    /// Bare RGB frames, one after the other
+    Rgb,
+}
+
+impl VideoFormat {
+    /// Y4M for a .y4m file, raw RGB for anything else.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("y4m") => VideoFormat::Y4m,
+            _ => VideoFormat::Rgb,
+        }
+    }
+}
+
+/// Writes the screen out as video, one capture() per frame.
+pub struct VideoRecorder<W: Write> {
+    out: W,
+    format: VideoFormat,
+    palette: Palette,
+    // the size of the video, fixed by the screen when recording started
+    width: usize,
+    height: usize,
+    frames: u64,
+}
+
+impl VideoRecorder<BufWriter<File>> {
+    /// Start recording to `path` in the format its name asks for, with each pixel of the current
+    /// screen `scale` x `scale` pixels in the video. A screen that changes resolution partway is
+    /// stretched to fit.
+    pub fn create(path: impl AsRef<Path>, display: &Display, scale: u32, palette: &Palette) -> io::Result<Self> {
+        let path = path.as_ref();
+        VideoRecorder::new(BufWriter::new(File::create(path)?), VideoFormat::for_path(path), display, scale, palette)
+    }
+}
+
+impl<W: Write> VideoRecorder<W> {
+    /// Start recording to anything that can be written to.
+    pub fn new(mut out: W, format: VideoFormat, display: &Display, scale: u32, palette: &Palette) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let (width, height) = (display.width() * scale, display.height() * scale);
+        if format == VideoFormat::Y4m {
+            writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FRAME_RATE)?;
+        }
+        Ok(VideoRecorder { out, format, palette: *palette, width, height, frames: 0 })
+    }
+
+    /// The video's width and height in pixels.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Add the screen as it is now, call once per frame.
+    pub fn capture(&mut self, display: &Display) -> io::Result<()> {
+        let image = self.scale(display);
+        match self.format {
+            VideoFormat::Rgb => {
+                let bytes: Vec<u8> = image.into_iter().flatten().collect();
+                self.out.write_all(&bytes)?;
+            }
+            VideoFormat::Y4m => {
+                let yuv: Vec<[u8; 3]> = image.into_iter().map(yuv).collect();
+                let mut frame = Vec::with_capacity(6 + yuv.len() * 3);
+                frame.extend_from_slice(b"FRAME\n");
+                for plane in 0..3 {
+                    frame.extend(yuv.iter().map(|pixel| pixel[plane]));
+                }
+                self.out.write_all(&frame)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flush what's left out to the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    // A colour for every pixel of the video, each one from the pixel it lands on in the screen
+    fn scale(&self, display: &Display) -> Vec<[u8; 3]> {
+        let pixels: Vec<[u8; 3]> = display.rgb_pixels(&self.palette).collect();
+        let mut image = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            let row = y * display.height() / self.height * display.width();
+            image.extend((0..self.width).map(|x| pixels[row + x * display.width() / self.width]));
+        }
+        image
+    }
+}
+
+// BT.601 with Y from 16 to 235, what Y4M files usually hold
+fn yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn format_from_the_name() {
+        assert_eq!(VideoFormat::for_path(Path::new("game.Y4M")), VideoFormat::Y4m);
+        assert_eq!(VideoFormat::for_path(Path::new("game.rgb")), VideoFormat::Rgb);
+        assert_eq!(VideoFormat::for_path(Path::new("fifo")), VideoFormat::Rgb);
+    }
+
+    #[test]
+    fn y4m_frames() {
+        let display = Display::new();
+        let mut out = Vec::new();
+        let mut recorder = VideoRecorder::new(&mut out, VideoFormat::Y4m, &display, 2, &Palette::default()).unwrap();
+        assert_eq!(recorder.size(), (128, 64));
+        recorder.capture(&display).unwrap();
+        recorder.capture(&display).unwrap();
+        assert_eq!(recorder.frames(), 2);
+        recorder.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + 2 * (6 + 128 * 64 * 3));
+        assert_eq!(&out[header.len()..header.len() + 6], b"FRAME\n");
+    }
+
+    #[test]
+    fn rgb_frames_are_scaled() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80], false);
+        let palette = Palette::default();
+        let mut out = Vec::new();
+        let mut recorder = VideoRecorder::new(&mut out, VideoFormat::Rgb, &display, 3, &palette).unwrap();
+        recorder.capture(&display).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(out.len(), 192 * 96 * 3);
+        let pixel = |x: usize, y: usize| &out[(y * 192 + x) * 3..(y * 192 + x) * 3 + 3];
+        // the lit pixel is the 3x3 in the corner
+        assert_eq!(pixel(2, 2), pixel(0, 0));
+        assert_ne!(pixel(3, 0), pixel(0, 0));
+    }
+
+    #[test]
+    fn black_and_white_in_range() {
+        assert_eq!(yuv([0, 0, 0]), [16, 128, 128]);
+        assert_eq!(yuv([255, 255, 255]), [235, 128, 128]);
+    }
+}
@@ -0,0 +1,85 @@
+// COSMAC VIP timing.
+// Normally every instruction takes the same time, clock_speed of them a second. On the VIP they
+// didn't: the interpreter was 1802 code, and a 6XNN was a handful of machine cycles while a
+// DXYN took thousands. Games that were tuned by ear on the real thing (music routines, delay
+// loops that don't use the delay timer, anything racing the display) only run at the right speed
+// if each instruction costs what it did.
+//
+// With Timing::CosmacVip a frame isn't a number of instructions any more, it's the machine
+// cycles the interpreter got between interrupts: the 1802 ran at 1.76MHz, 8 clocks a machine
+// cycle, so 3668 a frame, less the 1024 the display's DMA took and the interrupt routine's 46.
+// Each instruction is charged the 40 cycles the interpreter's fetch and decode took plus its own
+// cost below, and one that runs past the end of the frame (a big DXYN) eats into the next ones.
+// clock_speed doesn't count in this mode.
+//
+// The costs are close to the real interpreter's, not exact: the ones that depend on their
+// operands (DXYN on the sprite's height and whether it's byte aligned, FX33 on the digits, FX55
+// and FX65 on X) do here too, but branches inside an instruction are averaged out. Opcodes the
+// VIP didn't have get a flat cost.
+
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+/// How long instructions take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// All the same, clock_speed of them a second.
+    #[default]
+    Instructions,
+    /// What they cost on a COSMAC VIP, see the top of this file.
+    CosmacVip,
+}
+
+/// Machine cycles the interpreter gets each 60Hz frame.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668 - 1024 - 46;
+
+// What every instruction costs before it does anything
+const FETCH_DECODE: u32 = 40;
+
+/// Machine cycles `instruction` takes on a VIP, run on `cpu` as it is now.
+pub fn instruction_cycles(cpu: &Cpu, instruction: Instruction) -> u32 {
+    use Instruction::*;
+
+    let reg = |x: u8| cpu.registers[x as usize];
+    // a skip that's taken has to step over the next instruction as well
+    let skip = |taken: bool| if taken { 14 } else { 10 };
+    let key = |x: u8| cpu.keypad[(reg(x) & 0xF) as usize];
+    let cycles = match instruction {
+        // clearing is a loop over all 256 bytes of the display
+        Clear => 24 + 256 * 12,
+        Return => 10,
+        Jump(_) => 12,
+        Call(_) => 26,
+        SkipIfEqual(x, kk) => skip(reg(x) == kk),
+        SkipIfNotEqual(x, kk) => skip(reg(x) != kk),
+        SkipIfRegistersEqual(x, y) => skip(reg(x) == reg(y)) + 4,
+        SkipIfRegistersDiffer(x, y) => skip(reg(x) != reg(y)) + 4,
+        LoadByte(..) => 6,
+        AddByte(..) => 10,
+        Move(..) | Or(..) | And(..) | Xor(..) | Add(..) | Sub(..) | ShiftRight(..) | SubReversed(..) | ShiftLeft(..) => 44,
+        LoadIndex(_) => 12,
+        JumpWithOffset(..) => 22,
+        Random(..) => 36,
+        // every row gets shifted into place bit by bit unless it's already byte aligned, and
+        // then XOR'd into the display a byte at a time
+        Draw(x, _, n) => {
+            let per_row = if reg(x) % 8 == 0 { 46 } else { 68 };
+            26 + n as u32 * per_row
+        }
+        SkipIfKey(x) => skip(key(x)),
+        SkipIfNotKey(x) => skip(!key(x)),
+        ReadDelay(_) | SetDelay(_) | SetSound(_) => 10,
+        // a loop round the keypad scan until something's pressed, then the debounce
+        WaitForKey(_) => 18,
+        AddIndex(_) => 16,
+        LoadFont(_) => 16,
+        // the digits come out by subtracting 100s and then 10s, once for each
+        StoreBcd(x) => {
+            let vx = reg(x);
+            84 + 16 * (vx / 100 + vx / 10 % 10) as u32
+        }
+        StoreRegisters(x) | LoadRegisters(x) => 14 + 14 * (x as u32 + 1),
+        _ => 20,
+    };
+    FETCH_DECODE + cycles
+}
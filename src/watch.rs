@@ -0,0 +1,70 @@
+// Watching a ROM for changes.
+// `chip8 run --watch` starts the ROM again whenever its file changes, for a tight assemble and
+// test loop: leave it running, rebuild the ROM, and the new one's running by the time you look.
+// Only the machine restarts, so the keymap, anything watching it (traces, scripts) and gdb's
+// breakpoints all carry on as they were.
+//
+// It polls the file's modified time and size rather than asking the OS to say when it changes,
+// which needs nothing platform specific and is quick enough for something saved by hand.
+// Assemblers and editors don't always write a file in one go, so a change only counts once the
+// file's stayed the same for a whole poll.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::rom_format::{self, RomFormat};
+
+/// How often the file gets looked at.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A ROM file being watched, see the top of this file.
+pub struct RomWatcher {
+    path: PathBuf,
+    format: RomFormat,
+    // modified time and size the last time it was loaded
+    loaded: Option<(SystemTime, u64)>,
+    // what it was at the last poll, if that was different
+    changing: Option<(SystemTime, u64)>,
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    /// Watch `path`, a ROM in `format`, taking what's there now as already loaded.
+    pub fn new(path: &Path, format: RomFormat) -> Self {
+        RomWatcher {
+            path: path.to_path_buf(),
+            format,
+            loaded: stamp(path),
+            changing: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// The ROM, decoded as `format`, if the file's changed since it
+    /// was last loaded and has settled down. Cheap enough to call every frame, it only looks
+    /// every POLL_INTERVAL. A file that's missing or won't read counts as still changing.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let now = stamp(&self.path)?;
+        if Some(now) == self.loaded {
+            self.changing = None;
+            return None;
+        }
+        if self.changing.replace(now) != Some(now) {
+            return None;
+        }
+        let rom = rom_format::read_as(&self.path, self.format).ok()?;
+        self.loaded = Some(now);
+        self.changing = None;
+        Some(rom)
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
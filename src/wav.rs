@@ -0,0 +1,186 @@
+// WAV recording.
+// The sound to go with a recording, so a video of a music demo isn't silent. It's rendered
+// from the machine's sound state a frame at a time rather than captured from the speakers, so
+// it's the same whether or not there's an audio device, and it lines up with the video
+// exactly: 48000 samples a second is 800 a frame, so frame N of the video and samples 800N on
+// are the same moment however fast the host was really going. ffmpeg puts them together with
+//
+//   ffmpeg -i game.y4m -i game.wav -c:v libx264 -crf 12 -c:a aac game.mp4
+//
+// 16 bit mono PCM. The sizes in the header get filled in by finish(); a pipe that can't seek
+// back keeps the "unknown" 0xFFFFFFFF placeholders, which ffmpeg reads as going on to the end.
+//
+// It reads them as well, for a sample to use as the beep (see src/audio.rs).
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::audio::{AudioEngine, Sound, Tone};
+
+/// Samples per second.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+// one 60Hz frame's worth
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+// the RIFF header before the samples
+const HEADER_LEN: u32 = 44;
+
+/// Writes the machine's sound to a WAV file, one capture() per frame.
+pub struct WavRecorder<W: Write + Seek> {
+    out: W,
+    engine: AudioEngine,
+    samples: u32,
+}
+
+impl WavRecorder<BufWriter<File>> {
+    /// Start recording to `path`, with the buzzer sounding like `tone`.
+    pub fn create(path: impl AsRef<Path>, tone: Tone) -> io::Result<Self> {
+        WavRecorder::new(BufWriter::new(File::create(path)?), tone)
+    }
+}
+
+impl<W: Write + Seek> WavRecorder<W> {
+    /// Start recording to anything that can be written to.
+    pub fn new(mut out: W, tone: Tone) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&u32::MAX.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // mono
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes a second
+        out.write_all(&2u16.to_le_bytes())?; // bytes a sample
+        out.write_all(&16u16.to_le_bytes())?; // bits a sample
+        out.write_all(b"data")?;
+        out.write_all(&u32::MAX.to_le_bytes())?;
+        Ok(WavRecorder { out, engine: AudioEngine::with_tone(SAMPLE_RATE, tone), samples: 0 })
+    }
+
+    /// Add a frame of `sound`, call once per frame.
+    pub fn capture(&mut self, sound: &Sound) -> io::Result<()> {
+        let mut frame = [0.0; SAMPLES_PER_FRAME];
+        self.engine.fill(sound, &mut frame);
+        let bytes: Vec<u8> = frame.iter().flat_map(|&sample| ((sample * i16::MAX as f32) as i16).to_le_bytes()).collect();
+        self.out.write_all(&bytes)?;
+        self.samples += SAMPLES_PER_FRAME as u32;
+        Ok(())
+    }
+
+    /// Fill in the header's sizes and close the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        let data_len = self.samples * 2;
+        self.out.flush()?;
+        let sizes = [(4, HEADER_LEN - 8 + data_len), (HEADER_LEN as u64 - 4, data_len)];
+        for (at, size) in sizes {
+            match self.out.seek(SeekFrom::Start(at)) {
+                Ok(_) => self.out.write_all(&size.to_le_bytes())?,
+                // a pipe, see the top of this file
+                Err(_) => return Ok(()),
+            }
+        }
+        self.out.flush()
+    }
+}
+
+/// The sample rate and the samples (from -1 to 1, channels mixed down to mono) in a WAV file.
+/// Takes 8 and 16 bit PCM and 32 bit float, which is what sound editors save.
+pub fn decode(bytes: &[u8]) -> Result<(u32, Vec<f32>), &'static str> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file");
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+
+    // (format, channels, sample rate, bits a sample) from the fmt chunk
+    let mut format = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let (id, len) = (&bytes[at..at + 4], u32_at(at + 4) as usize);
+        let body = &bytes[at + 8..(at + 8).saturating_add(len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some((u16_at(at + 8), u16_at(at + 10).max(1) as usize, u32_at(at + 12), u16_at(at + 22))),
+            b"data" => {
+                let (tag, channels, sample_rate, bits) = format.ok_or("the data comes before the format")?;
+                if sample_rate == 0 {
+                    return Err("the sample rate is 0");
+                }
+                let sample: fn(&[u8]) -> f32 = match (tag, bits) {
+                    (1, 8) => |bytes| (bytes[0] as f32 - 128.0) / 128.0,
+                    (1, 16) => |bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+                    (3, 32) => |bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    _ => return Err("only 8 and 16 bit PCM and 32 bit float samples are supported"),
+                };
+                let width = bits as usize / 8;
+                let samples = body
+                    .chunks_exact(width * channels)
+                    .map(|frame| frame.chunks_exact(width).map(sample).sum::<f32>() / channels as f32)
+                    .collect();
+                return Ok((sample_rate, samples));
+            }
+            _ => {}
+        }
+        // chunks are padded to an even length
+        at = at.saturating_add(8 + len + len % 2);
+    }
+    Err("there's no data chunk")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record(sounds: &[Sound]) -> Vec<u8> {
+        let mut file = Cursor::new(Vec::new());
+        let mut recorder = WavRecorder::new(&mut file, Tone::default()).unwrap();
+        for sound in sounds {
+            recorder.capture(sound).unwrap();
+        }
+        recorder.finish().unwrap();
+        file.into_inner()
+    }
+
+    #[test]
+    fn records_what_decode_reads() {
+        let bytes = record(&[Sound::default(), Sound { playing: true, ..Sound::default() }]);
+        assert_eq!(bytes.len(), HEADER_LEN as usize + 2 * SAMPLES_PER_FRAME * 2);
+        let (sample_rate, samples) = decode(&bytes).unwrap();
+        assert_eq!((sample_rate, samples.len()), (SAMPLE_RATE, 2 * SAMPLES_PER_FRAME));
+        // a silent frame, then the beep
+        assert!(samples[..SAMPLES_PER_FRAME].iter().all(|&sample| sample == 0.0));
+        assert!(samples[SAMPLES_PER_FRAME..].iter().any(|&sample| sample > 0.2));
+    }
+
+    #[test]
+    fn finish_fills_in_the_sizes() {
+        let bytes = record(&[Sound::default()]);
+        let data_len = (SAMPLES_PER_FRAME * 2) as u32;
+        assert_eq!(&bytes[4..8], &(HEADER_LEN - 8 + data_len).to_le_bytes());
+        assert_eq!(&bytes[40..44], &data_len.to_le_bytes());
+    }
+
+    #[test]
+    fn decode_mixes_down_to_mono() {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        // 8 bit PCM, stereo, 8000Hz
+        bytes.extend_from_slice(&[1, 0, 2, 0]);
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&16000u32.to_le_bytes());
+        bytes.extend_from_slice(&[2, 0, 8, 0]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&[255, 1, 128, 192]);
+        let (sample_rate, samples) = decode(&bytes).unwrap();
+        assert_eq!(sample_rate, 8000);
+        assert_eq!(samples, [0.0, 0.25]);
+    }
+
+    #[test]
+    fn decode_rejects_what_it_cant_play() {
+        assert!(decode(b"RIFF").is_err());
+        assert!(decode(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+}
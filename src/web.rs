@@ -0,0 +1,241 @@
+// WebAssembly frontend.
+// Browsers can't block, so there's no run loop here: the page calls frame() from
+// requestAnimationFrame, which runs one Cpu::run_frame, draws the canvas and updates the beep.
+// Keyboard events are passed in from JavaScript with key_down/key_up (see web/index.html).
+// Build instructions are in web/index.html.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+use web_sys::{AudioContext, CanvasRenderingContext2d, GainNode, HtmlCanvasElement, ImageData, OscillatorType};
+
+use crate::audio::BEEP_FREQUENCY;
+use crate::audio::Sound;
+use crate::cpu::Cpu;
+use crate::frontend::AudioSink;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::variant::Variant;
+
+const VOLUME: f32 = 0.1;
+
+#[wasm_bindgen]
+pub struct WebEmulator {
+    cpu: Cpu,
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+    // RGBA, reused every frame
+    pixels: Vec<u8>,
+    palette: Palette,
+    keymap: Keymap,
+    beeper: Option<Beeper>,
+    // kept here as well, for a beeper that doesn't exist yet
+    volume: f32,
+    muted: bool,
+}
+
+#[wasm_bindgen]
+impl WebEmulator {
+    /// Draws to `canvas`, which is resized to the display's resolution. Scale it up with CSS
+    /// (`image-rendering: pixelated` keeps it sharp).
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas: HtmlCanvasElement) -> Result<WebEmulator, JsValue> {
+        let context = canvas
+            .get_context("2d")?
+            .ok_or("canvas has no 2d context")?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+
+        Ok(WebEmulator {
+            cpu: Cpu::with_variant(Variant::Chip8),
+            canvas,
+            context,
+            pixels: Vec::new(),
+            palette: Palette::default(),
+            keymap: Keymap::default(),
+            beeper: None,
+            volume: 1.0,
+            muted: false,
+        })
+    }
+
+    /// Reset to a fresh machine of the given variant ("chip8", "schip", "xochip", "chip8x", "megachip", "eti660" or "chip8hires") and load a ROM.
+    pub fn load_rom(&mut self, rom: &[u8], variant: &str) -> Result<(), JsValue> {
+        let variant = match variant {
+            "chip8" => Variant::Chip8,
+            "schip" => Variant::SuperChip,
+            "xochip" => Variant::XoChip,
+            "chip8x" => Variant::Chip8X,
+            "megachip" => Variant::MegaChip,
+            "eti660" => Variant::Eti660,
+            "chip8hires" => Variant::Chip8Hires,
+            other => return Err(format!("unknown variant {}", other).into()),
+        };
+        let clock_speed = self.cpu.clock_speed;
+        let phosphor_decay = self.cpu.display.phosphor_decay();
+        self.cpu = Cpu::with_variant(variant);
+        self.cpu.clock_speed = clock_speed;
+        self.cpu.display.set_phosphor_decay(phosphor_decay);
+        self.cpu.load_rom(rom).map_err(|e| JsValue::from(e.to_string()))
+    }
+
+    pub fn set_clock_speed(&mut self, instructions_per_second: u32) {
+        self.cpu.clock_speed = instructions_per_second;
+    }
+
+    /// Fade pixels out over this many frames instead of turning them straight off, 0 for off.
+    pub fn set_phosphor_decay(&mut self, frames: u8) {
+        self.cpu.display.set_phosphor_decay(frames);
+    }
+
+    /// Change the colours, to a preset ("green", "amber", "paper") or hex colours
+    /// ("000000,FFFFFF"). Returns false if it wasn't either.
+    pub fn set_palette(&mut self, palette: &str) -> bool {
+        match Palette::parse(palette) {
+            Some(palette) => {
+                self.palette = palette;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Change which keys drive the keypad, to a layout ("qwerty", "azerty", "dvorak", "colemak")
+    /// or 16 keys in keypad order ("1234qwerasdfzxcv"). Returns false if it wasn't either.
+    pub fn set_keymap(&mut self, keymap: &str) -> bool {
+        match Keymap::parse(keymap) {
+            Some(keymap) => {
+                self.keymap = keymap;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start sound. Browsers only allow audio after a user gesture, so call this from a click handler.
+    pub fn enable_audio(&mut self) -> Result<(), JsValue> {
+        if self.beeper.is_none() {
+            let mut beeper = Beeper::new()?;
+            beeper.set_volume(self.volume);
+            beeper.set_muted(self.muted);
+            self.beeper = Some(beeper);
+        }
+        Ok(())
+    }
+
+    /// How loud the beep is, from 0 (silent) to 100 (the default).
+    pub fn set_volume(&mut self, percent: u8) {
+        self.volume = percent.min(100) as f32 / 100.0;
+        if let Some(beeper) = &mut self.beeper {
+            beeper.set_volume(self.volume);
+        }
+    }
+
+    /// Turn the sound off, or back on at the volume it was.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        if let Some(beeper) = &mut self.beeper {
+            beeper.set_muted(muted);
+        }
+    }
+
+    /// Run one 60Hz frame and present it. Call from requestAnimationFrame.
+    pub fn frame(&mut self) -> Result<(), JsValue> {
+        self.cpu.run_frame();
+        if let Some(beeper) = &mut self.beeper {
+            beeper.update(self.cpu.sound());
+        }
+        self.draw()
+    }
+
+    /// `key` is a KeyboardEvent.key, returns true if it's bound to the keypad (so the page can preventDefault).
+    pub fn key_down(&mut self, key: &str) -> bool {
+        self.set_key(key, true)
+    }
+
+    pub fn key_up(&mut self, key: &str) -> bool {
+        self.set_key(key, false)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+}
+
+impl WebEmulator {
+    fn set_key(&mut self, key: &str, pressed: bool) -> bool {
+        let mut chars = key.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else { return false };
+        match self.keymap.key_for(c) {
+            Some(k) => {
+                self.cpu.set_key(k, pressed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), JsValue> {
+        let display = &self.cpu.display;
+        let (width, height) = (display.width() as u32, display.height() as u32);
+        if self.canvas.width() != width || self.canvas.height() != height {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+        }
+
+        self.pixels.clear();
+        for [r, g, b] in display.rgb_pixels(&self.palette) {
+            self.pixels.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&self.pixels), width, height)?;
+        self.context.put_image_data(&image, 0.0, 0.0)
+    }
+}
+
+// A square wave that's always running, the sound timer just turns the volume up and down
+struct Beeper {
+    _context: AudioContext,
+    gain: GainNode,
+    playing: bool,
+    volume: f32,
+    muted: bool,
+}
+
+impl Beeper {
+    fn new() -> Result<Self, JsValue> {
+        let context = AudioContext::new()?;
+        let oscillator = context.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Square);
+        oscillator.frequency().set_value(BEEP_FREQUENCY);
+
+        let gain = context.create_gain()?;
+        gain.gain().set_value(0.0);
+
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&context.destination())?;
+        oscillator.start()?;
+
+        Ok(Beeper { _context: context, gain, playing: false, volume: 1.0, muted: false })
+    }
+
+    fn set_gain(&self) {
+        let on = self.playing && !self.muted;
+        self.gain.gain().set_value(if on { VOLUME * self.volume } else { 0.0 });
+    }
+}
+
+impl AudioSink for Beeper {
+    fn update(&mut self, sound: Sound) {
+        self.playing = sound.playing;
+        self.set_gain();
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.set_gain();
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.set_gain();
+    }
+}
@@ -0,0 +1,78 @@
+// chip8 transpile's output has to build against this crate and do what the interpreter does.
+// This recompiles the ROMs in roms/, builds each one in a scratch crate with a test added to
+// the end that runs it next to the plain interpreter, and checks the two agree after every frame.
+// The first run builds the crate's dependencies again in the scratch crate's target directory,
+// so it takes a while.
+
+#![cfg(feature = "terminal")]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use chip_8_emulator::transpile::transpile;
+use chip_8_emulator::Variant;
+
+const ROMS: [(&str, &[u8]); 3] = [
+    ("ibm_logo", include_bytes!("../roms/ibm-logo.ch8")),
+    ("opcode_test", include_bytes!("../roms/opcode-test.ch8")),
+    ("catch", include_bytes!("../roms/catch.ch8")),
+];
+
+// Added to the end of each recompiled ROM, it can see the ROM, BLOCKS and Recompiled
+const SAME_STATE_TEST: &str = r#"
+#[test]
+fn same_state_as_the_interpreter() {
+    assert!(!BLOCKS.is_empty(), "nothing was recompiled");
+    let mut interpreted = Cpu::with_variant(Variant::Chip8);
+    let mut compiled = Cpu::with_variant(Variant::Chip8);
+    interpreted.load_rom(ROM).unwrap();
+    compiled.load_rom(ROM).unwrap();
+    compiled.take_code_writes();
+    let mut recompiled = Recompiled { stale: [false; BLOCKS.len()] };
+
+    for frame in 0..600 {
+        // hold 6 for a bit so catch's paddle moves
+        let held = (100..200).contains(&frame);
+        interpreted.set_key(0x6, held);
+        compiled.set_key(0x6, held);
+        interpreted.run_frame();
+        compiled.run_frame_with(&mut recompiled);
+        assert_eq!(interpreted.state_digest(), compiled.state_digest(), "frame {}", frame);
+    }
+}
+"#;
+
+#[test]
+fn recompiled_roms_build_and_match_the_interpreter() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("transpiled");
+    fs::create_dir_all(dir.join("src/bin")).unwrap();
+    // its own workspace, or cargo goes looking for this crate's
+    let manifest = format!(
+        "[package]\nname = \"transpiled\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n[workspace]\n\n\
+         [dependencies]\nchip_8_emulator = {{ path = {:?}, default-features = false, features = [\"terminal\"] }}\n",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    // the same dependency versions as this crate, so it builds offline
+    fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.lock"), dir.join("Cargo.lock")).unwrap();
+
+    for (name, rom) in ROMS {
+        let source = transpile(rom, Variant::Chip8, name).unwrap();
+        fs::write(dir.join("src/bin").join(format!("{}.rs", name)), [source.as_str(), SAME_STATE_TEST].concat()).unwrap();
+    }
+
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args(["test", "--offline", "--quiet"])
+        .current_dir(&dir)
+        .env_remove("RUSTFLAGS")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}